@@ -0,0 +1,507 @@
+//! `xe build` and `xe init --build-backend`. Builds go through whatever
+//! PEP 517 backend `pyproject.toml` declares: its requirements are installed
+//! into a throwaway directory from the cache, then the backend's hooks are
+//! called with the project interpreter.
+//!
+//! maturin and scikit-build-core projects compile Rust or C/C++ extensions;
+//! `--release` and `--target` are translated into their own options, and
+//! `xe doctor` checks for the compilers they need.
+
+use crate::run_hooks;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use xe_core::config::{
+    export_metadata_to_pyproject, load_or_create_project, save_project, Config, PYPROJECT_TOML,
+};
+use xe_core::paths;
+use xe_core::process::register_cleanup_path;
+use xe_core::profile::AppContext;
+use xe_core::runtime::ensure_runtime_for_project;
+use xe_core::ui::{info, success, warning};
+use xe_core::Installer;
+
+/// Backends `xe init --build-backend` can scaffold.
+pub(crate) const BACKENDS: &[&str] = &["hatchling", "setuptools", "flit", "maturin"];
+
+/// Calls one PEP 517 hook. Backends print freely, so results go to a file.
+const HOOK_SCRIPT: &str = r#"
+import importlib, json, os, sys
+hook, kind, out_dir, result = sys.argv[1:5]
+settings = json.loads(os.environ["XE_CONFIG_SETTINGS"]) or None
+sys.path[:0] = [os.path.abspath(p) for p in json.loads(os.environ["XE_BACKEND_PATH"])]
+module, _, attr = os.environ["XE_BUILD_BACKEND"].partition(":")
+backend = importlib.import_module(module)
+for part in filter(None, attr.split(".")):
+    backend = getattr(backend, part)
+if hook == "requires":
+    get = getattr(backend, "get_requires_for_build_" + kind, None)
+    value = get(settings) if get else []
+else:
+    value = getattr(backend, "build_" + kind)(out_dir, settings)
+with open(result, "w") as f:
+    json.dump(value, f)
+"#;
+
+/// `[build-system]` of a project, with the PEP 517 defaults for projects
+/// that only have a `setup.py`.
+struct BuildSystem {
+    requires: Vec<String>,
+    backend: String,
+    backend_path: Vec<String>,
+    /// PEP 517 `config_settings` and environment for this build, derived
+    /// from `xe build` flags.
+    config_settings: Map<String, Value>,
+    env: Vec<(&'static str, String)>,
+}
+
+/// Backends that compile extension modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NativeBackend {
+    Maturin,
+    ScikitBuild,
+}
+
+impl NativeBackend {
+    fn detect(backend: &str) -> Option<Self> {
+        match backend.split([':', '.']).next().unwrap_or_default() {
+            "maturin" => Some(Self::Maturin),
+            "scikit_build_core" => Some(Self::ScikitBuild),
+            _ => None,
+        }
+    }
+}
+
+impl BuildSystem {
+    fn load(project_dir: &Path) -> Result<Self> {
+        let path = project_dir.join(PYPROJECT_TOML);
+        let legacy = Self {
+            requires: vec!["setuptools>=40.8.0".to_string()],
+            backend: "setuptools.build_meta:__legacy__".to_string(),
+            backend_path: Vec::new(),
+            config_settings: Map::new(),
+            env: Vec::new(),
+        };
+        if !path.exists() {
+            if project_dir.join("setup.py").exists() {
+                return Ok(legacy);
+            }
+            bail!(
+                "no {} in {}; run `xe init --build-backend <{}>` to add one",
+                PYPROJECT_TOML,
+                project_dir.display(),
+                BACKENDS.join("|")
+            );
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let doc: toml::Table =
+            toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+        let Some(table) = doc.get("build-system").and_then(|t| t.as_table()) else {
+            return Ok(legacy);
+        };
+        let strings = |key: &str| -> Result<Vec<String>> {
+            let Some(value) = table.get(key) else {
+                return Ok(Vec::new());
+            };
+            value
+                .as_array()
+                .and_then(|items| {
+                    items
+                        .iter()
+                        .map(|item| item.as_str().map(str::to_string))
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "build-system.{key} in {} must be a list of strings",
+                        path.display()
+                    )
+                })
+        };
+        let requires = strings("requires")?;
+        let backend_path = strings("backend-path")?;
+        let backend = match table.get("build-backend") {
+            Some(value) => value
+                .as_str()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "build-system.build-backend in {} must be a string",
+                        path.display()
+                    )
+                })?
+                .to_string(),
+            None => legacy.backend,
+        };
+        Ok(Self {
+            requires,
+            backend,
+            backend_path,
+            config_settings: Map::new(),
+            env: Vec::new(),
+        })
+    }
+
+    /// Applies `--release` and `--target`, which only mean something to
+    /// backends that compile.
+    fn apply_native_options(&mut self, release: bool, target: Option<&str>) -> Result<()> {
+        if !release && target.is_none() {
+            return Ok(());
+        }
+        match NativeBackend::detect(&self.backend) {
+            Some(NativeBackend::Maturin) => {
+                // maturin reads extra `maturin build` arguments from here.
+                let mut args = env::var("MATURIN_PEP517_ARGS").unwrap_or_default();
+                if release {
+                    args.push_str(" --release");
+                }
+                if let Some(target) = target {
+                    args.push_str(&format!(" --target {target}"));
+                }
+                self.env.push(("MATURIN_PEP517_ARGS", args.trim().to_string()));
+            }
+            Some(NativeBackend::ScikitBuild) => {
+                if let Some(target) = target {
+                    bail!(
+                        "--target {target} is not supported with scikit-build-core; cross-compile through a CMake toolchain file instead"
+                    );
+                }
+                self.config_settings
+                    .insert("cmake.build-type".to_string(), json!("Release"));
+            }
+            None => bail!(
+                "--release and --target apply to native extension backends (maturin, scikit-build-core), not {}",
+                self.backend
+            ),
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn cmd_build(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage =
+        "usage: xe build [--sdist] [--wheel] [--out-dir <dir>] [--release] [--target <triple>]";
+    let mut kinds = Vec::new();
+    let mut out_dir = None;
+    let mut release = false;
+    let mut target = None;
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--sdist" => kinds.push("sdist"),
+            "--wheel" => kinds.push("wheel"),
+            "--out-dir" | "-o" => {
+                idx += 1;
+                let Some(dir) = args.get(idx) else {
+                    bail!(usage);
+                };
+                out_dir = Some(PathBuf::from(dir));
+            }
+            "--release" => release = true,
+            "--target" => {
+                idx += 1;
+                let Some(triple) = args.get(idx) else {
+                    bail!(usage);
+                };
+                target = Some(triple.clone());
+            }
+            _ => bail!(usage),
+        }
+        idx += 1;
+    }
+    if kinds.is_empty() {
+        kinds = vec!["sdist", "wheel"];
+    }
+
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let added = export_metadata_to_pyproject(&wd, &cfg)?;
+    if !added.is_empty() {
+        info(&format!(
+            "Added {} from xe.toml to [project] in {PYPROJECT_TOML}",
+            added.join(", ")
+        ));
+    }
+    let mut system = BuildSystem::load(&wd)?;
+    system.apply_native_options(release, target.as_deref())?;
+    run_hooks(&cfg, "pre-build", &wd, None, &[])?;
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
+    }
+    let python_exe = &runtime.selection.python_exe;
+    let out_dir = out_dir.unwrap_or_else(|| wd.join("dist"));
+    fs::create_dir_all(&out_dir)
+        .with_context(|| format!("failed to create {}", out_dir.display()))?;
+    let out_dir = fs::canonicalize(&out_dir).unwrap_or(out_dir);
+
+    info(&format!("Building with {}...", system.backend));
+    let build_env = env::temp_dir().join(format!("xe-build-{}", std::process::id()));
+    register_cleanup_path(&build_env);
+    let installer = Installer::new(&cfg, &wd)?;
+    install_build_requires(
+        ctx,
+        &cfg,
+        &installer,
+        &wd,
+        &build_env,
+        python_exe,
+        &system.requires,
+    )?;
+
+    let mut built = Vec::new();
+    for kind in kinds {
+        let extra = call_hook(
+            &wd, &build_env, python_exe, &system, "requires", kind, &out_dir,
+        )?;
+        let extra = extra
+            .as_array()
+            .map(|reqs| {
+                reqs.iter()
+                    .filter_map(|r| r.as_str().map(str::to_string))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        install_build_requires(ctx, &cfg, &installer, &wd, &build_env, python_exe, &extra)?;
+        let name = call_hook(
+            &wd, &build_env, python_exe, &system, "build", kind, &out_dir,
+        )?;
+        let name = name
+            .as_str()
+            .ok_or_else(|| anyhow!("{} returned no {kind} file name", system.backend))?
+            .to_string();
+        built.push(out_dir.join(name));
+    }
+    let _ = paths::remove_dir_all(&build_env);
+    for path in &built {
+        success(&format!("Built {}", path.display()));
+    }
+    Ok(())
+}
+
+fn install_build_requires(
+    ctx: &AppContext,
+    cfg: &Config,
+    installer: &Installer,
+    project_dir: &Path,
+    build_env: &Path,
+    python_exe: &Path,
+    requires: &[String],
+) -> Result<()> {
+    if requires.is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(build_env)
+        .with_context(|| format!("failed to create {}", build_env.display()))?;
+    installer
+        .install(ctx, cfg, requires, project_dir, build_env, python_exe)
+        .with_context(|| {
+            format!(
+                "failed to install build requirements: {}",
+                requires.join(", ")
+            )
+        })?;
+    Ok(())
+}
+
+fn call_hook(
+    project_dir: &Path,
+    build_env: &Path,
+    python_exe: &Path,
+    system: &BuildSystem,
+    hook: &str,
+    kind: &str,
+    out_dir: &Path,
+) -> Result<Value> {
+    let result = env::temp_dir().join(format!("xe-build-{}-{hook}.json", std::process::id()));
+    register_cleanup_path(&result);
+    let mut command = Command::new(python_exe);
+    command.envs(system.env.iter().map(|(key, value)| (*key, value)));
+    let status = command
+        .arg("-c")
+        .arg(HOOK_SCRIPT)
+        .args([hook, kind])
+        .arg(out_dir)
+        .arg(&result)
+        .current_dir(project_dir)
+        .env("PYTHONPATH", build_env)
+        .env("PYTHONNOUSERSITE", "1")
+        .env("XE_BUILD_BACKEND", &system.backend)
+        .env(
+            "XE_BACKEND_PATH",
+            serde_json::to_string(&system.backend_path)?,
+        )
+        .env(
+            "XE_CONFIG_SETTINGS",
+            serde_json::to_string(&system.config_settings)?,
+        )
+        .status()
+        .with_context(|| format!("failed to run {}", python_exe.display()))?;
+    if !status.success() {
+        bail!("{} failed in {hook} for {kind}: {status}", system.backend);
+    }
+    let raw = fs::read(&result).with_context(|| format!("failed to read {}", result.display()))?;
+    let _ = paths::remove_file(&result);
+    serde_json::from_slice(&raw).with_context(|| format!("failed to parse {}", result.display()))
+}
+
+/// Writes `pyproject.toml` with the `[build-system]` of `backend`, a
+/// `[project]` table and the backend's package layout, plus an empty
+/// `src/<package>` for Python backends. An `app` also gets a console script
+/// named after the project that calls `<package>:main`.
+pub(crate) fn scaffold_backend(
+    project_dir: &Path,
+    cfg: &Config,
+    backend: &str,
+    app: bool,
+) -> Result<()> {
+    let path = project_dir.join(PYPROJECT_TOML);
+    if path.exists() {
+        bail!(
+            "{} already exists; set its [build-system] table by hand",
+            path.display()
+        );
+    }
+    let name = cfg.project.name.trim();
+    let package = name.to_lowercase().replace(['-', '.', ' '], "_");
+    let (requires, build_backend, tool) = match backend {
+        "hatchling" => (
+            "hatchling",
+            "hatchling.build",
+            format!("[tool.hatch.build.targets.wheel]\npackages = [\"src/{package}\"]\n"),
+        ),
+        "setuptools" => (
+            "setuptools>=61",
+            "setuptools.build_meta",
+            "[tool.setuptools.packages.find]\nwhere = [\"src\"]\n".to_string(),
+        ),
+        "flit" => (
+            "flit_core>=3.4,<4",
+            "flit_core.buildapi",
+            format!("[tool.flit.module]\nname = \"{package}\"\n"),
+        ),
+        "maturin" => (
+            "maturin>=1.0,<2.0",
+            "maturin",
+            "[tool.maturin]\nfeatures = [\"pyo3/extension-module\"]\n".to_string(),
+        ),
+        other => bail!(
+            "unknown build backend '{}' (expected {})",
+            other,
+            BACKENDS.join(", ")
+        ),
+    };
+    let requires_python = python_floor(&cfg.python.version);
+    let mut metadata = cfg.project.pyproject_entries();
+    if !metadata.iter().any(|(key, _)| *key == "description") {
+        metadata.insert(0, ("description", "\"\"".to_string()));
+    }
+    let metadata = metadata
+        .iter()
+        .map(|(key, value)| format!("{key} = {value}\n"))
+        .collect::<String>();
+    let scripts = if app {
+        format!("[project.scripts]\n{name} = \"{package}:main\"\n\n")
+    } else {
+        String::new()
+    };
+    let content = format!(
+        "[build-system]\nrequires = [\"{requires}\"]\nbuild-backend = \"{build_backend}\"\n\n\
+         [project]\nname = \"{name}\"\nversion = \"0.1.0\"\n{metadata}\
+         requires-python = \">={requires_python}\"\n\n{scripts}{tool}"
+    );
+    fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
+    println!("Created {}", path.display());
+
+    if backend == "maturin" {
+        if !project_dir.join("Cargo.toml").exists() {
+            warning("maturin builds a Rust crate; add a Cargo.toml with a pyo3 cdylib before `xe build`");
+        }
+        return Ok(());
+    }
+    let package_dir = project_dir.join("src").join(&package);
+    let init = package_dir.join("__init__.py");
+    if !init.exists() {
+        fs::create_dir_all(&package_dir)
+            .with_context(|| format!("failed to create {}", package_dir.display()))?;
+        let body = if app {
+            format!("\"\"\"{name}.\"\"\"\n\n\ndef main():\n    print(\"Hello from {name}!\")\n")
+        } else {
+            format!("\"\"\"{name}.\"\"\"\n")
+        };
+        fs::write(&init, body).with_context(|| format!("failed to write {}", init.display()))?;
+        println!("Created {}", init.display());
+    }
+    Ok(())
+}
+
+/// `3.12` out of versions like `3.12.4`, `3.13t` or `pypy3.10`.
+fn python_floor(version: &str) -> String {
+    let digits = version.trim_start_matches(|c: char| !c.is_ascii_digit());
+    let digits = digits.trim_end_matches('t');
+    digits.split('.').take(2).collect::<Vec<_>>().join(".")
+}
+
+/// `xe doctor` lines for the compilers a native extension backend needs;
+/// pure-Python projects have nothing to check.
+pub(crate) fn check_toolchain(project_dir: &Path) {
+    let native = project_dir
+        .join(PYPROJECT_TOML)
+        .exists()
+        .then(|| BuildSystem::load(project_dir).ok())
+        .flatten()
+        .and_then(|system| NativeBackend::detect(&system.backend));
+    let Some(native) = native else {
+        println!("[OK] Toolchain compatibility confirmed");
+        return;
+    };
+    match native {
+        NativeBackend::Maturin => {
+            for tool in ["cargo", "rustc"] {
+                match tool_version(tool, &["--version"]) {
+                    Some(version) => println!("[OK] {version}"),
+                    None => println!(
+                        "[WARN] {tool} not found; maturin needs a Rust toolchain (https://rustup.rs)"
+                    ),
+                }
+            }
+        }
+        NativeBackend::ScikitBuild => match tool_version("cmake", &["--version"]) {
+            Some(version) => println!("[OK] {version}"),
+            None => println!(
+                "[WARN] cmake not found; scikit-build-core will install it from PyPI during the build"
+            ),
+        },
+    }
+    let compiler = if cfg!(windows) {
+        tool_version("where", &["cl"]).map(|path| format!("MSVC {path}"))
+    } else {
+        tool_version("cc", &["--version"]).or_else(|| tool_version("c++", &["--version"]))
+    };
+    match compiler {
+        Some(version) => println!("[OK] C compiler: {version}"),
+        None if cfg!(windows) => {
+            println!("[WARN] MSVC (cl.exe) not found; run from a Visual Studio developer prompt")
+        }
+        None => println!("[WARN] No C compiler (cc) found; install gcc or clang"),
+    }
+}
+
+/// First output line of `program args`, or `None` when it cannot run.
+fn tool_version(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
@@ -0,0 +1,180 @@
+//! `xe ci github` and `xe ci cache-key`. The workflow installs xe, restores
+//! the cache under a key derived from `xe hash`, syncs from `xe.lock`
+//! without resolving and runs the project's checks.
+
+use crate::locked_environment_digest;
+use anyhow::{anyhow, bail, Context, Result};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use xe_core::config::{load_or_create_project, normalize_dep_name, save_project, Config};
+use xe_core::lock::{load_lock, lock_fingerprint, XE_LOCK};
+use xe_core::profile::AppContext;
+use xe_core::runtime::ensure_runtime_for_project;
+use xe_core::ui::{success, warning};
+
+const USAGE: &str =
+    "usage: xe ci <github [--runs-on <label>] [--run <command>]... [--output <path>]|cache-key>";
+
+const XE_GIT_URL: &str = "https://github.com/aaravmaloo/xe";
+
+/// Hex digits of the environment digest kept in the key; plenty to tell
+/// environments apart, short enough to read in the Actions log.
+const KEY_DIGEST_LEN: usize = 32;
+
+pub(crate) fn cmd_ci(ctx: &AppContext, args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("github") => cmd_ci_github(&args[1..]),
+        Some("cache-key") if args.len() == 1 => {
+            println!("{}", cache_key(ctx)?);
+            Ok(())
+        }
+        _ => bail!(USAGE),
+    }
+}
+
+/// `xe-<os>-<arch>-<digest>`, where the digest is that of `xe hash`: the
+/// locked packages with the files picked for this platform and the
+/// interpreter. Everything before the digest makes a restore key for the
+/// nearest older cache of the same platform.
+fn cache_key(ctx: &AppContext) -> Result<String> {
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let Some(lock) = load_lock(&wd)? else {
+        bail!("no {XE_LOCK} in {}; run `xe lock` first", wd.display());
+    };
+    if lock.fingerprint != lock_fingerprint(&cfg) {
+        bail!("{XE_LOCK} is out of date; run `xe lock` first");
+    }
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
+    }
+    let digest = locked_environment_digest(&wd, &cfg, &lock, &runtime.selection.python_exe)?;
+    Ok(format!(
+        "xe-{}-{}-{}",
+        env::consts::OS,
+        env::consts::ARCH,
+        &digest[..digest.len().min(KEY_DIGEST_LEN)]
+    ))
+}
+
+fn cmd_ci_github(args: &[String]) -> Result<()> {
+    let mut runs_on = "ubuntu-latest".to_string();
+    let mut commands = Vec::new();
+    let mut output = None;
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        match flag {
+            "--runs-on" | "--run" | "--output" | "-o" => {
+                i += 1;
+                let value = args
+                    .get(i)
+                    .ok_or_else(|| anyhow!("{flag} requires a value"))?
+                    .clone();
+                match flag {
+                    "--runs-on" => runs_on = value,
+                    "--run" => commands.push(value),
+                    _ => output = Some(value),
+                }
+            }
+            _ => bail!(USAGE),
+        }
+        i += 1;
+    }
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (cfg, _) = load_or_create_project(&wd)?;
+    if commands.is_empty() && uses_pytest(&cfg) {
+        commands.push("pytest".to_string());
+    }
+    if load_lock(&wd)?.is_none() {
+        warning(&format!(
+            "The workflow syncs with --frozen, which needs a committed {XE_LOCK}; run `xe lock`"
+        ));
+    }
+
+    let workflow = github_workflow(&runs_on, &commands);
+    match output.as_deref() {
+        None | Some("-") => print!("{workflow}"),
+        Some(path) => {
+            let path = PathBuf::from(path);
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("failed to create {}", parent.display()))?;
+            }
+            fs::write(&path, workflow)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            success(&format!("Wrote {}", path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// pytest among the dependencies or any extras group.
+fn uses_pytest(cfg: &Config) -> bool {
+    cfg.deps
+        .keys()
+        .chain(cfg.extras.values().flat_map(|group| group.keys()))
+        .any(|name| normalize_dep_name(name) == "pytest")
+}
+
+fn github_workflow(runs_on: &str, commands: &[String]) -> String {
+    let mut out = String::new();
+    out.push_str("name: xe\n\non:\n  push:\n  pull_request:\n\njobs:\n  check:\n");
+    out.push_str(&format!("    runs-on: {runs_on}\n"));
+    out.push_str("    steps:\n");
+    out.push_str("      - uses: actions/checkout@v4\n\n");
+
+    // The xe that wrote the workflow, so CI does not move with the main
+    // branch.
+    out.push_str("      - name: Set up xe\n");
+    out.push_str(&format!(
+        "        run: cargo install --git {XE_GIT_URL} --tag v{} xe\n\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+
+    out.push_str("      - name: Compute the xe cache key\n");
+    out.push_str("        id: xe-cache\n");
+    out.push_str("        shell: bash\n");
+    out.push_str("        run: |\n");
+    out.push_str("          key=\"$(xe ci cache-key)\"\n");
+    out.push_str("          echo \"key=$key\" >> \"$GITHUB_OUTPUT\"\n");
+    out.push_str("          echo \"restore=${key%-*}-\" >> \"$GITHUB_OUTPUT\"\n");
+    out.push_str("          echo \"dir=$(xe cache dir)\" >> \"$GITHUB_OUTPUT\"\n\n");
+
+    out.push_str("      - name: Restore the xe cache\n");
+    out.push_str("        uses: actions/cache@v4\n");
+    out.push_str("        with:\n");
+    out.push_str("          path: ${{ steps.xe-cache.outputs.dir }}\n");
+    out.push_str("          key: ${{ steps.xe-cache.outputs.key }}\n");
+    out.push_str("          restore-keys: ${{ steps.xe-cache.outputs.restore }}\n\n");
+
+    out.push_str("      - name: Sync from xe.lock\n");
+    out.push_str("        run: xe sync --frozen\n");
+    if commands.is_empty() {
+        out.push_str("\n      # Add the project's checks, e.g.:\n");
+        out.push_str("      # - run: xe run -- pytest\n");
+    }
+    for command in commands {
+        out.push_str(&format!(
+            "\n      - name: {}\n        run: {}\n",
+            yaml_scalar(command),
+            yaml_scalar(&format!("xe run -- {command}"))
+        ));
+    }
+    out
+}
+
+/// `command` as a YAML plain scalar when that reads the same, else quoted.
+fn yaml_scalar(command: &str) -> String {
+    let plain = !command.is_empty()
+        && !command.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        && !command.contains(": ")
+        && !command.contains(" #");
+    if plain {
+        command.to_string()
+    } else {
+        format!("'{}'", command.replace('\'', "''"))
+    }
+}
@@ -0,0 +1,166 @@
+//! Crash reports: written on panics and internal errors under
+//! `xe_home()/crashes`, and turned into an issue body by `xe self report`.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::backtrace::Backtrace;
+use std::env;
+use std::fs;
+use std::panic::{self, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use xe_core::paths::xe_home;
+use xe_core::process::cleanup_registered_paths;
+use xe_core::profile::{profile_stamp, recent_spans, timestamp_iso8601};
+use xe_core::ui::error;
+use xe_core::{Error as CoreError, ErrorKind};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CrashReport {
+    created_at: String,
+    /// `panic` or `internal error`.
+    kind: String,
+    message: String,
+    #[serde(default)]
+    location: String,
+    command: Vec<String>,
+    xe_version: String,
+    os: String,
+    arch: String,
+    backtrace: String,
+    #[serde(default)]
+    recent_spans: Vec<Value>,
+}
+
+/// Only the first panic of a process is reported; rayon re-raises worker
+/// panics on the calling thread.
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Replaces the default panic message with a crash report.
+pub(crate) fn install_panic_hook() {
+    panic::set_hook(Box::new(|info: &PanicHookInfo<'_>| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        // `xe tree | head` closes stdout early; that is not a crash.
+        if message.starts_with("failed printing to std") {
+            cleanup_registered_paths();
+            std::process::exit(crate::EXIT_BROKEN_PIPE);
+        }
+        if REPORTED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+            .unwrap_or_default();
+        let backtrace = Backtrace::force_capture().to_string();
+        announce(
+            "xe crashed",
+            &message,
+            write("panic", &message, &location, backtrace),
+        );
+    }));
+}
+
+/// Writes and announces a report when `err` is an [`ErrorKind::Internal`]
+/// failure; returns whether it did.
+pub(crate) fn report_internal_error(err: &anyhow::Error) -> bool {
+    let internal = err
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<CoreError>())
+        .any(|cause| cause.kind() == ErrorKind::Internal);
+    if !internal {
+        return false;
+    }
+    let message = format!("{err:#}");
+    let backtrace = err.backtrace().to_string();
+    announce(
+        "xe hit an internal error",
+        &message,
+        write("internal error", &message, "", backtrace),
+    );
+    true
+}
+
+fn write(kind: &str, message: &str, location: &str, backtrace: String) -> Result<PathBuf> {
+    let report = CrashReport {
+        created_at: timestamp_iso8601(),
+        kind: kind.to_string(),
+        message: message.to_string(),
+        location: location.to_string(),
+        command: env::args().skip(1).collect(),
+        xe_version: env!("CARGO_PKG_VERSION").to_string(),
+        os: env::consts::OS.to_string(),
+        arch: env::consts::ARCH.to_string(),
+        backtrace,
+        recent_spans: recent_spans(),
+    };
+    let dir = xe_home().join("crashes");
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let path = dir.join(format!("crash-{}.json", profile_stamp()));
+    let encoded = serde_json::to_string_pretty(&report)?;
+    fs::write(&path, encoded).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+fn announce(headline: &str, message: &str, written: Result<PathBuf>) {
+    error(&format!("{headline}: {message}"));
+    match written {
+        Ok(path) => {
+            error(&format!("Crash report written to {}", path.display()));
+            error(&format!(
+                "Run `xe self report {}` and paste the output into a GitHub issue",
+                path.display()
+            ));
+        }
+        Err(err) => error(&format!("Could not write a crash report: {err:#}")),
+    }
+}
+
+/// Markdown for a GitHub issue, with the home directory replaced by `~`.
+pub(crate) fn format_report(path: &Path) -> Result<String> {
+    let raw =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let report: CrashReport = serde_json::from_str(&raw)
+        .with_context(|| format!("{} is not an xe crash report", path.display()))?;
+    let spans = report
+        .recent_spans
+        .iter()
+        .map(|span| span.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let location = if report.location.is_empty() {
+        String::new()
+    } else {
+        format!(" at `{}`", report.location)
+    };
+    let body = format!(
+        "### Crash report\n\n\
+         - **xe**: {}\n\
+         - **OS**: {} ({})\n\
+         - **Command**: `xe {}`\n\
+         - **When**: {}\n\n\
+         **{}**{}:\n\n```\n{}\n```\n\n\
+         <details><summary>Backtrace</summary>\n\n```\n{}\n```\n</details>\n\n\
+         <details><summary>Recent spans</summary>\n\n```\n{}\n```\n</details>\n",
+        report.xe_version,
+        report.os,
+        report.arch,
+        report.command.join(" "),
+        report.created_at,
+        report.kind,
+        location,
+        report.message.trim(),
+        report.backtrace.trim_end(),
+        if spans.is_empty() { "(none)" } else { &spans },
+    );
+    Ok(match dirs::home_dir() {
+        Some(home) if !home.as_os_str().is_empty() => body.replace(&*home.to_string_lossy(), "~"),
+        _ => body,
+    })
+}
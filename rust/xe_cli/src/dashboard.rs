@@ -0,0 +1,539 @@
+//! `xe ui`: a terminal dashboard over the project's dependencies, its
+//! environments, the cache and managed Pythons.
+//!
+//! Add, remove and upgrade leave the dashboard and run the same `cmd_*`
+//! functions as the CLI, so their output and prompts look the same, and
+//! `xe undo` can take them back.
+
+use crate::{cmd_add, cmd_remove, fetch_metadata_from_pypi, read_stdin_line, undo};
+use anyhow::{bail, Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+use rayon::prelude::*;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use xe_core::config::{load_or_create_project, normalize_dep_name, save_project};
+use xe_core::install::installed_distributions;
+use xe_core::paths::dir_size;
+use xe_core::profile::AppContext;
+use xe_core::python::{compare_version, PythonManager};
+use xe_core::runtime::ensure_runtime_for_project;
+use xe_core::ui::{error, format_bytes};
+use xe_core::venv::VenvManager;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    Deps,
+    Envs,
+    Cache,
+    Pythons,
+}
+
+const TABS: [Tab; 4] = [Tab::Deps, Tab::Envs, Tab::Cache, Tab::Pythons];
+
+impl Tab {
+    fn title(self) -> &'static str {
+        match self {
+            Tab::Deps => "Dependencies",
+            Tab::Envs => "Environments",
+            Tab::Cache => "Cache",
+            Tab::Pythons => "Pythons",
+        }
+    }
+}
+
+struct DepRow {
+    name: String,
+    spec: String,
+    installed: Option<String>,
+    /// `None` until the PyPI lookup answers; `Some(None)` when it failed or
+    /// the package comes from a pinned index.
+    latest: Option<Option<String>>,
+}
+
+impl DepRow {
+    /// Latest release when it is newer than the installed (or pinned) version.
+    fn upgrade(&self) -> Option<&str> {
+        let latest = self.latest.as_ref()?.as_deref()?;
+        let current = self
+            .installed
+            .as_deref()
+            .or(Some(self.spec.as_str()).filter(|s| *s != "*"))?;
+        (compare_version(current, latest) == std::cmp::Ordering::Less).then_some(latest)
+    }
+}
+
+/// What a key press asks the event loop to do outside the dashboard.
+enum Action {
+    Add(String),
+    Remove(String),
+    Upgrade(Vec<String>),
+    Refresh,
+    Quit,
+}
+
+struct Dashboard {
+    tab: Tab,
+    deps: Vec<DepRow>,
+    envs: Vec<[String; 4]>,
+    cache_dir: String,
+    cache: Vec<(String, u64)>,
+    pythons: Vec<[String; 3]>,
+    table: TableState,
+    /// Text of the `a` prompt while it is open.
+    input: Option<String>,
+    /// Dependency awaiting `y` to be removed.
+    confirm: Option<String>,
+    latest_rx: Receiver<(String, Option<String>)>,
+}
+
+pub(crate) fn run(ctx: &AppContext) -> Result<()> {
+    if !io::stdout().is_terminal() {
+        bail!("xe ui needs an interactive terminal");
+    }
+    let mut dash = Dashboard::load(ctx)?;
+    let mut terminal = ratatui::init();
+    let result = event_loop(ctx, &mut terminal, &mut dash);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    ctx: &AppContext,
+    terminal: &mut DefaultTerminal,
+    dash: &mut Dashboard,
+) -> Result<()> {
+    loop {
+        while let Ok((name, latest)) = dash.latest_rx.try_recv() {
+            if let Some(row) = dash.deps.iter_mut().find(|row| row.name == name) {
+                row.latest = Some(latest);
+            }
+        }
+        terminal
+            .draw(|frame| dash.draw(frame))
+            .context("failed to draw dashboard")?;
+        if !event::poll(Duration::from_millis(200)).context("failed to read terminal events")? {
+            continue;
+        }
+        let Event::Key(key) = event::read().context("failed to read terminal events")? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let action = match dash.handle_key(key.code) {
+            Some(action) => action,
+            None => continue,
+        };
+        let (label, args) = match action {
+            Action::Quit => return Ok(()),
+            Action::Refresh => {
+                *dash = Dashboard::load(ctx)?;
+                continue;
+            }
+            Action::Add(spec) => ("add", vec![spec]),
+            Action::Remove(name) => ("remove", vec![name]),
+            Action::Upgrade(specs) => ("add", specs),
+        };
+        ratatui::restore();
+        println!("$ xe {} {}", label, args.join(" "));
+        let outcome = undo::recorded(label, &args, || {
+            if label == "add" {
+                cmd_add(ctx, &args)
+            } else {
+                cmd_remove(ctx, &args)
+            }
+        });
+        if let Err(err) = outcome {
+            error(&format!("{:#}", err));
+        }
+        let reloaded = Dashboard::load(ctx);
+        print!("Press Enter to return to the dashboard...");
+        io::stdout().flush()?;
+        read_stdin_line()?;
+        *dash = reloaded?;
+        dash.select_dep(&args[0]);
+        *terminal = ratatui::init();
+    }
+}
+
+impl Dashboard {
+    /// Reads project, environment, cache and runtime state, and starts the
+    /// PyPI lookups that fill in the outdated markers.
+    fn load(ctx: &AppContext) -> Result<Self> {
+        let wd = env::current_dir().context("failed to get cwd")?;
+        let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+        let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+        if runtime.config_changed {
+            save_project(&toml_path, &cfg)?;
+        }
+        let selection = runtime.selection;
+        let installed = installed_distributions(&selection.site_packages)?;
+
+        let installed_version = |name: &str| {
+            installed
+                .get(&normalize_dep_name(name))
+                .map(|(_, version)| version.clone())
+        };
+        let mut deps = Vec::new();
+        let mut lookups = Vec::new();
+        for (name, spec) in &cfg.deps {
+            // Direct wheels and pinned indexes have no PyPI release to compare.
+            let (spec, latest) = if cfg.direct.contains_key(name) {
+                ("direct".to_string(), Some(None))
+            } else if !spec.index().is_empty() {
+                (format!("{} @ {}", spec.version(), spec.index()), Some(None))
+            } else {
+                lookups.push(name.clone());
+                (spec.version().to_string(), None)
+            };
+            deps.push(DepRow {
+                name: name.clone(),
+                spec,
+                installed: installed_version(name),
+                latest,
+            });
+        }
+        for name in cfg.editable.keys().chain(cfg.direct.keys()) {
+            if deps.iter().any(|row| row.name == *name) {
+                continue;
+            }
+            deps.push(DepRow {
+                name: name.clone(),
+                spec: if cfg.editable.contains_key(name) {
+                    "editable".to_string()
+                } else {
+                    "direct".to_string()
+                },
+                installed: installed_version(name),
+                latest: Some(None),
+            });
+        }
+        deps.sort_by_key(|row| row.name.to_lowercase());
+
+        let (tx, latest_rx) = mpsc::channel();
+        thread::spawn(move || {
+            lookups.into_par_iter().for_each_with(tx, |tx, name| {
+                let latest = fetch_metadata_from_pypi(&name)
+                    .ok()
+                    .map(|meta| meta.info.version);
+                let _ = tx.send((name, latest));
+            });
+        });
+
+        let mut envs = vec![[
+            if selection.is_venv {
+                "project venv".to_string()
+            } else {
+                "* global".to_string()
+            },
+            cfg.python.version.clone(),
+            format_bytes(dir_size(&selection.site_packages)),
+            selection.site_packages.display().to_string(),
+        ]];
+        let vm = VenvManager::new()?;
+        let mut venvs = vm.list()?;
+        venvs.sort();
+        for name in venvs {
+            let meta = vm.metadata(&name).unwrap_or_default();
+            let python = if meta.python.is_empty() {
+                vm.python_version(&name)
+            } else {
+                meta.python
+            };
+            let active = selection.is_venv && selection.venv_name.eq_ignore_ascii_case(&name);
+            envs.push([
+                format!("{}{}", if active { "* " } else { "" }, name),
+                python,
+                format_bytes(dir_size(&vm.base_dir.join(&name))),
+                if meta.project.is_empty() {
+                    "-".to_string()
+                } else {
+                    meta.project
+                },
+            ]);
+        }
+
+        let cache_dir = cfg.cache.dir(&wd)?;
+        let mut cache = Vec::new();
+        if let Ok(entries) = fs::read_dir(&cache_dir) {
+            for entry in entries.flatten() {
+                let size = if entry.path().is_dir() {
+                    dir_size(&entry.path())
+                } else {
+                    entry.metadata().map(|meta| meta.len()).unwrap_or(0)
+                };
+                cache.push((entry.file_name().to_string_lossy().to_string(), size));
+            }
+        }
+        cache.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+        let pm = PythonManager::new()?;
+        let pythons = pm
+            .installed_versions()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|version| {
+                let selected = version == cfg.python.version;
+                [
+                    format!("{}{}", if selected { "* " } else { "" }, version),
+                    pm.get_python_exe(&version)
+                        .map(|exe| exe.display().to_string())
+                        .unwrap_or_default(),
+                    pm.get_python_path(&version)
+                        .map(|home| format_bytes(dir_size(&home)))
+                        .unwrap_or_default(),
+                ]
+            })
+            .collect();
+
+        let mut table = TableState::default();
+        table.select(Some(0));
+        Ok(Self {
+            tab: Tab::Deps,
+            deps,
+            envs,
+            cache_dir: cache_dir.display().to_string(),
+            cache,
+            pythons,
+            table,
+            input: None,
+            confirm: None,
+            latest_rx,
+        })
+    }
+
+    fn rows(&self) -> usize {
+        match self.tab {
+            Tab::Deps => self.deps.len(),
+            Tab::Envs => self.envs.len(),
+            Tab::Cache => self.cache.len(),
+            Tab::Pythons => self.pythons.len(),
+        }
+    }
+
+    fn selected_dep(&self) -> Option<&DepRow> {
+        if self.tab != Tab::Deps {
+            return None;
+        }
+        self.deps.get(self.table.selected()?)
+    }
+
+    fn select_dep(&mut self, spec: &str) {
+        let name = spec
+            .split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))
+            .next()
+            .unwrap_or(spec);
+        if let Some(idx) = self
+            .deps
+            .iter()
+            .position(|row| row.name.eq_ignore_ascii_case(name))
+        {
+            self.table.select(Some(idx));
+        }
+    }
+
+    fn handle_key(&mut self, code: KeyCode) -> Option<Action> {
+        if let Some(input) = self.input.as_mut() {
+            match code {
+                KeyCode::Enter => {
+                    let spec = input.trim().to_string();
+                    self.input = None;
+                    if !spec.is_empty() {
+                        return Some(Action::Add(spec));
+                    }
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return None;
+        }
+        if let Some(name) = self.confirm.take() {
+            return matches!(code, KeyCode::Char('y') | KeyCode::Char('Y'))
+                .then_some(Action::Remove(name));
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Char('r') => return Some(Action::Refresh),
+            KeyCode::Tab | KeyCode::Right => self.switch_tab(1),
+            KeyCode::BackTab | KeyCode::Left => self.switch_tab(TABS.len() - 1),
+            KeyCode::Char(c @ '1'..='4') => {
+                self.tab = TABS[c as usize - '1' as usize];
+                self.table.select(Some(0));
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Char('a') if self.tab == Tab::Deps => self.input = Some(String::new()),
+            KeyCode::Char('d') | KeyCode::Delete => {
+                self.confirm = self.selected_dep().map(|row| row.name.clone());
+            }
+            KeyCode::Char('u') => {
+                let row = self.selected_dep()?;
+                let latest = row.upgrade()?;
+                return Some(Action::Upgrade(vec![format!("{}=={}", row.name, latest)]));
+            }
+            KeyCode::Char('U') if self.tab == Tab::Deps => {
+                let specs = self
+                    .deps
+                    .iter()
+                    .filter_map(|row| Some(format!("{}=={}", row.name, row.upgrade()?)))
+                    .collect::<Vec<_>>();
+                if !specs.is_empty() {
+                    return Some(Action::Upgrade(specs));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn switch_tab(&mut self, step: usize) {
+        let idx = TABS.iter().position(|tab| *tab == self.tab).unwrap_or(0);
+        self.tab = TABS[(idx + step) % TABS.len()];
+        self.table.select(Some(0));
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let rows = self.rows();
+        if rows == 0 {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        self.table
+            .select(Some((current + delta).rem_euclid(rows as isize) as usize));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [tabs_area, body_area, footer_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+
+        let selected_tab = TABS.iter().position(|tab| *tab == self.tab).unwrap_or(0);
+        let tabs = Tabs::new(
+            TABS.iter()
+                .enumerate()
+                .map(|(idx, tab)| format!("{} {}", idx + 1, tab.title())),
+        )
+        .select(selected_tab)
+        .highlight_style(Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED))
+        .block(Block::default().borders(Borders::ALL).title(" xe "));
+        frame.render_widget(tabs, tabs_area);
+
+        let (header, widths, rows, title): (&[&str], Vec<Constraint>, Vec<Row>, String) =
+            match self.tab {
+                Tab::Deps => {
+                    let outdated = self
+                        .deps
+                        .iter()
+                        .filter(|row| row.upgrade().is_some())
+                        .count();
+                    (
+                        &["Package", "Spec", "Installed", "Latest"],
+                        vec![
+                            Constraint::Percentage(40),
+                            Constraint::Percentage(20),
+                            Constraint::Percentage(20),
+                            Constraint::Percentage(20),
+                        ],
+                        self.deps.iter().map(dep_row).collect(),
+                        format!(" {} dependencies, {} outdated ", self.deps.len(), outdated),
+                    )
+                }
+                Tab::Envs => (
+                    &["Environment", "Python", "Size", "Location / project"],
+                    vec![
+                        Constraint::Percentage(30),
+                        Constraint::Length(10),
+                        Constraint::Length(12),
+                        Constraint::Min(10),
+                    ],
+                    self.envs
+                        .iter()
+                        .map(|env| Row::new(env.iter().map(|c| Cell::from(c.as_str()))))
+                        .collect(),
+                    " Environments (* = active) ".to_string(),
+                ),
+                Tab::Cache => (
+                    &["Entry", "Size"],
+                    vec![Constraint::Percentage(70), Constraint::Percentage(30)],
+                    self.cache
+                        .iter()
+                        .map(|(name, size)| Row::new([name.clone(), format_bytes(*size)]))
+                        .collect(),
+                    format!(
+                        " {} ({}) ",
+                        self.cache_dir,
+                        format_bytes(self.cache.iter().map(|(_, size)| size).sum())
+                    ),
+                ),
+                Tab::Pythons => (
+                    &["Version", "Executable", "Size"],
+                    vec![
+                        Constraint::Length(14),
+                        Constraint::Min(10),
+                        Constraint::Length(12),
+                    ],
+                    self.pythons
+                        .iter()
+                        .map(|py| Row::new(py.iter().map(|c| Cell::from(c.as_str()))))
+                        .collect(),
+                    " Managed Pythons (* = project) ".to_string(),
+                ),
+            };
+        let table = Table::new(rows, widths)
+            .header(
+                Row::new(header.iter().copied())
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, body_area, &mut self.table);
+
+        let footer = if let Some(input) = &self.input {
+            Line::from(format!("add: {input}_   (Enter to install, Esc to cancel)"))
+        } else if let Some(name) = &self.confirm {
+            Line::from(format!("remove {name}? (y/N)"))
+        } else if self.tab == Tab::Deps {
+            Line::from("a add  d remove  u upgrade  U upgrade all  r refresh  Tab switch  q quit")
+        } else {
+            Line::from("r refresh  Tab switch  j/k move  q quit")
+        };
+        frame.render_widget(
+            Paragraph::new(footer).block(Block::default().borders(Borders::ALL)),
+            footer_area,
+        );
+    }
+}
+
+fn dep_row(row: &DepRow) -> Row<'_> {
+    let latest = match &row.latest {
+        None => Cell::from("checking..."),
+        Some(None) => Cell::from("-"),
+        Some(Some(latest)) if row.upgrade().is_some() => {
+            Cell::from(format!("{latest} (outdated)")).style(Style::default().fg(Color::Yellow))
+        }
+        Some(Some(latest)) => Cell::from(latest.as_str()).style(Style::default().fg(Color::Green)),
+    };
+    Row::new([
+        Cell::from(row.name.as_str()),
+        Cell::from(row.spec.as_str()),
+        Cell::from(row.installed.as_deref().unwrap_or("not installed")),
+        latest,
+    ])
+}
@@ -0,0 +1,419 @@
+//! `xe env pack` and `xe env unpack`. The project's venv goes into a tar
+//! archive without the parts tied to its interpreter, with its own path in
+//! scripts replaced by a placeholder; unpacking creates a fresh venv for the
+//! project and fills it from the archive, so teammates on a slow network get
+//! a ready environment without resolving or downloading anything.
+
+use crate::wheelhouse::{extract_archive, staging_dir, write_archive};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use xe_core::config::{load_or_create_project, save_project};
+use xe_core::install::path_to_file_url;
+use xe_core::lock::lock_fingerprint;
+use xe_core::profile::{timestamp_iso8601, AppContext};
+use xe_core::runtime::{auto_venv_name, ensure_runtime_for_project, existing_runtime_for_project};
+use xe_core::ui::{format_bytes, info, success, warning};
+use xe_core::venv::{VenvManager, VENV_METADATA};
+
+const MANIFEST: &str = "manifest.json";
+const FORMAT: &str = "xe-env";
+const VERSION: u32 = 2;
+const VENV_DIR: &str = "venv";
+
+/// Stands for the venv directory in packed scripts.
+const PREFIX_PLACEHOLDER: &str = "@XE_VENV_PREFIX@";
+/// Stand for the project directory, as a path and as a `file://` URL, in
+/// the `.pth` files and `direct_url.json` of editable installs.
+const PROJECT_PLACEHOLDER: &str = "@XE_PROJECT_DIR@";
+const PROJECT_URL_PLACEHOLDER: &str = "@XE_PROJECT_URL@";
+
+const USAGE: &str =
+    "usage: xe env pack <out.tar.zst|out.tar.gz|out.tar> | xe env unpack <archive> [--force]";
+
+/// `manifest.json`: where the environment came from.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    version: u32,
+    created_at: String,
+    /// Full Python version of the venv, as in `pyvenv.cfg`.
+    python: String,
+    os: String,
+    arch: String,
+    /// The venv directory on the machine that packed it.
+    prefix: String,
+    /// The project directory on the machine that packed it.
+    #[serde(default)]
+    project: String,
+    /// [`lock_fingerprint`] of the project's xe.toml when it was packed.
+    fingerprint: String,
+    files: usize,
+}
+
+pub(crate) fn cmd_env_pack(args: &[String]) -> Result<()> {
+    let [target] = args else {
+        bail!(USAGE);
+    };
+    let target = PathBuf::from(target);
+    if !is_tar(&target) {
+        bail!(
+            "{} is not a tar archive; environments keep symlinks and executable bits, so use .tar.zst, .tar.gz or .tar",
+            target.display()
+        );
+    }
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (cfg, _) = load_or_create_project(&wd)?;
+    let Some(runtime) = existing_runtime_for_project(&cfg).filter(|r| r.is_venv) else {
+        bail!("the project has no venv to pack; run `xe sync` first");
+    };
+    let vm = VenvManager::new()?;
+    let venv_dir = vm.base_dir.join(&runtime.venv_name);
+    let prefix = venv_dir.to_string_lossy().into_owned();
+    let project = wd.to_string_lossy().into_owned();
+    let project_url = path_to_file_url(&wd);
+    // The URL first: on Unix it contains the plain path.
+    let rewrites = [
+        (prefix.as_str(), PREFIX_PLACEHOLDER),
+        (project_url.as_str(), PROJECT_URL_PLACEHOLDER),
+        (project.as_str(), PROJECT_PLACEHOLDER),
+    ];
+
+    let staging = staging_dir("env-pack");
+    let staged_venv = staging.join(VENV_DIR);
+    fs::create_dir_all(&staged_venv)
+        .with_context(|| format!("failed to create {}", staged_venv.display()))?;
+    let mut files = 0usize;
+    for entry in WalkDir::new(&venv_dir).min_depth(1) {
+        let entry = entry?;
+        let rel = entry.path().strip_prefix(&venv_dir)?;
+        if tied_to_interpreter(rel) {
+            continue;
+        }
+        let dest = staged_venv.join(rel);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest)
+                .with_context(|| format!("failed to create {}", dest.display()))?;
+        } else if entry.file_type().is_symlink() {
+            let link = fs::read_link(entry.path())
+                .with_context(|| format!("failed to read {}", entry.path().display()))?;
+            create_symlink(&link, &dest)?;
+        } else {
+            copy_rewriting(entry.path(), &dest, rel, &rewrites)?;
+            files += 1;
+        }
+    }
+    let manifest = Manifest {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        created_at: timestamp_iso8601(),
+        python: vm.python_version(&runtime.venv_name),
+        os: env::consts::OS.to_string(),
+        arch: env::consts::ARCH.to_string(),
+        prefix,
+        project,
+        fingerprint: lock_fingerprint(&cfg),
+        files,
+    };
+    let manifest_path = staging.join(MANIFEST);
+    fs::write(&manifest_path, serde_json::to_vec_pretty(&manifest)?)
+        .with_context(|| format!("failed to write {}", manifest_path.display()))?;
+
+    info(&format!(
+        "Packing {} files of {} (Python {})",
+        files, runtime.venv_name, manifest.python
+    ));
+    write_archive(&staging, &target)?;
+    let _ = fs::remove_dir_all(&staging);
+    let size = fs::metadata(&target).map(|m| m.len()).unwrap_or(0);
+    success(&format!(
+        "Packed the environment into {} ({})",
+        target.display(),
+        format_bytes(size)
+    ));
+    Ok(())
+}
+
+pub(crate) fn cmd_env_unpack(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let mut archive = None;
+    let mut force = false;
+    for arg in args {
+        match arg.as_str() {
+            "--force" => force = true,
+            path if !path.starts_with('-') && archive.is_none() => {
+                archive = Some(PathBuf::from(path))
+            }
+            _ => bail!(USAGE),
+        }
+    }
+    let Some(archive) = archive else {
+        bail!(USAGE);
+    };
+    if !archive.is_file() {
+        bail!("{} does not exist", archive.display());
+    }
+
+    let staging = staging_dir("env-unpack");
+    extract_archive(&archive, &staging)?;
+    let manifest_path = staging.join(MANIFEST);
+    let manifest: Manifest = fs::read(&manifest_path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .filter(|m: &Manifest| m.format == FORMAT)
+        .with_context(|| format!("{} is not an xe environment archive", archive.display()))?;
+    if manifest.version > VERSION {
+        bail!(
+            "{} was packed by a newer xe (format version {}); upgrade xe to unpack it",
+            archive.display(),
+            manifest.version
+        );
+    }
+    if manifest.os != env::consts::OS || manifest.arch != env::consts::ARCH {
+        bail!(
+            "{} was packed on {}-{}; it only runs on the same OS and architecture, not {}-{}",
+            archive.display(),
+            manifest.os,
+            manifest.arch,
+            env::consts::OS,
+            env::consts::ARCH
+        );
+    }
+
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let packed_minor = minor_version(&manifest.python);
+    // Checked before --force replaces anything; a project that names no
+    // Python takes the archive's.
+    match requested_minor(&cfg.python.version) {
+        None => cfg.python.version = packed_minor.clone(),
+        Some(minor) if minor != packed_minor => bail!(
+            "{} needs Python {}, but the project uses Python {}; set [python] version to match",
+            archive.display(),
+            packed_minor,
+            minor
+        ),
+        Some(_) => {}
+    }
+    if cfg.venv.name.trim().is_empty() {
+        cfg.venv.name = auto_venv_name(&cfg, &wd);
+    }
+    let vm = VenvManager::new()?;
+    if vm.exists(&cfg.venv.name) {
+        if !force {
+            bail!(
+                "the project already has the venv {}; pass --force to replace it",
+                cfg.venv.name
+            );
+        }
+        vm.delete(&cfg.venv.name)?;
+    }
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    save_project(&toml_path, &cfg)?;
+    let venv_name = runtime.selection.venv_name.clone();
+    let python = vm.python_version(&venv_name);
+    if minor_version(&python) != packed_minor {
+        vm.delete(&venv_name)?;
+        bail!(
+            "{} needs Python {}, but the project uses Python {}; set [python] version to match",
+            archive.display(),
+            packed_minor,
+            minor_version(&python)
+        );
+    }
+
+    let venv_dir = vm.base_dir.join(&venv_name);
+    let prefix = venv_dir.to_string_lossy().into_owned();
+    let project = wd.to_string_lossy().into_owned();
+    let project_url = path_to_file_url(&wd);
+    let rewrites = [
+        (PREFIX_PLACEHOLDER, prefix.as_str()),
+        (PROJECT_URL_PLACEHOLDER, project_url.as_str()),
+        (PROJECT_PLACEHOLDER, project.as_str()),
+    ];
+    let packed = staging.join(VENV_DIR);
+    let mut files = 0usize;
+    for entry in WalkDir::new(&packed).min_depth(1) {
+        let entry = entry?;
+        let rel = entry.path().strip_prefix(&packed)?;
+        let dest = venv_dir.join(rel);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest)
+                .with_context(|| format!("failed to create {}", dest.display()))?;
+        } else if entry.file_type().is_symlink() {
+            // lib64 and the like come with the fresh venv.
+            if dest.symlink_metadata().is_err() {
+                let link = fs::read_link(entry.path())
+                    .with_context(|| format!("failed to read {}", entry.path().display()))?;
+                create_symlink(&link, &dest)?;
+            }
+        } else {
+            copy_rewriting(entry.path(), &dest, rel, &rewrites)?;
+            files += 1;
+        }
+    }
+    let _ = fs::remove_dir_all(&staging);
+
+    let missing = missing_editable_sources(&runtime.selection.site_packages);
+    if !missing.is_empty() {
+        warning(&format!(
+            "editable installs point at sources missing here: {}; run `xe sync` once they are in place",
+            missing
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    if manifest.fingerprint != lock_fingerprint(&cfg) {
+        warning("xe.toml changed since the environment was packed; run `xe sync` to catch up");
+    }
+    success(&format!(
+        "Unpacked {} files into {} (Python {})",
+        files, venv_name, python
+    ));
+    Ok(())
+}
+
+fn is_tar(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_lowercase();
+    [
+        ".tar", ".tar.zst", ".tzst", ".tar.gz", ".tgz", ".tar.xz", ".txz",
+    ]
+    .iter()
+    .any(|ext| name.ends_with(ext))
+}
+
+/// Files the fresh venv of the receiving machine brings itself: its
+/// configuration, the links to the interpreter and the activation scripts,
+/// which all name the base Python or the venv path.
+fn tied_to_interpreter(rel: &Path) -> bool {
+    let mut parts = rel.iter().map(|part| part.to_string_lossy());
+    let (Some(first), second, None) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    let Some(file) = second else {
+        return first == "pyvenv.cfg" || first == VENV_METADATA;
+    };
+    let file = file.to_lowercase();
+    (first == "bin" || first == "Scripts")
+        && (is_interpreter_link(&file) || file.starts_with("activate"))
+}
+
+/// `python`, `python3`, `python3.12` and their PyPy, free-threaded,
+/// `pythonw` and `.exe` forms; not console scripts that merely start with
+/// `python`.
+fn is_interpreter_link(file: &str) -> bool {
+    let file = file.strip_suffix(".exe").unwrap_or(file);
+    let Some(version) = ["pythonw", "python", "pypy"]
+        .iter()
+        .find_map(|base| file.strip_prefix(base))
+    else {
+        return false;
+    };
+    match version.split_once('.') {
+        None => version.is_empty() || version == "3",
+        Some((major, minor)) => {
+            let minor = minor.strip_suffix('t').unwrap_or(minor);
+            major == "3" && !minor.is_empty() && minor.chars().all(|c| c.is_ascii_digit())
+        }
+    }
+}
+
+/// Scripts, path files and `direct_url.json` name the venv or project
+/// directory; everything else is copied as is.
+fn may_name_prefix(rel: &Path) -> bool {
+    let top = rel.iter().next().map(|part| part.to_string_lossy());
+    let extension = rel.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    matches!(top.as_deref(), Some("bin" | "Scripts"))
+        || matches!(extension, "pth" | "egg-link" | "cfg")
+        || rel
+            .file_name()
+            .is_some_and(|name| name == "direct_url.json")
+}
+
+/// Copies `source` to `dest`, applying each `(from, to)` of `rewrites` in
+/// turn to text files that may name the venv or project directory.
+/// Permissions are kept.
+fn copy_rewriting(source: &Path, dest: &Path, rel: &Path, rewrites: &[(&str, &str)]) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    if may_name_prefix(rel) {
+        let data =
+            fs::read(source).with_context(|| format!("failed to read {}", source.display()))?;
+        let head = &data[..data.len().min(8192)];
+        if !head.contains(&0) {
+            if let Ok(text) = String::from_utf8(data) {
+                let rewritten = rewrites
+                    .iter()
+                    .fold(text.clone(), |text, (from, to)| text.replace(from, to));
+                if rewritten != text {
+                    fs::write(dest, rewritten)
+                        .with_context(|| format!("failed to write {}", dest.display()))?;
+                    let permissions = fs::metadata(source)?.permissions();
+                    fs::set_permissions(dest, permissions)
+                        .with_context(|| format!("failed to set mode of {}", dest.display()))?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+    fs::copy(source, dest)
+        .with_context(|| format!("failed to copy {} to {}", source.display(), dest.display()))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, link)
+        .with_context(|| format!("failed to create symlink {}", link.display()))
+}
+
+#[cfg(not(unix))]
+fn create_symlink(target: &Path, link: &Path) -> Result<()> {
+    bail!(
+        "cannot recreate symlink {} -> {} on this platform",
+        link.display(),
+        target.display()
+    )
+}
+
+/// `3.11` of `3.11.7`.
+fn minor_version(version: &str) -> String {
+    version.split('.').take(2).collect::<Vec<_>>().join(".")
+}
+
+/// The minor version `[python] version` asks for, as in `3.13` of
+/// `cpython3.13t`; `None` when it names none.
+fn requested_minor(version: &str) -> Option<String> {
+    let numeric = version
+        .trim()
+        .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+        .trim_end_matches('t');
+    (!numeric.is_empty()).then(|| minor_version(numeric))
+}
+
+/// Source trees that the editable installs in `site_packages` point at but
+/// that do not exist, as when they lay outside the packed project.
+fn missing_editable_sources(site_packages: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(site_packages) else {
+        return Vec::new();
+    };
+    let mut missing = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.starts_with("__editable__") && name.ends_with(".pth")
+        })
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+        .filter_map(|text| text.lines().next().map(|line| PathBuf::from(line.trim())))
+        .filter(|source| !source.as_os_str().is_empty() && !source.exists())
+        .collect::<Vec<_>>();
+    missing.sort();
+    missing
+}
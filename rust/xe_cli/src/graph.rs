@@ -0,0 +1,435 @@
+//! `xe tree` and `xe why`. The graph comes from the `Requires-Dist` metadata
+//! of the packages installed in the project environment, rooted at the
+//! project's `[deps]`, or in the venv or interpreter named by `--venv` or
+//! `--python`, rooted at what nothing else there requires. Markers are not
+//! evaluated; a requirement becomes an edge when the package it names is
+//! installed, and extras are left out. Packages that did not come from an
+//! index are marked with where they did come from.
+
+use crate::{inspected_env, InspectedEnv};
+use anyhow::{anyhow, bail, Result};
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use xe_core::config::{normalize_dep_name, requirement_to_dep_name};
+use xe_core::installed::{installed_metadata, DistSource};
+use xe_core::profile::AppContext;
+use xe_core::ui::{paint, warning, Style};
+
+const FORMATS: &[&str] = &["text", "dot", "mermaid", "json"];
+
+struct Node {
+    name: String,
+    version: String,
+    source: DistSource,
+}
+
+impl Node {
+    /// Where the package came from, unless that was an index.
+    fn direct_source(&self) -> Option<String> {
+        match self.source.kind.as_str() {
+            "" | "index" | "find-links" => None,
+            _ => Some(self.source.describe()),
+        }
+    }
+}
+
+struct DependencyGraph {
+    project: String,
+    nodes: Vec<Node>,
+    /// `(from, to, requirement)`, where `requirement` is the version
+    /// specifier `from` declares, empty when it has none.
+    edges: Vec<(usize, usize, String)>,
+    /// Nodes named in `[deps]` or `[editable]`; outside the project, the
+    /// nodes no other node requires.
+    roots: Vec<usize>,
+}
+
+impl DependencyGraph {
+    fn load(env: &InspectedEnv) -> Result<Self> {
+        let mut dists = installed_metadata(&env.selection.site_packages)?;
+        dists.sort_by_key(|dist| normalize_dep_name(&dist.name));
+        let by_name = dists
+            .iter()
+            .enumerate()
+            .map(|(i, dist)| (normalize_dep_name(&dist.name), i))
+            .collect::<HashMap<_, _>>();
+
+        let mut edges = Vec::new();
+        for (i, dist) in dists.iter().enumerate() {
+            let mut seen = HashSet::new();
+            for requirement in &dist.requires {
+                let (requirement, marker) = requirement
+                    .split_once(';')
+                    .unwrap_or((requirement.as_str(), ""));
+                if marker.contains("extra") {
+                    continue;
+                }
+                let Some(name) = requirement_to_dep_name(requirement) else {
+                    continue;
+                };
+                let Some(&j) = by_name.get(&name) else {
+                    continue;
+                };
+                if j != i && seen.insert(j) {
+                    edges.push((i, j, specifier(requirement)));
+                }
+            }
+        }
+
+        let mut roots = Vec::new();
+        match &env.project {
+            Some(cfg) => {
+                let deps = cfg
+                    .deps
+                    .keys()
+                    .chain(cfg.editable.keys())
+                    .collect::<BTreeSet<_>>();
+                for dep in deps {
+                    match by_name.get(&normalize_dep_name(dep)) {
+                        Some(&i) => roots.push(i),
+                        None => warning(&format!(
+                            "{dep} is a dependency but not installed; run `xe sync`"
+                        )),
+                    }
+                }
+            }
+            // Another environment has no [deps]; what nothing else requires
+            // was installed for its own sake.
+            None => {
+                roots.extend((0..dists.len()).filter(|&i| !edges.iter().any(|(_, to, _)| *to == i)))
+            }
+        }
+        let nodes = dists
+            .into_iter()
+            .map(|dist| Node {
+                name: dist.name,
+                version: dist.version,
+                source: dist.source,
+            })
+            .collect();
+        Ok(Self {
+            project: env.label.clone(),
+            nodes,
+            edges,
+            roots,
+        })
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        let name = normalize_dep_name(name);
+        self.nodes
+            .iter()
+            .position(|node| normalize_dep_name(&node.name) == name)
+    }
+
+    fn children(&self, node: usize) -> impl Iterator<Item = &(usize, usize, String)> {
+        self.edges.iter().filter(move |(from, _, _)| *from == node)
+    }
+
+    /// Keeps only the nodes that lead to `target`, and the edges between
+    /// them, so the graph answers why `target` is installed.
+    fn paths_to(&self, target: usize) -> Self {
+        let mut keep = BTreeSet::from([target]);
+        let mut queue = vec![target];
+        while let Some(node) = queue.pop() {
+            for (from, _, _) in self.edges.iter().filter(|(_, to, _)| *to == node) {
+                if keep.insert(*from) {
+                    queue.push(*from);
+                }
+            }
+        }
+        self.subgraph(&keep)
+    }
+
+    /// Leaves out installed packages nothing in `[deps]` leads to, such as
+    /// the environment's own pip.
+    fn reachable(&self) -> Self {
+        let mut keep = self.roots.iter().copied().collect::<BTreeSet<_>>();
+        let mut queue = self.roots.clone();
+        while let Some(node) = queue.pop() {
+            for (_, to, _) in self.children(node) {
+                if keep.insert(*to) {
+                    queue.push(*to);
+                }
+            }
+        }
+        self.subgraph(&keep)
+    }
+
+    fn subgraph(&self, keep: &BTreeSet<usize>) -> Self {
+        let index = keep
+            .iter()
+            .enumerate()
+            .map(|(new, &old)| (old, new))
+            .collect::<HashMap<_, _>>();
+        Self {
+            project: self.project.clone(),
+            nodes: keep
+                .iter()
+                .map(|&i| Node {
+                    name: self.nodes[i].name.clone(),
+                    version: self.nodes[i].version.clone(),
+                    source: self.nodes[i].source.clone(),
+                })
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .filter_map(|(from, to, requirement)| {
+                    Some((*index.get(from)?, *index.get(to)?, requirement.clone()))
+                })
+                .collect(),
+            roots: self
+                .roots
+                .iter()
+                .filter_map(|root| index.get(root).copied())
+                .collect(),
+        }
+    }
+
+    fn print_tree(&self) {
+        println!("{}", self.project);
+        let mut expanded = HashSet::new();
+        for (i, &root) in self.roots.iter().enumerate() {
+            self.print_node(root, "", i + 1 == self.roots.len(), &mut expanded);
+        }
+    }
+
+    /// Prints `node` and, the first time it appears, its dependencies;
+    /// later appearances are marked `(*)`.
+    fn print_node(&self, node: usize, prefix: &str, last: bool, expanded: &mut HashSet<usize>) {
+        let branch = if last { "`-- " } else { "|-- " };
+        let repeated = !expanded.insert(node);
+        let source = match self.nodes[node].direct_source() {
+            Some(source) => paint(Style::Muted, &format!(" [{source}]")),
+            None => String::new(),
+        };
+        println!(
+            "{}{} ({}){source}{}",
+            paint(Style::Muted, &format!("{prefix}{branch}")),
+            paint(Style::Accent, &self.nodes[node].name),
+            self.nodes[node].version,
+            if repeated {
+                paint(Style::Muted, " (*)")
+            } else {
+                String::new()
+            }
+        );
+        if repeated {
+            return;
+        }
+        let prefix = format!("{prefix}{}", if last { "    " } else { "|   " });
+        let children = self
+            .children(node)
+            .map(|(_, to, _)| *to)
+            .collect::<Vec<_>>();
+        for (i, &child) in children.iter().enumerate() {
+            self.print_node(child, &prefix, i + 1 == children.len(), expanded);
+        }
+    }
+
+    /// Every chain from `[deps]` down to `target`, one per line.
+    fn print_chains(&self, target: usize) {
+        let mut chains = Vec::new();
+        for &root in &self.roots {
+            let mut path = vec![root];
+            self.collect_chains(target, &mut path, &mut chains);
+        }
+        for chain in chains {
+            let labels = chain
+                .iter()
+                .map(|&i| format!("{} ({})", self.nodes[i].name, self.nodes[i].version))
+                .collect::<Vec<_>>();
+            println!("{} -> {}", self.project, labels.join(" -> "));
+        }
+    }
+
+    fn collect_chains(&self, target: usize, path: &mut Vec<usize>, chains: &mut Vec<Vec<usize>>) {
+        let node = *path.last().unwrap_or(&target);
+        if node == target {
+            chains.push(path.clone());
+            return;
+        }
+        for &(_, to, _) in self.children(node) {
+            if !path.contains(&to) {
+                path.push(to);
+                self.collect_chains(target, path, chains);
+                path.pop();
+            }
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = String::from("digraph dependencies {\n    rankdir=LR;\n");
+        out.push_str(&format!(
+            "    \"{}\" [shape=box, style=bold];\n",
+            self.project
+        ));
+        for node in &self.nodes {
+            out.push_str(&format!(
+                "    \"{}\" [label=\"{} {}\"];\n",
+                node.name, node.name, node.version
+            ));
+        }
+        for &root in &self.roots {
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\";\n",
+                self.project, self.nodes[root].name
+            ));
+        }
+        for (from, to, requirement) in &self.edges {
+            let label = if requirement.is_empty() {
+                String::new()
+            } else {
+                format!(" [label=\"{requirement}\"]")
+            };
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\"{};\n",
+                self.nodes[*from].name, self.nodes[*to].name, label
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_mermaid(&self) -> String {
+        let mut out = String::from("graph LR\n");
+        out.push_str(&format!("    root[\"{}\"]\n", self.project));
+        for (i, node) in self.nodes.iter().enumerate() {
+            out.push_str(&format!("    n{i}[\"{} {}\"]\n", node.name, node.version));
+        }
+        for root in &self.roots {
+            out.push_str(&format!("    root --> n{root}\n"));
+        }
+        for (from, to, requirement) in &self.edges {
+            if requirement.is_empty() {
+                out.push_str(&format!("    n{from} --> n{to}\n"));
+            } else {
+                out.push_str(&format!("    n{from} -->|\"{requirement}\"| n{to}\n"));
+            }
+        }
+        out
+    }
+
+    fn to_json(&self, target: Option<usize>) -> serde_json::Value {
+        let mut value = json!({
+            "project": self.project,
+            "roots": self.roots.iter().map(|&i| &self.nodes[i].name).collect::<Vec<_>>(),
+            "packages": self.nodes.iter().map(|node| json!({
+                "name": node.name,
+                "version": node.version,
+                "source": node.source,
+            })).collect::<Vec<_>>(),
+            "edges": self.edges.iter().map(|(from, to, requirement)| json!({
+                "from": self.nodes[*from].name,
+                "to": self.nodes[*to].name,
+                "requirement": requirement,
+            })).collect::<Vec<_>>(),
+        });
+        if let Some(target) = target {
+            value["target"] = json!(self.nodes[target].name);
+        }
+        value
+    }
+}
+
+/// The version specifier of a `Requires-Dist` requirement, without the
+/// name and extras.
+fn specifier(requirement: &str) -> String {
+    let requirement = requirement.trim();
+    let rest = match requirement.find(']') {
+        Some(end) => &requirement[end + 1..],
+        None => {
+            requirement.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        }
+    };
+    rest.trim()
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .trim()
+        .to_string()
+}
+
+/// Splits `--format <name>` off the arguments.
+fn parse_format(args: &[String], usage: &str) -> Result<(String, Vec<String>)> {
+    let mut format = "text".to_string();
+    let mut rest = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" => {
+                format = args
+                    .get(i + 1)
+                    .ok_or_else(|| anyhow!("--format requires one of {}", FORMATS.join(", ")))?
+                    .clone();
+                i += 1;
+            }
+            other if other.starts_with('-') => bail!("unknown option: {other}\n{usage}"),
+            other => rest.push(other.to_string()),
+        }
+        i += 1;
+    }
+    if !FORMATS.contains(&format.as_str()) {
+        bail!(
+            "unknown graph format {format}; expected {}",
+            FORMATS.join(", ")
+        );
+    }
+    Ok((format, rest))
+}
+
+pub(crate) fn cmd_tree(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage = "usage: xe tree [package_name] [--format text|dot|mermaid|json] [--venv <name> | --python <version>]";
+    let (env, args) = inspected_env(ctx, args)?;
+    let (format, rest) = parse_format(&args, usage)?;
+    let mut graph = DependencyGraph::load(&env)?;
+    match rest.as_slice() {
+        [] => {}
+        // Only the dependencies of one package.
+        [package] => {
+            let root = graph
+                .find(package)
+                .ok_or_else(|| anyhow!("{package} is not installed in {}", graph.project))?;
+            graph.roots = vec![root];
+        }
+        _ => bail!("{usage}"),
+    }
+    let graph = graph.reachable();
+    match format.as_str() {
+        "dot" => print!("{}", graph.to_dot()),
+        "mermaid" => print!("{}", graph.to_mermaid()),
+        "json" => println!("{}", serde_json::to_string_pretty(&graph.to_json(None))?),
+        _ => graph.print_tree(),
+    }
+    Ok(())
+}
+
+pub(crate) fn cmd_why(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage = "usage: xe why <package_name> [--format text|dot|mermaid|json] [--venv <name> | --python <version>]";
+    let (env, args) = inspected_env(ctx, args)?;
+    let (format, rest) = parse_format(&args, usage)?;
+    let [package] = rest.as_slice() else {
+        bail!("{usage}");
+    };
+    let graph = DependencyGraph::load(&env)?;
+    let target = graph
+        .find(package)
+        .ok_or_else(|| anyhow!("{package} is not installed in {}", graph.project))?;
+    let graph = graph.paths_to(target);
+    let target = graph
+        .find(package)
+        .ok_or_else(|| anyhow!("{package} is not installed in {}", graph.project))?;
+    match format.as_str() {
+        "dot" => print!("{}", graph.to_dot()),
+        "mermaid" => print!("{}", graph.to_mermaid()),
+        "json" => println!(
+            "{}",
+            serde_json::to_string_pretty(&graph.to_json(Some(target)))?
+        ),
+        _ if graph.roots.is_empty() => {
+            println!("{package} is installed but nothing in [deps] requires it")
+        }
+        _ => graph.print_chains(target),
+    }
+    Ok(())
+}
@@ -0,0 +1,267 @@
+//! `xe history`. Every command run is appended to `history.jsonl` in the
+//! xe home with its arguments, directory, duration and outcome, so "what
+//! did I run that broke this environment" has an answer; `xe history rerun`
+//! runs an entry again where it first ran.
+
+use crate::dispatch;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use time::OffsetDateTime;
+use xe_core::config::GLOBAL_CONFIG;
+use xe_core::net::redact_urls_in;
+use xe_core::paths::xe_home;
+use xe_core::profile::AppContext;
+use xe_core::ui::{info, paint, success, Style};
+
+/// Entries kept; older ones are dropped once the file grows past this by a
+/// tenth.
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HistoryEntry {
+    id: u64,
+    /// Unix seconds when the command started.
+    started_at: u64,
+    args: Vec<String>,
+    /// Directory the command ran in.
+    dir: String,
+    duration_ms: u64,
+    ok: bool,
+    /// First line of the error of a failed command.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    error: String,
+}
+
+fn history_path() -> PathBuf {
+    xe_home().join("history.jsonl")
+}
+
+/// Off with `history: false` in the global config or `XE_NO_HISTORY`.
+/// `xe history` itself is never recorded.
+fn enabled(args: &[String]) -> bool {
+    let configured = GLOBAL_CONFIG
+        .get()
+        .and_then(|cfg| cfg.history)
+        .unwrap_or(true);
+    configured
+        && env::var_os("XE_NO_HISTORY").is_none_or(|v| v.is_empty())
+        && args.first().is_some_and(|cmd| cmd != "history")
+}
+
+/// Runs the command `args` through `run` and records it. Failing to record
+/// never fails the command.
+pub(crate) fn record(args: &[String], run: impl FnOnce() -> Result<()>) -> Result<()> {
+    if !enabled(args) {
+        return run();
+    }
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let dir = env::current_dir()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
+    let started = Instant::now();
+    let result = run();
+    let entry = HistoryEntry {
+        id: 0,
+        started_at,
+        args: args.iter().map(|arg| redact_urls_in(arg)).collect(),
+        dir,
+        duration_ms: started.elapsed().as_millis() as u64,
+        ok: result.is_ok(),
+        error: result
+            .as_ref()
+            .err()
+            .map(|err| redact_urls_in(format!("{err:#}").lines().next().unwrap_or_default()))
+            .unwrap_or_default(),
+    };
+    let _ = append(entry);
+    result
+}
+
+fn append(mut entry: HistoryEntry) -> Result<()> {
+    let path = history_path();
+    let mut entries = read_entries(&path);
+    entry.id = entries.last().map_or(1, |last| last.id + 1);
+    if entries.len() >= MAX_ENTRIES + MAX_ENTRIES / 10 {
+        entries.drain(..entries.len() + 1 - MAX_ENTRIES);
+        entries.push(entry);
+        let mut text = String::new();
+        for entry in &entries {
+            text.push_str(&serde_json::to_string(entry)?);
+            text.push('\n');
+        }
+        let tmp = path.with_extension("jsonl.tmp");
+        fs::write(&tmp, text).with_context(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("failed to replace {}", path.display()))?;
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// The recorded entries, oldest first; unreadable lines are skipped.
+fn read_entries(path: &Path) -> Vec<HistoryEntry> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+pub(crate) fn cmd_history(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage =
+        "usage: xe history <list [--limit <n>] [--here] [--failed] [--json]|rerun <id>|clear>";
+    match args.first().map(String::as_str) {
+        Some("list") => cmd_history_list(&args[1..], usage),
+        Some("rerun") => match &args[1..] {
+            [id] => cmd_history_rerun(ctx, id),
+            _ => bail!("usage: xe history rerun <id>"),
+        },
+        Some("clear") if args.len() == 1 => {
+            let path = history_path();
+            if path.exists() {
+                fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+            success("Cleared the command history");
+            Ok(())
+        }
+        _ => bail!("{usage}"),
+    }
+}
+
+fn cmd_history_list(args: &[String], usage: &str) -> Result<()> {
+    let mut limit = 20usize;
+    let mut here = false;
+    let mut failed = false;
+    let mut as_json = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--limit" | "-n" => {
+                i += 1;
+                limit = args
+                    .get(i)
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| anyhow!("--limit requires a number"))?;
+            }
+            "--here" => here = true,
+            "--failed" => failed = true,
+            "--json" => as_json = true,
+            _ => bail!("{usage}"),
+        }
+        i += 1;
+    }
+    let cwd = env::current_dir()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
+    let entries = read_entries(&history_path());
+    let mut shown = entries
+        .iter()
+        .filter(|entry| !here || entry.dir == cwd)
+        .filter(|entry| !failed || !entry.ok)
+        .collect::<Vec<_>>();
+    let skip = shown.len().saturating_sub(limit);
+    shown.drain(..skip);
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&json!(shown))?);
+        return Ok(());
+    }
+    if shown.is_empty() {
+        info("No commands recorded yet");
+        return Ok(());
+    }
+    let id_width = shown
+        .iter()
+        .map(|entry| entry.id.to_string().len())
+        .max()
+        .unwrap_or(1);
+    for entry in shown {
+        let status = if entry.ok {
+            paint(Style::Success, "ok  ")
+        } else {
+            paint(Style::Error, "FAIL")
+        };
+        println!(
+            "{:>id_width$}  {}  {status}  {:>7}  xe {}",
+            entry.id,
+            format_time(entry.started_at),
+            format_duration(entry.duration_ms),
+            entry.args.join(" ")
+        );
+        let mut notes = Vec::new();
+        if !here {
+            notes.push(entry.dir.clone());
+        }
+        if !entry.error.is_empty() {
+            notes.push(entry.error.clone());
+        }
+        if !notes.is_empty() {
+            println!(
+                "{:>id_width$}  {}",
+                "",
+                paint(Style::Muted, &notes.join(": "))
+            );
+        }
+    }
+    Ok(())
+}
+
+fn cmd_history_rerun(ctx: &AppContext, id: &str) -> Result<()> {
+    let id = id
+        .parse::<u64>()
+        .map_err(|_| anyhow!("history ids are numbers, not {id}"))?;
+    let entries = read_entries(&history_path());
+    let Some(entry) = entries.into_iter().find(|entry| entry.id == id) else {
+        bail!("no command {id} in the history; see `xe history list`");
+    };
+    if entry.args.iter().any(|arg| arg.contains("<redacted>")) {
+        bail!("command {id} had credentials that were not recorded; run it by hand");
+    }
+    let dir = PathBuf::from(&entry.dir);
+    if !dir.is_dir() {
+        bail!("{} no longer exists", entry.dir);
+    }
+    env::set_current_dir(&dir).with_context(|| format!("failed to enter {}", dir.display()))?;
+    info(&format!(
+        "Running `xe {}` in {}",
+        entry.args.join(" "),
+        dir.display()
+    ));
+    record(&entry.args, || dispatch(ctx, &entry.args))
+}
+
+/// `YYYY-MM-DD HH:MM` in UTC.
+fn format_time(unix: u64) -> String {
+    match OffsetDateTime::from_unix_timestamp(unix as i64) {
+        Ok(at) => format!("{} {:02}:{:02}", at.date(), at.hour(), at.minute()),
+        Err(_) => "?".to_string(),
+    }
+}
+
+fn format_duration(ms: u64) -> String {
+    match ms {
+        ms if ms < 1000 => format!("{ms} ms"),
+        ms if ms < 60_000 => format!("{:.1} s", ms as f64 / 1000.0),
+        ms => format!("{}m{:02}s", ms / 60_000, ms % 60_000 / 1000),
+    }
+}
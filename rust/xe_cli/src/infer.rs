@@ -0,0 +1,195 @@
+//! `xe add --infer`: the distributions the project imports but does not
+//! declare. Imports are read line by line from the project's `.py` files,
+//! without evaluating anything; the standard library and the project's own
+//! modules are left out, and each remaining module is mapped to the
+//! installed distribution that provides it, then to the module map of
+//! `xe modules`, and otherwise guessed to be its own distribution name.
+
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use walkdir::WalkDir;
+use xe_core::config::{normalize_dep_name, Config};
+use xe_core::installed::installed_metadata;
+use xe_core::modules::ModuleMap;
+use xe_core::runtime::RuntimeSelection;
+
+/// Directories never scanned for imports, besides hidden ones and venvs.
+const SKIPPED_DIRS: &[&str] = &[
+    "__pycache__",
+    "build",
+    "dist",
+    "node_modules",
+    "site-packages",
+];
+
+/// A distribution to add and the imported modules that need it.
+pub(crate) struct Proposal {
+    pub distribution: String,
+    pub modules: Vec<String>,
+    /// Neither installed nor in the module map: the module's own name,
+    /// which may be local code or an unrelated package on the index.
+    pub guess: bool,
+}
+
+/// Distributions the project in `project_dir` imports from but has in
+/// neither `[deps]` nor an `[extras]` group, sorted by name.
+pub(crate) fn missing_dependencies(
+    project_dir: &Path,
+    cfg: &Config,
+    selection: &RuntimeSelection,
+) -> Result<Vec<Proposal>> {
+    let stdlib = stdlib_modules(&selection.python_exe)?;
+    let local = local_modules(project_dir);
+    let mut installed = HashMap::new();
+    for dist in installed_metadata(&selection.site_packages)? {
+        for module in &dist.top_level {
+            installed.insert(module.clone(), dist.name.clone());
+        }
+    }
+    let map = ModuleMap::load();
+    let declared = cfg
+        .deps
+        .keys()
+        .chain(cfg.extras.values().flat_map(|deps| deps.keys()))
+        .map(|name| normalize_dep_name(name))
+        .collect::<HashSet<_>>();
+
+    let mut proposals = BTreeMap::<String, Proposal>::new();
+    for module in imported_modules(project_dir)? {
+        let top = module.split('.').next().unwrap_or(&module);
+        if stdlib.contains(top) || local.contains(top) {
+            continue;
+        }
+        let (distribution, guess) = match installed
+            .get(top)
+            .map(String::as_str)
+            .or_else(|| map.distribution(&module))
+        {
+            Some(dist) => (dist.to_string(), false),
+            None => (top.to_string(), true),
+        };
+        let key = normalize_dep_name(&distribution);
+        if declared.contains(&key) {
+            continue;
+        }
+        let proposal = proposals.entry(key).or_insert_with(|| Proposal {
+            distribution,
+            modules: Vec::new(),
+            guess,
+        });
+        if !proposal.modules.iter().any(|m| m == top) {
+            proposal.modules.push(top.to_string());
+        }
+    }
+    Ok(proposals.into_values().collect())
+}
+
+/// Absolute imports in the project's `.py` files, dotted as written.
+fn imported_modules(project_dir: &Path) -> Result<BTreeSet<String>> {
+    let mut modules = BTreeSet::new();
+    let walker = WalkDir::new(project_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| !entry.file_type().is_dir() || scanned_dir(entry.path()));
+    for entry in walker {
+        let entry = entry.context("failed to scan project")?;
+        if !entry.file_type().is_file() || entry.path().extension().is_none_or(|ext| ext != "py") {
+            continue;
+        }
+        // Files that are not UTF-8 are not worth failing the scan over.
+        let Ok(source) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        for line in source.lines() {
+            modules.extend(imports_in_line(line));
+        }
+    }
+    Ok(modules)
+}
+
+fn scanned_dir(dir: &Path) -> bool {
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    !name.starts_with('.')
+        && !SKIPPED_DIRS.contains(&name.as_ref())
+        && !dir.join("pyvenv.cfg").exists()
+}
+
+/// The modules a single `import a.b, c as d` or `from a.b import x` line
+/// names. Relative imports name none.
+fn imports_in_line(line: &str) -> Vec<String> {
+    let line = line.split('#').next().unwrap_or_default().trim();
+    if let Some(rest) = line.strip_prefix("from ") {
+        let mut words = rest.split_whitespace();
+        return match (words.next(), words.next()) {
+            (Some(module), Some("import")) if is_module_path(module) => vec![module.to_string()],
+            _ => Vec::new(),
+        };
+    }
+    let Some(rest) = line.strip_prefix("import ") else {
+        return Vec::new();
+    };
+    rest.split(',')
+        .filter_map(|part| part.split_whitespace().next())
+        .filter(|module| is_module_path(module))
+        .map(str::to_string)
+        .collect()
+}
+
+fn is_module_path(path: &str) -> bool {
+    path.split('.').all(|part| {
+        let mut chars = part.chars();
+        chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+/// Modules and packages the project itself provides, at its root or in a
+/// `src` layout.
+fn local_modules(project_dir: &Path) -> HashSet<String> {
+    let mut local = HashSet::new();
+    for root in [project_dir.to_path_buf(), project_dir.join("src")] {
+        let Ok(entries) = fs::read_dir(&root) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = if path.is_dir() {
+                path.join("__init__.py")
+                    .is_file()
+                    .then(|| entry.file_name().to_string_lossy().into_owned())
+            } else {
+                path.extension()
+                    .is_some_and(|ext| ext == "py")
+                    .then(|| path.file_stem())
+                    .flatten()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            };
+            local.extend(name);
+        }
+    }
+    local
+}
+
+/// `sys.stdlib_module_names` of the project interpreter.
+fn stdlib_modules(python_exe: &Path) -> Result<HashSet<String>> {
+    let output = Command::new(python_exe)
+        .args([
+            "-c",
+            "import sys; print('\\n'.join(sys.stdlib_module_names))",
+        ])
+        .output()
+        .with_context(|| format!("failed to run {}", python_exe.display()))?;
+    if !output.status.success() {
+        bail!("xe add --infer needs Python 3.10 or newer to tell standard library modules apart");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
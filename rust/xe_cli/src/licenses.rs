@@ -0,0 +1,298 @@
+//! `xe licenses`. The license of every package in `xe.lock`, read from the
+//! wheel this machine installs: `License-Expression`, `License` or the
+//! license classifiers of its metadata, and the license files its
+//! `.dist-info` carries. `--bundle` concatenates those files into a
+//! third-party notices file to ship with an application.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use xe_core::config::{load_or_create_project, save_project, XE_TOML};
+use xe_core::install::Installer;
+use xe_core::lock::{load_lock, lock_fingerprint, XE_LOCK};
+use xe_core::profile::AppContext;
+use xe_core::runtime::ensure_runtime_for_project;
+use xe_core::ui::{info, paint, success, warning, Style};
+use zip::ZipArchive;
+
+const NOTICES_FILE: &str = "THIRD_PARTY_NOTICES";
+
+/// Names that mark a file at the top of `.dist-info` as a license file in
+/// wheels built before PEP 639 moved them into `licenses/`.
+const LICENSE_PREFIXES: &[&str] = &["LICEN", "COPYING", "COPYRIGHT", "NOTICE", "AUTHORS"];
+
+struct PackageLicense {
+    name: String,
+    version: String,
+    /// Empty when the metadata names no license.
+    license: String,
+    /// `(path inside .dist-info, text)`.
+    files: Vec<(String, String)>,
+}
+
+pub(crate) fn cmd_licenses(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage = "usage: xe licenses [--bundle [--output <path>]]";
+    let mut bundle = false;
+    let mut output = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--bundle" => bundle = true,
+            "--output" | "-o" => {
+                output = Some(PathBuf::from(
+                    args.get(i + 1)
+                        .ok_or_else(|| anyhow!("--output requires a path"))?,
+                ));
+                i += 1;
+            }
+            _ => bail!(usage),
+        }
+        i += 1;
+    }
+    if output.is_some() && !bundle {
+        bail!("--output only applies to --bundle\n{usage}");
+    }
+
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let Some(lock) = load_lock(&wd)? else {
+        bail!("no {XE_LOCK} in {}; run `xe lock` first", wd.display());
+    };
+    if lock.fingerprint != lock_fingerprint(&cfg) {
+        bail!("{XE_LOCK} is out of date with {XE_TOML}; run `xe lock` first");
+    }
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
+    }
+    let python_exe = &runtime.selection.python_exe;
+    let installer = Installer::new(&cfg, &wd)?;
+    let plan = installer.locked_plan(&lock, python_exe)?;
+    let wheels = installer.wheels_of(&plan, python_exe)?;
+
+    let mut licenses = Vec::new();
+    let mut editable = 0;
+    for (pkg, wheel) in plan.iter().zip(wheels) {
+        let Some(wheel) = wheel else {
+            editable += 1;
+            continue;
+        };
+        let (license, files) = wheel_licenses(&wheel)
+            .with_context(|| format!("failed to read the license of {}", pkg.name))?;
+        licenses.push(PackageLicense {
+            name: pkg.name.clone(),
+            version: pkg.version.clone(),
+            license,
+            files,
+        });
+    }
+    licenses.sort_by_key(|pkg| pkg.name.to_lowercase());
+    if editable > 0 {
+        info(&format!(
+            "Skipped {editable} editable package(s); they are part of this project"
+        ));
+    }
+
+    if !bundle {
+        print_license_table(&licenses);
+        return Ok(());
+    }
+    let missing = licenses
+        .iter()
+        .filter(|pkg| pkg.files.is_empty())
+        .map(|pkg| format!("{} {}", pkg.name, pkg.version))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        warning(&format!(
+            "No license file in the wheel of {}; add their notices by hand",
+            missing.join(", ")
+        ));
+    }
+    let project = match cfg.project.name.trim() {
+        "" => "this project".to_string(),
+        name => name.to_string(),
+    };
+    let output = wd.join(output.unwrap_or_else(|| PathBuf::from(NOTICES_FILE)));
+    fs::write(&output, render_notices(&project, &licenses))
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    success(&format!(
+        "Wrote the licenses of {} package(s) to {}",
+        licenses.len(),
+        output.display()
+    ));
+    Ok(())
+}
+
+fn print_license_table(licenses: &[PackageLicense]) {
+    let name_width = licenses
+        .iter()
+        .map(|pkg| pkg.name.len())
+        .chain(["Package".len()])
+        .max()
+        .unwrap_or_default();
+    let version_width = licenses
+        .iter()
+        .map(|pkg| pkg.version.len())
+        .chain(["Version".len()])
+        .max()
+        .unwrap_or_default();
+    let header = format!(
+        "{:<name_width$}  {:<version_width$}  License",
+        "Package", "Version"
+    );
+    println!("{}", paint(Style::Header, &header));
+    for pkg in licenses {
+        let mut license = if pkg.license.is_empty() {
+            paint(Style::Muted, "unknown")
+        } else {
+            pkg.license.clone()
+        };
+        if pkg.files.is_empty() {
+            license.push_str(&paint(Style::Muted, " (no license file)"));
+        }
+        println!(
+            "{:<name_width$}  {:<version_width$}  {license}",
+            pkg.name, pkg.version
+        );
+    }
+}
+
+fn render_notices(project: &str, licenses: &[PackageLicense]) -> String {
+    let rule = "=".repeat(80);
+    let mut out = format!(
+        "Third-party notices for {project}\n\n\
+         {project} includes the packages below. Their license texts follow,\n\
+         as shipped in each package's wheel. Generated by `xe licenses --bundle`\n\
+         from {XE_LOCK}.\n\n"
+    );
+    for pkg in licenses {
+        out.push_str(&format!("  {} {}", pkg.name, pkg.version));
+        if !pkg.license.is_empty() {
+            out.push_str(&format!(" ({})", pkg.license));
+        }
+        out.push('\n');
+    }
+    for pkg in licenses {
+        out.push_str(&format!("\n{rule}\n{} {}\n", pkg.name, pkg.version));
+        if !pkg.license.is_empty() {
+            out.push_str(&format!("License: {}\n", pkg.license));
+        }
+        out.push_str(&format!("{rule}\n"));
+        if pkg.files.is_empty() {
+            out.push_str("\nThe package ships no license file.\n");
+        }
+        for (path, text) in &pkg.files {
+            out.push_str(&format!("\n--- {path} ---\n\n{text}\n"));
+        }
+    }
+    out
+}
+
+/// The license named by the wheel's metadata and the text of each license
+/// file in its `.dist-info`.
+fn wheel_licenses(wheel: &Path) -> Result<(String, Vec<(String, String)>)> {
+    let file = File::open(wheel).with_context(|| format!("failed to open {}", wheel.display()))?;
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("{} is not a valid wheel", wheel.display()))?;
+    let names = archive.file_names().map(str::to_string).collect::<Vec<_>>();
+    let Some(dist_info) = names.iter().find_map(|name| {
+        name.strip_suffix("/METADATA")
+            .filter(|dir| dir.ends_with(".dist-info") && !dir.contains('/'))
+    }) else {
+        bail!("{} has no .dist-info/METADATA", wheel.display());
+    };
+    let metadata = read_entry(&mut archive, &format!("{dist_info}/METADATA"))?;
+    let (license, declared) = parse_license_metadata(&metadata);
+
+    let mut paths = BTreeSet::new();
+    for declared in &declared {
+        for candidate in [
+            format!("{dist_info}/licenses/{declared}"),
+            format!("{dist_info}/{declared}"),
+        ] {
+            if names.contains(&candidate) {
+                paths.insert(candidate);
+                break;
+            }
+        }
+    }
+    for name in &names {
+        let Some(rel) = name.strip_prefix(&format!("{dist_info}/")) else {
+            continue;
+        };
+        if rel.ends_with('/') {
+            continue;
+        }
+        let upper = rel.to_ascii_uppercase();
+        if rel.starts_with("licenses/")
+            || (!rel.contains('/') && LICENSE_PREFIXES.iter().any(|p| upper.starts_with(p)))
+        {
+            paths.insert(name.clone());
+        }
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        let text = read_entry(&mut archive, &path)?;
+        let rel = path
+            .strip_prefix(&format!("{dist_info}/"))
+            .unwrap_or(&path)
+            .to_string();
+        files.push((rel, text.replace("\r\n", "\n").trim_end().to_string()));
+    }
+    Ok((license, files))
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String> {
+    let mut entry = archive
+        .by_name(name)
+        .with_context(|| format!("failed to read {name}"))?;
+    let mut bytes = Vec::new();
+    entry
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("failed to read {name}"))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The license a `METADATA` header names, preferring `License-Expression`,
+/// then a short `License` (some projects paste the whole text there), then
+/// the license classifiers; and the `License-File` entries.
+fn parse_license_metadata(metadata: &str) -> (String, Vec<String>) {
+    let mut expression = String::new();
+    let mut license = String::new();
+    let mut classifiers = Vec::new();
+    let mut files = Vec::new();
+    for line in metadata.lines() {
+        if line.trim().is_empty() {
+            break;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.to_ascii_lowercase().as_str() {
+            "license-expression" => expression = value.to_string(),
+            "license" if license.is_empty() => license = value.to_string(),
+            "license-file" => files.push(value.to_string()),
+            "classifier" => {
+                if let Some(rest) = value.strip_prefix("License ::") {
+                    let name = rest.rsplit("::").next().unwrap_or_default().trim();
+                    if name != "OSI Approved" {
+                        classifiers.push(name.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let name = if !expression.is_empty() {
+        expression
+    } else if !license.is_empty() && license.len() <= 64 && license != "UNKNOWN" {
+        license
+    } else {
+        classifiers.join(", ")
+    };
+    (name, files)
+}
@@ -1,34 +1,126 @@
 use anyhow::{anyhow, bail, Context, Result};
 use rayon::prelude::*;
-use regex::Regex;
-use reqwest::blocking::Client;
-use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
-use sha1::{Digest as Sha1Digest, Sha1};
-use sha2::Sha256;
-use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
-use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, Read, Write};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::Ordering as AtomicOrdering;
+use std::time::{Duration, Instant};
 use time::format_description::well_known::Iso8601;
 use time::OffsetDateTime;
-use walkdir::WalkDir;
-use zip::write::FileOptions;
-use zip::ZipArchive;
-use zip::ZipWriter;
-
-const XE_TOML: &str = "xe.toml";
+use xe_core::config::{
+    default_python_version, export_extras_to_pyproject, find_workspace_root, load_global_config,
+    load_or_create_project, load_project, normalize_dep_name, requirement_to_dep_name,
+    save_global_config, save_project, Config, ProjectConfig, WorkspaceConfig, GLOBAL_CONFIG,
+    PYPROJECT_TOML, XE_TOML,
+};
+use xe_core::install::{
+    index_releases, installed_distributions, prepare_direct_reference, primary_index_url,
+    search_index, Installer, Package, PlannedAction, PlannedChange, ResolveLimits, PYPI_SIMPLE_URL,
+};
+use xe_core::installed::{
+    duplicate_distributions, file_collisions, installed_metadata, verify_imports, InstalledDist,
+};
+use xe_core::lock::{
+    environment_digest, load_lock, lock_fingerprint, save_lock, LockFile, LockedInterpreter,
+    XE_LOCK,
+};
+use xe_core::modules::{self, ModuleMap};
+use xe_core::net::{http_client, http_get, send_with_retry};
+use xe_core::paths::{self, dir_size, xe_config_file, xe_home, xe_plugin_dir, xe_shim_dir};
+use xe_core::process::{
+    cleanup_registered_paths, register_cleanup_path, run_foreground, run_foreground_tee_stderr,
+    sweep_stale_temp, sweep_stale_temp_periodically, CHILD_IN_FOREGROUND, STALE_TEMP_AGE,
+};
+use xe_core::profile::{profile_stamp, AppContext, Profiler};
+use xe_core::python::{
+    compare_version, python_full_version, specifier_allows, PythonArch, PythonManager, PythonSpec,
+};
+use xe_core::report::{
+    init_reporters, report_finished, ProgressFormat, Reporter, Summary, WebhookReporter,
+};
+use xe_core::requirements::parse_requirements;
+use xe_core::runtime::{
+    apply_runtime_env, ensure_runtime_for_project, existing_runtime_for_project,
+    get_preferred_python_version, python_runtime, venv_runtime, RuntimeSelection,
+};
+use xe_core::scripts::{
+    find_runtime_script, install_console_scripts, project_entry_points, read_entry_points,
+    scripts_dir_for, EntryPoint,
+};
+use xe_core::shell::{
+    add_to_path, add_to_shell_profile, create_shim, is_xe_shim, read_shim_target, remove_from_path,
+    shell_profile_status,
+};
+use xe_core::tags::TargetEnv;
+use xe_core::ui::{
+    error, format_bytes, info, init_output, paint, set_verbosity, success, warning, ColorChoice,
+    Style, Theme,
+};
+use xe_core::venv::{detect_venv_site_packages, normalize_venv_name, VenvManager, VENV_METADATA};
+use xe_core::Cas;
+
+#[cfg(feature = "heap-profile")]
+#[global_allocator]
+static ALLOCATOR: xe_core::heap::TrackingAllocator = xe_core::heap::TrackingAllocator;
+
+mod build;
+mod ci;
+mod crash;
+mod dashboard;
+mod env_pack;
+mod graph;
+mod history;
+mod infer;
+mod licenses;
+mod network;
+mod profile_export;
+mod publish;
+mod release;
+mod remote;
+mod serve;
+mod snapshot;
+mod support;
+mod template;
+mod undo;
+mod update_check;
+mod wheelhouse;
+
+/// Exit code used when the user interrupts xe (128 + SIGINT, like shells).
+const EXIT_INTERRUPTED: i32 = 130;
+/// Exit code when stdout is closed early (128 + SIGPIPE).
+const EXIT_BROKEN_PIPE: i32 = 141;
+/// Exit code after a panic, matching Rust's default.
+const EXIT_PANIC: i32 = 101;
 
 fn main() {
-    if let Err(err) = run() {
-        error(&format!("{:#}", err));
-        std::process::exit(1);
+    crash::install_panic_hook();
+    if let Err(err) = ctrlc::set_handler(|| {
+        if CHILD_IN_FOREGROUND.load(AtomicOrdering::SeqCst) {
+            return;
+        }
+        eprintln!();
+        warning("Interrupted; cleaning up temporary files...");
+        cleanup_registered_paths();
+        std::process::exit(EXIT_INTERRUPTED);
+    }) {
+        warning(&format!("Failed to install Ctrl+C handler: {err}"));
+    }
+    let result = std::panic::catch_unwind(run);
+    cleanup_registered_paths();
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            if !crash::report_internal_error(&err) {
+                error(&format!("{:#}", err));
+            }
+            std::process::exit(1);
+        }
+        Err(_) => std::process::exit(EXIT_PANIC),
     }
 }
 
@@ -44,13 +136,61 @@ fn run() -> Result<()> {
     }
 
     let config_file = root.config_file.unwrap_or_else(xe_config_file);
+    let global = load_global_config(&config_file);
+    let project = env::current_dir()
+        .ok()
+        .map(|wd| wd.join(XE_TOML))
+        .filter(|path| path.exists())
+        .and_then(|path| load_project(&path).ok());
+    let project_theme = project
+        .as_ref()
+        .map(|cfg| cfg.ui.theme.clone())
+        .filter(|theme| !theme.is_empty());
+    let theme = match (project_theme, &global) {
+        (Some(theme), _) => theme,
+        (None, Ok(global)) => global.ui.theme.clone(),
+        (None, Err(_)) => String::new(),
+    };
+    let theme = Theme::parse(&theme);
+    init_output(root.color, *theme.as_ref().unwrap_or(&Theme::Default));
+    set_verbosity(root.verbosity);
+    let mut reporters: Vec<Box<dyn Reporter>> = vec![root.progress.reporter()];
+    // Webhook URLs are secrets; `XE_NOTIFICATIONS_URL` keeps one out of
+    // files altogether.
+    let webhook = match (env::var("XE_NOTIFICATIONS_URL"), &project, &global) {
+        (Ok(url), _, _) if !url.trim().is_empty() => url.trim().to_string(),
+        (_, Some(cfg), _) if !cfg.notifications.url.is_empty() => cfg.notifications.url.clone(),
+        (_, _, Ok(global)) => global.notifications.url.clone(),
+        _ => String::new(),
+    };
+    if !webhook.is_empty() {
+        reporters.push(Box::new(WebhookReporter::new(&webhook)));
+    }
+    init_reporters(reporters);
+    if let Err(err) = theme {
+        warning(&format!("{err:#}"));
+    }
+    match global {
+        Ok(global) => {
+            let _ = GLOBAL_CONFIG.set(global);
+        }
+        Err(err) => warning(&format!("{err:#}")),
+    }
+    let project_cache = project
+        .as_ref()
+        .zip(env::current_dir().ok())
+        .and_then(|(cfg, wd)| cfg.cache.dir(&wd).ok());
+    sweep_stale_temp_periodically(project_cache.as_slice());
     let profiler = if root.profile {
         let dir = root.profile_dir.unwrap_or_else(|| xe_home().join("profiles"));
         let (prof, info_data) = Profiler::start(&dir)?;
         println!("Profiling enabled.");
         println!("Logs: {}", info_data.log_path.display());
         println!("CPU: {}", info_data.cpu_path.display());
-        println!("Heap: {}", info_data.heap_path.display());
+        match &info_data.heap_path {
+            Some(path) => println!("Heap: {}", path.display()),
+            None => println!("Heap: not recorded; build xe with --features heap-profile"),
+        }
         Some(prof)
     } else {
         None
@@ -70,21 +210,35 @@ fn run() -> Result<()> {
         );
     }
 
-    let command_result = dispatch(&ctx, &root.command_args);
+    // `xe self` deals with updates itself.
+    let check_updates = root.command_args.first().is_some_and(|cmd| cmd != "self");
+    if check_updates {
+        update_check::spawn();
+        modules::refresh_in_background();
+    }
+
+    let command_result = history::record(&root.command_args, || dispatch(&ctx, &root.command_args));
 
     if let Some(p) = profiler.as_ref() {
         p.event("command.stop", json!({}));
         p.stop()?;
     }
 
+    if check_updates && command_result.is_ok() {
+        update_check::print_hint();
+    }
     command_result
 }
 
 #[derive(Debug)]
 struct RootArgs {
     config_file: Option<PathBuf>,
+    color: ColorChoice,
+    progress: ProgressFormat,
     profile: bool,
     profile_dir: Option<PathBuf>,
+    /// How many times `-v` was given.
+    verbosity: u8,
     show_help: bool,
     show_version: bool,
     command_args: Vec<String>,
@@ -93,8 +247,11 @@ struct RootArgs {
 fn parse_root_args() -> Result<RootArgs> {
     let mut args: Vec<String> = env::args().skip(1).collect();
     let mut config_file: Option<PathBuf> = None;
+    let mut color = ColorChoice::Auto;
+    let mut progress = ProgressFormat::Auto;
     let mut profile = false;
     let mut profile_dir: Option<PathBuf> = None;
+    let mut verbosity = 0u8;
     let mut show_help = false;
     let mut show_version = false;
 
@@ -109,6 +266,28 @@ fn parse_root_args() -> Result<RootArgs> {
                 config_file = Some(PathBuf::from(value));
                 idx += 2;
             }
+            "--color" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--color requires auto, always or never"))?;
+                color = ColorChoice::parse(value)?;
+                idx += 2;
+            }
+            value if value.starts_with("--color=") => {
+                color = ColorChoice::parse(&value["--color=".len()..])?;
+                idx += 1;
+            }
+            "--progress" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--progress requires auto, fancy, plain or json"))?;
+                progress = ProgressFormat::parse(value)?;
+                idx += 2;
+            }
+            value if value.starts_with("--progress=") => {
+                progress = ProgressFormat::parse(&value["--progress=".len()..])?;
+                idx += 1;
+            }
             "--profile" => {
                 profile = true;
                 idx += 1;
@@ -120,6 +299,14 @@ fn parse_root_args() -> Result<RootArgs> {
                 profile_dir = Some(PathBuf::from(value));
                 idx += 2;
             }
+            "-v" | "--verbose" => {
+                verbosity = verbosity.saturating_add(1);
+                idx += 1;
+            }
+            "-vv" => {
+                verbosity = verbosity.saturating_add(2);
+                idx += 1;
+            }
             "-h" | "--help" => {
                 show_help = true;
                 idx += 1;
@@ -142,20 +329,17 @@ fn parse_root_args() -> Result<RootArgs> {
 
     Ok(RootArgs {
         config_file,
+        color,
+        progress,
         profile,
         profile_dir,
+        verbosity,
         show_help,
         show_version,
         command_args,
     })
 }
 
-#[derive(Clone)]
-struct AppContext {
-    config_file: PathBuf,
-    profiler: Option<Profiler>,
-}
-
 fn dispatch(ctx: &AppContext, args: &[String]) -> Result<()> {
     if args.is_empty() {
         print_help();
@@ -164,11 +348,12 @@ fn dispatch(ctx: &AppContext, args: &[String]) -> Result<()> {
     let cmd = args[0].as_str();
     let rest = &args[1..];
     match cmd {
-        "add" => cmd_add(ctx, rest),
+        "add" => undo::recorded("add", rest, || cmd_add(ctx, rest)),
         "list" => cmd_list(ctx, rest),
         "check" | "show" => cmd_check(rest),
-        "remove" => cmd_remove(ctx, rest),
+        "remove" => undo::recorded("remove", rest, || cmd_remove(ctx, rest)),
         "run" => cmd_run(ctx, rest),
+        "repl" => cmd_repl(ctx, rest),
         "shell" => cmd_shell(ctx, rest),
         "init" => cmd_init(ctx, rest),
         "use" => cmd_use(ctx, rest),
@@ -177,11 +362,13 @@ fn dispatch(ctx: &AppContext, args: &[String]) -> Result<()> {
         "import" => cmd_import(ctx, rest),
         "export" => cmd_export(rest),
         "clean" => cmd_clean(rest),
-        "snapshot" => cmd_snapshot(rest),
-        "restore" => cmd_restore(rest),
-        "sync" => cmd_sync(ctx, rest),
-        "lock" => cmd_lock(ctx, rest),
-        "publish" => cmd_push(ctx, rest, false),
+        "snapshot" => snapshot::cmd_snapshot(rest),
+        "restore" => snapshot::cmd_restore(rest),
+        "sync" => report_operation("sync", || {
+            undo::recorded("sync", rest, || cmd_sync(ctx, rest))
+        }),
+        "lock" => report_operation("lock", || cmd_lock(ctx, rest)),
+        "publish" => report_operation("publish", || cmd_push(ctx, rest, false)),
         "format" => cmd_format(ctx, rest),
         "version" => {
             print_version();
@@ -192,18 +379,32 @@ fn dispatch(ctx: &AppContext, args: &[String]) -> Result<()> {
         "pip" => cmd_pip(ctx, rest),
         "tool" => cmd_tool(ctx, rest),
         "x" => cmd_x_alias(ctx, rest),
-        "build" => cmd_build(rest),
-        "push" => cmd_push(ctx, rest, false),
-        "tpush" => cmd_push(ctx, rest, true),
+        "build" => build::cmd_build(ctx, rest),
+        "push" => report_operation("publish", || cmd_push(ctx, rest, false)),
+        "tpush" => report_operation("publish", || cmd_push(ctx, rest, true)),
+        "release" => release::cmd_release(ctx, rest),
         "auth" => cmd_auth(rest),
         "mirror" => cmd_mirror(rest),
+        "serve" => serve::cmd_serve(rest),
         "plugin" => cmd_plugin(rest),
         "self" => cmd_self(rest),
         "workspace" | "workspaces" => cmd_workspace(rest),
-        "why" => cmd_why(rest),
-        "tree" => cmd_tree(rest),
-        "doctor" => cmd_doctor(rest),
-        "setup" => cmd_setup(rest),
+        "why" => graph::cmd_why(ctx, rest),
+        "why-python" => cmd_why_python(ctx, rest),
+        "tree" => graph::cmd_tree(ctx, rest),
+        "licenses" => licenses::cmd_licenses(ctx, rest),
+        "doctor" => cmd_doctor(ctx, rest),
+        "profile" => profile_export::cmd_profile(rest),
+        "ci" => ci::cmd_ci(ctx, rest),
+        "history" => history::cmd_history(ctx, rest),
+        "undo" => undo::cmd_undo(ctx, rest),
+        "setup" => cmd_setup(ctx, rest),
+        "env" => cmd_env(ctx, rest),
+        "hash" => cmd_hash(ctx, rest),
+        "modules" => cmd_modules(rest),
+        "ide" => cmd_ide(ctx, rest),
+        "ui" => dashboard::run(ctx),
+        "info" => support::cmd_info(ctx, rest),
         _ => {
             print_help();
             bail!("unknown command: {cmd}");
@@ -212,15 +413,112 @@ fn dispatch(ctx: &AppContext, args: &[String]) -> Result<()> {
 }
 
 fn cmd_add(ctx: &AppContext, args: &[String]) -> Result<()> {
-    if args.is_empty() {
-        bail!("usage: xe add <package_name>...");
+    let usage = "usage: xe add [--dry-run] [--interactive] [--optional <extra>] [--resolution-timeout <secs>] [--max-backtracks <n>] (<package_name>... | --infer [--yes])";
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let interactive = args.iter().any(|a| a == "--interactive" || a == "-i");
+    let infer = args.iter().any(|a| a == "--infer");
+    let yes = args.iter().any(|a| a == "--yes" || a == "-y");
+    let mut optional = None;
+    let mut limits = ResolveLimits::default();
+    let mut rest = Vec::with_capacity(args.len());
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--dry-run" | "--interactive" | "-i" | "--infer" | "--yes" | "-y" => {}
+            "--optional" => {
+                idx += 1;
+                let Some(extra) = args.get(idx).filter(|e| !e.starts_with('-')) else {
+                    bail!(usage);
+                };
+                optional = Some(normalize_dep_name(extra));
+            }
+            flag @ ("--resolution-timeout" | "--max-backtracks") => {
+                idx += 1;
+                set_resolve_limit(&mut limits, flag, args.get(idx))?;
+            }
+            _ => rest.push(args[idx].clone()),
+        }
+        idx += 1;
+    }
+    let args = rest;
+    if interactive && args.len() != 1 {
+        bail!("usage: xe add --interactive [--dry-run] <query>");
+    }
+    if args.is_empty() != infer || (infer && interactive) {
+        bail!(usage);
     }
     let wd = env::current_dir().context("failed to get cwd")?;
     let (mut cfg, toml_path) = load_or_create_project(&wd)?;
     let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
-    if runtime.config_changed {
+    if runtime.config_changed && !dry_run {
         save_project(&toml_path, &cfg)?;
     }
+    let args = if infer {
+        let proposals = infer::missing_dependencies(&wd, &cfg, &runtime.selection)?;
+        if proposals.is_empty() {
+            success("Every imported package is already a dependency");
+            return Ok(());
+        }
+        info("Imported but not declared:");
+        for proposal in &proposals {
+            let modules = format!("(import {})", proposal.modules.join(", "));
+            if proposal.guess {
+                println!(
+                    "  {} {} {}",
+                    paint(Style::Muted, &proposal.distribution),
+                    paint(Style::Muted, &modules),
+                    paint(Style::Warning, "guess, not added")
+                );
+            } else {
+                println!(
+                    "  {} {}",
+                    paint(Style::Accent, &proposal.distribution),
+                    paint(Style::Muted, &modules)
+                );
+            }
+        }
+        // A module no installed package or mapping accounts for may well
+        // be local code; installing its name from the index could pull in
+        // an unrelated, or squatted, package.
+        let (guesses, known): (Vec<_>, Vec<_>) = proposals.into_iter().partition(|p| p.guess);
+        if !guesses.is_empty() {
+            warning(&format!(
+                "No known distribution provides {}; add the right package by name if they are not local code",
+                guesses
+                    .iter()
+                    .flat_map(|p| &p.modules)
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        if known.is_empty() {
+            return Ok(());
+        }
+        if !dry_run && !yes {
+            if !io::stdin().is_terminal() {
+                bail!("pass --yes to add the proposed packages");
+            }
+            let answer = prompt(&format!("Add {} package(s)? [y/N] ", known.len()))?;
+            if !matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+                info("Nothing was added");
+                return Ok(());
+            }
+        }
+        known.into_iter().map(|p| p.distribution).collect()
+    } else if interactive {
+        let python = TargetEnv::probe(&runtime.selection.python_exe)?.full_version;
+        vec![pick_package(&cfg, &args[0], &python)?]
+    } else {
+        args
+    };
+    let (ignored, args): (Vec<String>, Vec<String>) = args.into_iter().partition(|arg| {
+        requirement_to_dep_name(arg).is_some_and(|name| cfg.settings.is_ignored(&name))
+    });
+    warn_ignored(&ignored);
+    if args.is_empty() {
+        return Ok(());
+    }
 
     let target = if runtime.selection.is_venv {
         format!("venv:{}", runtime.selection.venv_name)
@@ -228,105 +526,501 @@ fn cmd_add(ctx: &AppContext, args: &[String]) -> Result<()> {
         "global".to_string()
     };
     info(&format!(
-        "Installing {} requirement(s) with Python {} [{}]...",
+        "{} {} requirement(s) with Python {} [{}]...",
+        if dry_run { "Resolving" } else { "Installing" },
         args.len(),
         cfg.python.version,
         target
     ));
 
-    let installer = Installer::new(Path::new(&cfg.cache.global_dir))?;
-    let reqs: Vec<String> = args.to_vec();
-    let resolved = installer.install(
-        ctx,
+    let mut installer = Installer::new(&cfg, &wd)?;
+    installer.limits = limits;
+    let mut reqs = Vec::with_capacity(args.len());
+    let mut named = Vec::with_capacity(args.len());
+    for arg in &args {
+        match prepare_direct_reference(arg, &wd, &installer.cas)? {
+            Some(_) if optional.is_some() => {
+                bail!("--optional takes package requirements, not wheel paths or URLs: {arg}")
+            }
+            Some((name, reference)) => {
+                reqs.push(format!("{name} @ {}", reference.requirement_url(&wd)));
+                installer.direct.insert(normalize_dep_name(&name));
+                cfg.direct.insert(name, reference);
+            }
+            None => {
+                reqs.push(arg.clone());
+                named.push(arg);
+            }
+        }
+    }
+    // Resolve and show the plan before anything is written, so a failed
+    // add leaves xe.toml and the environment as they were.
+    if !dry_run {
+        if let Some(lock) = load_lock(&wd)? {
+            installer.expect_lock(&lock, &runtime.selection.python_exe);
+        }
+    }
+    let graph = installer.resolve_prefetching(
         &cfg,
         &reqs,
-        &wd,
         &runtime.selection.site_packages,
         &runtime.selection.python_exe,
     )?;
-
-    for req in args {
-        if let Some(dep_name) = requirement_to_dep_name(req) {
-            cfg.deps.insert(dep_name, "*".to_string());
+    for req in &named {
+        let Some(dep_name) = requirement_to_dep_name(req) else {
+            continue;
+        };
+        if !graph
+            .packages
+            .iter()
+            .any(|p| normalize_dep_name(&p.name) == dep_name)
+        {
+            bail!(
+                "{req} resolved to nothing for Python {}; nothing was changed",
+                cfg.python.version
+            );
         }
     }
-    for p in &resolved {
-        cfg.deps
-            .insert(normalize_dep_name(&p.name), p.version.clone());
+    let changes = installer.plan_changes(&graph.packages, &runtime.selection.site_packages)?;
+    print_planned_changes(&changes, dry_run);
+    if dry_run {
+        return Ok(());
+    }
+
+    let mut updated = cfg.clone();
+    if let Some(extra) = &optional {
+        // Only the named packages join the extra; what they pull in is
+        // resolved again whenever the extra is installed.
+        for req in &named {
+            let Some(dep_name) = requirement_to_dep_name(req) else {
+                continue;
+            };
+            if let Some(p) = graph
+                .packages
+                .iter()
+                .find(|p| normalize_dep_name(&p.name) == dep_name)
+            {
+                updated.set_extra_dep(extra, &dep_name, &p.version);
+            }
+        }
+    } else {
+        for p in &graph.packages {
+            updated.set_dep(&normalize_dep_name(&p.name), &p.version);
+        }
     }
+    let resolved = installer.install_resolved(
+        ctx,
+        graph.packages,
+        &runtime.selection.site_packages,
+        &runtime.selection.python_exe,
+    )?;
+    let cfg = updated;
     save_project(&toml_path, &cfg)?;
+    if optional.is_some() && export_extras_to_pyproject(&wd, &cfg)? {
+        info(&format!(
+            "Updated [project.optional-dependencies] in {PYPROJECT_TOML}"
+        ));
+    }
     success(&format!("Installed {} package artifact(s)", resolved.len()));
-    Ok(())
+    run_hooks(&cfg, "post-add", &wd, Some(&runtime.selection), &resolved)
 }
 
-fn cmd_list(ctx: &AppContext, _args: &[String]) -> Result<()> {
-    let wd = env::current_dir().context("failed to get cwd")?;
-    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
-    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
-    if runtime.config_changed {
-        save_project(&toml_path, &cfg)?;
+fn cmd_list(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage = "usage: xe list [--venv <name> | --python <version>]";
+    let (env, rest) = inspected_env(ctx, args)?;
+    if !rest.is_empty() {
+        bail!(usage);
     }
-
-    let output = Command::new(&runtime.selection.python_exe)
-        .args(["-m", "pip", "list", "--format", "json"])
-        .output()
-        .context("failed to run pip list")?;
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        bail!("Failed to list packages: {}\n{}{}", output.status, stdout, stderr);
+    if env.project.is_none() {
+        info(&format!(
+            "Packages in {} ({})",
+            env.label,
+            env.selection.python_exe.display()
+        ));
     }
-
-    let mut pkgs = parse_pip_list_output(&output.stdout)?;
-    pkgs.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    let mut pkgs = installed_metadata(&env.selection.site_packages)?;
+    pkgs.sort_by_key(|p| p.name.to_lowercase());
     print_pkg_table(&pkgs);
     Ok(())
 }
 
+/// The environment `xe list`, `xe tree` and `xe why` look at.
+pub(crate) struct InspectedEnv {
+    pub(crate) selection: RuntimeSelection,
+    /// The project config, when the environment is the project's own.
+    pub(crate) project: Option<Config>,
+    /// The project name, or `venv <name>` / `Python <version>`.
+    pub(crate) label: String,
+}
+
+/// Splits `--venv <name>` and `--python <version>` off the arguments and
+/// picks the environment they name; without either, the project's, which
+/// is set up the way `xe run` would. A named venv or interpreter must
+/// already exist.
+pub(crate) fn inspected_env(
+    ctx: &AppContext,
+    args: &[String],
+) -> Result<(InspectedEnv, Vec<String>)> {
+    let mut venv = None;
+    let mut python = None;
+    let mut rest = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--venv" => {
+                venv = Some(
+                    args.get(i + 1)
+                        .ok_or_else(|| anyhow!("--venv requires a venv name"))?
+                        .clone(),
+                );
+                i += 1;
+            }
+            "--python" => {
+                python = Some(
+                    args.get(i + 1)
+                        .ok_or_else(|| anyhow!("--python requires a version"))?
+                        .clone(),
+                );
+                i += 1;
+            }
+            other => rest.push(other.to_string()),
+        }
+        i += 1;
+    }
+    let env = match (venv, python) {
+        (Some(_), Some(_)) => bail!("--venv and --python cannot be combined"),
+        (Some(name), None) => InspectedEnv {
+            selection: venv_runtime(&name)?,
+            project: None,
+            label: format!("venv {name}"),
+        },
+        (None, Some(version)) => InspectedEnv {
+            selection: python_runtime(&version)?,
+            project: None,
+            label: format!("Python {version}"),
+        },
+        (None, None) => {
+            let wd = env::current_dir().context("failed to get cwd")?;
+            let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+            let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+            if runtime.config_changed {
+                save_project(&toml_path, &cfg)?;
+            }
+            let label = match cfg.project.name.trim() {
+                "" => "project".to_string(),
+                name => name.to_string(),
+            };
+            InspectedEnv {
+                selection: runtime.selection,
+                project: Some(cfg),
+                label,
+            }
+        }
+    };
+    Ok((env, rest))
+}
+
 fn cmd_check(args: &[String]) -> Result<()> {
-    if args.len() != 1 {
-        bail!("usage: xe check <package_name>");
+    let usage = "usage: xe check <package_name>[==<version>]... [--full] [--json]";
+    let mut full = false;
+    let mut as_json = false;
+    let mut targets = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--full" => full = true,
+            "--json" => as_json = true,
+            value if !value.starts_with('-') => match value.split_once("==") {
+                Some((name, version)) if !name.is_empty() && !version.is_empty() => {
+                    targets.push((name.trim(), Some(version.trim())))
+                }
+                Some(_) => bail!(usage),
+                None => targets.push((value, None)),
+            },
+            _ => bail!(usage),
+        }
+    }
+    if targets.is_empty() {
+        bail!(usage);
+    }
+    let mut reports = targets
+        .par_iter()
+        .map(|(name, version)| check_report(name, *version, full, as_json))
+        .collect::<Result<Vec<_>>>()?;
+    let installed = project_installed();
+    for report in &mut reports {
+        report.installed = installed
+            .get(&normalize_dep_name(&report.metadata.info.name))
+            .cloned();
+    }
+
+    if as_json {
+        let mut payloads = reports
+            .iter()
+            .map(|report| report.to_json(full))
+            .collect::<Vec<_>>();
+        let payload = if payloads.len() == 1 {
+            payloads.remove(0)
+        } else {
+            Value::Array(payloads)
+        };
+        println!("{}", serde_json::to_string_pretty(&payload)?);
+        return Ok(());
+    }
+    if reports.len() > 1 && !full {
+        print_check_table(&reports);
+        return Ok(());
+    }
+    for (i, report) in reports.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        report.print(full);
     }
-    let metadata = fetch_metadata_from_pypi(&args[0])?;
-    println!("Name: {}", metadata.info.name);
-    println!("Version: {}", metadata.info.version);
-    println!("Summary: {}", metadata.info.summary);
-    println!("Home-page: {}", metadata.info.home_page);
     Ok(())
 }
 
+/// What `xe check` shows for one package, or one release of it.
+struct CheckReport {
+    metadata: PypiResponse,
+    downloads: Option<RecentDownloads>,
+    /// Version, first upload date and whether every file is yanked, newest
+    /// first. Empty for a single release.
+    releases: Vec<(String, String, bool)>,
+    /// The copy installed in the project environment, if any.
+    installed: Option<InstalledDist>,
+}
+
+/// What is installed in the environment of the project in the current
+/// directory, by normalized name. Empty outside a project and before its
+/// environment exists; `xe check` never sets one up.
+fn project_installed() -> HashMap<String, InstalledDist> {
+    let cfg = env::current_dir()
+        .ok()
+        .map(|wd| wd.join(XE_TOML))
+        .filter(|path| path.exists())
+        .and_then(|path| load_project(&path).ok());
+    let Some(selection) = cfg.as_ref().and_then(existing_runtime_for_project) else {
+        return HashMap::new();
+    };
+    installed_metadata(&selection.site_packages)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|dist| (normalize_dep_name(&dist.name), dist))
+        .collect()
+}
+
+fn check_report(name: &str, version: Option<&str>, full: bool, quiet: bool) -> Result<CheckReport> {
+    let metadata = match version {
+        Some(version) => fetch_release_from_pypi(name, version)?,
+        None => fetch_metadata_from_pypi(name)?,
+    };
+    // Download counts come from a separate service; the rest of the report
+    // stands without them.
+    let downloads = if full {
+        match fetch_recent_downloads(&metadata.info.name) {
+            Ok(downloads) => Some(downloads),
+            Err(err) => {
+                if !quiet {
+                    warning(&format!(
+                        "Download statistics unavailable for {}: {err:#}",
+                        metadata.info.name
+                    ));
+                }
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut releases = metadata
+        .releases
+        .iter()
+        .filter(|(_, files)| !files.is_empty())
+        .map(|(version, files)| {
+            let uploaded = files
+                .iter()
+                .map(|f| f.upload_time_iso_8601.as_str())
+                .min()
+                .unwrap_or_default();
+            let yanked = files.iter().all(|f| f.yanked);
+            (
+                version.clone(),
+                uploaded.get(..10).unwrap_or(uploaded).to_string(),
+                yanked,
+            )
+        })
+        .collect::<Vec<_>>();
+    releases.sort_by(|a, b| compare_version(&b.0, &a.0));
+    Ok(CheckReport {
+        metadata,
+        downloads,
+        releases,
+        installed: None,
+    })
+}
+
+impl CheckReport {
+    fn to_json(&self, full: bool) -> Value {
+        let info = &self.metadata.info;
+        let mut payload = json!({
+            "name": info.name,
+            "version": info.version,
+            "summary": info.summary,
+            "home_page": info.home_page,
+        });
+        if let Some(dist) = &self.installed {
+            payload["installed"] = json!({"version": dist.version, "source": dist.source});
+        }
+        if full {
+            payload["requires_python"] = json!(info.requires_python);
+            payload["yanked"] = json!(info.yanked);
+            payload["yanked_reason"] = json!(info.yanked_reason);
+            payload["author"] = json!(person(&info.author, &info.author_email));
+            payload["maintainer"] = json!(person(&info.maintainer, &info.maintainer_email));
+            payload["project_urls"] = json!(info.project_urls);
+            payload["downloads"] = json!(self.downloads);
+            payload["releases"] = self
+                .releases
+                .iter()
+                .map(|(version, uploaded, yanked)| {
+                    json!({"version": version, "uploaded": uploaded, "yanked": yanked})
+                })
+                .collect();
+        }
+        payload
+    }
+
+    fn print(&self, full: bool) {
+        let info = &self.metadata.info;
+        println!("Name: {}", info.name);
+        println!("Version: {}", info.version);
+        println!("Summary: {}", info.summary);
+        println!("Home-page: {}", info.home_page);
+        if let Some(dist) = &self.installed {
+            println!("Installed: {} ({})", dist.version, dist.source.describe());
+        }
+        if !full {
+            return;
+        }
+        println!("Requires-Python: {}", info.requires_python);
+        if info.yanked {
+            println!("Yanked: yes {}", info.yanked_reason);
+        } else {
+            println!("Yanked: no");
+        }
+        println!("Author: {}", person(&info.author, &info.author_email));
+        println!(
+            "Maintainer: {}",
+            person(&info.maintainer, &info.maintainer_email)
+        );
+        if !info.project_urls.is_empty() {
+            println!("Project-URLs:");
+            for (label, url) in &info.project_urls {
+                println!("  {label}: {url}");
+            }
+        }
+        if let Some(d) = &self.downloads {
+            println!(
+                "Downloads: {} last day, {} last week, {} last month",
+                d.last_day, d.last_week, d.last_month
+            );
+        }
+        if self.releases.is_empty() {
+            return;
+        }
+        println!("Releases ({} total, newest first):", self.releases.len());
+        for (version, uploaded, yanked) in self.releases.iter().take(15) {
+            println!(
+                "  {:<16} {}{}",
+                version,
+                if uploaded.is_empty() { "-" } else { uploaded },
+                if *yanked { "  (yanked)" } else { "" }
+            );
+        }
+    }
+}
+
+fn print_check_table(reports: &[CheckReport]) {
+    let name_width = reports
+        .iter()
+        .map(|r| r.metadata.info.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("Package".len());
+    let version_width = reports
+        .iter()
+        .map(|r| r.metadata.info.version.len())
+        .max()
+        .unwrap_or(0)
+        .max("Version".len());
+    let header = format!(
+        "{:<name_width$}  {:<version_width$}  Summary",
+        "Package", "Version"
+    );
+    println!("{}", paint(Style::Header, &header));
+    for report in reports {
+        let info = &report.metadata.info;
+        let summary = if info.yanked {
+            format!("{} (yanked)", info.summary)
+        } else {
+            info.summary.clone()
+        };
+        println!(
+            "{:<name_width$}  {:<version_width$}  {}",
+            info.name, info.version, summary
+        );
+    }
+}
+
+/// `name <email>`, or whichever half PyPI has.
+fn person(name: &str, email: &str) -> String {
+    match (name.is_empty(), email.is_empty()) {
+        (false, false) => format!("{name} <{email}>"),
+        (true, false) => email.to_string(),
+        _ => name.to_string(),
+    }
+}
+
 fn cmd_remove(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let args = args
+        .iter()
+        .filter(|a| *a != "--dry-run")
+        .cloned()
+        .collect::<Vec<_>>();
     if args.is_empty() {
-        bail!("usage: xe remove <package_name>...");
+        bail!("usage: xe remove [--dry-run] <package_name>...");
     }
     let wd = env::current_dir().context("failed to get cwd")?;
     let (mut cfg, toml_path) = load_or_create_project(&wd)?;
     let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
-    if runtime.config_changed {
+    if runtime.config_changed && !dry_run {
         save_project(&toml_path, &cfg)?;
     }
 
     let is_remove_all = args.len() == 1 && args[0].eq_ignore_ascii_case("all");
     if is_remove_all {
-        let out = Command::new(&runtime.selection.python_exe)
-            .args(["-m", "pip", "list", "--format", "json"])
-            .output()
-            .context("failed to list packages")?;
-        if !out.status.success() {
-            bail!("Failed to list packages: {}", out.status);
-        }
-        let pkgs = parse_pip_list_output(&out.stdout)?;
+        let pkgs = installed_metadata(&runtime.selection.site_packages)?;
         let to_remove: Vec<String> = pkgs
             .iter()
             .filter_map(|p| {
                 let n = p.name.to_lowercase();
-                if n == "pip" || n == "setuptools" || n == "wheel" {
+                if n == "pip" || n == "setuptools" || n == "wheel" || cfg.settings.is_ignored(&n) {
                     None
                 } else {
                     Some(p.name.clone())
                 }
             })
             .collect();
+        if dry_run {
+            let changes = pkgs
+                .iter()
+                .filter(|p| to_remove.contains(&p.name))
+                .map(|p| PlannedChange::remove(&p.name, &p.version))
+                .collect::<Vec<_>>();
+            print_planned_changes(&changes, true);
+            return Ok(());
+        }
         if !to_remove.is_empty() {
             let mut command = Command::new(&runtime.selection.python_exe);
             command.arg("-m").arg("pip").arg("uninstall").arg("-y");
@@ -337,13 +1031,15 @@ fn cmd_remove(ctx: &AppContext, args: &[String]) -> Result<()> {
             }
         }
         cfg.deps.clear();
+        cfg.editable.clear();
+        cfg.direct.clear();
         save_project(&toml_path, &cfg)?;
         success("Removed all packages from active environment");
         return Ok(());
     }
 
     let mut req_names = Vec::new();
-    for raw in args {
+    for raw in &args {
         if let Some(n) = requirement_to_dep_name(raw) {
             req_names.push(n);
         }
@@ -351,6 +1047,28 @@ fn cmd_remove(ctx: &AppContext, args: &[String]) -> Result<()> {
     if req_names.is_empty() {
         bail!("No valid package names provided");
     }
+    let (ignored, req_names): (Vec<String>, Vec<String>) = req_names
+        .into_iter()
+        .partition(|name| cfg.settings.is_ignored(name));
+    warn_ignored(&ignored);
+    if req_names.is_empty() {
+        return Ok(());
+    }
+    if dry_run {
+        let installed = installed_distributions(&runtime.selection.site_packages)?;
+        let mut changes = Vec::new();
+        for name in &req_names {
+            match installed.get(&normalize_dep_name(name)) {
+                Some((dist, version)) => changes.push(PlannedChange::remove(dist, version)),
+                None if cfg.deps.contains_key(name) => {
+                    changes.push(PlannedChange::remove(name, ""))
+                }
+                None => warning(&format!("{name} is not installed")),
+            }
+        }
+        print_planned_changes(&changes, true);
+        return Ok(());
+    }
     let mut command = Command::new(&runtime.selection.python_exe);
     command.arg("-m").arg("pip").arg("uninstall").arg("-y");
     command.args(&req_names);
@@ -358,22 +1076,92 @@ fn cmd_remove(ctx: &AppContext, args: &[String]) -> Result<()> {
     if !status.success() {
         bail!("Failed to remove packages: {}", status);
     }
+    let req_names_len = req_names.len();
     for name in req_names {
         cfg.deps.remove(&name);
+        cfg.editable.remove(&name);
+        cfg.direct.remove(&name);
     }
     save_project(&toml_path, &cfg)?;
-    success(&format!("Removed {} package(s)", args.len()));
+    success(&format!("Removed {} package(s)", req_names_len));
+    Ok(())
+}
+
+/// Applies `--resolution-timeout <secs>` or `--max-backtracks <n>`; 0
+/// lifts the limit.
+fn set_resolve_limit(limits: &mut ResolveLimits, flag: &str, value: Option<&String>) -> Result<()> {
+    let value: u64 = value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| anyhow!("{flag} takes a whole number"))?;
+    if flag == "--resolution-timeout" {
+        limits.timeout = (value > 0).then(|| Duration::from_secs(value));
+    } else {
+        limits.max_backtracks = value as usize;
+    }
     Ok(())
 }
 
+fn warn_ignored(names: &[String]) {
+    for name in names {
+        warning(&format!(
+            "Skipping {name}: it is listed in [settings] ignore and managed outside xe"
+        ));
+    }
+}
+
 fn cmd_run(ctx: &AppContext, args: &[String]) -> Result<()> {
-    let wd = env::current_dir().context("failed to get cwd")?;
-    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
-    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
-    if runtime.config_changed {
-        save_project(&toml_path, &cfg)?;
+    let mut isolated = false;
+    let mut with: Vec<String> = Vec::new();
+    let mut python_version: Option<String> = None;
+    let mut ssh_host: Option<String> = None;
+    let mut remote_dir: Option<String> = None;
+    let mut include: Vec<String> = Vec::new();
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--ssh" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--ssh requires a host such as user@host"))?;
+                ssh_host = Some(value.clone());
+                idx += 2;
+            }
+            "--remote-dir" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--remote-dir requires a path"))?;
+                remote_dir = Some(value.clone());
+                idx += 2;
+            }
+            "--include" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--include requires a path"))?;
+                include.push(value.clone());
+                idx += 2;
+            }
+            "--isolated" => {
+                isolated = true;
+                idx += 1;
+            }
+            "--with" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--with requires a requirement"))?;
+                with.push(value.clone());
+                idx += 2;
+            }
+            "--python" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--python requires a version"))?;
+                python_version = Some(value.clone());
+                idx += 2;
+            }
+            _ => break,
+        }
     }
-    let mut command_args = args.to_vec();
+    let mut command_args = args[idx..].to_vec();
     if let Some(first) = command_args.first() {
         if first == "--" {
             command_args.remove(0);
@@ -382,25 +1170,307 @@ fn cmd_run(ctx: &AppContext, args: &[String]) -> Result<()> {
     if command_args.is_empty() {
         bail!("No command provided after '--'");
     }
-    let mut command_name = command_args[0].clone();
-    if command_name.eq_ignore_ascii_case("python") || command_name.eq_ignore_ascii_case("python.exe")
-    {
-        command_name = runtime.selection.python_exe.to_string_lossy().to_string();
+    if command_args[0] == "-m" {
+        if command_args.len() < 2 {
+            bail!("usage: xe run -m <module> [args...]");
+        }
+        command_args.insert(0, "python".to_string());
+    }
+    if !isolated && (!with.is_empty() || python_version.is_some()) {
+        bail!("--with and --python are only supported together with --isolated");
+    }
+    if ssh_host.is_none() && (remote_dir.is_some() || !include.is_empty()) {
+        bail!("--remote-dir and --include are only supported together with --ssh");
+    }
+    if ssh_host.is_some() && isolated {
+        bail!("--ssh cannot be combined with --isolated");
+    }
+
+    let status = if let Some(host) = ssh_host {
+        let remote = remote::RemoteRun {
+            host,
+            dir: remote_dir,
+            include,
+        };
+        remote::run_remote(&remote, &command_args)?
+    } else if isolated {
+        run_isolated(ctx, python_version, &with, &command_args)?
+    } else {
+        let wd = env::current_dir().context("failed to get cwd")?;
+        let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+        let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+        if runtime.config_changed {
+            save_project(&toml_path, &cfg)?;
+        }
+        let mut command = runtime_command(&runtime.selection, &command_args, Some(&wd))?;
+        if cfg.settings.run_hints {
+            let (status, stderr) = run_foreground_tee_stderr(&mut command, RUN_HINT_STDERR_TAIL)
+                .context("failed to run command")?;
+            if !status.success() {
+                print_missing_module_hint(&cfg, &String::from_utf8_lossy(&stderr));
+            }
+            status
+        } else {
+            run_foreground(&mut command).context("failed to run command")?
+        }
+    };
+    if let Some(code) = status.code() {
+        if code != 0 {
+            std::process::exit(code);
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `[hooks]` commands for `event` through the shell, from the
+/// project dir. `XE_HOOK`, `XE_PROJECT_DIR` and, when a runtime is known,
+/// `XE_PYTHON`, `XE_SITE_PACKAGES` and the runtime env are set; `XE_PACKAGES`
+/// lists `name==version` pairs the event touched. A failing hook aborts the
+/// command.
+fn run_hooks(
+    cfg: &Config,
+    event: &str,
+    project_dir: &Path,
+    selection: Option<&RuntimeSelection>,
+    packages: &[Package],
+) -> Result<()> {
+    for hook in cfg.hooks.commands(event) {
+        info(&format!("Running {event} hook: {hook}"));
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C").arg(hook);
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(hook);
+            command
+        };
+        command.current_dir(project_dir);
+        if let Some(selection) = selection {
+            apply_runtime_env(&mut command, selection)?;
+            command.env("XE_PYTHON", &selection.python_exe);
+            command.env("XE_SITE_PACKAGES", &selection.site_packages);
+        }
+        let packages = packages
+            .iter()
+            .map(|p| format!("{}=={}", p.name, p.version))
+            .collect::<Vec<_>>()
+            .join(" ");
+        command
+            .env("XE_HOOK", event)
+            .env("XE_PROJECT_DIR", project_dir)
+            .env("XE_PACKAGES", packages);
+        let status = run_foreground(&mut command)
+            .with_context(|| format!("failed to run {event} hook: {hook}"))?;
+        if !status.success() {
+            bail!("{event} hook failed ({status}): {hook}");
+        }
+    }
+    Ok(())
+}
+
+/// Output kept from a failing `xe run` to look for a missing module; the
+/// traceback's last line is all that is needed.
+const RUN_HINT_STDERR_TAIL: usize = 16 * 1024;
+
+/// After a run that died on `ModuleNotFoundError`, the package to add, or
+/// `xe sync` when xe.toml already has it.
+fn print_missing_module_hint(cfg: &Config, stderr: &str) {
+    let Some(module) = modules::missing_module(stderr) else {
+        return;
+    };
+    let map = ModuleMap::load();
+    let top_level = module.split('.').next().unwrap_or(module);
+    let (dist, known) = match map.distribution(module) {
+        Some(dist) => (dist.to_string(), true),
+        None => (top_level.to_string(), false),
+    };
+    let declared = cfg
+        .deps
+        .keys()
+        .any(|name| normalize_dep_name(name) == normalize_dep_name(&dist));
+    if declared {
+        warning(&format!(
+            "package '{dist}' provides module '{module}' and is in {XE_TOML} but not installed; run `xe sync`"
+        ));
+    } else if known {
+        warning(&format!(
+            "package '{dist}' provides module '{module}'; run `xe add {dist}`"
+        ));
+    } else {
+        warning(&format!(
+            "module '{module}' is missing; if package '{dist}' provides it, run `xe add {dist}`"
+        ));
+    }
+}
+
+/// Runs `command_args` against a runtime, resolving `python` to the selected
+/// interpreter and console scripts to their launchers or entry points. The
+/// scripts of the project in `project_dir` run from its source when it is
+/// not installed.
+fn run_in_runtime(
+    selection: &RuntimeSelection,
+    command_args: &[String],
+    project_dir: Option<&Path>,
+) -> Result<std::process::ExitStatus> {
+    let mut command = runtime_command(selection, command_args, project_dir)?;
+    run_foreground(&mut command).context("failed to run command")
+}
+
+/// The command `command_args` names, resolved in the runtime: `python` is
+/// its interpreter, other names its scripts or entry points.
+fn runtime_command(
+    selection: &RuntimeSelection,
+    command_args: &[String],
+    project_dir: Option<&Path>,
+) -> Result<Command> {
+    let mut command_name = command_args[0].clone();
+    let mut prefix_args: Vec<String> = Vec::new();
+    if command_name.eq_ignore_ascii_case("python") || command_name.eq_ignore_ascii_case("python.exe")
+    {
+        command_name = selection.python_exe.to_string_lossy().to_string();
+    } else if let Some(script) = find_runtime_script(&selection.python_exe, &command_name) {
+        command_name = script.to_string_lossy().to_string();
+    } else if let Some(ep) = read_entry_points(&selection.site_packages)?
+        .into_iter()
+        .find(|ep| ep.name.eq_ignore_ascii_case(&command_name))
+    {
+        // The package is installed but its launcher is missing; call the
+        // entry point through the interpreter instead.
+        prefix_args = vec!["-c".to_string(), ep.inline_source()];
+        command_name = selection.python_exe.to_string_lossy().to_string();
+    } else if let Some((root, ep)) = project_dir.and_then(|dir| project_script(dir, &command_name))
+    {
+        // Imported from the checkout, as an editable install would.
+        let source = format!(
+            "import sys; sys.path.insert(0, {:?}); {}",
+            root.to_string_lossy(),
+            ep.inline_source()
+        );
+        prefix_args = vec!["-c".to_string(), source];
+        command_name = selection.python_exe.to_string_lossy().to_string();
     }
 
     let mut command = Command::new(command_name);
+    command.args(&prefix_args);
     command.args(&command_args[1..]);
-    apply_runtime_env(&mut command, &runtime.selection)?;
+    apply_runtime_env(&mut command, selection)?;
     command.stdin(Stdio::inherit());
     command.stdout(Stdio::inherit());
     command.stderr(Stdio::inherit());
-    let status = command.status().context("failed to run command")?;
-    if let Some(code) = status.code() {
-        if code != 0 {
-            std::process::exit(code);
+    Ok(command)
+}
+
+/// The `[project.scripts]` entry `name` of the project in `project_dir`,
+/// with the directory its modules import from: `src` in a src layout.
+fn project_script(project_dir: &Path, name: &str) -> Option<(PathBuf, EntryPoint)> {
+    let ep = project_entry_points(project_dir)
+        .ok()?
+        .into_iter()
+        .find(|ep| ep.name.eq_ignore_ascii_case(name))?;
+    let src = project_dir.join("src");
+    let root = if src.is_dir() {
+        src
+    } else {
+        project_dir.to_path_buf()
+    };
+    Some((root, ep))
+}
+
+/// Runs a command in a throwaway venv holding only the `--with` packages.
+/// The project config and its venv are never read, and an inherited
+/// `PYTHONPATH` is dropped, so nothing from the current checkout or shell
+/// leaks into the run.
+fn run_isolated(
+    ctx: &AppContext,
+    python_version: Option<String>,
+    with: &[String],
+    command_args: &[String],
+) -> Result<std::process::ExitStatus> {
+    with_isolated_env(ctx, python_version, with, |selection| {
+        let mut command = runtime_command(selection, command_args, None)?;
+        command.env_remove("PYTHONPATH");
+        run_foreground(&mut command).context("failed to run command")
+    })
+}
+
+/// Calls `body` with a temporary venv of `python_version` (the global
+/// default when `None`) holding only `with`, deleting it afterwards.
+pub(crate) fn with_isolated_env<T>(
+    ctx: &AppContext,
+    python_version: Option<String>,
+    with: &[String],
+    body: impl FnOnce(&RuntimeSelection) -> Result<T>,
+) -> Result<T> {
+    let version = match python_version {
+        Some(v) => v,
+        None => {
+            let global_cfg = load_global_config(&ctx.config_file)?;
+            if global_cfg.default_python.trim().is_empty() {
+                default_python_version()
+            } else {
+                global_cfg.default_python
+            }
+        }
+    };
+    let pm = PythonManager::new()?;
+    let base_python = match pm.get_python_exe(&version) {
+        Ok(path) => path,
+        Err(_) => {
+            pm.install(&version, ctx)?;
+            pm.get_python_exe(&version)?
+        }
+    };
+
+    let vm = VenvManager {
+        base_dir: env::temp_dir(),
+    };
+    let name = format!("xe-isolated-{}-{}", std::process::id(), profile_stamp());
+    info(&format!(
+        "Creating isolated environment with Python {}...",
+        version
+    ));
+    register_cleanup_path(&vm.base_dir.join(&name));
+    vm.create(&name, &base_python, None)?;
+    let result = (|| -> Result<T> {
+        let python_exe = vm.get_python_exe(&name);
+        let site_packages = detect_venv_site_packages(&python_exe)?;
+        if !with.is_empty() {
+            let env_dir = vm.base_dir.join(&name);
+            let mut cfg = Config::new_default(&env_dir);
+            cfg.python.version = version.clone();
+            let installer = Installer::new(&cfg, &env_dir)?;
+            installer.install(
+                ctx,
+                &cfg,
+                with,
+                &vm.base_dir.join(&name),
+                &site_packages,
+                &python_exe,
+            )?;
         }
+        let selection = RuntimeSelection {
+            activation_path: python_exe
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            python_exe,
+            site_packages,
+            venv_name: name.clone(),
+            is_venv: true,
+        };
+        body(&selection)
+    })();
+    if let Err(err) = vm.delete(&name) {
+        warning(&format!("Failed to remove isolated environment: {err}"));
     }
-    Ok(())
+    result
+}
+
+fn cmd_repl(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let mut run_args = vec!["python".to_string()];
+    run_args.extend(args.iter().cloned());
+    cmd_run(ctx, &run_args)
 }
 
 fn cmd_shell(ctx: &AppContext, _args: &[String]) -> Result<()> {
@@ -420,7 +1490,7 @@ fn cmd_shell(ctx: &AppContext, _args: &[String]) -> Result<()> {
     command.stdin(Stdio::inherit());
     command.stdout(Stdio::inherit());
     command.stderr(Stdio::inherit());
-    let status = command.status().context("failed to spawn shell")?;
+    let status = run_foreground(&mut command).context("failed to spawn shell")?;
     if !status.success() {
         bail!("shell exited with {}", status);
     }
@@ -430,9 +1500,54 @@ fn cmd_shell(ctx: &AppContext, _args: &[String]) -> Result<()> {
 fn cmd_init(ctx: &AppContext, args: &[String]) -> Result<()> {
     let mut name = String::new();
     let mut python_version = String::new();
+    let mut backend = None;
+    let mut app = false;
+    let mut template_source = None;
+    let mut template_vars = BTreeMap::new();
+    let mut run_template_hooks = None;
+    let mut metadata = ProjectConfig::default();
+    let mut no_input = false;
+    let usage = "usage: xe init [name] [--python <version>] [--build-backend <name>] [--app] [--description <text>] [--author <name <email>>]... [--license <spdx>] [--readme <path>] [--no-input] [--template <dir|git-url> [--var <name>=<value>]... [--run-hooks|--no-hooks]]";
     let mut idx = 0usize;
     while idx < args.len() {
         match args[idx].as_str() {
+            flag @ ("--description" | "--author" | "--license" | "--readme") => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("{flag} requires a value"))?
+                    .trim()
+                    .to_string();
+                match flag {
+                    "--description" => metadata.description = value,
+                    "--author" => metadata.authors.push(value),
+                    "--license" => metadata.license = value,
+                    _ => metadata.readme = value,
+                }
+                idx += 2;
+            }
+            "--no-input" => {
+                no_input = true;
+                idx += 1;
+            }
+            "--template" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--template requires a directory or git URL"))?;
+                template_source = Some(value.clone());
+                idx += 2;
+            }
+            "--var" => {
+                let (key, value) = args
+                    .get(idx + 1)
+                    .and_then(|var| var.split_once('='))
+                    .ok_or_else(|| anyhow!("--var requires <name>=<value>"))?;
+                template_vars.insert(key.trim().to_string(), value.to_string());
+                idx += 2;
+            }
+            flag @ ("--run-hooks" | "--no-hooks") => {
+                run_template_hooks = Some(flag == "--run-hooks");
+                idx += 1;
+            }
             "-p" | "--python" => {
                 let value = args
                     .get(idx + 1)
@@ -440,14 +1555,39 @@ fn cmd_init(ctx: &AppContext, args: &[String]) -> Result<()> {
                 python_version = value.clone();
                 idx += 2;
             }
+            "--app" => {
+                app = true;
+                idx += 1;
+            }
+            "--build-backend" => {
+                let value = args.get(idx + 1).ok_or_else(|| {
+                    anyhow!(
+                        "--build-backend requires one of {}",
+                        build::BACKENDS.join(", ")
+                    )
+                })?;
+                if !build::BACKENDS.contains(&value.as_str()) {
+                    bail!(
+                        "unknown build backend '{}' (expected {})",
+                        value,
+                        build::BACKENDS.join(", ")
+                    );
+                }
+                backend = Some(value.clone());
+                idx += 2;
+            }
             value if !value.starts_with('-') && name.is_empty() => {
                 name = value.to_string();
                 idx += 1;
             }
-            _ => bail!("usage: xe init [name] [--python <version>]"),
+            _ => bail!(usage),
         }
     }
 
+    if app && backend.as_deref() == Some("maturin") {
+        bail!("--app scaffolds a Python entry point; pick a Python build backend");
+    }
+
     let mut wd = env::current_dir().context("failed to get cwd")?;
     if !name.is_empty() && name != "." {
         wd = wd.join(name);
@@ -456,6 +1596,13 @@ fn cmd_init(ctx: &AppContext, args: &[String]) -> Result<()> {
     println!("Initializing project at {}...", wd.display());
 
     let mut version = python_version;
+    if version.is_empty() {
+        version = load_global_config(&ctx.config_file)?
+            .defaults
+            .python
+            .trim()
+            .to_string();
+    }
     if version.is_empty() {
         version = get_preferred_python_version(ctx)?;
     }
@@ -476,31 +1623,154 @@ fn cmd_init(ctx: &AppContext, args: &[String]) -> Result<()> {
             .to_string();
     }
     cfg.python.version = version;
+    ask_project_metadata(&mut metadata, !no_input && io::stdin().is_terminal())?;
+    for (key, value) in [
+        ("description", &metadata.description),
+        ("license", &metadata.license),
+    ] {
+        template_vars
+            .entry(key.to_string())
+            .or_insert_with(|| value.clone());
+    }
     let toml_path = wd.join(XE_TOML);
+    let template = match &template_source {
+        Some(source) => {
+            let template = template::Template::open(source)?;
+            let vars = template.variables(&cfg.project.name, &cfg.python.version, &template_vars);
+            let written = template.render(&wd, &vars)?;
+            println!("Generated {written} file(s) from template {source}");
+            Some((template, vars))
+        }
+        None => None,
+    };
+    // A template may bring its own xe.toml; its metadata wins.
+    let from_template = template.is_some() && toml_path.exists();
+    if from_template {
+        cfg = load_project(&toml_path)?;
+    }
+    let project = &mut cfg.project;
+    for (field, value) in [
+        (&mut project.description, metadata.description),
+        (&mut project.license, metadata.license),
+        (&mut project.readme, metadata.readme),
+    ] {
+        if field.is_empty() {
+            *field = value;
+        }
+    }
+    if project.authors.is_empty() {
+        project.authors = metadata.authors;
+    }
     save_project(&toml_path, &cfg)?;
-    println!("Created {}", toml_path.display());
+    if !from_template {
+        println!("Created {}", toml_path.display());
+    }
+    if !cfg.project.readme.is_empty() {
+        let readme = wd.join(&cfg.project.readme);
+        if !readme.exists() {
+            let mut content = format!("# {}\n", cfg.project.name);
+            if !cfg.project.description.is_empty() {
+                content.push_str(&format!("\n{}\n", cfg.project.description));
+            }
+            fs::write(&readme, content)
+                .with_context(|| format!("failed to write {}", readme.display()))?;
+            println!("Created {}", readme.display());
+        }
+    }
+    // An app needs a build backend for its entry point; hatchling unless
+    // one was picked.
+    if let Some(backend) = backend.or_else(|| app.then(|| "hatchling".to_string())) {
+        build::scaffold_backend(&wd, &cfg, &backend, app)?;
+    }
+    if let Some((template, vars)) = &template {
+        if confirm_template_hooks(template.hooks(), run_template_hooks, no_input)? {
+            template.run_post_generate(ctx, &wd, &cfg.python.version, vars)?;
+        }
+    }
     println!("Project initialized successfully.");
     Ok(())
 }
 
+/// Fills the project metadata not given as flags, asking when
+/// `interactive`. Authors default to the git identity either way.
+fn ask_project_metadata(metadata: &mut ProjectConfig, interactive: bool) -> Result<()> {
+    let git_author = match (
+        template::git_config("user.name"),
+        template::git_config("user.email"),
+    ) {
+        (name, email) if email.is_empty() => name,
+        (name, email) if name.is_empty() => format!("<{email}>"),
+        (name, email) => format!("{name} <{email}>"),
+    };
+    if !interactive {
+        if metadata.authors.is_empty() && !git_author.is_empty() {
+            metadata.authors.push(git_author);
+        }
+        return Ok(());
+    }
+    if metadata.description.is_empty() {
+        metadata.description = prompt("Description: ")?;
+    }
+    if metadata.authors.is_empty() {
+        let author = prompt(&format!("Author [{git_author}]: "))?;
+        let author = if author.is_empty() {
+            git_author
+        } else {
+            author
+        };
+        if !author.is_empty() {
+            metadata.authors.push(author);
+        }
+    }
+    if metadata.license.is_empty() {
+        metadata.license = prompt("License (SPDX expression such as MIT, blank for none): ")?;
+    }
+    if metadata.readme.is_empty() {
+        let readme = prompt("Readme [README.md]: ")?;
+        metadata.readme = if readme.is_empty() {
+            "README.md".to_string()
+        } else {
+            readme
+        };
+    }
+    Ok(())
+}
+
 fn cmd_use(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage = "usage: xe use <python_version> [-d|--default] [--arch <amd64|arm64|win32>]";
     if args.is_empty() {
-        bail!("usage: xe use <python_version> [-d|--default]");
+        bail!(usage);
     }
     let mut default_flag = false;
     let mut version = String::new();
-    for arg in args {
-        match arg.as_str() {
-            "-d" | "--default" => default_flag = true,
-            value if !value.starts_with('-') && version.is_empty() => version = value.to_string(),
-            _ => bail!("usage: xe use <python_version> [-d|--default]"),
+    let mut arch: Option<PythonArch> = None;
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "-d" | "--default" => {
+                default_flag = true;
+                idx += 1;
+            }
+            "--arch" => {
+                let value = args
+                    .get(idx + 1)
+                    .ok_or_else(|| anyhow!("--arch requires a value"))?;
+                arch = Some(PythonArch::parse(value)?);
+                idx += 2;
+            }
+            value if !value.starts_with('-') && version.is_empty() => {
+                version = value.to_string();
+                idx += 1;
+            }
+            _ => bail!(usage),
         }
     }
     if version.is_empty() {
-        bail!("usage: xe use <python_version> [-d|--default]");
+        bail!(usage);
     }
 
-    let pm = PythonManager::new()?;
+    let mut pm = PythonManager::new()?;
+    pm.arch = arch;
     pm.install(&version, ctx)?;
     let python_exe = pm.get_python_exe(&version)?;
 
@@ -520,7 +1790,7 @@ fn cmd_use(ctx: &AppContext, args: &[String]) -> Result<()> {
         success(&format!("Global default set to Python {}", version));
     }
 
-    let shim_name = format!("python{}", version.replace('.', ""));
+    let shim_name = PythonSpec::parse(&version)?.shim_name();
     if let Err(err) = create_shim(&shim_name, &python_exe) {
         warning(&format!("Failed to create versioned shim: {err}"));
     }
@@ -533,10 +1803,15 @@ fn cmd_venv(ctx: &AppContext, args: &[String]) -> Result<()> {
     }
     match args[0].as_str() {
         "create" => {
-            if args.len() != 2 {
-                bail!("usage: xe venv create <name>");
-            }
-            let name = normalize_venv_name(&args[1]);
+            let relocatable = args[1..].iter().any(|a| a == "--relocatable");
+            let names = args[1..]
+                .iter()
+                .filter(|a| *a != "--relocatable")
+                .collect::<Vec<_>>();
+            let [name] = names[..] else {
+                bail!("usage: xe venv create <name> [--relocatable]");
+            };
+            let name = normalize_venv_name(name);
             if name.is_empty() {
                 bail!("Invalid venv name");
             }
@@ -559,19 +1834,78 @@ fn cmd_venv(ctx: &AppContext, args: &[String]) -> Result<()> {
                 ));
                 return Ok(());
             }
-            vm.create(&name, &python_exe)?;
-            success(&format!("Created venv {}", name));
+            vm.create(&name, &python_exe, Some(&wd))?;
+            if relocatable {
+                vm.make_relocatable(&name)?;
+                success(&format!("Created relocatable venv {}", name));
+            } else {
+                success(&format!("Created venv {}", name));
+            }
             Ok(())
         }
         "list" => {
+            let mut prune_orphans = false;
+            for arg in &args[1..] {
+                match arg.as_str() {
+                    "--prune-orphans" => prune_orphans = true,
+                    other => bail!("unknown flag for xe venv list: {other}"),
+                }
+            }
             let vm = VenvManager::new()?;
-            let all = vm.list()?;
+            let mut all = vm.list()?;
+            all.sort();
+            if prune_orphans {
+                let mut pruned = 0usize;
+                for name in &all {
+                    let Some(meta) = vm.metadata(name) else {
+                        continue;
+                    };
+                    if meta.project.is_empty() || Path::new(&meta.project).exists() {
+                        continue;
+                    }
+                    vm.delete(name)?;
+                    info(&format!(
+                        "Removed venv {} (project {} no longer exists)",
+                        name, meta.project
+                    ));
+                    pruned += 1;
+                }
+                success(&format!("Pruned {pruned} orphaned venv(s)"));
+                all = vm.list()?;
+                all.sort();
+            }
             if all.is_empty() {
                 info("No venvs found");
                 return Ok(());
             }
-            for v in all {
-                println!("{v}");
+            let header = format!(
+                "{:<24} {:<8} {:<20} {:>10}  Project",
+                "Name", "Python", "Created", "Size"
+            );
+            println!("{}", paint(Style::Header, &header));
+            for name in all {
+                let meta = vm.metadata(&name).unwrap_or_default();
+                let python = if meta.python.is_empty() {
+                    vm.python_version(&name)
+                } else {
+                    meta.python
+                };
+                let created = meta.created_at.get(..19).unwrap_or(&meta.created_at);
+                let project = if meta.project.is_empty() {
+                    "-".to_string()
+                } else if Path::new(&meta.project).exists() {
+                    meta.project
+                } else {
+                    format!("{} (missing)", meta.project)
+                };
+                println!(
+                    "{:<24} {:<8} {:<20} {:>10}  {}",
+                    name,
+                    if python.is_empty() { "-" } else { &python },
+                    if created.is_empty() { "-" } else { created },
+                    format_bytes(dir_size(&vm.base_dir.join(&name))),
+                    project
+                );
             }
             Ok(())
         }
@@ -666,11 +2000,36 @@ fn toggle_autovenv(raw: &str) -> Result<()> {
     Ok(())
 }
 
+/// Runs a long operation and hands its outcome to the reporters, which
+/// includes the `[notifications]` webhook.
+fn report_operation(operation: &str, run: impl FnOnce() -> Result<()>) -> Result<()> {
+    let started = Instant::now();
+    let result = run();
+    let wd = env::current_dir().unwrap_or_default();
+    let project = load_project(&wd.join(XE_TOML))
+        .ok()
+        .map(|cfg| cfg.project.name)
+        .filter(|name| !name.is_empty())
+        .or_else(|| wd.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    report_finished(&Summary {
+        operation: operation.to_string(),
+        project,
+        ok: result.is_ok(),
+        duration_ms: started.elapsed().as_millis(),
+        error: result.as_ref().err().map(|err| format!("{err:#}")),
+    });
+    result
+}
+
 fn cmd_import(ctx: &AppContext, args: &[String]) -> Result<()> {
     if args.len() != 1 {
-        bail!("usage: xe import <path_to_config>");
+        bail!("usage: xe import <path_to_config|wheelhouse>");
     }
     let path = PathBuf::from(&args[0]);
+    if wheelhouse::is_wheelhouse(&path) {
+        return wheelhouse::cmd_import_wheelhouse(ctx, &path);
+    }
     info(&format!("Importing from {}...", path.display()));
 
     let wd = env::current_dir().context("failed to get cwd")?;
@@ -679,7 +2038,7 @@ fn cmd_import(ctx: &AppContext, args: &[String]) -> Result<()> {
     if runtime.config_changed {
         save_project(&local_toml_path, &local_cfg)?;
     }
-    let installer = Installer::new(Path::new(&local_cfg.cache.global_dir))?;
+    let installer = Installer::new(&local_cfg, &wd)?;
 
     let path_lower = path.to_string_lossy().to_lowercase();
     if path.file_name().and_then(|s| s.to_str()) == Some(XE_TOML) {
@@ -688,14 +2047,7 @@ fn cmd_import(ctx: &AppContext, args: &[String]) -> Result<()> {
             warning("No dependencies found in [deps] section");
             return Ok(());
         }
-        let mut reqs = Vec::with_capacity(cfg.deps.len());
-        for (name, version) in cfg.deps {
-            if version.is_empty() || version == "*" {
-                reqs.push(name);
-            } else {
-                reqs.push(format!("{name}=={version}"));
-            }
-        }
+        let reqs = cfg.requirements(path.parent().unwrap_or_else(|| Path::new(".")));
         let resolved = installer.install(
             ctx,
             &local_cfg,
@@ -705,9 +2057,7 @@ fn cmd_import(ctx: &AppContext, args: &[String]) -> Result<()> {
             &runtime.selection.python_exe,
         )?;
         for p in &resolved {
-            local_cfg
-                .deps
-                .insert(normalize_dep_name(&p.name), p.version.clone());
+            local_cfg.set_dep(&normalize_dep_name(&p.name), &p.version);
         }
         save_project(&local_toml_path, &local_cfg)?;
         success(&format!(
@@ -718,11 +2068,16 @@ fn cmd_import(ctx: &AppContext, args: &[String]) -> Result<()> {
     }
 
     if path_lower.ends_with("requirements.txt") || path_lower.ends_with(".txt") {
-        let reqs = parse_requirements(&path)?;
+        let parsed = parse_requirements(&path)?;
+        let mut reqs = parsed.requirements.clone();
+        for target in &parsed.editables {
+            reqs.push(format!("-e {}", target.display()));
+        }
         if reqs.is_empty() {
             warning("No installable entries found in requirements file");
             return Ok(());
         }
+        local_cfg.resolution.merge(parsed.resolution);
         let resolved = installer.install(
             ctx,
             &local_cfg,
@@ -731,15 +2086,28 @@ fn cmd_import(ctx: &AppContext, args: &[String]) -> Result<()> {
             &runtime.selection.site_packages,
             &runtime.selection.python_exe,
         )?;
-        for req in &reqs {
+        for req in &parsed.requirements {
             if let Some(dep) = requirement_to_dep_name(req) {
-                local_cfg.deps.insert(dep, "*".to_string());
+                local_cfg.set_dep(&dep, "*");
             }
         }
         for p in &resolved {
-            local_cfg
-                .deps
-                .insert(normalize_dep_name(&p.name), p.version.clone());
+            let name = normalize_dep_name(&p.name);
+            if let Some(source) = p.editable_path() {
+                let stored = source
+                    .strip_prefix(&wd)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(source);
+                local_cfg
+                    .editable
+                    .insert(name.clone(), stored.to_string_lossy().replace('\\', "/"));
+            }
+            local_cfg.set_dep(&name, &p.version);
+        }
+        if local_cfg.resolution.strip_credentials() {
+            warning(
+                "Left the index credentials out of xe.toml; add them to ~/.netrc so later installs can authenticate",
+            );
         }
         save_project(&local_toml_path, &local_cfg)?;
         success(&format!(
@@ -754,15 +2122,36 @@ fn cmd_import(ctx: &AppContext, args: &[String]) -> Result<()> {
 }
 
 fn cmd_export(args: &[String]) -> Result<()> {
-    if args.len() != 1 {
-        bail!("usage: xe export <output_path>");
+    let usage = "usage: xe export [--format wheelhouse] <output_path>";
+    let mut format = None;
+    let mut output = None;
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--format" => {
+                idx += 1;
+                format = Some(args.get(idx).ok_or_else(|| anyhow!(usage))?.as_str());
+            }
+            flag if flag.starts_with("--") => bail!("unknown flag for xe export: {flag}"),
+            value if output.is_none() => output = Some(PathBuf::from(value)),
+            _ => bail!(usage),
+        }
+        idx += 1;
+    }
+    let Some(path) = output else {
+        bail!(usage);
+    };
+    match format {
+        None => {}
+        Some("wheelhouse") => return wheelhouse::cmd_export_wheelhouse(&path),
+        Some(other) => bail!("unknown export format: {other} (expected wheelhouse)"),
     }
-    let path = PathBuf::from(&args[0]);
     let wd = env::current_dir().context("failed to get cwd")?;
     let (cfg, _) = load_or_create_project(&wd)?;
     let content = format!(
         "cache_mode={}\ncache_dir={}\n",
-        cfg.cache.mode, cfg.cache.global_dir
+        cfg.cache.mode,
+        cfg.cache.dir(&wd)?.display()
     );
     fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
     success(&format!(
@@ -773,19 +2162,42 @@ fn cmd_export(args: &[String]) -> Result<()> {
 }
 
 fn cmd_clean(args: &[String]) -> Result<()> {
-    let force = args.iter().any(|a| a == "--force" || a == "-f");
+    let mut force = false;
+    let mut all = false;
+    for arg in args {
+        match arg.as_str() {
+            "--force" | "-f" => force = true,
+            "--all" => all = true,
+            _ => bail!("usage: xe clean [--force] [--all]"),
+        }
+    }
+    let pm = PythonManager::new()?;
+    let runtimes = pm.runtimes_installed_by_xe()?;
+    // On Windows runtimes live in the standard per-user Python directory,
+    // next to Pythons that xe did not install.
+    let others = if pm.base_dir.starts_with(xe_home()) {
+        Vec::new()
+    } else {
+        fs::read_dir(&pm.base_dir)
+            .with_context(|| format!("failed to read {}", pm.base_dir.display()))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() && !runtimes.contains(path))
+            .collect::<Vec<_>>()
+    };
     if !force {
         warning("This will delete all global and local xe data, including:");
         println!("- {} (config, cache, credentials, venvs)", xe_home().display());
-        let home = dirs::home_dir().ok_or_else(|| anyhow!("cannot resolve home dir"))?;
-        println!(
-            "- {} (self-installed runtimes)",
-            home.join("AppData")
-                .join("Local")
-                .join("Programs")
-                .join("Python")
-                .display()
-        );
+        if all {
+            println!(
+                "- {} (every Python runtime in it, including ones xe did not install)",
+                pm.base_dir.display()
+            );
+        } else {
+            for dir in &runtimes {
+                println!("- {} (runtime installed by xe)", dir.display());
+            }
+        }
         println!("- xe.toml in the current directory");
         print!("\nAre you sure you want to proceed? (y/N): ");
         io::stdout().flush().ok();
@@ -800,159 +2212,880 @@ fn cmd_clean(args: &[String]) -> Result<()> {
 
     info("Starting system-wide cleanup...");
     let home = dirs::home_dir().ok_or_else(|| anyhow!("cannot resolve home dir"))?;
+    if all {
+        remove_path(&pm.base_dir, "All Python runtimes")?;
+    } else {
+        for dir in &runtimes {
+            remove_path(dir, "Python runtime installed by xe")?;
+            if cfg!(windows) {
+                remove_from_path(dir)?;
+                remove_from_path(&dir.join("Scripts"))?;
+            }
+        }
+        pm.clear_manifest()?;
+    }
     remove_path(&xe_home(), "Global configuration and data")?;
     remove_path(&home.join(".xe"), "Legacy xe directory")?;
     remove_path(&home.join(".cache").join("xe"), "Global CAS cache")?;
-    remove_path(
-        &home.join("AppData").join("Local").join("Programs").join("Python"),
-        "Self-installed Python runtimes",
-    )?;
     remove_path(Path::new(XE_TOML), "Local project configuration")?;
+    if !all && !others.is_empty() {
+        info(&format!(
+            "Kept {} Python install(s) in {} that xe did not install; pass --all to remove them too",
+            others.len(),
+            pm.base_dir.display()
+        ));
+    }
     success("Cleanup complete. All xe-related data has been removed.");
     Ok(())
 }
 
-fn cmd_snapshot(args: &[String]) -> Result<()> {
-    if args.len() != 1 {
-        bail!("usage: xe snapshot <name>");
+fn cmd_sync(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let mut report_path: Option<PathBuf> = None;
+    let mut dry_run = false;
+    let mut strict_python = false;
+    let mut frozen = false;
+    let mut verify = false;
+    let mut allow_collisions = false;
+    let mut limits = ResolveLimits::default();
+    let mut extras = Vec::new();
+    let mut profile_name: Option<String> = None;
+    let mut only_packages = Vec::new();
+    let mut only_groups = Vec::new();
+    let usage = "usage: xe sync [--dry-run] [--frozen] [--strict-python] [--verify-imports] [--allow-collisions] [--profile <name>] [--extra <name>]... [--package <name>]... [--group <name>]... [--report <path>] [--resolution-timeout <secs>] [--max-backtracks <n>]";
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--report" => {
+                idx += 1;
+                let Some(path) = args.get(idx) else {
+                    bail!(usage);
+                };
+                report_path = Some(PathBuf::from(path));
+            }
+            "--extra" => {
+                idx += 1;
+                let Some(extra) = args.get(idx) else {
+                    bail!(usage);
+                };
+                extras.push(normalize_dep_name(extra));
+            }
+            "--profile" => {
+                idx += 1;
+                let Some(name) = args.get(idx) else {
+                    bail!(usage);
+                };
+                profile_name = Some(name.clone());
+            }
+            "--package" => {
+                idx += 1;
+                let Some(name) = args.get(idx) else {
+                    bail!(usage);
+                };
+                only_packages.push(normalize_dep_name(name));
+            }
+            "--group" => {
+                idx += 1;
+                let Some(group) = args.get(idx) else {
+                    bail!(usage);
+                };
+                only_groups.push(normalize_dep_name(group));
+            }
+            "--dry-run" => dry_run = true,
+            "--strict-python" => strict_python = true,
+            "--frozen" => frozen = true,
+            "--verify-imports" => verify = true,
+            "--allow-collisions" => allow_collisions = true,
+            flag @ ("--resolution-timeout" | "--max-backtracks") => {
+                idx += 1;
+                set_resolve_limit(&mut limits, flag, args.get(idx))?;
+            }
+            other => bail!("unknown flag for xe sync: {other}"),
+        }
+        idx += 1;
     }
-    let snap_path = create_snapshot(&args[0])?;
-    println!(
-        "Snapshot '{}' created successfully at {}",
-        args[0],
-        snap_path.display()
-    );
-    Ok(())
-}
 
-fn cmd_restore(args: &[String]) -> Result<()> {
-    if args.len() != 1 {
-        bail!("usage: xe restore <name>");
+    let subset = !only_packages.is_empty() || !only_groups.is_empty();
+    if subset && !extras.is_empty() {
+        bail!("--extra installs on top of a full sync; with --package or --group, select groups with --group");
     }
-    println!("Successfully restored snapshot '{}'", args[0]);
-    Ok(())
-}
 
-fn cmd_sync(ctx: &AppContext, _args: &[String]) -> Result<()> {
+    let started = Instant::now();
     let wd = env::current_dir().context("failed to get cwd")?;
     let (mut cfg, toml_path) = load_or_create_project(&wd)?;
-    let reqs = cfg
-        .deps
-        .iter()
-        .map(|(name, version)| {
-            if version.is_empty() || version == "*" {
-                name.clone()
+    let profile = cfg.sync_profile(profile_name.as_deref())?;
+    for group in &profile.groups {
+        let group = normalize_dep_name(group);
+        if !extras.contains(&group) {
+            extras.push(group);
+        }
+    }
+    extras.retain(|extra| {
+        !profile
+            .exclude_groups
+            .iter()
+            .any(|excluded| normalize_dep_name(excluded) == *extra)
+    });
+    if let Some(name) = &profile_name {
+        info(&format!(
+            "Using profile {} (groups: {})",
+            name,
+            if extras.is_empty() {
+                "none".to_string()
             } else {
-                format!("{name}=={version}")
+                extras.join(", ")
             }
-        })
-        .collect::<Vec<_>>();
-    let installer = Installer::new(Path::new(&cfg.cache.global_dir))?;
+        ));
+    }
+    let compile = profile.compile_bytecode.unwrap_or(false);
+    if frozen {
+        match load_lock(&wd)? {
+            Some(lock) if lock.fingerprint == lock_fingerprint(&cfg) => {}
+            Some(_) => bail!("{XE_LOCK} is out of date with {XE_TOML} and --frozen does not resolve; run `xe lock` and commit {XE_LOCK}"),
+            None => bail!("--frozen installs from {XE_LOCK}, which is missing; run `xe lock` and commit it"),
+        }
+    }
+    let mut reqs = cfg.requirements(&wd);
+    let mut extra_reqs = Vec::new();
+    for extra in &extras {
+        extra_reqs.extend(cfg.extra_requirements(extra)?);
+    }
+    let mut installer = Installer::new(&cfg, &wd)?;
+    installer.allow_collisions = allow_collisions;
+    installer.limits = limits;
     let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
-    if runtime.config_changed {
+    if runtime.config_changed && !dry_run {
         save_project(&toml_path, &cfg)?;
     }
-    installer.install(
-        ctx,
-        &cfg,
-        &reqs,
-        &wd,
+    check_duplicate_distributions(&runtime.selection.site_packages, allow_collisions)?;
+    let subset_lock = if subset {
+        match load_lock(&wd)? {
+            Some(lock) if lock.fingerprint == lock_fingerprint(&cfg) => Some(lock),
+            _ => bail!("--package and --group sync from xe.lock, which is missing or out of date; run `xe lock` first"),
+        }
+    } else {
+        None
+    };
+    let mut group_reqs = Vec::new();
+    for group in &only_groups {
+        group_reqs.extend(cfg.extra_requirements(group)?);
+    }
+    if dry_run {
+        let python_exe = &runtime.selection.python_exe;
+        let packages = match subset_lock.or(load_lock(&wd)?) {
+            Some(lock) if subset => {
+                check_locked_interpreter(&lock, python_exe, strict_python)?;
+                info("Planning a subset of xe.lock");
+                installer.locked_subset(&cfg, &lock, &only_packages, &group_reqs, python_exe)?
+            }
+            Some(lock) if lock.fingerprint == lock_fingerprint(&cfg) => {
+                check_locked_interpreter(&lock, python_exe, strict_python)?;
+                info("Planning from xe.lock");
+                if extra_reqs.is_empty() {
+                    installer.locked_plan(&lock, python_exe)?
+                } else {
+                    let reqs = locked_with_extras(&cfg, &lock, &extra_reqs);
+                    installer.resolve(&cfg, &reqs, python_exe)?.packages
+                }
+            }
+            _ => {
+                reqs.extend(extra_reqs);
+                installer.resolve(&cfg, &reqs, python_exe)?.packages
+            }
+        };
+        let changes = installer.plan_changes(&packages, &runtime.selection.site_packages)?;
+        print_planned_changes(&changes, true);
+        return Ok(());
+    }
+    run_hooks(&cfg, "pre-sync", &wd, Some(&runtime.selection), &[])?;
+    if let Some(lock) = &subset_lock {
+        let python_exe = &runtime.selection.python_exe;
+        check_locked_interpreter(lock, python_exe, strict_python)?;
+        let plan = installer.locked_subset(&cfg, lock, &only_packages, &group_reqs, python_exe)?;
+        let installed =
+            installer.install_resolved(ctx, plan, &runtime.selection.site_packages, python_exe)?;
+        if let Some(path) = &report_path {
+            write_install_report(path, &installer, started)?;
+        }
+        success(&format!("Synced {} package(s) of xe.lock", installed.len()));
+        if compile {
+            compile_bytecode(&runtime.selection)?;
+        }
+        if verify {
+            verify_synced_imports(&runtime.selection, &installed)?;
+        }
+        return run_hooks(&cfg, "post-sync", &wd, Some(&runtime.selection), &installed);
+    }
+    if let Some(lock) = load_lock(&wd)? {
+        if lock.fingerprint == lock_fingerprint(&cfg) {
+            check_locked_interpreter(&lock, &runtime.selection.python_exe, strict_python)?;
+            let mut installed = installer.install_locked(
+                ctx,
+                &lock,
+                &runtime.selection.site_packages,
+                &runtime.selection.python_exe,
+            )?;
+            if !extra_reqs.is_empty() {
+                installed.extend(installer.install(
+                    ctx,
+                    &cfg,
+                    &locked_with_extras(&cfg, &lock, &extra_reqs),
+                    &wd,
+                    &runtime.selection.site_packages,
+                    &runtime.selection.python_exe,
+                )?);
+                info(&format!("Installed extras: {}", extras.join(", ")));
+            }
+            if let Some(path) = &report_path {
+                write_install_report(path, &installer, started)?;
+            }
+            success("Project synced from xe.lock");
+            if compile {
+                compile_bytecode(&runtime.selection)?;
+            }
+            if verify {
+                verify_synced_imports(&runtime.selection, &installed)?;
+            }
+            return run_hooks(&cfg, "post-sync", &wd, Some(&runtime.selection), &installed);
+        }
+        warning(
+            "xe.lock is out of date with xe.toml; resolving again (run `xe lock` to refresh it)",
+        );
+        installer.expect_lock(&lock, &runtime.selection.python_exe);
+    }
+    reqs.extend(extra_reqs);
+    let installed = installer.install(
+        ctx,
+        &cfg,
+        &reqs,
+        &wd,
         &runtime.selection.site_packages,
         &runtime.selection.python_exe,
     )?;
+    if let Some(path) = &report_path {
+        write_install_report(path, &installer, started)?;
+    }
     success("Project synced from xe.toml");
+    if compile {
+        compile_bytecode(&runtime.selection)?;
+    }
+    if verify {
+        verify_synced_imports(&runtime.selection, &installed)?;
+    }
+    run_hooks(&cfg, "post-sync", &wd, Some(&runtime.selection), &installed)
+}
+
+/// Precompiles site-packages so the first import in a fresh container or
+/// read-only image does not pay for it.
+fn compile_bytecode(selection: &RuntimeSelection) -> Result<()> {
+    info("Compiling installed packages to bytecode...");
+    let status = Command::new(&selection.python_exe)
+        .args(["-m", "compileall", "-q", "-j", "0"])
+        .arg(&selection.site_packages)
+        .stdout(Stdio::null())
+        .status()
+        .context("failed to run compileall")?;
+    // compileall exits 1 when some file does not compile, which test data
+    // and py2-only modules in packages routinely trigger.
+    if status.code() == Some(1) {
+        warning("Some files could not be compiled to bytecode");
+    } else if !status.success() {
+        bail!("compileall failed: {status}");
+    }
     Ok(())
 }
 
-fn cmd_lock(ctx: &AppContext, _args: &[String]) -> Result<()> {
-    let wd = env::current_dir().context("failed to get cwd")?;
-    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
-    let reqs = cfg
-        .deps
+/// Imports the top-level modules of every synced package, so a wheel built
+/// for another ABI fails the sync rather than the first run.
+fn verify_synced_imports(selection: &RuntimeSelection, installed: &[Package]) -> Result<()> {
+    let synced: HashSet<String> = installed
         .iter()
-        .map(|(name, version)| {
-            if version.is_empty() || version == "*" {
-                name.clone()
-            } else {
-                format!("{name}=={version}")
-            }
-        })
+        .map(|pkg| normalize_dep_name(&pkg.name))
+        .collect();
+    let dists: Vec<InstalledDist> = installed_metadata(&selection.site_packages)?
+        .into_iter()
+        .filter(|dist| synced.contains(&normalize_dep_name(&dist.name)))
+        .collect();
+    let modules: usize = dists.iter().map(|dist| dist.top_level.len()).sum();
+    info(&format!(
+        "Verifying imports of {} module(s) from {} package(s)",
+        modules,
+        dists.len()
+    ));
+    let failures = verify_imports(selection, &dists)?;
+    if failures.is_empty() {
+        success("All packages import cleanly");
+        return Ok(());
+    }
+    let mut broken = BTreeSet::new();
+    for failure in &failures {
+        error(&format!(
+            "{} (import {}): {}",
+            failure.package, failure.module, failure.error
+        ));
+        broken.insert(failure.package.as_str());
+    }
+    bail!(
+        "{} package(s) failed to import: {}",
+        broken.len(),
+        broken.into_iter().collect::<Vec<_>>().join(", ")
+    )
+}
+
+/// Several metadata directories for one project make its installed version
+/// ambiguous to pip, importlib.metadata and xe alike.
+fn check_duplicate_distributions(site_packages: &Path, allow: bool) -> Result<()> {
+    let duplicates = duplicate_distributions(site_packages)?;
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+    for dup in &duplicates {
+        let message = format!("{} is installed more than once: {}", dup.name, dup.dirs.join(", "));
+        if allow {
+            warning(&message);
+        } else {
+            error(&message);
+        }
+    }
+    if !allow {
+        bail!(
+            "{} package(s) have duplicate metadata in {}; remove the stale copies or pass --allow-collisions",
+            duplicates.len(),
+            site_packages.display()
+        );
+    }
+    Ok(())
+}
+
+/// Extras are not part of xe.lock; they are resolved next to the locked
+/// versions, pinned, so installing them cannot move a locked package.
+fn locked_with_extras(cfg: &Config, lock: &LockFile, extra_reqs: &[String]) -> Vec<String> {
+    let mut reqs = lock
+        .packages
+        .iter()
+        .filter(|p| !p.editable && !cfg.direct.contains_key(&normalize_dep_name(&p.name)))
+        .map(|p| format!("{}=={}", p.name, p.version))
         .collect::<Vec<_>>();
-    let installer = Installer::new(Path::new(&cfg.cache.global_dir))?;
+    reqs.extend(extra_reqs.iter().cloned());
+    reqs
+}
+
+/// Compares the interpreter recorded in xe.lock with the one being synced;
+/// markers and wheel tags were evaluated for the former.
+fn check_locked_interpreter(lock: &LockFile, python_exe: &Path, strict: bool) -> Result<()> {
+    let Some(locked) = &lock.interpreter else {
+        return Ok(());
+    };
+    let mismatches = locked.mismatches(&TargetEnv::probe(python_exe)?);
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    let details = mismatches.join(", ");
+    if strict {
+        bail!("xe.lock was made for a different interpreter: {details}; run `xe lock` with this interpreter");
+    }
+    warning(&format!(
+        "xe.lock was made for a different interpreter: {details}"
+    ));
+    info("Markers may resolve differently here; run `xe lock` to refresh, or pass --strict-python to fail");
+    Ok(())
+}
+
+fn write_install_report(path: &Path, installer: &Installer, started: Instant) -> Result<()> {
+    let mut report = installer.take_report();
+    report.packages.sort_by_key(|p| p.name.to_lowercase());
+    let count = |action: &str| {
+        report
+            .packages
+            .iter()
+            .filter(|p| p.action == action)
+            .count()
+    };
+    let cache_hits = report.packages.iter().filter(|p| p.cache == "hit").count();
+    let cache_misses = report.packages.iter().filter(|p| p.cache == "miss").count();
+    let payload = json!({
+        "version": 1,
+        "generated_at": OffsetDateTime::now_utc().format(&Iso8601::DEFAULT).unwrap_or_default(),
+        "total_ms": started.elapsed().as_millis(),
+        "summary": {
+            "installed": count("installed"),
+            "skipped": count("skipped"),
+            "editable": count("editable"),
+            "cache_hits": cache_hits,
+            "cache_misses": cache_misses,
+        },
+        "resolution": {
+            "source": report.resolution_source,
+            "duration_ms": report.resolution_ms,
+            "requirements": report.requirements,
+        },
+        "packages": report.packages,
+    });
+    let encoded =
+        serde_json::to_string_pretty(&payload).context("failed to encode install report")?;
+    fs::write(path, encoded).with_context(|| format!("failed to write {}", path.display()))?;
+    info(&format!("Install report written to {}", path.display()));
+    Ok(())
+}
+
+fn cmd_lock(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let mut limits = ResolveLimits::default();
+    let mut target_platform: Option<String> = None;
+    let mut target_python: Option<String> = None;
+    let mut check = false;
+    let usage = "usage: xe lock [--check] [--python-platform <platform>] [--python-version <x.y>] [--resolution-timeout <secs>] [--max-backtracks <n>]";
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--check" => check = true,
+            "--python-platform" => {
+                idx += 1;
+                let Some(platform) = args.get(idx) else {
+                    bail!(usage);
+                };
+                target_platform = Some(platform.clone());
+            }
+            "--python-version" => {
+                idx += 1;
+                let Some(version) = args.get(idx) else {
+                    bail!(usage);
+                };
+                target_python = Some(version.clone());
+            }
+            flag @ ("--resolution-timeout" | "--max-backtracks") => {
+                idx += 1;
+                set_resolve_limit(&mut limits, flag, args.get(idx))?;
+            }
+            other => bail!("unknown flag for xe lock: {other}"),
+        }
+        idx += 1;
+    }
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let reqs = cfg.requirements(&wd);
+    let mut installer = Installer::new(&cfg, &wd)?;
+    installer.limits = limits;
     let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
-    if runtime.config_changed {
+    if runtime.config_changed && !check {
         save_project(&toml_path, &cfg)?;
     }
-    let resolved = installer.install(
-        ctx,
-        &cfg,
-        &reqs,
-        &wd,
-        &runtime.selection.site_packages,
-        &runtime.selection.python_exe,
-    )?;
-    for p in resolved {
-        cfg.deps.insert(normalize_dep_name(&p.name), p.version);
+    let python_exe = &runtime.selection.python_exe;
+    let mut target = TargetEnv::probe(python_exe)?;
+    let cross = target_platform.is_some() || target_python.is_some();
+    if let Some(platform) = &target_platform {
+        target = target.with_platform(platform)?;
+    }
+    if let Some(version) = &target_python {
+        target = target.with_python_version(version)?;
+    }
+    let resolved = if cross || check {
+        // A check writes nothing, and the host cannot install another
+        // target's wheels, so both only resolve.
+        if cross {
+            info(&format!(
+                "Locking for Python {}.{} on {}",
+                target.major,
+                target.minor,
+                target.platform_name()
+            ));
+            installer.target = Some(target.clone());
+        }
+        installer.resolve(&cfg, &reqs, python_exe)?.packages
+    } else {
+        if let Some(lock) = load_lock(&wd)? {
+            installer.expect_lock(&lock, python_exe);
+        }
+        installer.install(
+            ctx,
+            &cfg,
+            &reqs,
+            &wd,
+            &runtime.selection.site_packages,
+            python_exe,
+        )?
+    };
+    for p in &resolved {
+        let name = normalize_dep_name(&p.name);
+        // Another platform's dependencies stay out of [deps]; they may not
+        // even exist for the host.
+        if !cross || cfg.deps.contains_key(&name) {
+            cfg.set_dep(&name, &p.version);
+        }
+    }
+    if check {
+        return check_lock(&wd, &cfg, &resolved);
     }
     save_project(&toml_path, &cfg)?;
-    success("Locked dependencies");
+
+    let lock = LockFile::new(&cfg, &resolved, &target)?;
+    save_lock(&wd, &lock)?;
+    success(&format!(
+        "Locked {} package(s) in {}",
+        lock.packages.len(),
+        XE_LOCK
+    ));
     Ok(())
 }
 
+/// `xe lock --check`: compares a fresh resolution with the committed lock
+/// and fails, listing what differs, when `xe lock` would change it.
+fn check_lock(wd: &Path, cfg: &Config, resolved: &[Package]) -> Result<()> {
+    let Some(lock) = load_lock(wd)? else {
+        bail!("no {XE_LOCK} in {}; run `xe lock`", wd.display());
+    };
+    let versions = |packages: &[Package]| {
+        packages
+            .iter()
+            .map(|p| (normalize_dep_name(&p.name), p.version.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let locked = versions(&lock.packages);
+    let fresh = versions(resolved);
+    let mut differences = Vec::new();
+    for (name, version) in &fresh {
+        match locked.get(name) {
+            None => differences.push(format!("{} {name} {version}", paint(Style::Success, "+"))),
+            Some(old) if old != version => differences.push(format!(
+                "{} {name} {old} -> {version}",
+                paint(Style::Info, "~")
+            )),
+            Some(_) => {}
+        }
+    }
+    for (name, version) in &locked {
+        if !fresh.contains_key(name) {
+            differences.push(format!("{} {name} {version}", paint(Style::Error, "-")));
+        }
+    }
+    let stale_inputs = lock.fingerprint != lock_fingerprint(cfg);
+    if differences.is_empty() && !stale_inputs {
+        success(&format!(
+            "{XE_LOCK} is up to date ({} package(s))",
+            lock.packages.len()
+        ));
+        return Ok(());
+    }
+    for line in &differences {
+        println!("  {line}");
+    }
+    if stale_inputs {
+        println!("  {XE_TOML} changed since {XE_LOCK} was written");
+    }
+    bail!("{XE_LOCK} is out of date; run `xe lock` and commit the result");
+}
+
+/// Formats through the backend named by `[settings] formatter`. `--check`
+/// and `--diff` mean the same to black and `ruff format`, so they are passed
+/// through as is.
 fn cmd_format(ctx: &AppContext, args: &[String]) -> Result<()> {
-    let target = if args.is_empty() { "." } else { &args[0] };
-    let run_args = vec![
-        "--".to_string(),
-        "python".to_string(),
-        "-m".to_string(),
-        "black".to_string(),
-        target.to_string(),
-    ];
+    let mut flags = Vec::new();
+    let mut targets = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" | "--diff" => flags.push(arg.clone()),
+            flag if flag.starts_with('-') => bail!("unknown flag for xe format: {flag}"),
+            target => targets.push(target.to_string()),
+        }
+    }
+    if targets.is_empty() {
+        targets.push(".".to_string());
+    }
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let formatter = load_project(&wd.join(XE_TOML))
+        .map(|cfg| cfg.settings.formatter)
+        .unwrap_or_default();
+    let module: &[&str] = match formatter.as_str() {
+        "" | "black" => &["black"],
+        "ruff" => &["ruff", "format"],
+        other => bail!("unknown formatter '{other}' in [settings]; expected black or ruff"),
+    };
+    let mut run_args = vec!["--".to_string(), "python".to_string(), "-m".to_string()];
+    run_args.extend(module.iter().map(|part| part.to_string()));
+    run_args.extend(flags);
+    run_args.extend(targets);
     cmd_run(ctx, &run_args)
 }
 
 fn cmd_cache(ctx: &AppContext, args: &[String]) -> Result<()> {
     if args.is_empty() {
-        bail!("usage: xe cache <dir|clean|prune>");
+        bail!("usage: xe cache <dir|clean|prune|warm|invalidate-solutions>");
     }
     match args[0].as_str() {
         "dir" => {
+            let docker = match &args[1..] {
+                [] => false,
+                [flag] if flag == "--docker" => true,
+                _ => bail!("usage: xe cache dir [--docker]"),
+            };
             let wd = env::current_dir().context("failed to get cwd")?;
             let (cfg, _) = load_or_create_project(&wd)?;
-            println!("{}", cfg.cache.global_dir);
+            let cache_dir = cfg.cache.dir(&wd)?;
+            if docker {
+                print_docker_cache_mounts(&cfg, &cache_dir);
+            } else {
+                println!("{}", cache_dir.display());
+            }
             Ok(())
         }
         "clean" => {
             let wd = env::current_dir().context("failed to get cwd")?;
             let (cfg, _) = load_or_create_project(&wd)?;
-            if Path::new(&cfg.cache.global_dir).exists() {
-                fs::remove_dir_all(&cfg.cache.global_dir)
-                    .with_context(|| format!("failed to clean {}", cfg.cache.global_dir))?;
+            let cache_dir = cfg.cache.dir(&wd)?;
+            if cache_dir.exists() {
+                paths::remove_dir_all(&cache_dir)
+                    .with_context(|| format!("failed to clean {}", cache_dir.display()))?;
             }
             success("Cache cleaned");
             Ok(())
         }
         "prune" => {
             let _ = ctx;
-            info("Prune currently keeps CAS blobs and removes no files.");
+            let wd = env::current_dir().context("failed to get cwd")?;
+            let (cfg, _) = load_or_create_project(&wd)?;
+            let dirs = [env::temp_dir(), cfg.cache.dir(&wd)?];
+            let (removed, bytes) = sweep_stale_temp(&dirs, STALE_TEMP_AGE);
+            success(&format!(
+                "Removed {removed} stale temp file(s) of interrupted runs ({})",
+                format_bytes(bytes)
+            ));
+            info("CAS blobs are kept; `xe cache clean` removes them.");
+            Ok(())
+        }
+        "warm" => cmd_cache_warm(ctx, &args[1..]),
+        "invalidate-solutions" => {
+            let wd = env::current_dir().context("failed to get cwd")?;
+            let (cfg, _) = load_or_create_project(&wd)?;
+            let removed = Cas::new(&cfg.cache.dir(&wd)?)?.clear_solutions()?;
+            success(&format!("Removed {removed} cached solution(s)"));
             Ok(())
         }
-        _ => bail!("usage: xe cache <dir|clean|prune>"),
+        _ => bail!("usage: xe cache <dir|clean|prune|warm|invalidate-solutions>"),
+    }
+}
+
+/// Fills the cache ahead of going offline with the packages this machine
+/// installs most, counted from `--profile` traces, or with those named in
+/// the given install reports, traces or requirements files.
+/// Where the cache goes inside images unless `XE_CACHE_DIR` says
+/// otherwise; the same on every build, so BuildKit cache mounts hit.
+const DOCKER_CACHE_DIR: &str = "/var/cache/xe";
+
+/// The cache path and BuildKit cache mounts that keep it across image
+/// builds. The mount id does not depend on the project, so every image
+/// built on the machine shares one cache, as it does outside containers.
+fn print_docker_cache_mounts(cfg: &Config, cache_dir: &Path) {
+    let target = env::var("XE_CACHE_DIR")
+        .ok()
+        .filter(|dir| dir.starts_with('/'))
+        .unwrap_or_else(|| DOCKER_CACHE_DIR.to_string());
+    println!("{}", cache_dir.display());
+    println!();
+    if cfg.cache.mode.trim() == "project" {
+        warning(
+            "cache.mode = \"project\" keeps the cache in the build context; the mounts below need the default global-cas mode",
+        );
+    }
+    println!("# syntax=docker/dockerfile:1");
+    println!("# Dockerfile (BuildKit); the cache lives at {target} in the image");
+    println!("ENV XE_CACHE_DIR={target}");
+    println!("RUN --mount=type=cache,id=xe-cache,target={target},sharing=locked \\");
+    println!("    xe sync");
+    println!();
+    println!("# Building as a non-root user: give the mount that user's ids");
+    println!(
+        "RUN --mount=type=cache,id=xe-cache,target={target},sharing=locked,uid=1000,gid=1000 \\"
+    );
+    println!("    xe sync");
+    println!();
+    println!("# docker run, reusing a host directory as the cache");
+    println!(
+        "docker run -e XE_CACHE_DIR={target} -v {}:{target} <image> xe sync",
+        cache_dir.display()
+    );
+}
+
+fn cmd_cache_warm(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage =
+        "usage: xe cache warm [--top <n>] [--from <report.json|trace.jsonl|requirements.txt>]... [--dry-run]";
+    let mut top = 50usize;
+    let mut sources = Vec::new();
+    let mut dry_run = false;
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--top" => {
+                top = args
+                    .get(idx + 1)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| anyhow!(usage))?;
+                idx += 2;
+            }
+            "--from" => {
+                let value = args.get(idx + 1).ok_or_else(|| anyhow!(usage))?;
+                sources.push(PathBuf::from(value));
+                idx += 2;
+            }
+            "--dry-run" => {
+                dry_run = true;
+                idx += 1;
+            }
+            _ => bail!(usage),
+        }
+    }
+    if sources.is_empty() {
+        sources = fs::read_dir(xe_home().join("profiles"))
+            .ok()
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                name.starts_with("trace-") && name.ends_with(".jsonl")
+            })
+            .collect();
+    }
+
+    // Per package: how often it was seen, and how often each requirement.
+    let mut usage_counts: HashMap<String, (usize, HashMap<String, usize>)> = HashMap::new();
+    for source in &sources {
+        for requirement in warm_requirements(source)? {
+            let Some(name) = requirement_to_dep_name(&requirement) else {
+                continue;
+            };
+            let (count, variants) = usage_counts.entry(name).or_default();
+            *count += 1;
+            *variants.entry(requirement).or_default() += 1;
+        }
+    }
+    if usage_counts.is_empty() {
+        bail!(
+            "no install history found; run installs with --profile or pass --from <report|trace|requirements>"
+        );
+    }
+    let mut ranked = usage_counts
+        .into_iter()
+        .map(|(name, (count, variants))| {
+            let requirement = variants
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+                .map(|(requirement, _)| requirement)
+                .unwrap_or(name.clone());
+            (count, name, requirement)
+        })
+        .collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    ranked.truncate(top);
+
+    println!("{}", paint(Style::Header, "  Seen  Requirement"));
+    for (count, _, requirement) in &ranked {
+        println!("  {count:>4}  {requirement}");
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
+    }
+    let python_exe = runtime.selection.python_exe;
+    let installer = Installer::new(&cfg, &wd)?;
+    info(&format!(
+        "Resolving {} requirement(s) with Python {}...",
+        ranked.len(),
+        cfg.python.version
+    ));
+    // One at a time: packages from different projects need not resolve
+    // together, and one failure should not stop the rest.
+    let mut seen = HashSet::new();
+    let mut packages = Vec::new();
+    for (_, _, requirement) in &ranked {
+        match installer.resolve(&cfg, std::slice::from_ref(requirement), &python_exe) {
+            Ok(graph) => {
+                packages.extend(graph.packages.into_iter().filter(|pkg| {
+                    seen.insert((normalize_dep_name(&pkg.name), pkg.version.clone()))
+                }))
+            }
+            Err(err) => warning(&format!("Skipping {requirement}: {err:#}")),
+        }
+    }
+    installer.prefetch(&packages, &python_exe)?;
+    let report = installer.take_report();
+    let downloaded = report
+        .packages
+        .iter()
+        .filter(|pkg| pkg.cache == "miss")
+        .count();
+    success(&format!(
+        "Cache warmed: {} package(s), {} downloaded, {} already cached",
+        report.packages.len(),
+        downloaded,
+        report.packages.len() - downloaded
+    ));
+    Ok(())
+}
+
+/// Requirements named by an install report (`xe sync --report`), a profile
+/// trace, or a requirements file, one per package occurrence.
+fn warm_requirements(path: &Path) -> Result<Vec<String>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let pinned = |pkg: &serde_json::Value| {
+        let name = pkg["name"].as_str().unwrap_or_default();
+        match pkg["version"].as_str().unwrap_or_default() {
+            "" => name.to_string(),
+            version => format!("{name}=={version}"),
+        }
+    };
+    if let Ok(report) = serde_json::from_str::<serde_json::Value>(&text) {
+        if let Some(packages) = report["packages"].as_array() {
+            return Ok(packages.iter().map(pinned).collect());
+        }
+    }
+    if path.extension().is_some_and(|ext| ext == "jsonl") {
+        return Ok(text
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter(|event| event["event"] == "install.package")
+            .map(|event| pinned(&event))
+            .collect());
     }
+    Ok(parse_requirements(path)?.requirements)
 }
 
 fn cmd_python(ctx: &AppContext, args: &[String]) -> Result<()> {
     if args.is_empty() {
-        bail!("usage: xe python <install|list|find|pin|dir> ...");
+        bail!("usage: xe python <install|list|find|pin|upgrade|dir|exec|shim> ...");
     }
-    let pm = PythonManager::new()?;
+    let mut pm = PythonManager::new()?;
     match args[0].as_str() {
         "install" => {
-            if args.len() != 2 {
-                bail!("usage: xe python install <version>");
+            let usage = "usage: xe python install <version> [--arch <amd64|arm64|win32>]";
+            let mut version = String::new();
+            let mut idx = 1usize;
+            while idx < args.len() {
+                match args[idx].as_str() {
+                    "--arch" => {
+                        let value = args
+                            .get(idx + 1)
+                            .ok_or_else(|| anyhow!("--arch requires a value"))?;
+                        pm.arch = Some(PythonArch::parse(value)?);
+                        idx += 2;
+                    }
+                    value if !value.starts_with('-') && version.is_empty() => {
+                        version = value.to_string();
+                        idx += 1;
+                    }
+                    _ => bail!(usage),
+                }
+            }
+            if version.is_empty() {
+                bail!(usage);
             }
-            pm.install(&args[1], ctx)?;
-            success(&format!("Installed Python {}", args[1]));
+            pm.install(&version, ctx)?;
+            success(&format!("Installed Python {}", version));
             Ok(())
         }
         "list" => {
@@ -973,66 +3106,412 @@ fn cmd_python(ctx: &AppContext, args: &[String]) -> Result<()> {
             Ok(())
         }
         "pin" => cmd_use(ctx, &args[1..]),
+        "upgrade" => {
+            let mut repair_venvs = false;
+            let mut versions = Vec::new();
+            for arg in &args[1..] {
+                match arg.as_str() {
+                    "--repair-venvs" => repair_venvs = true,
+                    value if !value.starts_with('-') => versions.push(value.to_string()),
+                    _ => bail!("usage: xe python upgrade [<minor>...] [--repair-venvs]"),
+                }
+            }
+            if versions.is_empty() {
+                versions = pm.installed_versions()?;
+            }
+            if versions.is_empty() {
+                info("No managed Python runtimes found");
+                return Ok(());
+            }
+            for version in versions {
+                let Some(upgrade) = pm.upgrade(&version, ctx)? else {
+                    success(&format!(
+                        "Python {} is already on the latest release",
+                        version
+                    ));
+                    continue;
+                };
+                success(&format!(
+                    "Upgraded Python {} from {} to {}",
+                    version, upgrade.old, upgrade.new
+                ));
+                let target_dir = pm.get_python_path(&version)?;
+                let vm = VenvManager::new()?;
+                let stale = vm.built_from(&target_dir, &upgrade.old_python)?;
+                if stale.is_empty() {
+                    continue;
+                }
+                if !repair_venvs {
+                    warning(&format!(
+                        "{} venv(s) were built from Python {}: {} (rerun with --repair-venvs to update them)",
+                        stale.len(),
+                        upgrade.old_python,
+                        stale.join(", ")
+                    ));
+                    continue;
+                }
+                let exe = pm.get_python_exe(&version)?;
+                for name in stale {
+                    vm.upgrade_base(&name, &exe)?;
+                    success(&format!("Repaired venv {}", name));
+                }
+            }
+            Ok(())
+        }
         "dir" => {
             println!("{}", pm.base_dir.display());
             Ok(())
         }
-        _ => bail!("usage: xe python <install|list|find|pin|dir> ..."),
+        "exec" => cmd_python_exec(ctx, &args[1..]),
+        "shim" => {
+            if args.len() > 1 {
+                bail!("usage: xe python shim");
+            }
+            let wd = env::current_dir().context("failed to get cwd")?;
+            let dir = write_project_shims(&wd)?;
+            success(&format!(
+                "Wrote python and python3 shims to {}",
+                dir.display()
+            ));
+            info("Put it first on PATH to run the project interpreter as `python`");
+            Ok(())
+        }
+        _ => bail!("usage: xe python <install|list|find|pin|upgrade|dir|exec|shim> ..."),
     }
 }
 
-fn cmd_pip(ctx: &AppContext, args: &[String]) -> Result<()> {
-    if args.is_empty() {
-        bail!("usage: xe pip <install|uninstall|list|show|tree|check|sync|compile>");
+/// Runs the project interpreter with `args`. The project is the current
+/// directory, or `XE_PROJECT_DIR` when set, which is how the `.xe/bin`
+/// shims find theirs from anywhere.
+fn cmd_python_exec(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let wd = match env::var_os("XE_PROJECT_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::current_dir().context("failed to get cwd")?,
+    };
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
     }
-    match args[0].as_str() {
-        "install" => cmd_add(ctx, &args[1..]),
-        "uninstall" => cmd_remove(ctx, &args[1..]),
-        "list" => cmd_list(ctx, &args[1..]),
-        "show" => cmd_check(&args[1..]),
-        "tree" => cmd_tree(&args[1..]),
-        "check" => cmd_doctor(&args[1..]),
-        "sync" => cmd_sync(ctx, &args[1..]),
-        "compile" => cmd_lock(ctx, &args[1..]),
-        _ => bail!("usage: xe pip <install|uninstall|list|show|tree|check|sync|compile>"),
+    let mut command_args = vec!["python".to_string()];
+    command_args.extend(args.iter().cloned());
+    let status = run_in_runtime(&runtime.selection, &command_args, None)?;
+    if let Some(code) = status.code() {
+        if code != 0 {
+            std::process::exit(code);
+        }
     }
+    Ok(())
 }
 
-fn cmd_tool(ctx: &AppContext, args: &[String]) -> Result<()> {
-    if args.is_empty() {
-        bail!("usage: xe tool <run|install|list|update|uninstall|upgrade|sync|dir> ...");
-    }
-    match args[0].as_str() {
-        "run" => cmd_run(ctx, &args[1..]),
-        "install" => cmd_add(ctx, &args[1..]),
-        "list" => {
-            let wd = env::current_dir().context("failed to get cwd")?;
+/// Writes `python` and `python3` into `<project>/.xe/bin`. They call back
+/// into `xe python exec` rather than the interpreter itself, so they keep
+/// following `xe use` and venv changes.
+fn write_project_shims(project_dir: &Path) -> Result<PathBuf> {
+    let xe = env::current_exe().context("failed to locate the xe executable")?;
+    let dir = project_dir.join(".xe").join("bin");
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    for name in ["python", "python3"] {
+        if cfg!(windows) {
+            let path = dir.join(format!("{name}.cmd"));
+            let content = format!(
+                "@echo off\r\nsetlocal\r\nset \"XE_PROJECT_DIR={}\"\r\n\"{}\" python exec %*\r\n",
+                project_dir.display(),
+                xe.display()
+            );
+            fs::write(&path, content)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            continue;
+        }
+        let path = dir.join(name);
+        let content = format!(
+            "#!/bin/sh\nXE_PROJECT_DIR=\"{}\" exec \"{}\" python exec \"$@\"\n",
+            project_dir.display(),
+            xe.display()
+        );
+        fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+                .with_context(|| format!("failed to make {} executable", path.display()))?;
+        }
+    }
+    Ok(dir)
+}
+
+fn cmd_pip(ctx: &AppContext, args: &[String]) -> Result<()> {
+    if args.is_empty() {
+        bail!("usage: xe pip <install|uninstall|list|show|tree|check|sync|compile|raw>");
+    }
+    match args[0].as_str() {
+        "install" => undo::recorded("pip install", &args[1..], || cmd_add(ctx, &args[1..])),
+        "uninstall" => undo::recorded("pip uninstall", &args[1..], || cmd_remove(ctx, &args[1..])),
+        "list" => cmd_list(ctx, &args[1..]),
+        "show" => cmd_check(&args[1..]),
+        "tree" => graph::cmd_tree(ctx, &args[1..]),
+        "check" => cmd_doctor(ctx, &args[1..]),
+        "sync" => undo::recorded("pip sync", &args[1..], || cmd_sync(ctx, &args[1..])),
+        "compile" => cmd_lock(ctx, &args[1..]),
+        "raw" => cmd_pip_raw(ctx, &args[1..]),
+        _ => bail!("usage: xe pip <install|uninstall|list|show|tree|check|sync|compile|raw>"),
+    }
+}
+
+/// Runs pip unchanged in the project runtime, then folds what it installed,
+/// upgraded or removed back into xe.toml and xe.lock so the manifest does
+/// not drift from the environment.
+fn cmd_pip_raw(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let args = match args.first() {
+        Some(first) if first == "--" => &args[1..],
+        _ => args,
+    };
+    if args.is_empty() {
+        bail!("usage: xe pip raw -- <pip arguments>...");
+    }
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    let site_packages = &runtime.selection.site_packages;
+
+    let before = installed_distributions(site_packages)?;
+    let mut command = Command::new(&runtime.selection.python_exe);
+    command.arg("-m").arg("pip").args(args);
+    apply_runtime_env(&mut command, &runtime.selection)?;
+    let status = command.status().context("failed to run pip")?;
+    let after = installed_distributions(site_packages)?;
+
+    // Packages something else still requires came in as dependencies; only
+    // the rest were asked for and become project dependencies.
+    let required: HashSet<String> = installed_metadata(site_packages)?
+        .iter()
+        .flat_map(|dist| dist.requires.iter())
+        .filter(|req| !req.contains("extra =="))
+        .filter_map(|req| requirement_to_dep_name(req.split(';').next().unwrap_or_default()))
+        .collect();
+    let mut changed = false;
+    for (key, (name, version)) in &after {
+        let dep = normalize_dep_name(name);
+        if cfg.settings.is_ignored(&dep) {
+            continue;
+        }
+        match before.get(key) {
+            Some((_, old)) if old == version => {}
+            Some((_, old)) => {
+                // An upgrade by hand is a deliberate pin, even of a
+                // transitive package.
+                info(&format!("{name}: {old} -> {version}"));
+                cfg.set_dep(&dep, version);
+                changed = true;
+            }
+            None if required.contains(&dep) && !cfg.deps.contains_key(&dep) => {
+                info(&format!("{name} {version} (dependency)"));
+            }
+            None => {
+                info(&format!("{name} {version} (added to xe.toml)"));
+                cfg.set_dep(&dep, version);
+                changed = true;
+            }
+        }
+    }
+    for (key, (name, version)) in &before {
+        let dep = normalize_dep_name(name);
+        if after.contains_key(key) || cfg.settings.is_ignored(&dep) {
+            continue;
+        }
+        info(&format!("{name} {version} removed"));
+        let declared = cfg.deps.remove(&dep).is_some()
+            | cfg.editable.remove(&dep).is_some()
+            | cfg.direct.remove(&dep).is_some();
+        changed |= declared;
+    }
+
+    if changed {
+        save_project(&toml_path, &cfg)?;
+        success(&format!("Updated {XE_TOML} with the changes pip made"));
+        if wd.join(XE_LOCK).exists() {
+            info(&format!("Refreshing {XE_LOCK}"));
+            cmd_lock(ctx, &[])?;
+        }
+    } else if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
+    }
+    if !status.success() {
+        bail!("pip exited with {}", status);
+    }
+    Ok(())
+}
+
+fn cmd_tool(ctx: &AppContext, args: &[String]) -> Result<()> {
+    if args.is_empty() {
+        bail!("usage: xe tool <run|install|list|update|uninstall|upgrade|sync|dir|expose> ...");
+    }
+    match args[0].as_str() {
+        "run" => cmd_run(ctx, &args[1..]),
+        "install" => undo::recorded("tool install", &args[1..], || cmd_add(ctx, &args[1..])),
+        "list" if args[1..].iter().any(|a| a == "--shims") => list_tool_shims(),
+        "list" => {
+            let wd = env::current_dir().context("failed to get cwd")?;
             let (cfg, _) = load_or_create_project(&wd)?;
             let mut keys = cfg.deps.keys().cloned().collect::<Vec<_>>();
             keys.sort();
             for key in keys {
-                if let Some(v) = cfg.deps.get(&key) {
-                    println!("{key} {v}");
+                if let Some(spec) = cfg.deps.get(&key) {
+                    println!("{key} {}", spec.version());
                 }
             }
             Ok(())
         }
-        "update" => cmd_add(ctx, &args[1..]),
-        "uninstall" => cmd_remove(ctx, &args[1..]),
-        "upgrade" => cmd_sync(ctx, &args[1..]),
-        "sync" => cmd_sync(ctx, &args[1..]),
+        "update" => undo::recorded("tool update", &args[1..], || cmd_add(ctx, &args[1..])),
+        "uninstall" => undo::recorded("tool uninstall", &args[1..], || cmd_remove(ctx, &args[1..])),
+        "upgrade" => undo::recorded("tool upgrade", &args[1..], || cmd_sync(ctx, &args[1..])),
+        "sync" => undo::recorded("tool sync", &args[1..], || cmd_sync(ctx, &args[1..])),
         "dir" => {
+            if args[1..].iter().any(|a| a == "--bin") {
+                println!("{}", xe_shim_dir().display());
+            } else {
+                println!("{}", VenvManager::new()?.base_dir.display());
+            }
+            Ok(())
+        }
+        "expose" => {
+            if args.len() < 2 {
+                bail!("usage: xe tool expose <script>...");
+            }
             let wd = env::current_dir().context("failed to get cwd")?;
             let (mut cfg, toml_path) = load_or_create_project(&wd)?;
             let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
             if runtime.config_changed {
                 save_project(&toml_path, &cfg)?;
             }
-            println!("{}", runtime.selection.site_packages.display());
+            let python_exe = &runtime.selection.python_exe;
+            install_console_scripts(&runtime.selection.site_packages, python_exe)?;
+            for name in &args[1..] {
+                let script = find_runtime_script(python_exe, name).ok_or_else(|| {
+                    anyhow!(
+                        "no console script named {} in {}",
+                        name,
+                        scripts_dir_for(python_exe).display()
+                    )
+                })?;
+                let vm = VenvManager::new()?;
+                if let Some(previous) = shim_path(name)
+                    .as_deref()
+                    .and_then(read_shim_target)
+                    .filter(|previous| *previous != script)
+                {
+                    warning(&format!(
+                        "{} was exposed from {}; it now runs the one from {}",
+                        name,
+                        shim_owner(&previous, &vm),
+                        shim_owner(&script, &vm)
+                    ));
+                }
+                create_shim(name, &script)?;
+                success(&format!("Exposed {} via {}", name, xe_shim_dir().display()));
+            }
             Ok(())
         }
-        _ => bail!("usage: xe tool <run|install|list|update|uninstall|upgrade|sync|dir> ..."),
+        _ => {
+            bail!("usage: xe tool <run|install|list|update|uninstall|upgrade|sync|dir|expose> ...")
+        }
+    }
+}
+
+/// The shim file for `name`, if one exists.
+fn shim_path(name: &str) -> Option<PathBuf> {
+    let file = if cfg!(windows) {
+        format!("{name}.bat")
+    } else {
+        name.to_string()
+    };
+    Some(xe_shim_dir().join(file)).filter(|path| path.is_file())
+}
+
+/// The environment a shim target belongs to: a venv name, or the
+/// interpreter directory for base runtimes.
+fn shim_owner(target: &Path, vm: &VenvManager) -> String {
+    if let Ok(relative) = target.strip_prefix(&vm.base_dir) {
+        if let Some(venv) = relative.components().next() {
+            return format!("venv {}", venv.as_os_str().to_string_lossy());
+        }
+    }
+    let scripts = target.parent().unwrap_or(target);
+    format!("runtime {}", scripts.parent().unwrap_or(scripts).display())
+}
+
+/// Prints every shim in the shim dir with the environment it runs from,
+/// flagging dangling shims and script names that several venvs provide.
+fn list_tool_shims() -> Result<()> {
+    let shim_dir = xe_shim_dir();
+    let mut shims = Vec::new();
+    if shim_dir.exists() {
+        for entry in fs::read_dir(&shim_dir)
+            .with_context(|| format!("failed to read {}", shim_dir.display()))?
+        {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            shims.push((shim_name(&path), read_shim_target(&path)));
+        }
+    }
+    if shims.is_empty() {
+        info(&format!("No shims in {}", shim_dir.display()));
+        return Ok(());
+    }
+    shims.sort();
+    let vm = VenvManager::new()?;
+    let venvs = vm.list().unwrap_or_default();
+    let mut conflicts = 0usize;
+    for (name, target) in &shims {
+        let Some(target) = target else {
+            println!(
+                "{} {}",
+                paint(Style::Accent, name),
+                paint(Style::Warning, "(not an xe shim)")
+            );
+            continue;
+        };
+        let owner = shim_owner(target, &vm);
+        let mut line = format!(
+            "{} -> {} {}",
+            paint(Style::Accent, name),
+            target.display(),
+            paint(Style::Muted, &format!("[{owner}]"))
+        );
+        if !target.exists() {
+            line.push_str(&paint(
+                Style::Warning,
+                " missing target; `xe setup` removes it",
+            ));
+        }
+        // Every venv has its own python, so only console scripts can clash.
+        let interpreter = target
+            .file_name()
+            .and_then(|s| s.to_str())
+            .is_some_and(|file| file.starts_with("python"));
+        let others = venvs
+            .iter()
+            .filter(|venv| !interpreter && owner != format!("venv {venv}"))
+            .filter(|venv| find_runtime_script(&vm.get_python_exe(venv), name).is_some())
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !others.is_empty() {
+            conflicts += 1;
+            line.push_str(&paint(
+                Style::Warning,
+                &format!(" also provided by venv {}", others.join(", ")),
+            ));
+        }
+        println!("{line}");
     }
+    if conflicts > 0 {
+        warning(&format!(
+            "{conflicts} shim name(s) are provided by more than one environment; each runs the one shown until `xe tool expose` in another project switches it"
+        ));
+    }
+    Ok(())
 }
 
 fn cmd_x_alias(ctx: &AppContext, args: &[String]) -> Result<()> {
@@ -1044,14 +3523,35 @@ fn cmd_x_alias(ctx: &AppContext, args: &[String]) -> Result<()> {
     cmd_run(ctx, &filtered)
 }
 
-fn cmd_build(_args: &[String]) -> Result<()> {
-    println!("Building wheel...");
-    println!("Successfully built xe_project-1.0.0-py3-none-any.whl");
-    Ok(())
-}
+/// Uploads the named wheels and sdists, by default everything in `dist/`.
+fn cmd_push(_ctx: &AppContext, args: &[String], test_pypi: bool) -> Result<()> {
+    if let Some(flag) = args.iter().find(|a| a.starts_with('-')) {
+        bail!("unknown option {flag}; usage: xe publish [<file>...]");
+    }
+    let dists = if args.is_empty() {
+        let dist_dir = env::current_dir()
+            .context("failed to get cwd")?
+            .join("dist");
+        publish::dist_files(&dist_dir, None)?
+    } else {
+        args.iter()
+            .map(|arg| {
+                publish::Distribution::new(PathBuf::from(arg))
+                    .filter(|dist| dist.path.is_file())
+                    .ok_or_else(|| anyhow!("{arg} is not a wheel or .tar.gz sdist"))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+    if dists.is_empty() {
+        bail!("no wheels or sdists in dist/; run `xe build` first");
+    }
 
-fn cmd_push(_ctx: &AppContext, _args: &[String], test_pypi: bool) -> Result<()> {
-    let mut token = load_token().unwrap_or_default();
+    let registry = if test_pypi {
+        "testpypi"
+    } else {
+        DEFAULT_REGISTRY
+    };
+    let mut token = load_token(registry).unwrap_or_default();
     if token.trim().is_empty() {
         if test_pypi {
             println!("No TestPyPI token found in secure storage.");
@@ -1065,43 +3565,76 @@ fn cmd_push(_ctx: &AppContext, _args: &[String], test_pypi: bool) -> Result<()>
         if token.is_empty() {
             bail!("Push requires an authentication token.");
         }
-        save_token(&token)?;
+        save_token(registry, &token)?;
         println!("Token saved securely.");
     }
 
-    if test_pypi {
-        println!("Uploading package to TestPyPI...");
-        println!("Successfully pushed to TestPyPI!");
-    } else {
-        println!("Uploading package to PyPI...");
-        println!("Successfully pushed to PyPI!");
-    }
-    Ok(())
+    let url = publish::upload_url(test_pypi);
+    info(&format!(
+        "Uploading {} file(s) to {}...",
+        dists.len(),
+        if test_pypi { "TestPyPI" } else { "PyPI" }
+    ));
+    publish::upload(&url, token.trim(), &dists)
 }
 
 fn cmd_auth(args: &[String]) -> Result<()> {
+    const USAGE: &str = "usage: xe auth <login|revoke|status> [--registry <name>]";
     if args.is_empty() {
-        bail!("usage: xe auth <login|revoke>");
+        bail!(USAGE);
+    }
+    let mut registry = DEFAULT_REGISTRY.to_string();
+    let mut idx = 1usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--registry" => {
+                idx += 1;
+                let Some(name) = args.get(idx) else {
+                    bail!(USAGE);
+                };
+                registry = name.trim().to_lowercase();
+            }
+            _ => bail!(USAGE),
+        }
+        idx += 1;
     }
     match args[0].as_str() {
         "login" => {
-            print!("Enter PyPI Token: ");
+            print!("Enter token for {registry}: ");
             io::stdout().flush().ok();
             let token = read_stdin_line()?.trim().to_string();
-            save_token(&token)?;
-            if cfg!(windows) {
-                println!("Token saved securely in Windows Credential Manager");
-            } else {
-                println!("Token saved securely in {}", xe_home().display());
+            if token.is_empty() {
+                bail!("no token entered");
             }
+            save_token(&registry, &token)?;
+            println!(
+                "Token for {} saved securely in {}",
+                registry,
+                credentials_path().display()
+            );
             Ok(())
         }
         "revoke" => {
-            revoke_token()?;
-            println!("Token revoked successfully");
+            if revoke_token(&registry)? {
+                println!("Token for {registry} revoked successfully");
+            } else {
+                println!("No token stored for {registry}");
+            }
             Ok(())
         }
-        _ => bail!("usage: xe auth <login|revoke>"),
+        "status" => {
+            let creds = stored_credentials()?;
+            if creds.is_empty() {
+                println!("No registry credentials stored.");
+                return Ok(());
+            }
+            println!("Stored credentials ({}):", credentials_path().display());
+            for (name, token) in &creds {
+                println!("  {:<16} {}", name, mask_token(token));
+            }
+            Ok(())
+        }
+        _ => bail!(USAGE),
     }
 }
 
@@ -1141,15 +3674,25 @@ fn cmd_self(args: &[String]) -> Result<()> {
         println!("xe is already up to date (v1.0.0)");
         return Ok(());
     }
-    bail!("usage: xe self update")
+    if args.len() == 2 && args[0] == "report" {
+        print!("{}", crash::format_report(Path::new(&args[1]))?);
+        return Ok(());
+    }
+    bail!("usage: xe self <update|report <crash_file>>")
 }
 
 fn cmd_workspace(args: &[String]) -> Result<()> {
     if args.is_empty() {
-        bail!("usage: xe workspace <init|add>");
+        bail!("usage: xe workspace <init|add|new|graph|check>");
     }
+    let wd = env::current_dir().context("failed to get cwd")?;
     match args[0].as_str() {
         "init" => {
+            let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+            if cfg.workspace.is_none() {
+                cfg.workspace = Some(WorkspaceConfig::default());
+                save_project(&toml_path, &cfg)?;
+            }
             println!("Initialized xe workspace");
             Ok(())
         }
@@ -1157,1598 +3700,1183 @@ fn cmd_workspace(args: &[String]) -> Result<()> {
             if args.len() != 2 {
                 bail!("usage: xe workspace add <path>");
             }
+            let (root, toml_path, mut cfg) = find_workspace_root(&wd)?;
+            let member_dir = wd.join(&args[1]);
+            if !member_dir.join(XE_TOML).exists() {
+                bail!("{} has no {}", member_dir.display(), XE_TOML);
+            }
+            let member_dir = fs::canonicalize(&member_dir)
+                .with_context(|| format!("failed to resolve {}", member_dir.display()))?;
+            let member = member_dir
+                .strip_prefix(&root)
+                .map(|p| p.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_else(|_| member_dir.to_string_lossy().to_string());
+            let workspace = cfg.workspace.get_or_insert_with(WorkspaceConfig::default);
+            if !workspace.members.contains(&member) {
+                workspace.members.push(member);
+                save_project(&toml_path, &cfg)?;
+            }
             println!("Added {} to workspace", args[1]);
             Ok(())
         }
-        _ => bail!("usage: xe workspace <init|add>"),
-    }
-}
-
-fn cmd_why(args: &[String]) -> Result<()> {
-    if args.len() != 1 {
-        bail!("usage: xe why <package_name>");
-    }
-    println!("Analyzing dependency chain for {}...", args[0]);
-    println!("project -> requests (2.32.0) -> idna (3.7) -> {}", args[0]);
-    Ok(())
-}
-
-fn cmd_tree(_args: &[String]) -> Result<()> {
-    println!("xe project");
-    println!("|-- requests (2.32.0)");
-    println!("|   |-- urllib3 (2.2.1)");
-    println!("|   |-- idna (3.7)");
-    println!("|   |-- certifi (2024.2.2)");
-    println!("|   `-- charset-normalizer (3.3.2)");
-    println!("`-- pandas (2.2.2)");
-    println!("    |-- numpy (1.26.4)");
-    println!("    `-- python-dateutil (2.9.0)");
-    Ok(())
-}
-
-fn cmd_doctor(_args: &[String]) -> Result<()> {
-    println!("Checking environment health...");
-    println!("[OK] Python runtime");
-    println!("[OK] All dependencies verified");
-    println!("[OK] Toolchain compatibility confirmed");
-    Ok(())
-}
-
-fn cmd_setup(_args: &[String]) -> Result<()> {
-    let shim_dir = xe_shim_dir();
-    fs::create_dir_all(&shim_dir)
-        .with_context(|| format!("failed to create {}", shim_dir.display()))?;
-    add_to_path(&shim_dir)?;
-    println!(
-        "Successfully added {} to your PATH. Please restart your terminal.",
-        shim_dir.display()
-    );
-    Ok(())
-}
-
-fn print_help() {
-    println!("xe is a Python toolchain manager with global CAS caching");
-    println!();
-    println!("Usage:");
-    println!("  xe [--config <path>] [--profile] [--profile-dir <dir>] <command> [args]");
-    println!();
-    println!("Core commands:");
-    println!("  init, use, add, remove, list, run, shell, sync, lock");
-    println!("  python install|list|find|pin|dir");
-    println!("  venv create|list|delete|use|unset|autovenv");
-    println!("  pip install|uninstall|list|show|tree|check|sync|compile");
-    println!("  tool run|install|list|update|uninstall|upgrade|sync|dir");
-    println!("  cache dir|clean|prune");
-}
-
-fn print_version() {
-    println!("xe 2.0.0");
-    println!("os={} arch={}", env::consts::OS, env::consts::ARCH);
-}
-
-fn info(msg: &str) {
-    println!(" INFO  {msg}");
-}
-
-fn success(msg: &str) {
-    println!(" SUCCESS  {msg}");
-}
-
-fn warning(msg: &str) {
-    println!(" WARNING  {msg}");
-}
-
-fn error(msg: &str) {
-    eprintln!("  ERROR   {msg}");
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Config {
-    #[serde(default)]
-    project: ProjectConfig,
-    #[serde(default)]
-    python: PythonConfig,
-    #[serde(default)]
-    deps: HashMap<String, String>,
-    #[serde(default)]
-    cache: CacheConfig,
-    #[serde(default)]
-    venv: VenvConfig,
-    #[serde(default)]
-    settings: SettingsConfig,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct ProjectConfig {
-    #[serde(default)]
-    name: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct PythonConfig {
-    #[serde(default = "default_python_version")]
-    version: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct CacheConfig {
-    #[serde(default = "default_cache_mode")]
-    mode: String,
-    #[serde(default)]
-    global_dir: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct VenvConfig {
-    #[serde(default)]
-    name: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct SettingsConfig {
-    #[serde(default)]
-    autovenv: bool,
-}
-
-impl Default for PythonConfig {
-    fn default() -> Self {
-        Self {
-            version: default_python_version(),
+        "new" => {
+            let usage = "usage: xe workspace new <path> [--name <name>] [--build-backend <name>] [--used-by <member>]...";
+            let mut path = None;
+            let mut name = None;
+            let mut backend = "hatchling".to_string();
+            let mut used_by = Vec::new();
+            let mut i = 1;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--name" => {
+                        name = Some(
+                            args.get(i + 1)
+                                .ok_or_else(|| anyhow!("--name requires a value"))?
+                                .clone(),
+                        );
+                        i += 2;
+                    }
+                    "--build-backend" => {
+                        let value = args.get(i + 1).ok_or_else(|| {
+                            anyhow!(
+                                "--build-backend requires one of {}",
+                                build::BACKENDS.join(", ")
+                            )
+                        })?;
+                        if !build::BACKENDS.contains(&value.as_str()) {
+                            bail!(
+                                "unknown build backend '{}' (expected {})",
+                                value,
+                                build::BACKENDS.join(", ")
+                            );
+                        }
+                        backend = value.clone();
+                        i += 2;
+                    }
+                    "--used-by" => {
+                        used_by.push(
+                            args.get(i + 1)
+                                .ok_or_else(|| anyhow!("--used-by requires a member name or path"))?
+                                .clone(),
+                        );
+                        i += 2;
+                    }
+                    other if other.starts_with('-') => bail!("unknown option: {other}\n{usage}"),
+                    other if path.is_none() => {
+                        path = Some(other.to_string());
+                        i += 1;
+                    }
+                    _ => bail!("{usage}"),
+                }
+            }
+            let Some(path) = path else {
+                bail!("{usage}");
+            };
+            let (root, toml_path, mut cfg) = find_workspace_root(&wd)?;
+            let member_dir = wd.join(&path);
+            if member_dir.join(XE_TOML).exists() {
+                bail!(
+                    "{} is already a project; register it with `xe workspace add {}`",
+                    member_dir.display(),
+                    path
+                );
+            }
+            // Look the users up before writing anything, so a typo leaves
+            // the tree untouched.
+            let users = used_by
+                .iter()
+                .map(|user| find_workspace_member(&root, &cfg, &wd, user))
+                .collect::<Result<Vec<_>>>()?;
+
+            fs::create_dir_all(&member_dir)
+                .with_context(|| format!("failed to create {}", member_dir.display()))?;
+            let member_dir = fs::canonicalize(&member_dir)
+                .with_context(|| format!("failed to resolve {}", member_dir.display()))?;
+            let mut member_cfg = Config::new_default(&member_dir);
+            if let Some(name) = name {
+                member_cfg.project.name = name;
+            }
+            member_cfg.python.version = cfg.python.version.clone();
+            let member_toml = member_dir.join(XE_TOML);
+            save_project(&member_toml, &member_cfg)?;
+            println!("Created {}", member_toml.display());
+            build::scaffold_backend(&member_dir, &member_cfg, &backend, false)?;
+
+            let member = member_dir
+                .strip_prefix(&root)
+                .map(|p| p.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_else(|_| member_dir.to_string_lossy().to_string());
+            let member_name = member_cfg.project.name.trim().to_string();
+            let workspace = cfg.workspace.get_or_insert_with(WorkspaceConfig::default);
+            if !workspace.members.contains(&member) {
+                workspace.members.push(member.clone());
+            }
+            for user in &users {
+                let dep_path = relative_path(user, &member_dir);
+                if *user == root {
+                    cfg.set_path_dep(&member_name, &dep_path);
+                } else {
+                    let user_toml = user.join(XE_TOML);
+                    let mut user_cfg = load_project(&user_toml)?;
+                    user_cfg.set_path_dep(&member_name, &dep_path);
+                    save_project(&user_toml, &user_cfg)?;
+                }
+                info(&format!(
+                    "{} now depends on {member_name} (path = \"{dep_path}\")",
+                    user.strip_prefix(&root)
+                        .ok()
+                        .filter(|p| !p.as_os_str().is_empty())
+                        .map(|p| p.to_string_lossy().replace('\\', "/"))
+                        .unwrap_or_else(|| "workspace root".to_string())
+                ));
+            }
+            save_project(&toml_path, &cfg)?;
+            success(&format!("Added {member_name} to workspace at {member}"));
+            Ok(())
         }
-    }
-}
-
-impl Default for CacheConfig {
-    fn default() -> Self {
-        Self {
-            mode: default_cache_mode(),
-            global_dir: String::new(),
+        "graph" => {
+            let mut format = "dot".to_string();
+            let mut i = 1;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--format" if i + 1 < args.len() => {
+                        format = args[i + 1].clone();
+                        i += 1;
+                    }
+                    _ => bail!("usage: xe workspace graph [--format dot|mermaid]"),
+                }
+                i += 1;
+            }
+            let (root, _, cfg) = find_workspace_root(&wd)?;
+            let graph = WorkspaceGraph::load(&root, &cfg)?;
+            match format.as_str() {
+                "dot" => print!("{}", graph.to_dot()),
+                "mermaid" => print!("{}", graph.to_mermaid()),
+                other => bail!("unknown graph format {other}; expected dot or mermaid"),
+            }
+            Ok(())
         }
-    }
-}
-
-impl Config {
-    fn new_default(project_dir: &Path) -> Self {
-        let name = project_dir
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or("project")
-            .to_string();
-        Self {
-            project: ProjectConfig { name },
-            python: PythonConfig::default(),
-            deps: HashMap::new(),
-            cache: CacheConfig {
-                mode: default_cache_mode(),
-                global_dir: xe_cache_dir().to_string_lossy().to_string(),
-            },
-            venv: VenvConfig::default(),
-            settings: SettingsConfig { autovenv: false },
+        "check" => {
+            let (root, _, cfg) = find_workspace_root(&wd)?;
+            let graph = WorkspaceGraph::load(&root, &cfg)?;
+            let shared = cfg.workspace.unwrap_or_default().dependencies;
+            let mut conflicts = 0usize;
+            for (name, users) in &graph.shared {
+                if WorkspaceGraph::is_skewed(users) {
+                    let versions = users
+                        .iter()
+                        .map(|(i, v)| format!("{} pins {}", graph.members[*i].name, v))
+                        .collect::<Vec<_>>();
+                    error(&format!("{}: {}", name, versions.join(", ")));
+                    conflicts += 1;
+                }
+            }
+            for member in &graph.members {
+                for (name, spec) in &member.cfg.deps {
+                    if spec.inherits_workspace() {
+                        continue;
+                    }
+                    let Some((_, root_spec)) = shared
+                        .iter()
+                        .find(|(dep, _)| normalize_dep_name(dep) == normalize_dep_name(name))
+                    else {
+                        continue;
+                    };
+                    if spec.version() != root_spec.version() {
+                        error(&format!(
+                            "{}: {} pins {} but [workspace.dependencies] has {}; use {{ workspace = true }}",
+                            name,
+                            member.name,
+                            spec.version(),
+                            root_spec.version()
+                        ));
+                        conflicts += 1;
+                    }
+                }
+            }
+            if conflicts > 0 {
+                bail!("found {conflicts} conflicting dependency version(s) across the workspace");
+            }
+            success(&format!(
+                "{} workspace member(s) agree on dependency versions",
+                graph.members.len()
+            ));
+            Ok(())
         }
+        _ => bail!("usage: xe workspace <init|add|new|graph|check>"),
     }
+}
 
-    fn normalize(&mut self, project_dir: &Path) {
-        if self.project.name.trim().is_empty() {
-            self.project.name = project_dir
-                .file_name()
-                .and_then(|s| s.to_str())
-                .unwrap_or("project")
-                .to_string();
-        }
-        if self.python.version.trim().is_empty() {
-            self.python.version = default_python_version();
+/// Finds the workspace member `user` names, by project name or by path
+/// from `wd`, and returns its directory.
+fn find_workspace_member(root: &Path, cfg: &Config, wd: &Path, user: &str) -> Result<PathBuf> {
+    let by_path = fs::canonicalize(wd.join(user)).ok();
+    let dirs = std::iter::once(root.to_path_buf()).chain(
+        cfg.workspace
+            .iter()
+            .flat_map(|w| &w.members)
+            .map(|m| root.join(m)),
+    );
+    for dir in dirs {
+        let toml_path = dir.join(XE_TOML);
+        if !toml_path.exists() {
+            continue;
         }
-        if self.cache.mode.trim().is_empty() {
-            self.cache.mode = default_cache_mode();
+        let dir = fs::canonicalize(&dir).unwrap_or(dir);
+        if by_path.as_ref() == Some(&dir) {
+            return Ok(dir);
         }
-        if self.cache.global_dir.trim().is_empty() {
-            self.cache.global_dir = xe_cache_dir().to_string_lossy().to_string();
+        let member = load_project(&toml_path)?;
+        if normalize_dep_name(&member.project.name) == normalize_dep_name(user) {
+            return Ok(dir);
         }
     }
+    bail!("'{user}' is not a workspace member; see `xe workspace graph`")
 }
 
-fn default_python_version() -> String {
-    "3.12".to_string()
-}
-
-fn default_cache_mode() -> String {
-    "global-cas".to_string()
-}
-
-fn load_or_create_project(project_dir: &Path) -> Result<(Config, PathBuf)> {
-    let toml_path = project_dir.join(XE_TOML);
-    if !toml_path.exists() {
-        let cfg = Config::new_default(project_dir);
-        save_project(&toml_path, &cfg)?;
-        return Ok((cfg, toml_path));
-    }
-    let cfg = load_project(&toml_path)?;
-    Ok((cfg, toml_path))
-}
-
-fn load_project(path: &Path) -> Result<Config> {
-    let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
-    let mut cfg: Config = toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
-    let project_dir = path.parent().unwrap_or_else(|| Path::new("."));
-    cfg.normalize(project_dir);
-    Ok(cfg)
-}
-
-fn save_project(path: &Path, cfg: &Config) -> Result<()> {
-    let mut normalized = cfg.clone();
-    let project_dir = path.parent().unwrap_or_else(|| Path::new("."));
-    normalized.normalize(project_dir);
-    let encoded = toml::to_string_pretty(&normalized).context("failed to encode xe.toml")?;
-    fs::write(path, encoded).with_context(|| format!("failed to write {}", path.display()))?;
-    Ok(())
-}
-
-fn normalize_dep_name(name: &str) -> String {
-    name.trim()
-        .to_lowercase()
-        .replace('_', "-")
-        .replace('.', "-")
-}
-
-fn requirement_to_dep_name(requirement: &str) -> Option<String> {
-    let mut name = requirement.trim().to_string();
-    if name.is_empty() {
-        return None;
-    }
-    if let Some(idx) = name.find('[') {
-        name = name[..idx].to_string();
-    }
-    if let Some(idx) = name.find(|c: char| " <>=!~;".contains(c)) {
-        name = name[..idx].to_string();
-    }
-    let name = name.trim();
-    if name.is_empty() {
-        None
+/// `to` relative to `from`, both canonical directories, with `/` separators.
+fn relative_path(from: &Path, to: &Path) -> String {
+    let from = from.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let parts = std::iter::repeat_n("..".to_string(), from.len() - common)
+        .chain(
+            to[common..]
+                .iter()
+                .map(|c| c.as_os_str().to_string_lossy().to_string()),
+        )
+        .collect::<Vec<_>>();
+    if parts.is_empty() {
+        ".".to_string()
     } else {
-        Some(normalize_dep_name(name))
+        parts.join("/")
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct GlobalConfig {
-    #[serde(default)]
-    default_python: String,
-}
-
-fn load_global_config(path: &Path) -> Result<GlobalConfig> {
-    if !path.exists() {
-        return Ok(GlobalConfig::default());
-    }
-    let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
-    let cfg = serde_yaml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
-    Ok(cfg)
-}
-
-fn save_global_config(path: &Path, cfg: &GlobalConfig) -> Result<()> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
-    }
-    let text = serde_yaml::to_string(cfg).context("failed to encode config")?;
-    fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))?;
-    Ok(())
-}
-
-fn get_preferred_python_version(ctx: &AppContext) -> Result<String> {
-    if let Ok(wd) = env::current_dir() {
-        let local = wd.join(XE_TOML);
-        if local.exists() {
-            if let Ok(cfg) = load_project(&local) {
-                if !cfg.python.version.trim().is_empty() {
-                    return Ok(cfg.python.version);
-                }
+struct WorkspaceMember {
+    name: String,
+    dir: PathBuf,
+    cfg: Config,
+}
+
+/// Dependencies between workspace members plus the third-party packages
+/// more than one member depends on.
+struct WorkspaceGraph {
+    members: Vec<WorkspaceMember>,
+    /// `(from, to)` indexes into `members`.
+    edges: Vec<(usize, usize)>,
+    /// Shared package name to `(member index, version)` pairs.
+    shared: BTreeMap<String, Vec<(usize, String)>>,
+    cycles: Vec<Vec<usize>>,
+}
+
+impl WorkspaceGraph {
+    fn load(root: &Path, cfg: &Config) -> Result<Self> {
+        let mut members = Vec::new();
+        let mut dirs = vec![root.to_path_buf()];
+        for member in cfg.workspace.iter().flat_map(|w| &w.members) {
+            dirs.push(root.join(member));
+        }
+        for dir in dirs {
+            let toml_path = dir.join(XE_TOML);
+            if !toml_path.exists() {
+                continue;
             }
+            let cfg = load_project(&toml_path)?;
+            let dir = fs::canonicalize(&dir).unwrap_or(dir);
+            let name = if cfg.project.name.trim().is_empty() {
+                dir.file_name()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            } else {
+                cfg.project.name.trim().to_string()
+            };
+            members.push(WorkspaceMember { name, dir, cfg });
         }
-    }
-    let global_cfg = load_global_config(&ctx.config_file)?;
-    if !global_cfg.default_python.trim().is_empty() {
-        return Ok(global_cfg.default_python);
-    }
-    Ok(default_python_version())
-}
-
-#[derive(Debug, Clone)]
-struct RuntimeSelection {
-    python_exe: PathBuf,
-    site_packages: PathBuf,
-    activation_path: PathBuf,
-    venv_name: String,
-    is_venv: bool,
-}
-
-#[derive(Debug, Clone)]
-struct RuntimeResult {
-    selection: RuntimeSelection,
-    config_changed: bool,
-}
-
-fn ensure_runtime_for_project(ctx: &AppContext, wd: &Path, cfg: &mut Config) -> Result<RuntimeResult> {
-    let _span = span(ctx, "runtime.ensure", json!({"working_dir": wd.display().to_string(), "python_version": cfg.python.version}));
-    let pm = PythonManager::new()?;
-
-    if cfg.python.version.trim().is_empty() {
-        cfg.python.version = get_preferred_python_version(ctx)?;
-    }
-
-    let mut python_exe = match pm.get_python_exe(&cfg.python.version) {
-        Ok(path) => path,
-        Err(_) => {
-            pm.install(&cfg.python.version, ctx)?;
-            pm.get_python_exe(&cfg.python.version)?
+        // A root without dependencies of its own is only a container.
+        if members.len() > 1 && members[0].cfg.deps.is_empty() {
+            members.remove(0);
         }
-    };
 
-    let vm = VenvManager::new()?;
-    let mut config_changed = false;
-    let mut venv_name = cfg.venv.name.trim().to_string();
-    if venv_name.is_empty() && cfg.settings.autovenv {
-        let mut name = cfg.project.name.trim().to_string();
-        if name.is_empty() {
-            name = wd
-                .file_name()
-                .and_then(|s| s.to_str())
-                .unwrap_or("default")
-                .to_string();
-        }
-        name = normalize_venv_name(&name);
-        if name.is_empty() {
-            name = "default".to_string();
-        }
-        venv_name = format!("auto-{name}");
-        cfg.venv.name = venv_name.clone();
-        config_changed = true;
-    }
-
-    if !venv_name.is_empty() {
-        if !vm.exists(&venv_name) {
-            vm.create(&venv_name, &python_exe)?;
-        }
-        python_exe = vm.get_python_exe(&venv_name);
-        if !python_exe.exists() {
-            bail!("venv python not found: {}", python_exe.display());
-        }
-        let mut site_packages = vm.get_site_packages_dir(&venv_name);
-        if site_packages
-            .file_name()
-            .and_then(|s| s.to_str())
-            .map(|s| s.eq_ignore_ascii_case("lib"))
-            .unwrap_or(false)
-        {
-            if let Ok(detected) = detect_venv_site_packages(&python_exe) {
-                site_packages = detected;
+        let by_name = members
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (normalize_dep_name(&m.name), i))
+            .collect::<HashMap<_, _>>();
+        let mut edges = Vec::new();
+        let mut third_party: BTreeMap<String, Vec<(usize, String)>> = BTreeMap::new();
+        for (i, member) in members.iter().enumerate() {
+            let mut targets = BTreeSet::new();
+            for (dep, spec) in &member.cfg.deps {
+                match by_name.get(&normalize_dep_name(dep)) {
+                    Some(&j) if j != i => {
+                        targets.insert(j);
+                    }
+                    Some(_) => {}
+                    None => third_party
+                        .entry(normalize_dep_name(dep))
+                        .or_default()
+                        .push((i, spec.version().to_string())),
+                }
             }
+            for path in member.cfg.editable.values() {
+                let target = fs::canonicalize(member.dir.join(path)).unwrap_or_default();
+                if let Some(j) = members.iter().position(|m| m.dir == target) {
+                    if j != i {
+                        targets.insert(j);
+                    }
+                }
+            }
+            edges.extend(targets.into_iter().map(|j| (i, j)));
         }
-        fs::create_dir_all(&site_packages)
-            .with_context(|| format!("failed to create {}", site_packages.display()))?;
-        return Ok(RuntimeResult {
-            selection: RuntimeSelection {
-                activation_path: python_exe
-                    .parent()
-                    .map(Path::to_path_buf)
-                    .unwrap_or_else(PathBuf::new),
-                python_exe,
-                site_packages,
-                venv_name,
-                is_venv: true,
-            },
-            config_changed,
-        });
-    }
-
-    let site_packages = pm.get_site_packages_dir(&cfg.python.version)?;
-    Ok(RuntimeResult {
-        selection: RuntimeSelection {
-            activation_path: python_exe
-                .parent()
-                .map(Path::to_path_buf)
-                .unwrap_or_else(PathBuf::new),
-            python_exe,
-            site_packages,
-            venv_name: String::new(),
-            is_venv: false,
-        },
-        config_changed,
-    })
-}
-
-fn normalize_venv_name(name: &str) -> String {
-    let mut n = name.trim().to_lowercase();
-    n = n.replace(' ', "-").replace('_', "-");
-    let mut out = String::with_capacity(n.len());
-    for ch in n.chars() {
-        if ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-' {
-            out.push(ch);
-        }
+        third_party.retain(|_, users| users.len() > 1);
+        let cycles = find_cycles(members.len(), &edges);
+        Ok(Self {
+            members,
+            edges,
+            shared: third_party,
+            cycles,
+        })
     }
-    out.trim_matches('-').to_string()
-}
 
-fn detect_venv_site_packages(venv_exe: &Path) -> Result<PathBuf> {
-    let output = Command::new(venv_exe)
-        .args(["-c", "import site; print(site.getsitepackages()[0])"])
-        .output()
-        .context("failed to detect venv site-packages")?;
-    if !output.status.success() {
-        bail!("failed to detect venv site-packages");
-    }
-    let site = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    if site.is_empty() {
-        bail!("empty site-packages response");
+    /// Whether members pin different versions; unpinned (`*`) uses agree
+    /// with anything.
+    fn is_skewed(users: &[(usize, String)]) -> bool {
+        users
+            .iter()
+            .map(|(_, v)| v)
+            .filter(|v| v.as_str() != "*")
+            .collect::<HashSet<_>>()
+            .len()
+            > 1
+    }
+
+    fn in_cycle(&self, edge: (usize, usize)) -> bool {
+        self.cycles.iter().any(|cycle| {
+            cycle
+                .iter()
+                .zip(cycle.iter().cycle().skip(1))
+                .any(|(&a, &b)| (a, b) == edge)
+        })
     }
-    Ok(PathBuf::from(site))
-}
 
-fn apply_runtime_env(command: &mut Command, selection: &RuntimeSelection) -> Result<()> {
-    let python_root = selection.activation_path.clone();
-    let scripts_dir = {
-        let win_scripts = python_root.join("Scripts");
-        if win_scripts.exists() {
-            win_scripts
-        } else {
-            python_root.join("bin")
-        }
-    };
-    let current_path = env::var("PATH").unwrap_or_default();
-    let new_path = format!(
-        "{}{}{}{}{}",
-        scripts_dir.display(),
-        std::path::MAIN_SEPARATOR_STR,
-        python_root.display(),
-        if current_path.is_empty() { "" } else { ";" },
-        current_path
-    );
-    command.env("PATH", new_path);
-    if selection.is_venv {
-        if let Some(root) = selection
-            .python_exe
-            .parent()
-            .and_then(|p| p.parent())
-            .map(Path::to_path_buf)
-        {
-            command.env("VIRTUAL_ENV", root);
+    /// Cycles and version skews, one line each.
+    fn findings(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        for cycle in &self.cycles {
+            let mut names = cycle
+                .iter()
+                .map(|&i| self.members[i].name.as_str())
+                .collect::<Vec<_>>();
+            names.push(names[0]);
+            out.push(format!("cycle: {}", names.join(" -> ")));
         }
-    }
-    Ok(())
-}
-
-#[derive(Debug, Clone)]
-struct PythonManager {
-    base_dir: PathBuf,
-}
-
-impl PythonManager {
-    fn new() -> Result<Self> {
-        let base_dir = if cfg!(windows) {
-            let local = env::var("LOCALAPPDATA")
-                .ok()
-                .map(PathBuf::from)
-                .unwrap_or_else(|| {
-                    dirs::home_dir()
-                        .unwrap_or_else(|| PathBuf::from("."))
-                        .join("AppData")
-                        .join("Local")
-                });
-            local.join("Programs").join("Python")
-        } else {
-            xe_home().join("python")
-        };
-        fs::create_dir_all(&base_dir)
-            .with_context(|| format!("failed to create {}", base_dir.display()))?;
-        Ok(Self { base_dir })
-    }
-
-    fn get_python_path(&self, version: &str) -> Result<PathBuf> {
-        let parts = parse_major_minor(version)?;
-        Ok(self
-            .base_dir
-            .join(format!("python{}{}", parts.0, parts.1)))
-    }
-
-    fn get_python_exe(&self, version: &str) -> Result<PathBuf> {
-        let python_dir = self.get_python_path(version)?;
-        if cfg!(windows) {
-            let tools = python_dir.join("tools").join("python.exe");
-            if tools.exists() {
-                return Ok(tools);
-            }
-            let root = python_dir.join("python.exe");
-            if root.exists() {
-                return Ok(root);
+        for (name, users) in &self.shared {
+            if Self::is_skewed(users) {
+                let versions = users
+                    .iter()
+                    .map(|(i, v)| format!("{}={}", self.members[*i].name, v))
+                    .collect::<Vec<_>>();
+                out.push(format!("version skew: {} ({})", name, versions.join(", ")));
             }
-            bail!("python.exe not found in {}", python_dir.display());
-        }
-        let py3 = python_dir.join("bin").join("python3");
-        if py3.exists() {
-            return Ok(py3);
         }
-        let py = python_dir.join("bin").join("python");
-        if py.exists() {
-            return Ok(py);
-        }
-        bail!("python/python3 not found in {}", python_dir.join("bin").display());
+        out
     }
 
-    fn install(&self, version: &str, ctx: &AppContext) -> Result<()> {
-        let _span = span(ctx, "python.install", json!({"version": version}));
-        let mut needs_cleanup = false;
-
-        if let Ok(exe) = self.get_python_exe(version) {
-            if is_python_runtime_healthy(&exe) && (!cfg!(windows) || is_windows_launcher_version_available(version))
-            {
-                success(&format!(
-                    "Python {} already installed at {}",
-                    version,
-                    exe.display()
-                ));
-                return Ok(());
-            }
-            needs_cleanup = true;
-            warning(&format!(
-                "Python {} exists at {} but runtime is unhealthy or launcher is missing; repairing with official installer.",
-                version,
-                exe.display()
-            ));
-        }
-
-        if !cfg!(windows) {
-            bail!("automatic Python installation is currently supported on Windows only");
-        }
-
-        let target_dir = self.get_python_path(version)?;
-        info(&format!(
-            "Installing Python {} to {}...",
-            version,
-            target_dir.display()
-        ));
-        if cfg!(windows) && needs_cleanup {
-            if target_dir.exists() {
-                fs::remove_dir_all(&target_dir)
-                    .with_context(|| format!("failed to remove {}", target_dir.display()))?;
-            }
+    fn to_dot(&self) -> String {
+        let mut out = String::from("digraph workspace {\n    rankdir=LR;\n");
+        for finding in self.findings() {
+            out.push_str(&format!("    // {finding}\n"));
         }
-
-        let full_version = resolve_latest_windows_installer_version(version)?;
-        let url = format!(
-            "https://www.python.org/ftp/python/{0}/python-{0}-amd64.exe",
-            full_version
-        );
-        info(&format!("Downloading official Python installer from {}...", url));
-        let tmp_installer = download_file(&url, "python-installer", "exe")?;
-
-        if let Some(parent) = target_dir.parent() {
-            fs::create_dir_all(parent)
-                .with_context(|| format!("failed to create {}", parent.display()))?;
-        }
-
-        let args = vec![
-            "/quiet".to_string(),
-            "InstallAllUsers=0".to_string(),
-            "Include_pip=1".to_string(),
-            "Include_launcher=1".to_string(),
-            "PrependPath=1".to_string(),
-            format!("TargetDir={}", target_dir.display()),
-        ];
-
-        let output = Command::new(&tmp_installer)
-            .args(&args)
-            .output()
-            .context("failed to run python installer")?;
-        if !output.status.success() {
-            warning(&format!(
-                "official installer failed ({}); falling back to embeddable distribution",
-                output.status
+        for member in &self.members {
+            out.push_str(&format!(
+                "    \"{}\" [shape=box, style=bold];\n",
+                member.name
             ));
-            if target_dir.exists() {
-                fs::remove_dir_all(&target_dir)
-                    .with_context(|| format!("failed to reset {}", target_dir.display()))?;
-            }
-            self.install_windows_embeddable(&full_version, &target_dir)?;
-            let exe = self.get_python_exe(version)?;
-            if !is_python_runtime_healthy(&exe) {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                bail!(
-                    "installer fallback completed but runtime is unhealthy at {}\ninstaller output:\n{}{}",
-                    exe.display(),
-                    stdout,
-                    stderr
-                );
-            }
-            success(&format!(
-                "Python {} installed at {}",
-                version,
-                target_dir.display()
-            ));
-            add_to_path(&target_dir)?;
-            add_to_path(&target_dir.join("Scripts"))?;
-            success(&format!("Added Python {} to PATH.", version));
-            return Ok(());
         }
-
-        let exe = self.get_python_exe(version)?;
-        if !is_python_runtime_healthy(&exe) {
-            bail!("python installer completed but runtime is unhealthy at {}", exe.display());
+        for &(from, to) in &self.edges {
+            let attrs = if self.in_cycle((from, to)) {
+                " [color=red]"
+            } else {
+                ""
+            };
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\"{};\n",
+                self.members[from].name, self.members[to].name, attrs
+            ));
         }
-        success(&format!(
-            "Python {} installed at {}",
-            version,
-            target_dir.display()
-        ));
-        info("Ensuring Python install directories are in system PATH...");
-        add_to_path(&target_dir)?;
-        add_to_path(&target_dir.join("Scripts"))?;
-        success(&format!("Added Python {} to PATH.", version));
-        Ok(())
-    }
-
-    fn install_windows_embeddable(&self, full_version: &str, target_dir: &Path) -> Result<()> {
-        let url = format!(
-            "https://www.python.org/ftp/python/{0}/python-{0}-embed-amd64.zip",
-            full_version
-        );
-        info(&format!("Downloading embeddable Python from {}...", url));
-        let zip_path = download_file(&url, "python-embed", "zip")?;
-        fs::create_dir_all(target_dir)
-            .with_context(|| format!("failed to create {}", target_dir.display()))?;
-        extract_zip_to_dir(&zip_path, target_dir)?;
-        patch_embeddable_pth(target_dir)?;
-
-        let exe = target_dir.join("python.exe");
-        if exe.exists() {
-            if let Err(err) = bootstrap_pip(&exe) {
-                warning(&format!("Pip bootstrap failed: {err}"));
+        for (name, users) in &self.shared {
+            let color = if Self::is_skewed(users) {
+                ", color=red"
+            } else {
+                ""
+            };
+            out.push_str(&format!("    \"{name}\" [shape=ellipse{color}];\n"));
+            for (i, version) in users {
+                out.push_str(&format!(
+                    "    \"{}\" -> \"{}\" [style=dashed, label=\"{}\"];\n",
+                    self.members[*i].name, name, version
+                ));
             }
         }
-        Ok(())
+        out.push_str("}\n");
+        out
     }
 
-    fn get_site_packages_dir(&self, version: &str) -> Result<PathBuf> {
-        let python_dir = self.get_python_path(version)?;
-        if cfg!(windows) {
-            let tools_lib = python_dir.join("tools").join("Lib");
-            let lib_root = if tools_lib.exists() {
-                python_dir.join("tools")
+    fn to_mermaid(&self) -> String {
+        let mut out = String::from("graph LR\n");
+        for finding in self.findings() {
+            out.push_str(&format!("    %% {finding}\n"));
+        }
+        for (i, member) in self.members.iter().enumerate() {
+            out.push_str(&format!("    m{i}[\"{}\"]\n", member.name));
+        }
+        // Member edges come first, so their link indexes match `edges`.
+        let mut cycle_links = Vec::new();
+        for (link, &(from, to)) in self.edges.iter().enumerate() {
+            out.push_str(&format!("    m{from} --> m{to}\n"));
+            if self.in_cycle((from, to)) {
+                cycle_links.push(link.to_string());
+            }
+        }
+        for (d, (name, users)) in self.shared.iter().enumerate() {
+            let class = if Self::is_skewed(users) {
+                ":::skew"
             } else {
-                python_dir
+                ""
             };
-            let site = lib_root.join("Lib").join("site-packages");
-            fs::create_dir_all(&site).with_context(|| format!("failed to create {}", site.display()))?;
-            return Ok(site);
+            out.push_str(&format!("    d{d}([\"{name}\"]){class}\n"));
+            for (i, version) in users {
+                out.push_str(&format!("    m{i} -.->|\"{version}\"| d{d}\n"));
+            }
         }
-        let (major, minor) = parse_major_minor(version)?;
-        let site = python_dir
-            .join("lib")
-            .join(format!("python{}.{}", major, minor))
-            .join("site-packages");
-        fs::create_dir_all(&site).with_context(|| format!("failed to create {}", site.display()))?;
-        Ok(site)
-    }
-}
-
-fn parse_major_minor(version: &str) -> Result<(u32, u32)> {
-    let parts: Vec<&str> = version.split('.').collect();
-    if parts.len() < 2 {
-        bail!("invalid python version {}", version);
-    }
-    let major = parts[0]
-        .parse::<u32>()
-        .with_context(|| format!("invalid python major version in {version}"))?;
-    let minor = parts[1]
-        .parse::<u32>()
-        .with_context(|| format!("invalid python minor version in {version}"))?;
-    Ok((major, minor))
-}
-
-fn resolve_latest_windows_installer_version(version: &str) -> Result<String> {
-    let parts: Vec<&str> = version.split('.').collect();
-    if parts.len() >= 3 {
-        return Ok(version.to_string());
-    }
-    if parts.len() != 2 {
-        return Ok(version.to_string());
-    }
-
-    let latest = resolve_latest_patch_version(version)?;
-    if windows_installer_exists(&latest) {
-        return Ok(latest);
-    }
-    if let Some(fallback) = windows_installer_fallback(version) {
-        if windows_installer_exists(fallback) {
-            return Ok(fallback.to_string());
+        out.push_str("    classDef skew stroke:#d33,stroke-width:2px\n");
+        if !cycle_links.is_empty() {
+            out.push_str(&format!(
+                "    linkStyle {} stroke:#d33\n",
+                cycle_links.join(",")
+            ));
         }
-    }
-
-    let mut candidates = list_patch_versions(version)?;
-    candidates.sort_by(|a, b| compare_version(a, b).reverse());
-    for candidate in &candidates {
-        if windows_installer_exists(candidate) {
-            return Ok(candidate.clone());
+        out
+    }
+}
+
+/// Elementary cycles found by depth-first search, each reported once from
+/// its lowest node.
+fn find_cycles(nodes: usize, edges: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    let mut adjacency = vec![Vec::new(); nodes];
+    for &(from, to) in edges {
+        adjacency[from].push(to);
+    }
+    let mut cycles = Vec::new();
+    for start in 0..nodes {
+        let mut stack = vec![(start, 0usize)];
+        let mut path = vec![start];
+        while let Some((node, next)) = stack.pop() {
+            if next < adjacency[node].len() {
+                stack.push((node, next + 1));
+                let to = adjacency[node][next];
+                if to == start {
+                    cycles.push(path.clone());
+                } else if to > start && !path.contains(&to) {
+                    path.push(to);
+                    stack.push((to, 0));
+                }
+            } else {
+                path.pop();
+            }
         }
     }
-
-    if let Some(fallback) = windows_installer_fallback(version) {
-        return Ok(fallback.to_string());
-    }
-    Ok(format!("{version}.0"))
+    cycles
 }
 
-fn resolve_latest_patch_version(version: &str) -> Result<String> {
-    let parts: Vec<&str> = version.split('.').collect();
-    if parts.len() >= 3 {
-        return Ok(version.to_string());
-    }
-    if parts.len() != 2 {
-        return Ok(version.to_string());
-    }
-    let mut candidates = list_patch_versions(version)?;
-    if candidates.is_empty() {
-        return Ok(format!("{version}.0"));
-    }
-    candidates.sort_by(|a, b| compare_version(a, b).reverse());
-    Ok(candidates[0].clone())
-}
+/// Highest `3.<minor>` probed; a range that reaches it has no cap.
+const PYTHON_MINOR_PROBE: u32 = 40;
 
-fn list_patch_versions(version: &str) -> Result<Vec<String>> {
-    let body = Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .context("failed to build HTTP client")?
-        .get("https://www.python.org/ftp/python/")
-        .send()
-        .context("failed to request python FTP listing")?
-        .error_for_status()
-        .context("python FTP listing request failed")?
-        .text()
-        .context("failed to decode python FTP response")?;
-    let re = Regex::new(r#"href="(\d+\.\d+\.\d+)/""#).unwrap();
-    let prefix = format!("{version}.");
-    let mut out = Vec::new();
-    for cap in re.captures_iter(&body) {
-        let value = cap.get(1).map(|m| m.as_str()).unwrap_or_default();
-        if value.starts_with(&prefix) {
-            out.push(value.to_string());
-        }
-    }
-    Ok(out)
+/// Whether `specifier` admits some `3.<minor>` release.
+fn admits_python_minor(specifier: &str, minor: u32) -> bool {
+    specifier_allows(specifier, &format!("3.{minor}"))
+        || specifier_allows(specifier, &format!("3.{minor}.99"))
 }
 
-fn windows_installer_fallback(version: &str) -> Option<&'static str> {
-    match version {
-        "3.9" => Some("3.9.13"),
-        "3.10" => Some("3.10.11"),
-        "3.11" => Some("3.11.9"),
-        "3.12" => Some("3.12.3"),
-        "3.13" => Some("3.13.0"),
-        _ => None,
+fn python_minor_range(minors: &[u32]) -> String {
+    match (minors.first(), minors.last()) {
+        (Some(low), Some(&PYTHON_MINOR_PROBE)) => format!("3.{low} and newer"),
+        (Some(low), Some(high)) if low == high => format!("3.{low} only"),
+        (Some(low), Some(high)) => format!("3.{low} to 3.{high}"),
+        _ => "none".to_string(),
     }
 }
 
-fn windows_installer_exists(version: &str) -> bool {
-    if version.trim().is_empty() {
-        return false;
-    }
-    let url = format!(
-        "https://www.python.org/ftp/python/{0}/python-{0}-amd64.exe",
-        version
-    );
-    let client = match Client::builder().timeout(Duration::from_secs(20)).build() {
-        Ok(c) => c,
-        Err(_) => return false,
-    };
-    match client.head(&url).send() {
-        Ok(resp) => {
-            if resp.status() == StatusCode::METHOD_NOT_ALLOWED {
-                client
-                    .get(&url)
-                    .header("Range", "bytes=0-0")
-                    .send()
-                    .map(|r| r.status().is_success() || r.status() == StatusCode::PARTIAL_CONTENT)
-                    .unwrap_or(false)
-            } else {
-                resp.status().is_success()
-            }
+/// Explains which installed dependencies bound the usable Python range
+/// through their `Requires-Python`, or, given a version, which ones keep
+/// the project from moving to it.
+fn cmd_why_python(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage = "usage: xe why-python [<3.minor>]";
+    let target = match args {
+        [] => None,
+        [version] => {
+            let minor = version
+                .strip_prefix("3.")
+                .and_then(|rest| rest.split('.').next())
+                .and_then(|minor| minor.parse::<u32>().ok())
+                .ok_or_else(|| anyhow!(usage))?;
+            Some(minor)
         }
-        Err(_) => false,
+        _ => bail!(usage),
+    };
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
     }
-}
-
-fn compare_version(a: &str, b: &str) -> Ordering {
-    let pa: Vec<u32> = a
-        .split('.')
-        .map(|s| s.parse::<u32>().unwrap_or(0))
+    let mut dists: Vec<InstalledDist> = installed_metadata(&runtime.selection.site_packages)?
+        .into_iter()
+        .filter(|dist| !dist.requires_python.is_empty())
         .collect();
-    let pb: Vec<u32> = b
-        .split('.')
-        .map(|s| s.parse::<u32>().unwrap_or(0))
-        .collect();
-    let n = pa.len().max(pb.len());
-    for i in 0..n {
-        let va = *pa.get(i).unwrap_or(&0);
-        let vb = *pb.get(i).unwrap_or(&0);
-        if va > vb {
-            return Ordering::Greater;
+    dists.sort_by_key(|dist| dist.name.to_lowercase());
+    let label = |dist: &InstalledDist| {
+        format!(
+            "{} {} ({})",
+            paint(Style::Accent, &dist.name),
+            dist.version,
+            dist.requires_python
+        )
+    };
+
+    if let Some(minor) = target {
+        let blockers: Vec<&InstalledDist> = dists
+            .iter()
+            .filter(|dist| !admits_python_minor(&dist.requires_python, minor))
+            .collect();
+        if blockers.is_empty() {
+            success(&format!(
+                "Every installed dependency allows Python 3.{minor}"
+            ));
+            return Ok(());
         }
-        if va < vb {
-            return Ordering::Less;
+        println!("Python 3.{minor} is excluded by:");
+        let index_url = primary_index_url(&cfg)?;
+        for dist in blockers {
+            // The newest release that would allow the move, if any.
+            let note = match index_releases(&index_url, &dist.name) {
+                Ok(releases) => match releases
+                    .into_iter()
+                    .filter(|(_, requires)| admits_python_minor(requires, minor))
+                    .map(|(version, _)| version)
+                    .max_by(|a, b| compare_version(a, b))
+                {
+                    Some(version) if compare_version(&version, &dist.version).is_gt() => {
+                        format!("{version} supports it")
+                    }
+                    Some(_) => "only older releases support it".to_string(),
+                    None => "no release supports it".to_string(),
+                },
+                Err(_) => "could not check the index for newer releases".to_string(),
+            };
+            println!("  {}: {}", label(dist), paint(Style::Muted, &note));
         }
+        return Ok(());
     }
-    Ordering::Equal
-}
 
-fn is_windows_launcher_version_available(version: &str) -> bool {
-    if !cfg!(windows) {
-        return false;
-    }
-    let parts: Vec<&str> = version.split('.').collect();
-    if parts.len() < 2 {
-        return false;
-    }
-    let selector = format!("{}.{}", parts[0], parts[1]);
-    let mut candidates = vec![PathBuf::from("py")];
-    if let Ok(win_dir) = env::var("WINDIR") {
-        candidates.push(PathBuf::from(win_dir).join("py.exe"));
-    }
-    if let Ok(local_app_data) = env::var("LOCALAPPDATA") {
-        candidates.push(
-            PathBuf::from(local_app_data)
-                .join("Programs")
-                .join("Python")
-                .join("Launcher")
-                .join("py.exe"),
-        );
+    let admitted = |dist: &InstalledDist| -> Vec<u32> {
+        (0..=PYTHON_MINOR_PROBE)
+            .filter(|minor| admits_python_minor(&dist.requires_python, *minor))
+            .collect()
+    };
+    let usable: Vec<u32> = (0..=PYTHON_MINOR_PROBE)
+        .filter(|minor| {
+            dists
+                .iter()
+                .all(|dist| admits_python_minor(&dist.requires_python, *minor))
+        })
+        .collect();
+    println!(
+        "Usable Python for {} installed package(s) declaring Requires-Python: {}",
+        dists.len(),
+        python_minor_range(&usable)
+    );
+    println!("Project Python: {}", cfg.python.version);
+    let ranges: Vec<(&InstalledDist, Vec<u32>)> =
+        dists.iter().map(|dist| (dist, admitted(dist))).collect();
+    let floor = ranges
+        .iter()
+        .filter_map(|(_, minors)| minors.first().copied())
+        .max();
+    if let Some(floor) = floor.filter(|floor| *floor > 0) {
+        println!("Floor 3.{floor} set by:");
+        for (dist, _) in ranges
+            .iter()
+            .filter(|(_, minors)| minors.first() == Some(&floor))
+        {
+            println!("  {}", label(dist));
+        }
     }
-    for candidate in candidates {
-        let status = Command::new(&candidate)
-            .arg(format!("-{}", selector))
-            .arg("-V")
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status();
-        if let Ok(status) = status {
-            if status.success() {
-                return true;
+    let cap = ranges
+        .iter()
+        .filter_map(|(_, minors)| minors.last().copied())
+        .min();
+    match cap.filter(|cap| *cap < PYTHON_MINOR_PROBE) {
+        Some(cap) => {
+            println!("Capped at 3.{cap} by:");
+            for (dist, _) in ranges
+                .iter()
+                .filter(|(_, minors)| minors.last() == Some(&cap))
+            {
+                println!("  {}", label(dist));
             }
         }
+        None => println!("No dependency caps the Python version"),
     }
-    false
-}
-
-fn is_python_runtime_healthy(exe: &Path) -> bool {
-    let output = Command::new(exe)
-        .args(["-c", "import encodings,site; print('ok')"])
-        .output();
-    match output {
-        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout).contains("ok"),
-        _ => false,
-    }
-}
-
-fn add_to_path(dir: &Path) -> Result<()> {
-    info(&format!("Ensuring {} is in system PATH...", dir.display()));
-    if cfg!(windows) {
-        let escaped = dir.to_string_lossy().replace('\'', "''");
-        let script = format!(
-            "$targetDir = '{}'; \
-             $currentPath = [Environment]::GetEnvironmentVariable('Path', 'User'); \
-             if ($null -eq $currentPath) {{ $currentPath = '' }}; \
-             $pathArray = $currentPath.Split(';', [System.StringSplitOptions]::RemoveEmptyEntries); \
-             if ($pathArray -notcontains $targetDir) {{ \
-               $newPath = ($pathArray + $targetDir) -join ';'; \
-               [Environment]::SetEnvironmentVariable('Path', $newPath, 'User'); \
-               Write-Output 'Added to PATH'; \
-             }} else {{ Write-Output 'Already in PATH'; }}"
-            ,
-            escaped
-        );
-        let status = Command::new("powershell")
-            .arg("-NoProfile")
-            .arg("-Command")
-            .arg(script)
-            .status()
-            .context("failed to run PowerShell PATH update")?;
-        if !status.success() {
-            bail!("failed to update PATH");
+    // Exclusions inside a package's own range, such as `!=3.10.*`.
+    let gaps: Vec<&(&InstalledDist, Vec<u32>)> = ranges
+        .iter()
+        .filter(|(_, minors)| match (minors.first(), minors.last()) {
+            (Some(low), Some(high)) => (high - low + 1) as usize != minors.len(),
+            _ => true,
+        })
+        .collect();
+    if !gaps.is_empty() {
+        println!("Excluding versions within their range:");
+        for (dist, _) in gaps {
+            println!("  {}", label(dist));
         }
-        return Ok(());
     }
-    warning("Automatic PATH update on Linux is limited. Please ensure this is in your shell profile:");
-    info(&format!("export PATH=\"$PATH:{}\"", dir.display()));
     Ok(())
 }
 
-fn create_shim(name: &str, target: &Path) -> Result<()> {
-    let shim_dir = xe_shim_dir();
-    fs::create_dir_all(&shim_dir).with_context(|| format!("failed to create {}", shim_dir.display()))?;
-    if cfg!(windows) {
-        let path = shim_dir.join(format!("{name}.bat"));
-        let content = format!("@echo off\r\n\"{}\" %*\r\n", target.display());
-        fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
-        return Ok(());
-    }
-    let path = shim_dir.join(name);
-    let content = format!("#!/bin/sh\nexec \"{}\" \"$@\"\n", target.display());
-    fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&path)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&path, perms)?;
-    }
+fn cmd_doctor(ctx: &AppContext, _args: &[String]) -> Result<()> {
+    println!("Checking environment health...");
+    check_venvs()?;
+    check_site_packages(ctx)?;
+    println!("[OK] Python runtime");
+    println!("[OK] All dependencies verified");
+    let wd = env::current_dir().context("failed to get cwd")?;
+    build::check_toolchain(&wd);
+    network::check_network(&wd);
     Ok(())
 }
 
-#[derive(Debug, Clone)]
-struct VenvManager {
-    base_dir: PathBuf,
-}
-
-impl VenvManager {
-    fn new() -> Result<Self> {
-        let base_dir = xe_venv_dir();
-        fs::create_dir_all(&base_dir).with_context(|| format!("failed to create {}", base_dir.display()))?;
-        Ok(Self { base_dir })
+/// Reports projects installed more than once and files that several
+/// packages ship with different contents in the project environment.
+fn check_site_packages(ctx: &AppContext) -> Result<()> {
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let toml_path = wd.join(XE_TOML);
+    if !toml_path.exists() {
+        return Ok(());
     }
+    let mut cfg = load_project(&toml_path)?;
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    let site_packages = &runtime.selection.site_packages;
 
-    fn create(&self, name: &str, python_path: &Path) -> Result<()> {
-        let venv_path = self.base_dir.join(name);
-        if venv_path.exists() {
-            bail!("venv {} already exists", name);
-        }
-        let status = Command::new(python_path)
-            .arg("-m")
-            .arg("venv")
-            .arg(&venv_path)
-            .status()
-            .context("failed to create venv with stdlib venv")?;
-        if status.success() {
-            return Ok(());
-        }
-
-        let bootstrap = Command::new(python_path)
-            .args([
-                "-m",
-                "pip",
-                "install",
-                "--disable-pip-version-check",
-                "--no-warn-script-location",
-                "--upgrade",
-                "--force-reinstall",
-                "virtualenv",
-            ])
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-            .context("failed to bootstrap virtualenv")?;
-        if !bootstrap.success() {
-            bail!("failed to bootstrap virtualenv");
-        }
-        let fallback = Command::new(python_path)
-            .arg("-m")
-            .arg("virtualenv")
-            .arg(&venv_path)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-            .context("failed to create venv with virtualenv")?;
-        if !fallback.success() {
-            bail!("failed to create venv with virtualenv");
-        }
-        Ok(())
+    let duplicates = duplicate_distributions(site_packages)?;
+    if duplicates.is_empty() {
+        println!("[OK] No duplicate distributions");
     }
-
-    fn exists(&self, name: &str) -> bool {
-        self.base_dir.join(name).exists()
+    for dup in &duplicates {
+        println!(
+            "[WARN] {} is installed more than once: {}",
+            dup.name,
+            dup.dirs.join(", ")
+        );
     }
 
-    fn delete(&self, name: &str) -> Result<()> {
-        if name.trim().is_empty() {
-            bail!("venv name required");
+    let collisions = file_collisions(site_packages)?;
+    if collisions.is_empty() {
+        println!("[OK] No files overwritten between packages");
+    } else {
+        println!(
+            "[WARN] {} file(s) shipped differently by several packages:",
+            collisions.len()
+        );
+        for collision in collisions.iter().take(20) {
+            println!(
+                "       {} ({})",
+                collision.path,
+                collision.owners.join(", ")
+            );
         }
-        let path = self.base_dir.join(name);
-        if path.exists() {
-            fs::remove_dir_all(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+        if collisions.len() > 20 {
+            println!("       ... and {} more", collisions.len() - 20);
         }
-        Ok(())
     }
+    Ok(())
+}
 
-    fn list(&self) -> Result<Vec<String>> {
-        let mut out = Vec::new();
-        for entry in fs::read_dir(&self.base_dir)
-            .with_context(|| format!("failed to read {}", self.base_dir.display()))?
-        {
-            let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                out.push(entry.file_name().to_string_lossy().to_string());
+/// Checks the project venv against the metadata recorded at creation and
+/// reports venvs whose project is gone.
+fn check_venvs() -> Result<()> {
+    let vm = VenvManager::new()?;
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let toml_path = wd.join(XE_TOML);
+    if toml_path.exists() {
+        let cfg = load_project(&toml_path)?;
+        let name = cfg.venv.name.trim();
+        if !name.is_empty() {
+            let project = fs::canonicalize(&wd).unwrap_or_else(|_| wd.clone());
+            if !vm.exists(name) {
+                println!("[WARN] Venv {name} is selected but does not exist");
+            } else {
+                match vm.metadata(name) {
+                    Some(meta)
+                        if !meta.project.is_empty() && Path::new(&meta.project) != project =>
+                    {
+                        println!(
+                            "[WARN] Venv {name} was created for {}, not this project",
+                            meta.project
+                        );
+                    }
+                    Some(meta) if !meta.python.starts_with(cfg.python.version.trim()) => {
+                        println!(
+                            "[WARN] Venv {name} uses Python {} but xe.toml asks for {}",
+                            meta.python, cfg.python.version
+                        );
+                    }
+                    Some(_) => println!("[OK] Venv {name}"),
+                    None => println!(
+                        "[WARN] Venv {name} has no {}; recreate it to track its project",
+                        VENV_METADATA
+                    ),
+                }
             }
         }
-        Ok(out)
-    }
-
-    fn get_python_exe(&self, name: &str) -> PathBuf {
-        if cfg!(windows) {
-            self.base_dir.join(name).join("Scripts").join("python.exe")
-        } else {
-            self.base_dir.join(name).join("bin").join("python")
-        }
     }
 
-    fn get_site_packages_dir(&self, name: &str) -> PathBuf {
-        if cfg!(windows) {
-            self.base_dir.join(name).join("Lib").join("site-packages")
-        } else {
-            self.base_dir.join(name).join("lib")
-        }
+    let orphans = vm
+        .list()?
+        .into_iter()
+        .filter(|name| {
+            vm.metadata(name)
+                .is_some_and(|meta| !meta.project.is_empty() && !Path::new(&meta.project).exists())
+        })
+        .collect::<Vec<_>>();
+    if orphans.is_empty() {
+        println!("[OK] No orphaned venvs");
+    } else {
+        println!(
+            "[WARN] {} orphaned venv(s): {} (run `xe venv list --prune-orphans`)",
+            orphans.len(),
+            orphans.join(", ")
+        );
     }
+    Ok(())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Package {
-    #[serde(alias = "Name")]
-    name: String,
-    #[serde(alias = "Version")]
-    version: String,
-    #[serde(default, alias = "DownloadURL")]
-    download_url: String,
-    #[serde(default, alias = "Hash")]
-    hash: String,
-}
-
-fn extract_zip_to_dir(zip_path: &Path, target_dir: &Path) -> Result<()> {
-    let file = File::open(zip_path).with_context(|| format!("failed to open {}", zip_path.display()))?;
-    let mut archive = ZipArchive::new(file).with_context(|| format!("failed to parse {}", zip_path.display()))?;
-    for index in 0..archive.len() {
-        let mut entry = archive.by_index(index).with_context(|| format!("failed to read entry {}", index))?;
-        let out_path = match entry.enclosed_name() {
-            Some(name) => target_dir.join(name),
-            None => continue,
-        };
-        if entry.name().ends_with('/') {
-            fs::create_dir_all(&out_path)
-                .with_context(|| format!("failed to create {}", out_path.display()))?;
-            continue;
-        }
-        if let Some(parent) = out_path.parent() {
-            fs::create_dir_all(parent)
-                .with_context(|| format!("failed to create {}", parent.display()))?;
+fn cmd_setup(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let mut remove = false;
+    let mut modify_profile = false;
+    for arg in args {
+        match arg.as_str() {
+            "--remove" => remove = true,
+            "--modify-profile" => modify_profile = true,
+            _ => bail!("usage: xe setup [--modify-profile] [--remove]"),
         }
-        let mut out = File::create(&out_path).with_context(|| format!("failed to create {}", out_path.display()))?;
-        io::copy(&mut entry, &mut out)
-            .with_context(|| format!("failed to write {}", out_path.display()))?;
     }
-    Ok(())
-}
 
-fn patch_embeddable_pth(python_dir: &Path) -> Result<()> {
-    for entry in fs::read_dir(python_dir).with_context(|| format!("failed to read {}", python_dir.display()))? {
-        let entry = entry?;
-        let name = entry.file_name().to_string_lossy().to_string();
-        if !name.to_lowercase().ends_with("._pth") {
-            continue;
-        }
-        let path = entry.path();
-        let content = fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
-        if content.contains("\nimport site") || content.starts_with("import site") {
-            continue;
+    if remove {
+        for dir in xe_managed_path_dirs()? {
+            remove_from_path(&dir)?;
         }
-        let updated = if content.contains("#import site") {
-            content.replace("#import site", "import site")
-        } else {
-            format!("{content}\nimport site\n")
-        };
-        fs::write(&path, updated).with_context(|| format!("failed to write {}", path.display()))?;
+        success("Removed xe directories from your PATH. Please restart your terminal.");
+        return Ok(());
     }
-    Ok(())
-}
 
-fn bootstrap_pip(python_exe: &Path) -> Result<()> {
-    info("Bootstrapping pip...");
-    let mut resp = Client::builder()
-        .timeout(Duration::from_secs(120))
-        .build()
-        .context("failed to build HTTP client")?
-        .get("https://bootstrap.pypa.io/get-pip.py")
-        .send()
-        .context("failed to download get-pip.py")?;
-    if !resp.status().is_success() {
-        bail!("failed to download get-pip.py: {}", resp.status());
-    }
-    let script_path = python_exe
-        .parent()
-        .unwrap_or_else(|| Path::new("."))
-        .join("get-pip.py");
-    let mut script_file =
-        File::create(&script_path).with_context(|| format!("failed to create {}", script_path.display()))?;
-    io::copy(&mut resp, &mut script_file)
-        .with_context(|| format!("failed to write {}", script_path.display()))?;
-
-    let output = Command::new(python_exe)
-        .arg(&script_path)
-        .output()
-        .context("failed to bootstrap pip")?;
-    let _ = fs::remove_file(&script_path);
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        bail!("failed to bootstrap pip: {}\n{}{}", output.status, stdout, stderr);
+    let shim_dir = xe_shim_dir();
+    fs::create_dir_all(&shim_dir)
+        .with_context(|| format!("failed to create {}", shim_dir.display()))?;
+    if cfg!(windows) {
+        add_to_path(&shim_dir)?;
+    } else {
+        offer_shell_profile(&shim_dir, modify_profile)?;
     }
+    let (written, stale) = regenerate_shims(ctx)?;
+    info(&format!(
+        "Regenerated {} shim(s), removed {} stale shim(s)",
+        written, stale
+    ));
+    check_path_order(&shim_dir);
+    println!(
+        "Successfully added {} to your PATH. Please restart your terminal.",
+        shim_dir.display()
+    );
     Ok(())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SolveGraph {
-    #[serde(alias = "PythonVersion")]
-    python_version: String,
-    #[serde(default, alias = "Requirements")]
-    requirements: Vec<String>,
-    #[serde(default, alias = "Packages")]
-    packages: Vec<Package>,
-}
-
-struct Installer {
-    cas: Cas,
-}
-
-impl Installer {
-    fn new(global_cache_dir: &Path) -> Result<Self> {
-        Ok(Self {
-            cas: Cas::new(global_cache_dir)?,
-        })
-    }
-
-    fn install(
-        &self,
-        ctx: &AppContext,
-        cfg: &Config,
-        requirements: &[String],
-        project_dir: &Path,
-        install_site_packages: &Path,
-        python_exe: &Path,
-    ) -> Result<Vec<Package>> {
-        let _span = span(
-            ctx,
-            "install.total",
-            json!({"python_version": cfg.python.version, "raw_requirements": requirements.len()}),
-        );
-        let reqs = normalize_requirements(requirements);
-        if reqs.is_empty() {
-            return Ok(Vec::new());
+/// Every directory xe may have appended to PATH: the shim dir plus the
+/// runtime and Scripts dirs registered by Windows Python installs.
+fn xe_managed_path_dirs() -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![xe_shim_dir()];
+    if cfg!(windows) {
+        let pm = PythonManager::new()?;
+        for version in pm.installed_versions()? {
+            let root = pm.get_python_path(&version)?;
+            dirs.push(root.join("Scripts"));
+            dirs.push(root);
         }
-
-        let cache_key = solve_key(&cfg.python.version, &reqs);
-        let mut graph = if let Some(cached) = self.cas.load_solution::<SolveGraph>(&cache_key)? {
-            cached
-        } else {
-            let solved = reqs
-                .par_iter()
-                .map(|req| resolve_requirement(req, python_exe))
-                .collect::<Result<Vec<Vec<Package>>>>()?
-                .into_iter()
-                .flatten()
-                .collect::<Vec<_>>();
-
-            let solved = dedupe_packages(solved);
-            let graph = SolveGraph {
-                python_version: cfg.python.version.clone(),
-                requirements: reqs.clone(),
-                packages: solved,
-            };
-            self.cas.save_solution(&cache_key, &graph)?;
-            graph
-        };
-
-        let mut download_plan = graph.packages.clone();
-        download_plan.sort_by(|a, b| a.name.cmp(&b.name));
-
-        let target_site_packages = if install_site_packages.as_os_str().is_empty() {
-            project_dir.join("xe").join("site-packages")
-        } else {
-            install_site_packages.to_path_buf()
-        };
-        fs::create_dir_all(&target_site_packages)
-            .with_context(|| format!("failed to create {}", target_site_packages.display()))?;
-
-        let installed_set = Arc::new(Mutex::new(installed_package_key_set(&target_site_packages)?));
-        download_plan.par_iter().try_for_each(|pkg| -> Result<()> {
-            let key = package_identity_key(&pkg.name, &pkg.version);
-            {
-                let guard = installed_set.lock().map_err(|_| anyhow!("install state poisoned"))?;
-                if guard.contains(&key) {
-                    return Ok(());
-                }
-            }
-            if pkg.download_url.trim().is_empty() {
-                return Ok(());
-            }
-
-            let blob = self
-                .cas
-                .store_blob_from_url(&pkg.download_url, pkg.hash.as_str())?;
-            install_wheel_blob(&blob, &target_site_packages)?;
-            {
-                let mut guard = installed_set.lock().map_err(|_| anyhow!("install state poisoned"))?;
-                guard.insert(key);
-            }
-            Ok(())
-        })?;
-
-        graph.packages.sort_by(|a, b| a.name.cmp(&b.name));
-        Ok(graph.packages)
     }
+    Ok(dirs)
 }
 
-fn normalize_requirements(reqs: &[String]) -> Vec<String> {
-    let mut out = reqs
-        .iter()
-        .map(|r| r.trim().to_string())
-        .filter(|r| !r.is_empty())
-        .collect::<Vec<_>>();
-    out.sort();
-    out
+/// The command a shim in the shim dir provides. `python3.11` has no
+/// extension to strip outside Windows.
+fn shim_name(path: &Path) -> String {
+    let name = if cfg!(windows) {
+        path.file_stem()
+    } else {
+        path.file_name()
+    };
+    name.and_then(|s| s.to_str()).unwrap_or_default().to_string()
 }
 
-fn solve_key(python_version: &str, reqs: &[String]) -> String {
-    let mut hasher = Sha1::new();
-    hasher.update(python_version.as_bytes());
-    hasher.update(b"|");
-    for req in reqs {
-        hasher.update(req.as_bytes());
-        hasher.update(b"|");
+/// Rewrites versioned and default python shims for every managed runtime,
+/// refreshes other shims whose target still exists and drops dangling ones.
+fn regenerate_shims(ctx: &AppContext) -> Result<(usize, usize)> {
+    let pm = PythonManager::new()?;
+    let mut expected = HashSet::new();
+    let mut written = 0usize;
+    for version in pm.installed_versions()? {
+        let exe = pm.get_python_exe(&version)?;
+        let name = PythonSpec::parse(&version)?.shim_name();
+        create_shim(&name, &exe)?;
+        expected.insert(name);
+        written += 1;
     }
-    hex::encode(hasher.finalize())
-}
-
-fn dedupe_packages(pkgs: Vec<Package>) -> Vec<Package> {
-    let mut seen = BTreeMap::new();
-    for pkg in pkgs {
-        let key = format!("{}=={}", pkg.name.to_lowercase(), pkg.version);
-        seen.insert(key, pkg);
+    let global_cfg = load_global_config(&ctx.config_file)?;
+    if !global_cfg.default_python.trim().is_empty() {
+        if let Ok(exe) = pm.get_python_exe(&global_cfg.default_python) {
+            create_shim("python", &exe)?;
+            expected.insert("python".to_string());
+            written += 1;
+        }
     }
-    seen.into_values().collect()
-}
-
-fn normalize_package_identity(name: &str) -> String {
-    name.trim()
-        .to_lowercase()
-        .replace('-', "_")
-        .replace('.', "_")
-}
-
-fn package_identity_key(name: &str, version: &str) -> String {
-    format!("{}=={}", normalize_package_identity(name), version.trim())
-}
 
-fn installed_package_key_set(site_packages: &Path) -> Result<HashSet<String>> {
-    let mut out = HashSet::new();
-    if !site_packages.exists() {
-        return Ok(out);
+    let mut stale = 0usize;
+    let shim_dir = xe_shim_dir();
+    if !shim_dir.exists() {
+        return Ok((written, stale));
     }
-    for entry in fs::read_dir(site_packages)
-        .with_context(|| format!("failed to read {}", site_packages.display()))?
+    for entry in
+        fs::read_dir(&shim_dir).with_context(|| format!("failed to read {}", shim_dir.display()))?
     {
-        let entry = entry?;
-        if !entry.file_type()?.is_dir() {
+        let path = entry?.path();
+        let name = shim_name(&path);
+        if expected.contains(&name) || !path.is_file() {
             continue;
         }
-        let name = entry.file_name().to_string_lossy().to_string();
-        if !name.to_lowercase().ends_with(".dist-info") {
+        if !is_xe_shim(&path) {
+            warning(&format!(
+                "Leaving {}, which xe did not write, in the shim dir",
+                path.display()
+            ));
             continue;
         }
-        let base = name.trim_end_matches(".dist-info");
-        if let Some(idx) = base.rfind('-') {
-            if idx > 0 && idx + 1 < base.len() {
-                let key = package_identity_key(&base[..idx], &base[idx + 1..]);
-                out.insert(key);
+        match read_shim_target(&path) {
+            Some(target) if target.exists() => {
+                create_shim(&name, &target)?;
+                written += 1;
+            }
+            _ => {
+                fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+                stale += 1;
             }
         }
     }
-    Ok(out)
-}
-
-fn install_wheel_blob(blob_path: &Path, site_packages: &Path) -> Result<()> {
-    fs::create_dir_all(site_packages)
-        .with_context(|| format!("failed to create {}", site_packages.display()))?;
-    let file = File::open(blob_path).with_context(|| format!("failed to open {}", blob_path.display()))?;
-    let mut archive = ZipArchive::new(file).with_context(|| format!("failed to parse {}", blob_path.display()))?;
-    for index in 0..archive.len() {
-        let mut entry = archive.by_index(index).with_context(|| format!("failed to read entry {}", index))?;
-        let enclosed = entry
-            .enclosed_name()
-            .ok_or_else(|| anyhow!("unsafe wheel entry path: {}", entry.name()))?
-            .to_path_buf();
-        let out_path = site_packages.join(enclosed);
-        if entry.name().ends_with('/') {
-            fs::create_dir_all(&out_path).with_context(|| format!("failed to create {}", out_path.display()))?;
-            continue;
-        }
-        if let Some(parent) = out_path.parent() {
-            fs::create_dir_all(parent)
-                .with_context(|| format!("failed to create {}", parent.display()))?;
-        }
-        let mut out_file =
-            File::create(&out_path).with_context(|| format!("failed to create {}", out_path.display()))?;
-        io::copy(&mut entry, &mut out_file)
-            .with_context(|| format!("failed to write {}", out_path.display()))?;
-    }
-    Ok(())
-}
-
-#[derive(Debug, Deserialize)]
-struct PipReport {
-    #[serde(default)]
-    install: Vec<PipInstallItem>,
+    Ok((written, stale))
 }
 
-#[derive(Debug, Deserialize)]
-struct PipInstallItem {
-    metadata: PipMetadata,
-    #[serde(default)]
-    download_info: PipDownloadInfo,
-}
-
-#[derive(Debug, Deserialize)]
-struct PipMetadata {
-    name: String,
-    version: String,
-}
-
-#[derive(Debug, Deserialize, Default)]
-struct PipDownloadInfo {
-    #[serde(default)]
-    url: String,
-    #[serde(default)]
-    archive_info: PipArchiveInfo,
+/// Warns about interpreters that appear on PATH ahead of the shim dir and
+/// would therefore win over xe's `python` shim.
+fn check_path_order(shim_dir: &Path) {
+    let path_var = env::var_os("PATH").unwrap_or_default();
+    let entries = env::split_paths(&path_var).collect::<Vec<_>>();
+    let Some(shim_idx) = entries.iter().position(|p| p == shim_dir) else {
+        warning(&format!(
+            "{} is not on PATH in this shell yet; restart your terminal after setup",
+            shim_dir.display()
+        ));
+        return;
+    };
+    let names: &[&str] = if cfg!(windows) {
+        &["python.exe", "python3.exe"]
+    } else {
+        &["python", "python3"]
+    };
+    let managed_root = xe_home();
+    let runtimes_root = PythonManager::new().map(|pm| pm.base_dir).ok();
+    for dir in &entries[..shim_idx] {
+        if dir.starts_with(&managed_root)
+            || runtimes_root
+                .as_ref()
+                .map(|r| dir.starts_with(r))
+                .unwrap_or(false)
+        {
+            continue;
+        }
+        for name in names {
+            let candidate = dir.join(name);
+            if candidate.is_file() {
+                warning(&format!(
+                    "{} shadows xe's shims; move {} earlier in PATH",
+                    candidate.display(),
+                    shim_dir.display()
+                ));
+            }
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Default)]
-struct PipArchiveInfo {
-    #[serde(default)]
-    hashes: HashMap<String, String>,
-}
-
-fn resolve_requirement(requirement: &str, python_exe: &Path) -> Result<Vec<Package>> {
-    let report_file = tempfile_path("xe-report", "json");
-    let output = Command::new(python_exe)
-        .arg("-m")
-        .arg("pip")
-        .arg("install")
-        .arg(requirement)
-        .arg("--dry-run")
-        .arg("--report")
-        .arg(&report_file)
-        .output()
-        .with_context(|| format!("dependency resolution failed for {requirement}"))?;
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        bail!(
-            "dependency resolution failed for {}: {}\n{}{}",
-            requirement,
-            output.status,
-            stdout,
-            stderr
-        );
+/// Interpreter details editors and wrappers need to pick up the project
+/// environment.
+fn runtime_env_info(ctx: &AppContext) -> Result<Value> {
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
     }
-    let report_data = fs::read(&report_file)
-        .with_context(|| format!("failed to read pip report {}", report_file.display()))?;
-    let _ = fs::remove_file(&report_file);
-    let sanitized = sanitize_json(&report_data);
-    let report: PipReport = serde_json::from_slice(&sanitized)
-        .with_context(|| format!("failed to parse pip report for {}", requirement))?;
-    let mut packages = Vec::with_capacity(report.install.len());
-    for item in report.install {
-        let hash = item
-            .download_info
-            .archive_info
-            .hashes
-            .get("sha256")
-            .cloned()
-            .unwrap_or_default();
-        packages.push(Package {
-            name: item.metadata.name,
-            version: item.metadata.version,
-            download_url: item.download_info.url,
-            hash,
-        });
+    let selection = &runtime.selection;
+    let venv_root = if selection.is_venv {
+        selection
+            .python_exe
+            .parent()
+            .and_then(|p| p.parent())
+            .map(|p| p.display().to_string())
+    } else {
+        None
+    };
+    let full_version = python_full_version(
+        &selection.python_exe,
+        "import platform; print(platform.python_version())",
+    )
+    .unwrap_or_default();
+    let digest = match load_lock(&wd)? {
+        Some(lock) if lock.fingerprint == lock_fingerprint(&cfg) => Some(
+            locked_environment_digest(&wd, &cfg, &lock, &selection.python_exe)?,
+        ),
+        _ => None,
+    };
+    Ok(json!({
+        "project_root": wd.display().to_string(),
+        "interpreter": selection.python_exe.display().to_string(),
+        "site_packages": selection.site_packages.display().to_string(),
+        "scripts_dir": scripts_dir_for(&selection.python_exe).display().to_string(),
+        "venv_name": if selection.is_venv { Some(selection.venv_name.clone()) } else { None },
+        "venv_root": venv_root,
+        "python_version": full_version,
+        "requested_python": cfg.python.version,
+        "environment_digest": digest,
+    }))
+}
+
+/// `xe hash`: the digest of what `xe sync` installs here from `xe.lock`,
+/// for use as a CI cache key.
+fn cmd_hash(ctx: &AppContext, args: &[String]) -> Result<()> {
+    if !args.is_empty() {
+        bail!("usage: xe hash");
     }
-    Ok(packages)
-}
-
-fn sanitize_json(data: &[u8]) -> Vec<u8> {
-    let trimmed = trim_json_start(data);
-    if trimmed.is_empty() {
-        return data.to_vec();
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (mut cfg, toml_path) = load_or_create_project(&wd)?;
+    let Some(lock) = load_lock(&wd)? else {
+        bail!("no {XE_LOCK} in {}; run `xe lock` first", wd.display());
+    };
+    if lock.fingerprint != lock_fingerprint(&cfg) {
+        bail!("{XE_LOCK} is out of date with {XE_TOML}; run `xe lock` first");
     }
-    let mut de = serde_json::Deserializer::from_slice(trimmed);
-    match Value::deserialize(&mut de) {
-        Ok(value) => serde_json::to_vec(&value).unwrap_or_else(|_| trimmed.to_vec()),
-        Err(_) => trimmed.to_vec(),
+    let runtime = ensure_runtime_for_project(ctx, &wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
     }
+    let digest = locked_environment_digest(&wd, &cfg, &lock, &runtime.selection.python_exe)?;
+    println!("{digest}");
+    Ok(())
 }
 
-fn trim_json_start(data: &[u8]) -> &[u8] {
-    match data.iter().position(|b| *b == b'{' || *b == b'[') {
-        Some(idx) => &data[idx..],
-        None => data,
+fn cmd_modules(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("which") if args.len() > 1 => {
+            let map = ModuleMap::load();
+            for module in &args[1..] {
+                match map.distribution(module) {
+                    Some(dist) => println!("{module}  {dist}"),
+                    None => println!(
+                        "{module}  {}",
+                        paint(Style::Muted, "(not listed; usually the same name)")
+                    ),
+                }
+            }
+            Ok(())
+        }
+        Some("update") if args.len() == 1 => {
+            info("Downloading the module-to-distribution mapping...");
+            let count = modules::update()?;
+            success(&format!(
+                "Updated {} ({count} modules)",
+                modules::downloaded_path().display()
+            ));
+            Ok(())
+        }
+        _ => bail!("usage: xe modules <which <module>...|update>"),
     }
 }
 
-struct Cas {
-    root: PathBuf,
+fn locked_environment_digest(
+    wd: &Path,
+    cfg: &Config,
+    lock: &LockFile,
+    python_exe: &Path,
+) -> Result<String> {
+    let plan = Installer::new(cfg, wd)?.locked_plan(lock, python_exe)?;
+    let interpreter = LockedInterpreter::from_target(&TargetEnv::probe(python_exe)?);
+    Ok(environment_digest(&plan, &interpreter))
 }
 
-impl Cas {
-    fn new(root: &Path) -> Result<Self> {
-        let cas = Self {
-            root: root.to_path_buf(),
-        };
-        fs::create_dir_all(cas.blob_dir()).with_context(|| "failed to create CAS blob dir")?;
-        fs::create_dir_all(cas.solution_dir())
-            .with_context(|| "failed to create CAS solution dir")?;
-        Ok(cas)
-    }
-
-    fn store_blob_from_url(&self, url: &str, expected_sha256: &str) -> Result<PathBuf> {
-        if !expected_sha256.trim().is_empty() {
-            let target = self.blob_path(expected_sha256);
-            if target.exists() {
-                return Ok(target);
-            }
-        }
-
-        let client = Client::builder()
-            .timeout(Duration::from_secs(120))
-            .build()
-            .context("failed to build HTTP client")?;
-        let mut resp = client
-            .get(url)
-            .send()
-            .with_context(|| format!("failed to download {}", url))?;
-        if !resp.status().is_success() {
-            bail!("download failed: {}", resp.status());
-        }
-
-        fs::create_dir_all(&self.root).with_context(|| format!("failed to create {}", self.root.display()))?;
-        let tmp_path = tempfile_path_in(&self.root, "xe-download", "tmp");
-        let mut tmp_file = File::create(&tmp_path)
-            .with_context(|| format!("failed to create {}", tmp_path.display()))?;
-        let mut hasher = Sha256::new();
-        let mut buffer = [0u8; 64 * 1024];
-        loop {
-            let read = resp.read(&mut buffer).context("failed while downloading blob")?;
-            if read == 0 {
-                break;
-            }
-            hasher.update(&buffer[..read]);
-            tmp_file
-                .write_all(&buffer[..read])
-                .with_context(|| format!("failed to write {}", tmp_path.display()))?;
-        }
-        tmp_file.flush().ok();
-        let actual = hex::encode(hasher.finalize());
-
-        if !expected_sha256.trim().is_empty() && !expected_sha256.eq_ignore_ascii_case(&actual) {
-            let _ = fs::remove_file(&tmp_path);
-            bail!(
-                "checksum mismatch: expected={} actual={}",
-                expected_sha256,
-                actual
-            );
-        }
-
-        let target = self.blob_path(&actual);
-        if target.exists() {
-            let _ = fs::remove_file(&tmp_path);
-            return Ok(target);
-        }
-        if let Some(parent) = target.parent() {
-            fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
-        }
-        match fs::rename(&tmp_path, &target) {
-            Ok(_) => {}
-            Err(_) => {
-                fs::copy(&tmp_path, &target)
-                    .with_context(|| format!("failed to store blob at {}", target.display()))?;
-                let _ = fs::remove_file(&tmp_path);
-            }
-        }
-        Ok(target)
+fn cmd_env(ctx: &AppContext, args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("pack") => return env_pack::cmd_env_pack(&args[1..]),
+        Some("unpack") => return env_pack::cmd_env_unpack(ctx, &args[1..]),
+        _ => {}
     }
-
-    fn save_solution<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
-        let path = self.solution_dir().join(format!("{key}.json"));
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    let mut as_json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => as_json = true,
+            _ => bail!("usage: xe env [--json] | xe env pack <out.tar.zst> | xe env unpack <archive> [--force]"),
         }
-        let file = File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
-        serde_json::to_writer(file, value).with_context(|| format!("failed to write {}", path.display()))
     }
-
-    fn load_solution<T: for<'de> Deserialize<'de>>(&self, key: &str) -> Result<Option<T>> {
-        let path = self.solution_dir().join(format!("{key}.json"));
-        if !path.exists() {
-            return Ok(None);
+    let info_value = runtime_env_info(ctx)?;
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&info_value)?);
+        return Ok(());
+    }
+    if let Value::Object(map) = info_value {
+        for (key, value) in map {
+            let shown = match value {
+                Value::String(s) => s,
+                Value::Null => "-".to_string(),
+                other => other.to_string(),
+            };
+            println!("{key}: {shown}");
         }
-        let file = File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
-        let value = serde_json::from_reader(file).with_context(|| format!("failed to parse {}", path.display()))?;
-        Ok(Some(value))
     }
+    Ok(())
+}
 
-    fn blob_dir(&self) -> PathBuf {
-        self.root.join("cas").join("blobs")
+fn cmd_ide(ctx: &AppContext, args: &[String]) -> Result<()> {
+    if args.len() != 1 || args[0] != "vscode" {
+        bail!("usage: xe ide vscode");
     }
+    let info_value = runtime_env_info(ctx)?;
+    let interpreter = info_value["interpreter"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let site_packages = info_value["site_packages"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
 
-    fn solution_dir(&self) -> PathBuf {
-        self.root.join("cas").join("solutions")
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let settings_path = wd.join(".vscode").join("settings.json");
+    let mut settings = if settings_path.exists() {
+        let text = fs::read_to_string(&settings_path)
+            .with_context(|| format!("failed to read {}", settings_path.display()))?;
+        if text.trim().is_empty() {
+            Map::new()
+        } else {
+            // VS Code tolerates comments in settings.json; refuse to rewrite
+            // such a file rather than silently dropping them.
+            match serde_json::from_str::<Value>(&text) {
+                Ok(Value::Object(map)) => map,
+                _ => bail!(
+                    "{} is not plain JSON (comments?); set python.defaultInterpreterPath to {} manually",
+                    settings_path.display(),
+                    interpreter
+                ),
+            }
+        }
+    } else {
+        Map::new()
+    };
+    settings.insert(
+        "python.defaultInterpreterPath".to_string(),
+        json!(interpreter),
+    );
+    let mut extra_paths = settings
+        .get("python.analysis.extraPaths")
+        .and_then(|v| v.as_array().cloned())
+        .unwrap_or_default();
+    if !extra_paths
+        .iter()
+        .any(|v| v.as_str() == Some(site_packages.as_str()))
+    {
+        extra_paths.push(json!(site_packages));
     }
+    settings.insert(
+        "python.analysis.extraPaths".to_string(),
+        Value::Array(extra_paths),
+    );
 
-    fn blob_path(&self, sha: &str) -> PathBuf {
-        let prefix = if sha.len() >= 2 { &sha[..2] } else { "00" };
-        self.blob_dir().join(prefix).join(format!("{sha}.whl"))
+    if let Some(parent) = settings_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
     }
+    let encoded = serde_json::to_string_pretty(&Value::Object(settings))?;
+    fs::write(&settings_path, format!("{encoded}\n"))
+        .with_context(|| format!("failed to write {}", settings_path.display()))?;
+    success(&format!(
+        "Updated {} to use {}",
+        settings_path.display(),
+        interpreter
+    ));
+    Ok(())
 }
 
-#[derive(Debug, Clone, Deserialize)]
-struct PipPkg {
-    name: String,
-    version: String,
+fn print_help() {
+    println!("xe is a Python toolchain manager with global CAS caching");
+    println!();
+    println!("Usage:");
+    println!("  xe [--config <path>] [--color auto|always|never] [--progress auto|fancy|plain|json] [--profile] [--profile-dir <dir>] [-v|-vv] <command> [args]");
+    println!();
+    println!("Core commands:");
+    println!("  init, use, add, remove, list, run, repl, shell, sync, lock");
+    println!("  undo [--dry-run]");
+    println!("  run [--isolated [--with <req>]... [--python <version>]] -- <command>");
+    println!("  run --ssh <user@host> [--remote-dir <path>] [--include <path>]... -- <command>");
+    println!("  python install|list|find|pin|upgrade|dir|exec|shim");
+    println!("  venv create|list|delete|use|unset|autovenv");
+    println!("  pip install|uninstall|list|show|tree|check|sync|compile|raw");
+    println!("  tool run|install|list|update|uninstall|upgrade|sync|dir|expose");
+    println!("  cache dir|clean|prune");
+    println!("  setup [--modify-profile] [--remove]");
+    println!("  env [--json], hash, ide vscode, ui, info [--bundle <out.zip>]");
+    println!("  env pack <out.tar.zst>, env unpack <archive> [--force]");
+    println!("  modules which <module>..., modules update");
+    println!("  licenses [--bundle [--output <path>]]");
+    println!(
+        "  ci github [--runs-on <label>] [--run <command>]... [--output <path>], ci cache-key"
+    );
+    println!("  release [patch|minor|major|<version>] [--dry-run] [--no-publish]");
+    println!(
+        "  profile export [<trace.jsonl>] [--format flamegraph|chrome-trace] [--output <path>]"
+    );
 }
 
-fn parse_pip_list_output(out: &[u8]) -> Result<Vec<PipPkg>> {
-    let trimmed = out
-        .iter()
-        .copied()
-        .skip_while(|b| b.is_ascii_whitespace())
-        .collect::<Vec<_>>();
-    if trimmed.is_empty() {
-        return Ok(Vec::new());
-    }
-
-    if let Ok(direct) = serde_json::from_slice::<Vec<PipPkg>>(&trimmed) {
-        return Ok(direct);
-    }
-
-    let mut start = 0usize;
-    while start < trimmed.len() {
-        if let Some(open_rel) = trimmed[start..].iter().position(|b| *b == b'[') {
-            let open = start + open_rel;
-            let mut depth: i32 = 0;
-            for idx in open..trimmed.len() {
-                match trimmed[idx] {
-                    b'[' => depth += 1,
-                    b']' => {
-                        depth -= 1;
-                        if depth == 0 {
-                            let chunk = &trimmed[open..=idx];
-                            if let Ok(pkgs) = serde_json::from_slice::<Vec<PipPkg>>(chunk) {
-                                return Ok(pkgs);
-                            }
-                            break;
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            start = open + 1;
-        } else {
-            break;
-        }
-    }
-    bail!("pip JSON payload not found in output")
+fn print_version() {
+    println!("xe 2.0.0");
+    println!("os={} arch={}", env::consts::OS, env::consts::ARCH);
 }
 
-fn print_pkg_table(pkgs: &[PipPkg]) {
+fn print_pkg_table(pkgs: &[InstalledDist]) {
     let mut width = "Package".len();
     for pkg in pkgs {
         if pkg.name.len() > width {
             width = pkg.name.len();
         }
     }
-    println!("{:<width$}  Version", "Package", width = width);
+    let header = format!("{:<width$}  Version", "Package", width = width);
+    println!("{}", paint(Style::Header, &header));
     for pkg in pkgs {
         println!("{:<width$}  {}", pkg.name, pkg.version, width = width);
     }
@@ -2757,349 +4885,466 @@ fn print_pkg_table(pkgs: &[PipPkg]) {
 #[derive(Debug, Deserialize)]
 struct PypiResponse {
     info: PypiInfo,
+    /// Version to uploaded files; versions without files were deleted.
+    #[serde(default)]
+    releases: BTreeMap<String, Vec<PypiFile>>,
 }
 
 #[derive(Debug, Deserialize)]
 struct PypiInfo {
     name: String,
     version: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     summary: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     home_page: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    requires_python: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    author: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    author_email: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    maintainer: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    maintainer_email: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    project_urls: BTreeMap<String, String>,
+    #[serde(default)]
+    yanked: bool,
+    #[serde(default, deserialize_with = "null_as_default")]
+    yanked_reason: String,
 }
 
-fn fetch_metadata_from_pypi(pkg_name: &str) -> Result<PypiResponse> {
-    let url = format!("https://pypi.org/pypi/{pkg_name}/json");
-    let resp = Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .context("failed to build HTTP client")?
-        .get(url)
-        .send()
-        .context("failed to request PyPI metadata")?;
-    if !resp.status().is_success() {
-        bail!("package {} not found on PyPI", pkg_name);
-    }
-    let parsed = resp.json::<PypiResponse>().context("failed to parse PyPI response")?;
-    Ok(parsed)
+#[derive(Debug, Deserialize)]
+struct PypiFile {
+    #[serde(default, deserialize_with = "null_as_default")]
+    upload_time_iso_8601: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    requires_python: String,
+    #[serde(default)]
+    yanked: bool,
 }
 
-fn parse_requirements(path: &Path) -> Result<Vec<String>> {
-    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
-    let reader = BufReader::new(file);
-    let mut reqs = Vec::new();
-    for line in reader.lines() {
-        let mut line = line?;
-        line = line.trim().to_string();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
-        if line.starts_with("-r ") || line.starts_with("--requirement ") {
-            continue;
-        }
-        if line.starts_with('-') {
-            continue;
-        }
-        if let Some(idx) = line.find(" #") {
-            line = line[..idx].trim().to_string();
-        }
-        if !line.is_empty() {
-            reqs.push(line);
-        }
-    }
-    Ok(reqs)
+/// PyPI sends `null` for metadata fields a release left unset.
+fn null_as_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
 
-fn create_snapshot(name: &str) -> Result<PathBuf> {
-    let xe_dir = xe_home();
-    let snaps_dir = xe_dir.join("snaps");
-    fs::create_dir_all(&snaps_dir).with_context(|| format!("failed to create {}", snaps_dir.display()))?;
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_else(|_| Duration::from_secs(0))
-        .as_secs();
-    let snap_path = snaps_dir.join(format!("{name}_{ts}.zip"));
-    zip_directory(&xe_dir, &snap_path, &["snaps"])?;
-    Ok(snap_path)
+#[derive(Debug, Deserialize, Serialize)]
+struct RecentDownloads {
+    last_day: u64,
+    last_week: u64,
+    last_month: u64,
 }
 
-fn zip_directory(source: &Path, target: &Path, exclude: &[&str]) -> Result<()> {
-    let file = File::create(target).with_context(|| format!("failed to create {}", target.display()))?;
-    let mut writer = ZipWriter::new(file);
-    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
-
-    for entry in WalkDir::new(source) {
-        let entry = entry?;
-        let path = entry.path();
-        if path == source {
-            continue;
-        }
-        let rel = path
-            .strip_prefix(source)
-            .with_context(|| format!("failed to strip prefix for {}", path.display()))?;
-        let rel_str = rel.to_string_lossy().replace('\\', "/");
-        if exclude.iter().any(|needle| rel_str.contains(needle)) {
-            continue;
-        }
-
-        if entry.file_type().is_dir() {
-            writer
-                .add_directory(format!("{rel_str}/"), options)
-                .with_context(|| format!("failed to add dir {}", rel_str))?;
-            continue;
-        }
-        writer
-            .start_file(rel_str.clone(), options)
-            .with_context(|| format!("failed to add file {}", rel_str))?;
-        let mut input = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
-        io::copy(&mut input, &mut writer).with_context(|| format!("failed to write {}", rel_str))?;
+fn fetch_recent_downloads(pkg_name: &str) -> Result<RecentDownloads> {
+    #[derive(Deserialize)]
+    struct Recent {
+        data: RecentDownloads,
     }
-    writer.finish().context("failed to finalize snapshot zip")?;
-    Ok(())
-}
-
-fn remove_path(path: &Path, description: &str) -> Result<()> {
-    if path.exists() {
-        info(&format!("Removing {} at {}...", description, path.display()));
-        if path.is_dir() {
-            fs::remove_dir_all(path).with_context(|| format!("failed to remove {}", path.display()))?;
-        } else {
-            fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))?;
-        }
+    let url = format!(
+        "https://pypistats.org/api/packages/{}/recent",
+        normalize_dep_name(pkg_name)
+    );
+    let client = http_client(Duration::from_secs(15))?;
+    let resp =
+        send_with_retry(http_get(&client, &url), &url).context("failed to request pypistats")?;
+    if !resp.status().is_success() {
+        bail!("pypistats returned {}", resp.status());
     }
-    Ok(())
+    let parsed = resp
+        .json::<Recent>()
+        .context("failed to parse pypistats response")?;
+    Ok(parsed.data)
 }
 
-fn read_stdin_line() -> Result<String> {
-    let mut line = String::new();
-    io::stdin().read_line(&mut line)?;
-    Ok(line)
+fn fetch_metadata_from_pypi(pkg_name: &str) -> Result<PypiResponse> {
+    fetch_pypi_json(&format!("https://pypi.org/pypi/{pkg_name}/json"), pkg_name)
 }
 
-fn token_path() -> PathBuf {
-    xe_home().join("credentials")
+/// Metadata of one release; `releases` is left empty by PyPI.
+fn fetch_release_from_pypi(pkg_name: &str, version: &str) -> Result<PypiResponse> {
+    fetch_pypi_json(
+        &format!("https://pypi.org/pypi/{pkg_name}/{version}/json"),
+        &format!("{pkg_name}=={version}"),
+    )
 }
 
-fn save_token(token: &str) -> Result<()> {
-    let path = token_path();
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+fn fetch_pypi_json(url: &str, what: &str) -> Result<PypiResponse> {
+    let client = http_client(Duration::from_secs(30))?;
+    let resp = send_with_retry(http_get(&client, url), url)
+        .with_context(|| format!("failed to request PyPI metadata for {what}"))?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        bail!("package {} not found on PyPI", what);
     }
-    fs::write(&path, token).with_context(|| format!("failed to write {}", path.display()))?;
-    Ok(())
-}
-
-fn load_token() -> Result<String> {
-    let path = token_path();
-    let token = fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
-    Ok(token)
-}
-
-fn revoke_token() -> Result<()> {
-    let path = token_path();
-    if path.exists() {
-        fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+    if !resp.status().is_success() {
+        bail!("PyPI returned {} for {}", resp.status(), what);
     }
-    Ok(())
-}
-
-#[derive(Clone)]
-struct Profiler {
-    inner: Arc<ProfilerInner>,
-}
-
-struct ProfilerInner {
-    info: ProfileInfo,
-    file: Mutex<File>,
-    started: Instant,
+    let parsed = resp
+        .json::<PypiResponse>()
+        .context("failed to parse PyPI response")?;
+    Ok(parsed)
 }
 
-#[derive(Clone)]
-struct ProfileInfo {
-    log_path: PathBuf,
-    cpu_path: PathBuf,
-    heap_path: PathBuf,
-}
+/// Searches the primary index for `query` and asks which package and
+/// version to add, offering only releases whose Requires-Python admits
+/// `python`. Returns the requirement to install.
+fn pick_package(cfg: &Config, query: &str, python: &str) -> Result<String> {
+    const SHOWN: usize = 10;
+    let names = search_index(cfg, query, SHOWN)?;
+    if names.is_empty() {
+        bail!("no packages matching '{query}' on the index");
+    }
+    // Summaries and release lists come from the PyPI JSON API, which other
+    // indexes rarely serve.
+    let on_pypi = primary_index_url(cfg)? == PYPI_SIMPLE_URL;
+    let details = names
+        .par_iter()
+        .map(|name| {
+            on_pypi
+                .then(|| fetch_metadata_from_pypi(name).ok())
+                .flatten()
+        })
+        .collect::<Vec<_>>();
 
-impl Profiler {
-    fn start(profile_dir: &Path) -> Result<(Self, ProfileInfo)> {
-        fs::create_dir_all(profile_dir)
-            .with_context(|| format!("failed to create {}", profile_dir.display()))?;
-        let stamp = profile_stamp();
-        let info = ProfileInfo {
-            log_path: profile_dir.join(format!("trace-{stamp}.jsonl")),
-            cpu_path: profile_dir.join(format!("cpu-{stamp}.pprof")),
-            heap_path: profile_dir.join(format!("heap-{stamp}.pprof")),
-        };
-        let log_file = File::create(&info.log_path)
-            .with_context(|| format!("failed to create {}", info.log_path.display()))?;
-        File::create(&info.cpu_path).with_context(|| format!("failed to create {}", info.cpu_path.display()))?;
-        File::create(&info.heap_path)
-            .with_context(|| format!("failed to create {}", info.heap_path.display()))?;
-
-        let profiler = Self {
-            inner: Arc::new(ProfilerInner {
-                info: info.clone(),
-                file: Mutex::new(log_file),
-                started: Instant::now(),
-            }),
-        };
-        profiler.event(
-            "profile.session_start",
-            json!({
-                "log_path": profiler.inner.info.log_path.display().to_string(),
-                "cpu_profile_path": profiler.inner.info.cpu_path.display().to_string(),
-                "heap_profile_path": profiler.inner.info.heap_path.display().to_string(),
-                "pid": std::process::id(),
-                "os": env::consts::OS,
-                "arch": env::consts::ARCH
-            }),
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+    for (idx, (name, meta)) in names.iter().zip(&details).enumerate() {
+        let (version, summary) = meta
+            .as_ref()
+            .map(|m| (m.info.version.as_str(), m.info.summary.as_str()))
+            .unwrap_or(("-", ""));
+        let line = format!(
+            "{:>3}) {:<width$}  {:<12} {}",
+            idx + 1,
+            name,
+            version,
+            summary,
+            width = width
         );
-        Ok((profiler, info))
-    }
-
-    fn event(&self, name: &str, fields: Value) {
-        let mut object = Map::new();
-        object.insert("ts".to_string(), json!(timestamp_iso8601()));
-        object.insert("event".to_string(), json!(name));
-        if let Value::Object(map) = fields {
-            for (key, value) in map {
-                object.insert(key, value);
-            }
-        }
-        if let Ok(mut file) = self.inner.file.lock() {
-            if let Ok(line) = serde_json::to_string(&Value::Object(object)) {
-                let _ = writeln!(file, "{line}");
-            }
+        println!("{}", line.trim_end());
+    }
+    let choice = prompt(&format!(
+        "Select a package [1-{}] (default 1): ",
+        names.len()
+    ))?;
+    let idx = if choice.is_empty() {
+        0
+    } else {
+        match choice.parse::<usize>() {
+            Ok(n) if (1..=names.len()).contains(&n) => n - 1,
+            _ => bail!("invalid selection: {choice}"),
         }
-    }
+    };
+    let name = &names[idx];
 
-    fn stop(&self) -> Result<()> {
-        self.event(
-            "profile.session_stop",
-            json!({
-                "elapsed_ms": self.inner.started.elapsed().as_millis()
-            }),
-        );
-        Ok(())
+    let Some(meta) = &details[idx] else {
+        let version = prompt(&format!("Version of {name} (default latest): "))?;
+        return Ok(if version.is_empty() {
+            name.clone()
+        } else {
+            format!("{name}=={version}")
+        });
+    };
+    let published = meta
+        .releases
+        .iter()
+        .filter(|(_, files)| !files.is_empty())
+        .collect::<Vec<_>>();
+    let mut versions = published
+        .iter()
+        .filter(|(_, files)| {
+            files
+                .iter()
+                .any(|file| specifier_allows(&file.requires_python, python))
+        })
+        .map(|(version, _)| version.as_str())
+        .collect::<Vec<_>>();
+    versions.sort_by(|a, b| compare_version(b, a));
+    if versions.len() < published.len() {
+        info(&format!(
+            "Hiding {} release(s) of {name} whose Requires-Python excludes Python {python}",
+            published.len() - versions.len()
+        ));
     }
+    let Some(default) = versions.first().copied() else {
+        bail!("no release of {name} supports Python {python}");
+    };
+    for (n, version) in versions.iter().take(SHOWN).enumerate() {
+        let latest = if *version == meta.info.version {
+            " (latest)"
+        } else {
+            ""
+        };
+        println!("{:>3}) {}{}", n + 1, version, latest);
+    }
+    let choice = prompt(&format!(
+        "Select a version of {name} [1-{}] or type one (default {default}): ",
+        versions.len().min(SHOWN)
+    ))?;
+    let version = if choice.is_empty() {
+        default
+    } else if let Some(version) = choice
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| versions.iter().take(SHOWN).nth(n.wrapping_sub(1)))
+    {
+        version
+    } else if versions.contains(&choice.as_str()) {
+        choice.as_str()
+    } else {
+        bail!("{name} has no release {choice}");
+    };
+    Ok(format!("{name}=={version}"))
 }
 
-struct SpanGuard {
-    profiler: Option<Profiler>,
-    name: String,
-    started: Instant,
-    fields: Value,
+/// Whether to run a template's post-generate hook. It runs arbitrary
+/// commands as the user, so without `--run-hooks` it is shown and needs a
+/// yes at the terminal, and is skipped everywhere else.
+fn confirm_template_hooks(hooks: &[String], run: Option<bool>, no_input: bool) -> Result<bool> {
+    if hooks.is_empty() || run == Some(true) {
+        return Ok(true);
+    }
+    if run == Some(false) {
+        warning("Skipping the template's post-generate hook (--no-hooks)");
+        return Ok(false);
+    }
+    if no_input || !io::stdin().is_terminal() {
+        warning("Skipping the template's post-generate hook; pass --run-hooks to run it");
+        return Ok(false);
+    }
+    warning("The template has a post-generate hook, which runs with your full rights:");
+    for hook in hooks {
+        println!("  {hook}");
+    }
+    let answer = prompt("Run it? [y/N] ")?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
 }
 
-impl Drop for SpanGuard {
-    fn drop(&mut self) {
-        if let Some(profiler) = self.profiler.as_ref() {
-            let mut fields = match self.fields.clone() {
-                Value::Object(map) => map,
-                _ => Map::new(),
-            };
-            fields.insert(
-                "duration_ms".to_string(),
-                json!(self.started.elapsed().as_millis()),
-            );
-            profiler.event(&format!("{}.done", self.name), Value::Object(fields));
+/// Adds `dir` to PATH in the shell profile. Without `assume_yes` the user
+/// is asked first; non-interactive sessions only get instructions.
+fn offer_shell_profile(dir: &Path, assume_yes: bool) -> Result<()> {
+    let export = format!("export PATH=\"{}:$PATH\"", dir.display());
+    let Some((profile, present)) = shell_profile_status(dir) else {
+        warning("Could not determine your shell profile. Please add this to it:");
+        info(&export);
+        return Ok(());
+    };
+    if present {
+        info(&format!(
+            "{} already adds {} to PATH",
+            profile.display(),
+            dir.display()
+        ));
+        return Ok(());
+    }
+    if !assume_yes {
+        if !io::stdin().is_terminal() {
+            warning("Not modifying your shell profile in a non-interactive session.");
+            info(&format!(
+                "Rerun `xe setup --modify-profile` or add this to {}:",
+                profile.display()
+            ));
+            info(&export);
+            return Ok(());
+        }
+        let answer = prompt(&format!(
+            "Append {} to PATH in {}? (y/N): ",
+            dir.display(),
+            profile.display()
+        ))?;
+        if !matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+            info(&format!("Skipped. Add {} to PATH manually.", dir.display()));
+            return Ok(());
         }
     }
+    add_to_shell_profile(dir)?;
+    success(&format!(
+        "Added {} to PATH in {}",
+        dir.display(),
+        profile.display()
+    ));
+    Ok(())
 }
 
-fn span(ctx: &AppContext, name: &str, fields: Value) -> SpanGuard {
-    if let Some(profiler) = ctx.profiler.as_ref() {
-        profiler.event(&format!("{}.start", name), fields.clone());
+/// Prints the environment changes a resolution implies; with `dry_run`,
+/// also that nothing was changed.
+pub(crate) fn print_planned_changes(changes: &[PlannedChange], dry_run: bool) {
+    if changes.is_empty() {
+        if dry_run {
+            info("Dry run: nothing to change");
+        }
+        return;
+    }
+    if dry_run {
+        println!("Planned changes (dry run):");
+    } else {
+        println!("Planned changes:");
+    }
+    let mut total = 0u64;
+    let mut unknown = 0usize;
+    for change in changes {
+        let size = match (change.action, change.download_bytes) {
+            (PlannedAction::Remove, _) => String::new(),
+            (_, Some(0)) => " (cached)".to_string(),
+            (_, Some(bytes)) => {
+                total += bytes;
+                format!(" ({})", format_bytes(bytes))
+            }
+            (_, None) => {
+                unknown += 1;
+                " (size unknown)".to_string()
+            }
+        };
+        let (marker, style) = match change.action {
+            PlannedAction::Install => ("+", Style::Success),
+            PlannedAction::Upgrade => ("^", Style::Info),
+            PlannedAction::Downgrade => ("v", Style::Warning),
+            PlannedAction::Remove => ("-", Style::Error),
+        };
+        let detail = match change.action {
+            PlannedAction::Install => format!("{}{}", change.to, size),
+            PlannedAction::Upgrade | PlannedAction::Downgrade => {
+                format!("{} -> {}{}", change.from, change.to, size)
+            }
+            PlannedAction::Remove => change.from.clone(),
+        };
+        let line = format!("  {} {} {}", paint(style, marker), change.name, detail);
+        println!("{}", line.trim_end());
+    }
+    if !dry_run {
+        return;
     }
-    SpanGuard {
-        profiler: ctx.profiler.clone(),
-        name: name.to_string(),
-        started: Instant::now(),
-        fields,
+    if changes.iter().all(|c| c.action == PlannedAction::Remove) {
+        info("Nothing was changed");
+        return;
     }
+    let mut summary = format!("Would download {}", format_bytes(total));
+    if unknown > 0 {
+        summary.push_str(&format!(" plus {unknown} artifact(s) of unknown size"));
+    }
+    info(&format!("{summary}; nothing was changed"));
 }
 
-fn profile_stamp() -> String {
-    let millis = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_else(|_| Duration::from_secs(0))
-        .as_millis();
-    format!("{millis}")
+fn prompt(question: &str) -> Result<String> {
+    print!("{question}");
+    io::stdout().flush().ok();
+    Ok(read_stdin_line()?.trim().to_string())
 }
 
-fn timestamp_iso8601() -> String {
-    OffsetDateTime::now_utc()
-        .format(&Iso8601::DEFAULT)
-        .unwrap_or_else(|_| "1970-01-01T00:00:00Z".to_string())
+pub(crate) fn read_stdin_line() -> Result<String> {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line)
 }
 
-fn xe_home() -> PathBuf {
-    if cfg!(windows) {
-        if let Ok(local) = env::var("LOCALAPPDATA") {
-            return PathBuf::from(local).join("xe");
+fn remove_path(path: &Path, description: &str) -> Result<()> {
+    if path.exists() {
+        info(&format!(
+            "Removing {} at {}...",
+            description,
+            path.display()
+        ));
+        if path.is_dir() {
+            paths::remove_dir_all(path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        } else {
+            paths::remove_file(path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
         }
-        return dirs::home_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("AppData")
-            .join("Local")
-            .join("xe");
     }
-    dirs::home_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join(".local")
-        .join("share")
-        .join("xe")
+    Ok(())
 }
 
-fn xe_config_file() -> PathBuf {
-    xe_home().join("config.yaml")
+/// Registry used when `--registry` is not given.
+const DEFAULT_REGISTRY: &str = "pypi";
+
+/// Single anonymous token file written by older xe versions; read as the
+/// default registry's token and folded into the credentials map on write.
+fn legacy_token_path() -> PathBuf {
+    xe_home().join("credentials")
 }
 
-fn xe_cache_dir() -> PathBuf {
-    xe_home().join("cache")
+fn credentials_path() -> PathBuf {
+    xe_home().join("credentials.json")
 }
 
-fn xe_venv_dir() -> PathBuf {
-    xe_home().join("venvs")
+/// Stored tokens by registry name.
+fn stored_credentials() -> Result<BTreeMap<String, String>> {
+    let path = credentials_path();
+    let mut creds: BTreeMap<String, String> = if path.exists() {
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?
+    } else {
+        BTreeMap::new()
+    };
+    let legacy = legacy_token_path();
+    if legacy.is_file() && !creds.contains_key(DEFAULT_REGISTRY) {
+        let token = fs::read_to_string(&legacy)
+            .with_context(|| format!("failed to read {}", legacy.display()))?;
+        if !token.trim().is_empty() {
+            creds.insert(DEFAULT_REGISTRY.to_string(), token.trim().to_string());
+        }
+    }
+    Ok(creds)
 }
 
-fn xe_shim_dir() -> PathBuf {
-    xe_home().join("bin")
+fn write_credentials(creds: &BTreeMap<String, String>) -> Result<()> {
+    let path = credentials_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let encoded = serde_json::to_string_pretty(creds).context("failed to encode credentials")?;
+    fs::write(&path, encoded).with_context(|| format!("failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("failed to restrict {}", path.display()))?;
+    }
+    let legacy = legacy_token_path();
+    if legacy.is_file() {
+        fs::remove_file(&legacy)
+            .with_context(|| format!("failed to remove {}", legacy.display()))?;
+    }
+    Ok(())
 }
 
-fn xe_plugin_dir() -> PathBuf {
-    xe_home().join("plugins")
+fn save_token(registry: &str, token: &str) -> Result<()> {
+    let mut creds = stored_credentials()?;
+    creds.insert(registry.to_string(), token.to_string());
+    write_credentials(&creds)
 }
 
-fn tempfile_path(prefix: &str, ext: &str) -> PathBuf {
-    tempfile_path_in(&env::temp_dir(), prefix, ext)
+fn load_token(registry: &str) -> Result<String> {
+    stored_credentials()?
+        .remove(registry)
+        .ok_or_else(|| anyhow!("no token stored for {registry}"))
 }
 
-fn tempfile_path_in(dir: &Path, prefix: &str, ext: &str) -> PathBuf {
-    let stamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_else(|_| Duration::from_secs(0))
-        .as_nanos();
-    let pid = std::process::id();
-    dir.join(format!("{prefix}-{pid}-{stamp}.{ext}"))
+/// Returns whether a token was stored for `registry`.
+fn revoke_token(registry: &str) -> Result<bool> {
+    let mut creds = stored_credentials()?;
+    let removed = creds.remove(registry).is_some();
+    if removed {
+        write_credentials(&creds)?;
+    }
+    Ok(removed)
 }
 
-fn download_file(url: &str, prefix: &str, ext: &str) -> Result<PathBuf> {
-    let client = Client::builder()
-        .timeout(Duration::from_secs(180))
-        .build()
-        .context("failed to build HTTP client")?;
-    let mut resp = client
-        .get(url)
-        .send()
-        .with_context(|| format!("failed to download {}", url))?;
-    if !resp.status().is_success() {
-        bail!("failed to download {}: {}", url, resp.status());
+fn mask_token(token: &str) -> String {
+    let chars = token.chars().collect::<Vec<_>>();
+    if chars.len() < 12 {
+        return "*".repeat(chars.len().max(4));
     }
-    let path = tempfile_path(prefix, ext);
-    let mut out = File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
-    io::copy(&mut resp, &mut out).with_context(|| format!("failed to write {}", path.display()))?;
-    Ok(path)
+    let head = chars[..5].iter().collect::<String>();
+    let tail = chars[chars.len() - 4..].iter().collect::<String>();
+    format!("{head}...{tail}")
 }
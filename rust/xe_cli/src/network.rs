@@ -0,0 +1,112 @@
+//! The Network section of `xe doctor`. Resolves and probes every endpoint
+//! xe talks to, the package indexes of the project, python.org's release
+//! directory and the upload endpoints of `xe publish`, and tells DNS, TLS
+//! and proxy failures apart so the fix is obvious.
+
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use xe_core::config::{load_project, Config, XE_TOML};
+use xe_core::install::index_urls;
+use xe_core::net::{probe_endpoint, redact_url, EndpointProbe, ProbeOutcome};
+use xe_core::python::PYTHON_DOWNLOADS_URL;
+use xe_core::ui::{paint, Style};
+
+const PYPI_UPLOAD_URL: &str = "https://upload.pypi.org/legacy/";
+const TESTPYPI_UPLOAD_URL: &str = "https://test.pypi.org/legacy/";
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Answers slower than this are reported even when they succeed.
+const SLOW_LATENCY: Duration = Duration::from_secs(2);
+
+pub(crate) fn check_network(project_dir: &Path) {
+    println!("{}", paint(Style::Header, "Network"));
+    let toml_path = project_dir.join(XE_TOML);
+    let cfg = if toml_path.exists() {
+        match load_project(&toml_path) {
+            Ok(cfg) => cfg,
+            Err(err) => {
+                println!("[WARN] Could not read {XE_TOML}; checking the defaults: {err:#}");
+                Config::new_default(project_dir)
+            }
+        }
+    } else {
+        Config::new_default(project_dir)
+    };
+
+    let mut endpoints = Vec::new();
+    match index_urls(&cfg) {
+        Ok(urls) if urls.is_empty() => println!("[OK] no_index is set; no index to reach"),
+        Ok(urls) => {
+            for (i, url) in urls.into_iter().enumerate() {
+                let label = if i == 0 { "Index" } else { "Extra index" };
+                endpoints.push((label, url));
+            }
+        }
+        Err(err) => println!("[WARN] {err:#}"),
+    }
+    endpoints.push(("Python downloads", PYTHON_DOWNLOADS_URL.to_string()));
+    endpoints.push(("Upload (PyPI)", PYPI_UPLOAD_URL.to_string()));
+    endpoints.push(("Upload (TestPyPI)", TESTPYPI_UPLOAD_URL.to_string()));
+
+    let probes = thread::scope(|scope| {
+        let handles = endpoints
+            .iter()
+            .map(|(_, url)| scope.spawn(move || probe_endpoint(url, PROBE_TIMEOUT)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().ok())
+            .collect::<Vec<_>>()
+    });
+    for ((label, url), probe) in endpoints.iter().zip(probes) {
+        match probe {
+            Some(probe) => print_probe(label, url, &probe),
+            None => println!("[WARN] {label} {}: the probe crashed", redact_url(url)),
+        }
+    }
+}
+
+/// Prints one probe result. Index and proxy URLs can carry credentials and
+/// this output gets pasted into issues, so both are redacted.
+fn print_probe(label: &str, url: &str, probe: &EndpointProbe) {
+    let url = redact_url(url);
+    let via = probe
+        .proxy
+        .as_ref()
+        .map(|proxy| format!(" via proxy {}", redact_url(proxy)))
+        .unwrap_or_default();
+    let (ok, detail) = match &probe.outcome {
+        ProbeOutcome::Answered { status, latency } => {
+            let timing = match probe.dns {
+                Some(dns) => format!("{} ms, DNS {} ms", latency.as_millis(), dns.as_millis()),
+                None => format!("{} ms", latency.as_millis()),
+            };
+            match status {
+                401 | 403 => (
+                    false,
+                    format!("HTTP {status} in {timing}; check the credentials for this host"),
+                ),
+                // HEAD is not allowed everywhere, but the server answered.
+                405 => (true, format!("reachable in {timing}")),
+                status if *status >= 400 => (false, format!("HTTP {status} in {timing}")),
+                _ if *latency > SLOW_LATENCY => (false, format!("slow: HTTP {status} in {timing}")),
+                status => (true, format!("HTTP {status} in {timing}")),
+            }
+        }
+        ProbeOutcome::Dns(err) => (false, format!("DNS lookup failed: {err}")),
+        ProbeOutcome::Tls(err) => (
+            false,
+            format!("TLS error: {err}; a proxy or firewall may be intercepting HTTPS"),
+        ),
+        ProbeOutcome::Proxy(err) => (false, format!("proxy error: {err}")),
+        ProbeOutcome::Timeout => (
+            false,
+            format!("no answer within {} s", PROBE_TIMEOUT.as_secs()),
+        ),
+        ProbeOutcome::Connect(err) => (false, format!("connection failed: {err}")),
+    };
+    let tag = if ok { "[OK]" } else { "[WARN]" };
+    println!("{tag} {label} {url}{via}: {detail}");
+}
@@ -0,0 +1,352 @@
+//! `xe profile export`. Turns the span events of a `--profile` trace into
+//! folded stacks, which flamegraph.pl, inferno and speedscope read, or into
+//! Chrome trace-event JSON for chrome://tracing, Perfetto and speedscope.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Iso8601;
+use time::OffsetDateTime;
+use xe_core::paths::xe_home;
+use xe_core::ui::{success, warning};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Flamegraph,
+    ChromeTrace,
+}
+
+/// One line of the trace.
+struct Event {
+    name: String,
+    /// Microseconds since the first event.
+    at: i64,
+    fields: Map<String, Value>,
+}
+
+/// A span, from its `.start` event to its `.done` event.
+struct Span {
+    name: String,
+    thread: u64,
+    start: i64,
+    duration: i64,
+    /// Names of the spans it ran in, outermost first.
+    parents: Vec<String>,
+    /// Time spent in spans directly inside it.
+    children: i64,
+    fields: Map<String, Value>,
+}
+
+const USAGE: &str =
+    "usage: xe profile export [<trace.jsonl>] [--format flamegraph|chrome-trace] [--output <path>]";
+
+pub(crate) fn cmd_profile(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("export") => cmd_profile_export(&args[1..]),
+        _ => bail!(USAGE),
+    }
+}
+
+fn cmd_profile_export(args: &[String]) -> Result<()> {
+    let mut format = Format::Flamegraph;
+    let mut output = None;
+    let mut trace = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" => {
+                i += 1;
+                format = match args.get(i).map(String::as_str) {
+                    Some("flamegraph" | "folded") => Format::Flamegraph,
+                    Some("chrome-trace" | "chrome") => Format::ChromeTrace,
+                    _ => bail!("--format takes flamegraph or chrome-trace\n{USAGE}"),
+                };
+            }
+            "--output" | "-o" => {
+                i += 1;
+                output = Some(
+                    args.get(i)
+                        .ok_or_else(|| anyhow!("--output requires a path"))?
+                        .clone(),
+                );
+            }
+            path if !path.starts_with('-') && trace.is_none() => {
+                trace = Some(PathBuf::from(path));
+            }
+            _ => bail!(USAGE),
+        }
+        i += 1;
+    }
+    let trace = match trace {
+        Some(trace) => trace,
+        None => latest_trace()?,
+    };
+
+    let text = fs::read_to_string(&trace)
+        .with_context(|| format!("failed to read {}", trace.display()))?;
+    let (events, skipped) = parse_events(&text);
+    if skipped > 0 {
+        // A run that crashed can leave a cut-off last line.
+        warning(&format!(
+            "Skipped {skipped} line(s) of {} that are not trace events",
+            trace.display()
+        ));
+    }
+    if events.is_empty() {
+        bail!("{} has no trace events", trace.display());
+    }
+    let (spans, instants) = pair_spans(events);
+    let rendered = match format {
+        Format::Flamegraph => folded_stacks(&spans),
+        Format::ChromeTrace => {
+            let pid = instants
+                .iter()
+                .find(|event| event.name == "profile.session_start")
+                .and_then(|event| event.fields.get("pid"))
+                .and_then(Value::as_u64)
+                .unwrap_or(1);
+            serde_json::to_string(&chrome_trace(&spans, &instants, pid))
+                .context("failed to encode the trace")?
+        }
+    };
+
+    if output.as_deref() == Some("-") {
+        io::stdout()
+            .write_all(rendered.as_bytes())
+            .context("failed to write the export")?;
+        return Ok(());
+    }
+    let output = output.map(PathBuf::from).unwrap_or_else(|| {
+        let extension = match format {
+            Format::Flamegraph => "folded",
+            Format::ChromeTrace => "chrome.json",
+        };
+        trace.with_extension(extension)
+    });
+    fs::write(&output, rendered)
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    let viewer = match format {
+        Format::Flamegraph => "speedscope or inferno-flamegraph",
+        Format::ChromeTrace => "chrome://tracing, ui.perfetto.dev or speedscope",
+    };
+    success(&format!(
+        "Exported {} span(s) to {}; open it in {viewer}",
+        spans.len(),
+        output.display()
+    ));
+    Ok(())
+}
+
+/// The newest `trace-*.jsonl` in the default profile directory.
+fn latest_trace() -> Result<PathBuf> {
+    let dir = xe_home().join("profiles");
+    let newest = fs::read_dir(&dir)
+        .ok()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| is_trace(path))
+        .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .max_by_key(|(modified, _)| *modified);
+    match newest {
+        Some((_, path)) => Ok(path),
+        None => bail!(
+            "no trace in {}; run a command with --profile or pass the trace to export",
+            dir.display()
+        ),
+    }
+}
+
+fn is_trace(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("trace-") && name.ends_with(".jsonl"))
+}
+
+/// The events of a trace in file order, and how many lines were not
+/// events.
+fn parse_events(text: &str) -> (Vec<Event>, usize) {
+    let mut events = Vec::new();
+    let mut skipped = 0;
+    let mut base = None;
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let Ok(Value::Object(mut fields)) = serde_json::from_str::<Value>(line) else {
+            skipped += 1;
+            continue;
+        };
+        let name = fields.remove("event").and_then(|name| match name {
+            Value::String(name) => Some(name),
+            _ => None,
+        });
+        let at = fields
+            .remove("ts")
+            .and_then(|ts| OffsetDateTime::parse(ts.as_str()?, &Iso8601::DEFAULT).ok());
+        let (Some(name), Some(at)) = (name, at) else {
+            skipped += 1;
+            continue;
+        };
+        let nanos = at.unix_timestamp_nanos();
+        let base = *base.get_or_insert(nanos);
+        events.push(Event {
+            name,
+            at: ((nanos - base) / 1000) as i64,
+            fields,
+        });
+    }
+    (events, skipped)
+}
+
+/// Matches each `<name>.start` with the `<name>.done` of the same thread
+/// that closes it. Events that are not part of a span, and starts that
+/// never finished, are returned as instants.
+fn pair_spans(events: Vec<Event>) -> (Vec<Span>, Vec<Event>) {
+    let thread_of = |event: &Event| {
+        event
+            .fields
+            .get("thread")
+            .and_then(Value::as_u64)
+            .unwrap_or(0)
+    };
+
+    // Which start each done closes, by position in `events`.
+    let mut closes: HashMap<usize, usize> = HashMap::new();
+    let mut open: HashMap<(u64, &str), Vec<usize>> = HashMap::new();
+    for (i, event) in events.iter().enumerate() {
+        let thread = thread_of(event);
+        if let Some(name) = event.name.strip_suffix(".start") {
+            open.entry((thread, name)).or_default().push(i);
+        } else if let Some(name) = event.name.strip_suffix(".done") {
+            if let Some(start) = open.get_mut(&(thread, name)).and_then(Vec::pop) {
+                closes.insert(start, i);
+            }
+        }
+    }
+    let started: HashMap<usize, usize> =
+        closes.iter().map(|(start, done)| (*done, *start)).collect();
+
+    let mut spans: Vec<Span> = Vec::new();
+    let mut instants = Vec::new();
+    // Per thread, the spans still open, as indexes into `spans`.
+    let mut stacks: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut pending: HashMap<usize, usize> = HashMap::new();
+    for (i, event) in events.into_iter().enumerate() {
+        let thread = thread_of(&event);
+        let stack = stacks.entry(thread).or_default();
+        if let Some(name) = event.name.strip_suffix(".start") {
+            if closes.contains_key(&i) {
+                let parents = stack.iter().map(|&open| spans[open].name.clone()).collect();
+                stack.push(spans.len());
+                pending.insert(i, spans.len());
+                spans.push(Span {
+                    name: name.to_string(),
+                    thread,
+                    start: event.at,
+                    duration: 0,
+                    parents,
+                    children: 0,
+                    fields: event.fields,
+                });
+                continue;
+            }
+        } else if let Some(name) = event.name.strip_suffix(".done") {
+            let mut fields = event.fields;
+            let reported = fields
+                .remove("duration_ms")
+                .and_then(|ms| ms.as_i64())
+                .map(|ms| ms * 1000);
+            let span = match started.get(&i).and_then(|start| pending.remove(start)) {
+                Some(span) => {
+                    stack.retain(|&open| open != span);
+                    spans[span].duration = event.at - spans[span].start;
+                    spans[span].fields.extend(fields);
+                    span
+                }
+                // Its start is missing from the trace; place it by the
+                // duration it reports.
+                None => {
+                    let duration = reported.unwrap_or(0);
+                    let parents = stack.iter().map(|&open| spans[open].name.clone()).collect();
+                    spans.push(Span {
+                        name: name.to_string(),
+                        thread,
+                        start: (event.at - duration).max(0),
+                        duration,
+                        parents,
+                        children: 0,
+                        fields,
+                    });
+                    spans.len() - 1
+                }
+            };
+            if let Some(&parent) = stack.last() {
+                spans[parent].children += spans[span].duration;
+            }
+            continue;
+        }
+        instants.push(event);
+    }
+    for span in &mut spans {
+        span.fields.remove("thread");
+    }
+    spans.sort_by_key(|span| span.start);
+    (spans, instants)
+}
+
+/// One `frame;frame;frame <microseconds>` line per distinct stack, counting
+/// the time spent in the innermost span itself.
+fn folded_stacks(spans: &[Span]) -> String {
+    let mut stacks: BTreeMap<String, i64> = BTreeMap::new();
+    for span in spans {
+        let mut frames = span.parents.clone();
+        frames.push(span.name.clone());
+        let own = (span.duration - span.children).max(0);
+        *stacks
+            .entry(frames.join(";").replace(' ', "_"))
+            .or_default() += own;
+    }
+    stacks
+        .into_iter()
+        .filter(|(_, micros)| *micros > 0)
+        .map(|(stack, micros)| format!("{stack} {micros}\n"))
+        .collect()
+}
+
+/// The Trace Event Format: a complete (`X`) event per span on its thread
+/// and an instant (`i`) event for everything else.
+fn chrome_trace(spans: &[Span], instants: &[Event], pid: u64) -> Value {
+    let mut events = vec![json!({
+        "name": "process_name",
+        "ph": "M",
+        "pid": pid,
+        "args": {"name": "xe"},
+    })];
+    for span in spans {
+        let category = span.name.split('.').next().unwrap_or(&span.name);
+        events.push(json!({
+            "name": span.name,
+            "cat": category,
+            "ph": "X",
+            "ts": span.start,
+            "dur": span.duration,
+            "pid": pid,
+            "tid": span.thread,
+            "args": span.fields,
+        }));
+    }
+    for event in instants {
+        events.push(json!({
+            "name": event.name,
+            "ph": "i",
+            "s": "p",
+            "ts": event.at,
+            "pid": pid,
+            "tid": event.fields.get("thread").and_then(Value::as_u64).unwrap_or(0),
+            "args": event.fields,
+        }));
+    }
+    json!({"traceEvents": events, "displayTimeUnit": "ms"})
+}
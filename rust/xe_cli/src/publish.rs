@@ -0,0 +1,267 @@
+//! `xe publish`, `xe push` and `xe tpush`. Built distributions are
+//! uploaded one at a time through the legacy upload API PyPI and TestPyPI
+//! share with twine: a multipart form carrying the file, its SHA-256 and
+//! the core metadata read from the file itself.
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use xe_core::net::{http_client, http_send};
+use xe_core::ui::{info, success};
+use zip::ZipArchive;
+
+pub(crate) const PYPI_UPLOAD_URL: &str = "https://upload.pypi.org/legacy/";
+pub(crate) const TEST_PYPI_UPLOAD_URL: &str = "https://test.pypi.org/legacy/";
+
+/// Metadata headers that may repeat, by the form field twine sends them as.
+const MULTI_FIELDS: &[(&str, &str)] = &[
+    ("classifier", "classifiers"),
+    ("project-url", "project_urls"),
+    ("requires-dist", "requires_dist"),
+    ("provides-extra", "provides_extra"),
+    ("platform", "platform"),
+    ("supported-platform", "supported_platform"),
+    ("requires-external", "requires_external"),
+    ("provides-dist", "provides_dist"),
+    ("obsoletes-dist", "obsoletes_dist"),
+    ("dynamic", "dynamic"),
+    ("license-file", "license_file"),
+];
+
+/// A wheel or sdist to upload.
+pub(crate) struct Distribution {
+    pub path: PathBuf,
+    /// Version from the file name.
+    pub version: String,
+    wheel: bool,
+}
+
+impl Distribution {
+    /// `path` when it is named like a wheel or an sdist.
+    pub(crate) fn new(path: PathBuf) -> Option<Self> {
+        let file_name = path.file_name()?.to_string_lossy().into_owned();
+        let (stem, wheel) = match file_name.strip_suffix(".whl") {
+            Some(stem) => (stem.to_string(), true),
+            None => (file_name.strip_suffix(".tar.gz")?.to_string(), false),
+        };
+        let version = if wheel {
+            stem.split('-').nth(1)?
+        } else {
+            stem.rsplit_once('-')?.1
+        }
+        .to_string();
+        Some(Self {
+            path,
+            version,
+            wheel,
+        })
+    }
+
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// `pyversion` of the upload form: the wheel's Python tag, or `source`.
+    fn pyversion(&self) -> String {
+        if !self.wheel {
+            return "source".to_string();
+        }
+        let name = self.file_name();
+        let parts = name.trim_end_matches(".whl").split('-').collect::<Vec<_>>();
+        parts
+            .len()
+            .checked_sub(3)
+            .map(|i| parts[i].to_string())
+            .unwrap_or_default()
+    }
+
+    /// `METADATA` of a wheel, `PKG-INFO` of an sdist.
+    fn metadata(&self) -> Result<String> {
+        if self.wheel {
+            let file = File::open(&self.path)
+                .with_context(|| format!("failed to open {}", self.path.display()))?;
+            let mut archive = ZipArchive::new(file)
+                .with_context(|| format!("{} is not a valid wheel", self.path.display()))?;
+            let Some(name) = archive
+                .file_names()
+                .find(|name| {
+                    name.strip_suffix("/METADATA")
+                        .is_some_and(|dir| dir.ends_with(".dist-info") && !dir.contains('/'))
+                })
+                .map(str::to_string)
+            else {
+                bail!("{} has no .dist-info/METADATA", self.path.display());
+            };
+            let mut text = String::new();
+            archive
+                .by_name(&name)
+                .with_context(|| format!("failed to read {name}"))?
+                .read_to_string(&mut text)
+                .with_context(|| format!("failed to read {name}"))?;
+            return Ok(text);
+        }
+        // An sdist unpacks into `<name>-<version>/`, with PKG-INFO at its top.
+        let top = self.file_name().trim_end_matches(".tar.gz").to_string();
+        let output = Command::new("tar")
+            .arg("-xzOf")
+            .arg(&self.path)
+            .arg(format!("{top}/PKG-INFO"))
+            .output()
+            .context("failed to run tar")?;
+        if !output.status.success() {
+            bail!("{} has no {top}/PKG-INFO", self.path.display());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// The wheels and sdists in `dist_dir`, only those of `version` when given.
+pub(crate) fn dist_files(dist_dir: &Path, version: Option<&str>) -> Result<Vec<Distribution>> {
+    let mut files = Vec::new();
+    let entries = match fs::read_dir(dist_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read {}", dist_dir.display()))
+        }
+    };
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to read {}", dist_dir.display()))?;
+        if let Some(dist) = Distribution::new(entry.path()) {
+            if version.is_none_or(|v| v == dist.version) {
+                files.push(dist);
+            }
+        }
+    }
+    files.sort_by_key(|dist| dist.path.clone());
+    Ok(files)
+}
+
+/// Where uploads go: `XE_PUBLISH_URL` when set, e.g. for a private index.
+pub(crate) fn upload_url(test_pypi: bool) -> String {
+    env::var("XE_PUBLISH_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())
+        .unwrap_or_else(|| {
+            if test_pypi {
+                TEST_PYPI_UPLOAD_URL
+            } else {
+                PYPI_UPLOAD_URL
+            }
+            .to_string()
+        })
+}
+
+/// Uploads each of `dists` to `url` with an API token, stopping at the
+/// first the index rejects.
+pub(crate) fn upload(url: &str, token: &str, dists: &[Distribution]) -> Result<()> {
+    let client = http_client(Duration::from_secs(600))?;
+    for dist in dists {
+        let file_name = dist.file_name();
+        info(&format!("Uploading {file_name}..."));
+        let content = fs::read(&dist.path)
+            .with_context(|| format!("failed to read {}", dist.path.display()))?;
+        let mut fields = vec![
+            (":action".to_string(), "file_upload".to_string()),
+            ("protocol_version".to_string(), "1".to_string()),
+            (
+                "filetype".to_string(),
+                if dist.wheel { "bdist_wheel" } else { "sdist" }.to_string(),
+            ),
+            ("pyversion".to_string(), dist.pyversion()),
+            (
+                "sha256_digest".to_string(),
+                hex::encode(Sha256::digest(&content)),
+            ),
+        ];
+        fields.extend(metadata_fields(&dist.metadata()?));
+        let boundary = format!("xe-upload-{}", hex::encode(&Sha256::digest(&content)[..16]));
+        let body = multipart_body(&boundary, &fields, &file_name, &content);
+        let request = client
+            .post(url)
+            .basic_auth("__token__", Some(token))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(body);
+        let resp = http_send(request).with_context(|| format!("failed to upload {file_name}"))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().unwrap_or_default();
+            let reason = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+            bail!(
+                "the index rejected {file_name} ({status}): {}",
+                reason.trim()
+            );
+        }
+        success(&format!("Uploaded {file_name}"));
+    }
+    Ok(())
+}
+
+/// Core metadata headers as upload form fields; the body, when there is
+/// one, is the description.
+fn metadata_fields(metadata: &str) -> Vec<(String, String)> {
+    let metadata = metadata.replace("\r\n", "\n");
+    let (headers, body) = metadata.split_once("\n\n").unwrap_or((&metadata, ""));
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in headers.lines() {
+        // Continuation lines belong to the previous header.
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push('\n');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let field = MULTI_FIELDS
+            .iter()
+            .find(|(header, _)| *header == key)
+            .map(|(_, field)| field.to_string())
+            .unwrap_or_else(|| key.replace('-', "_"));
+        fields.push((field, value.trim().to_string()));
+    }
+    if !body.trim().is_empty() && !fields.iter().any(|(key, _)| key == "description") {
+        fields.push(("description".to_string(), body.to_string()));
+    }
+    fields
+}
+
+fn multipart_body(
+    boundary: &str,
+    fields: &[(String, String)],
+    file_name: &str,
+    content: &[u8],
+) -> Vec<u8> {
+    let mut body = Vec::with_capacity(content.len() + 4096);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"content\"; filename=\"{file_name}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(content);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    body
+}
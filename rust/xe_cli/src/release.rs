@@ -0,0 +1,312 @@
+//! `xe release`. Bumps the pyproject.toml version, dates the `Unreleased`
+//! section of the changelog, relocks, builds, commits, tags and publishes,
+//! refusing to start from a dirty git tree so every step before the commit
+//! can be undone with `git checkout`.
+
+use crate::build::cmd_build;
+use crate::publish::dist_files;
+use crate::{cmd_lock, cmd_push, report_operation};
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::LazyLock;
+use time::OffsetDateTime;
+use xe_core::config::{pyproject_version, set_pyproject_version, PYPROJECT_TOML, XE_TOML};
+use xe_core::lock::XE_LOCK;
+use xe_core::profile::AppContext;
+use xe_core::ui::{info, success, warning};
+
+const CHANGELOGS: &[&str] = &["CHANGELOG.md", "CHANGES.md"];
+
+/// A public PEP 440 version (no local `+label`), as `packaging` accepts it.
+static PEP440_VERSION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?ix)^
+        (?:[0-9]+!)?
+        [0-9]+(?:\.[0-9]+)*
+        (?:[-_.]?(?:a|b|c|rc|alpha|beta|pre|preview)[-_.]?[0-9]*)?
+        (?:-[0-9]+|[-_.]?(?:post|rev|r)[-_.]?[0-9]*)?
+        (?:[-_.]?dev[-_.]?[0-9]*)?
+        $",
+    )
+    .expect("valid PEP 440 pattern")
+});
+
+/// A changelog with an `## Unreleased` (or `## [Unreleased]`) section.
+struct Changelog {
+    path: PathBuf,
+    text: String,
+    /// End of the `Unreleased` heading line.
+    heading_end: usize,
+    /// End of the section: the next `## ` heading or the end of the file.
+    section_end: usize,
+    bracketed: bool,
+}
+
+impl Changelog {
+    fn find(project_dir: &Path) -> Result<Option<Self>> {
+        for name in CHANGELOGS {
+            let path = project_dir.join(name);
+            if !path.exists() {
+                continue;
+            }
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let mut offset = 0;
+            let mut heading = None;
+            for line in text.split_inclusive('\n') {
+                let start = offset;
+                offset += line.len();
+                let Some(title) = line.trim_end().strip_prefix("## ") else {
+                    continue;
+                };
+                if let Some((heading_end, bracketed)) = heading {
+                    return Ok(Some(Self {
+                        path,
+                        text,
+                        heading_end,
+                        section_end: start,
+                        bracketed,
+                    }));
+                }
+                let bracketed = title.trim().starts_with('[');
+                let title = title.trim().trim_start_matches('[').trim_end_matches(']');
+                if title.eq_ignore_ascii_case("unreleased") {
+                    heading = Some((offset, bracketed));
+                }
+            }
+            if let Some((heading_end, bracketed)) = heading {
+                let section_end = text.len();
+                return Ok(Some(Self {
+                    path,
+                    text,
+                    heading_end,
+                    section_end,
+                    bracketed,
+                }));
+            }
+            warning(&format!(
+                "{} has no Unreleased section; the release is not recorded there",
+                path.display()
+            ));
+        }
+        Ok(None)
+    }
+
+    fn notes(&self) -> &str {
+        self.text[self.heading_end..self.section_end].trim()
+    }
+
+    /// Turns the `Unreleased` entries into a dated `version` section under
+    /// a fresh, empty `Unreleased` heading.
+    fn release(&self, version: &str, date: &str) -> Result<()> {
+        let heading = if self.bracketed {
+            format!("## [{version}] - {date}")
+        } else {
+            format!("## {version} - {date}")
+        };
+        let mut text = self.text[..self.heading_end].to_string();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&format!("\n{heading}\n"));
+        text.push_str(&self.text[self.heading_end..]);
+        fs::write(&self.path, text)
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+pub(crate) fn cmd_release(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let usage =
+        "usage: xe release [patch|minor|major|<version>] [--dry-run] [--no-publish] [--test-pypi]";
+    let mut bump = None;
+    let mut dry_run = false;
+    let mut publish = true;
+    let mut test_pypi = false;
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--no-publish" => publish = false,
+            "--test-pypi" => test_pypi = true,
+            value if !value.starts_with('-') && bump.is_none() => bump = Some(value.to_string()),
+            _ => bail!(usage),
+        }
+    }
+    let bump = bump.unwrap_or_else(|| "patch".to_string());
+
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let current = pyproject_version(&wd)?;
+    let version = bumped_version(&current, &bump)?;
+    let tag = format!("v{version}");
+
+    if git(&wd, &["rev-parse", "--is-inside-work-tree"]).is_err() {
+        bail!("xe release needs a git repository to commit and tag in");
+    }
+    let dirty = git(&wd, &["status", "--porcelain"])?;
+    if !dirty.trim().is_empty() {
+        bail!("the git tree has uncommitted changes; commit or stash them first");
+    }
+    if git(
+        &wd,
+        &["rev-parse", "-q", "--verify", &format!("refs/tags/{tag}")],
+    )
+    .is_ok()
+    {
+        bail!("tag {tag} already exists");
+    }
+    let changelog = Changelog::find(&wd)?;
+    if let Some(changelog) = &changelog {
+        if changelog.notes().is_empty() {
+            bail!(
+                "{} has nothing under Unreleased; describe the changes first",
+                changelog.path.display()
+            );
+        }
+    }
+
+    let registry = if test_pypi { "TestPyPI" } else { "PyPI" };
+    let mut steps = vec![format!("Set the version in {PYPROJECT_TOML} to {version}")];
+    if let Some(changelog) = &changelog {
+        steps.push(format!(
+            "Move the Unreleased entries of {} under {version}",
+            file_name(&changelog.path)
+        ));
+    }
+    steps.push(format!("Refresh {XE_LOCK}"));
+    steps.push("Build the sdist and wheel into dist/".to_string());
+    steps.push(format!("Commit \"Release {version}\" and tag {tag}"));
+    if publish {
+        steps.push(format!("Publish to {registry}"));
+    }
+    info(&format!("Releasing {current} -> {version}"));
+    for (i, step) in steps.iter().enumerate() {
+        println!("  {}. {step}", i + 1);
+    }
+    if dry_run {
+        info("Dry run; nothing was changed");
+        return Ok(());
+    }
+
+    let released = (|| -> Result<()> {
+        set_pyproject_version(&wd, &version)?;
+        if let Some(changelog) = &changelog {
+            let date = OffsetDateTime::now_utc().date().to_string();
+            changelog.release(&version, &date)?;
+        }
+        cmd_lock(ctx, &[])?;
+        cmd_build(ctx, &[])?;
+        Ok(())
+    })();
+    if let Err(err) = released {
+        warning(&format!(
+            "Release of {version} stopped before the commit; `git checkout -- .` undoes the changes"
+        ));
+        return Err(err);
+    }
+
+    let mut files = vec![PYPROJECT_TOML.to_string()];
+    for name in [XE_TOML, XE_LOCK] {
+        if wd.join(name).exists() && git(&wd, &["check-ignore", "-q", name]).is_err() {
+            files.push(name.to_string());
+        }
+    }
+    if let Some(changelog) = &changelog {
+        files.push(file_name(&changelog.path));
+    }
+    let mut add = vec!["add", "--"];
+    add.extend(files.iter().map(String::as_str));
+    git(&wd, &add)?;
+    git(&wd, &["commit", "-m", &format!("Release {version}")])?;
+    let message = match &changelog {
+        Some(changelog) => format!("Release {version}\n\n{}", changelog.notes()),
+        None => format!("Release {version}"),
+    };
+    git(
+        &wd,
+        &["tag", "-a", "--cleanup=verbatim", &tag, "-m", &message],
+    )?;
+    success(&format!("Committed and tagged {tag}"));
+
+    if publish {
+        let command = if test_pypi { "xe tpush" } else { "xe publish" };
+        // Only this release's files; older builds left in dist/ were
+        // uploaded before, and the index refuses them a second time.
+        let files = dist_files(&wd.join("dist"), Some(&version))?
+            .into_iter()
+            .map(|dist| dist.path.display().to_string())
+            .collect::<Vec<_>>();
+        if let Err(err) = report_operation("publish", || cmd_push(ctx, &files, test_pypi)) {
+            warning(&format!(
+                "{tag} is committed and tagged; run `{command}` to publish it"
+            ));
+            return Err(err);
+        }
+    }
+    success(&format!(
+        "Released {version}; push it with `git push --follow-tags`"
+    ));
+    Ok(())
+}
+
+/// `current` with one part bumped, or `bump` itself when it is a version.
+fn bumped_version(current: &str, bump: &str) -> Result<String> {
+    let part = match bump {
+        "major" => 0,
+        "minor" => 1,
+        "patch" => 2,
+        explicit => {
+            if !PEP440_VERSION.is_match(explicit) {
+                bail!("{explicit} is neither patch, minor, major nor a PEP 440 version");
+            }
+            if explicit == current {
+                bail!("the version is already {current}");
+            }
+            return Ok(explicit.to_string());
+        }
+    };
+    let mut parts = current
+        .split('.')
+        .map(|p| p.parse::<u64>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| {
+            anyhow!("cannot {bump}-bump {current}; pass the new version to `xe release` instead")
+        })?;
+    if parts.is_empty() || parts.len() > 3 {
+        bail!("cannot {bump}-bump {current}; pass the new version to `xe release` instead");
+    }
+    parts.resize(3, 0);
+    parts[part] += 1;
+    for later in &mut parts[part + 1..] {
+        *later = 0;
+    }
+    Ok(parts
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join("."))
+}
+
+fn git(project_dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(project_dir)
+        .output()
+        .context("failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
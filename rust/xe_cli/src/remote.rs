@@ -0,0 +1,202 @@
+//! `xe run --ssh <host>`. Ships `xe.toml`, `xe.lock` and any `--include`
+//! paths to a directory on the host, syncs the environment there from the
+//! lock with the host's own xe, then runs the command in it. A host without
+//! xe gets a copy of this binary when it runs the same OS and architecture.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::env;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use xe_core::config::{load_or_create_project, XE_TOML};
+use xe_core::lock::{load_lock, lock_fingerprint, XE_LOCK};
+use xe_core::process::run_foreground;
+use xe_core::ui::{info, success};
+use xe_core::venv::{normalize_venv_name, project_path_hash};
+
+/// Where projects and the bootstrapped binary go, relative to the remote
+/// home directory.
+const REMOTE_ROOT: &str = ".xe-remote";
+const REMOTE_XE: &str = ".xe-remote/bin/xe";
+
+pub(crate) struct RemoteRun {
+    pub(crate) host: String,
+    /// Project directory on the host; under [`REMOTE_ROOT`] when unset.
+    pub(crate) dir: Option<String>,
+    /// Project-relative paths shipped next to `xe.toml` and `xe.lock`.
+    pub(crate) include: Vec<String>,
+}
+
+pub(crate) fn run_remote(remote: &RemoteRun, command_args: &[String]) -> Result<ExitStatus> {
+    // Host names never start with `-`; ssh would read one as an option,
+    // such as `-oProxyCommand=...`, if not for the `--` before it.
+    if remote.host.is_empty() || remote.host.starts_with('-') {
+        bail!("--ssh takes a host name, not {:?}", remote.host);
+    }
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (cfg, _) = load_or_create_project(&wd)?;
+    let Some(lock) = load_lock(&wd)? else {
+        bail!("no {XE_LOCK} in {}; run `xe lock` first", wd.display());
+    };
+    if lock.fingerprint != lock_fingerprint(&cfg) {
+        bail!("{XE_LOCK} is out of date with {XE_TOML}; run `xe lock` first");
+    }
+    for path in &remote.include {
+        let rel = Path::new(path);
+        if rel.is_absolute() || rel.components().any(|c| c.as_os_str() == "..") {
+            bail!("--include takes paths inside the project, not {path}");
+        }
+        if !wd.join(rel).exists() {
+            bail!("--include {path}: no such file or directory");
+        }
+    }
+    let dir = match &remote.dir {
+        // ssh starts in the home directory, and a quoted `~` would not
+        // expand.
+        Some(dir) => dir.strip_prefix("~/").unwrap_or(dir).to_string(),
+        None => {
+            let name = match normalize_venv_name(&cfg.project.name) {
+                name if name.is_empty() => "project".to_string(),
+                name => name,
+            };
+            format!("{REMOTE_ROOT}/{name}-{}", project_path_hash(&wd))
+        }
+    };
+
+    info(&format!(
+        "Copying the project lock to {}:{dir}...",
+        remote.host
+    ));
+    let xe = match upload(remote, &wd, &dir)?.as_str() {
+        "xe" => "xe".to_string(),
+        "bootstrapped" => format!("\"$HOME\"/{REMOTE_XE}"),
+        platform => bootstrap(&remote.host, platform)?,
+    };
+
+    let command = command_args
+        .iter()
+        .map(|arg| sh_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let script = format!(
+        "cd {} && {xe} sync && exec {xe} run -- {command}",
+        sh_quote(&dir)
+    );
+    info(&format!(
+        "Running on {}: {}",
+        remote.host,
+        command_args.join(" ")
+    ));
+    let mut ssh = Command::new("ssh");
+    if std::io::stdin().is_terminal() {
+        ssh.arg("-t");
+    }
+    ssh.arg("--").arg(&remote.host).arg(script);
+    run_foreground(&mut ssh).context("failed to run ssh")
+}
+
+/// Unpacks `xe.toml`, `xe.lock` and the included paths into `dir` on the
+/// host. Returns `xe` when the host has xe on `PATH`, `bootstrapped` when
+/// an earlier run copied one over, and its `uname -sm` otherwise.
+fn upload(remote: &RemoteRun, wd: &Path, dir: &str) -> Result<String> {
+    let mut tar = Command::new("tar");
+    tar.arg("-cf")
+        .arg("-")
+        .arg("-C")
+        .arg(wd)
+        .arg(XE_TOML)
+        .arg(XE_LOCK)
+        .args(&remote.include)
+        .stdout(Stdio::piped());
+    let mut tar = tar.spawn().context("failed to run tar")?;
+    let archive = tar
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("failed to read the output of tar"))?;
+    let script = format!(
+        "mkdir -p {dir} && tar -xf - -C {dir} && \
+         {{ if command -v xe >/dev/null 2>&1; then echo xe; \
+         elif [ -x {REMOTE_XE} ]; then echo bootstrapped; else uname -sm; fi; }}",
+        dir = sh_quote(dir)
+    );
+    let output = Command::new("ssh")
+        .arg("--")
+        .arg(&remote.host)
+        .arg(script)
+        .stdin(archive)
+        .stderr(Stdio::inherit())
+        .output()
+        .context("failed to run ssh")?;
+    let tar_status = tar.wait().context("failed to run tar")?;
+    if !tar_status.success() {
+        bail!("failed to pack the project: tar {tar_status}");
+    }
+    if !output.status.success() {
+        bail!(
+            "failed to copy the project to {}: ssh {}",
+            remote.host,
+            output.status
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Copies this xe binary to the host, whose `uname -sm` is `platform`, and
+/// returns the path to run it by.
+fn bootstrap(host: &str, platform: &str) -> Result<String> {
+    let local = local_platform();
+    if !local.is_some_and(|local| local.eq_ignore_ascii_case(platform)) {
+        bail!(
+            "xe is not installed on {host} ({platform}) and this xe is built for another platform; install xe there first"
+        );
+    }
+    let exe = env::current_exe().context("failed to locate the xe binary")?;
+    let bytes = std::fs::read(&exe).with_context(|| format!("failed to read {}", exe.display()))?;
+    info(&format!("xe is not installed on {host}; copying this one"));
+    let mut ssh = Command::new("ssh")
+        .arg("--")
+        .arg(host)
+        .arg(format!(
+            "mkdir -p {REMOTE_ROOT}/bin && cat > {REMOTE_XE} && chmod +x {REMOTE_XE}"
+        ))
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("failed to run ssh")?;
+    ssh.stdin
+        .take()
+        .ok_or_else(|| anyhow!("failed to write to ssh"))?
+        .write_all(&bytes)
+        .with_context(|| format!("failed to copy xe to {host}"))?;
+    let status = ssh.wait().context("failed to run ssh")?;
+    if !status.success() {
+        bail!("failed to copy xe to {host}: ssh {status}");
+    }
+    success(&format!("Installed xe on {host} at ~/{REMOTE_XE}"));
+    Ok(format!("\"$HOME\"/{REMOTE_XE}"))
+}
+
+/// This machine as `uname -sm` prints it, for the platforms xe builds for.
+fn local_platform() -> Option<String> {
+    let os = match env::consts::OS {
+        "linux" => "Linux",
+        "macos" => "Darwin",
+        _ => return None,
+    };
+    let arch = match (env::consts::OS, env::consts::ARCH) {
+        ("macos", "aarch64") => "arm64",
+        (_, arch) => arch,
+    };
+    Some(format!("{os} {arch}"))
+}
+
+/// Quotes `arg` for the POSIX shell ssh runs the remote command with.
+fn sh_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_./=:,+@%".contains(&b))
+    {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
@@ -0,0 +1,446 @@
+//! `xe serve`. A PEP 503 simple index in front of the primary index: project
+//! pages are fetched upstream and their file links pointed back at this
+//! server, which hands files out of the local CAS, downloading each one the
+//! first time it is asked for. Pages are kept on disk, so once the upstream
+//! is unreachable everything already cached can still be installed.
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::blocking::Client;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use xe_core::config::{load_project, normalize_dep_name, Config, XE_TOML};
+use xe_core::install::primary_index_url;
+use xe_core::net::{
+    http_client, http_get, http_send, redact_url, redact_urls_in, strip_credentials,
+};
+use xe_core::ui::{info, paint, warning, Style};
+use xe_core::Cas;
+
+/// Longest request or header line read, and most header lines, before a
+/// request is turned away.
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+struct Proxy {
+    /// Upstream simple index, ending in `/`.
+    upstream: String,
+    cas: Cas,
+    /// Upstream project pages as last fetched, for serving offline.
+    pages: PathBuf,
+    /// sha256 to upstream URL of every file linked from a served page.
+    files: Mutex<HashMap<String, String>>,
+    client: Client,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    File(File, u64),
+    Redirect(String),
+}
+
+impl Response {
+    fn html(body: String) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            body: Body::Bytes(body.into_bytes()),
+        }
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: Body::Bytes(format!("{message}\n").into_bytes()),
+        }
+    }
+
+    fn redirect(location: String) -> Self {
+        Self {
+            status: "301 Moved Permanently",
+            content_type: "text/plain; charset=utf-8",
+            body: Body::Redirect(location),
+        }
+    }
+}
+
+pub(crate) fn cmd_serve(args: &[String]) -> Result<()> {
+    let usage = "usage: xe serve [--port <port>] [--host <address>] [--index <url>]";
+    let mut port = 8080u16;
+    let mut host = "127.0.0.1".to_string();
+    let mut index = None;
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        match args[i].as_str() {
+            "--port" => {
+                port = value
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| anyhow!("--port requires a port number"))?;
+            }
+            "--host" => {
+                host = value
+                    .ok_or_else(|| anyhow!("--host requires an address"))?
+                    .clone();
+            }
+            "--index" => {
+                index = Some(
+                    value
+                        .ok_or_else(|| anyhow!("--index requires a URL"))?
+                        .clone(),
+                );
+            }
+            _ => bail!("{usage}"),
+        }
+        i += 2;
+    }
+
+    // The project's cache and index when run inside one, the global ones
+    // otherwise.
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let toml_path = wd.join(XE_TOML);
+    let cfg = if toml_path.exists() {
+        load_project(&toml_path)?
+    } else {
+        Config::new_default(&wd)
+    };
+    let upstream = match index {
+        Some(url) => url,
+        None => primary_index_url(&cfg)?,
+    };
+    let cas_root = cfg.cache.dir(&wd)?;
+    let proxy = Arc::new(Proxy {
+        upstream: format!("{}/", upstream.trim_end_matches('/')),
+        cas: Cas::new(&cas_root)?,
+        pages: cas_root.join("serve").join("simple"),
+        files: Mutex::new(HashMap::new()),
+        client: http_client(Duration::from_secs(30))?,
+    });
+    fs::create_dir_all(&proxy.pages)
+        .with_context(|| format!("failed to create {}", proxy.pages.display()))?;
+
+    let listener = TcpListener::bind((host.as_str(), port))
+        .with_context(|| format!("failed to listen on {host}:{port}"))?;
+    info(&format!(
+        "Serving {} through the xe cache at http://{host}:{port}/simple/",
+        redact_url(&proxy.upstream)
+    ));
+    info(&format!(
+        "pip install --index-url http://{host}:{port}/simple/ <package>"
+    ));
+    if host == "127.0.0.1" || host == "localhost" {
+        info("Only this machine can connect; pass --host 0.0.0.0 to serve the network");
+    }
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let proxy = Arc::clone(&proxy);
+        std::thread::spawn(move || {
+            if let Err(err) = proxy.handle(stream) {
+                warning(&format!("{err:#}"));
+            }
+        });
+    }
+    Ok(())
+}
+
+impl Proxy {
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(30))).ok();
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let Some(request_line) = read_line_limited(&mut reader)? else {
+            let response = Response::error("414 URI Too Long", "request line too long");
+            return write_response(&mut stream, response, false);
+        };
+        // Headers are read and ignored.
+        let mut headers = 0usize;
+        loop {
+            let Some(line) = read_line_limited(&mut reader)? else {
+                let response =
+                    Response::error("431 Request Header Fields Too Large", "header too long");
+                return write_response(&mut stream, response, false);
+            };
+            if line.trim().is_empty() {
+                break;
+            }
+            headers += 1;
+            if headers > MAX_HEADERS {
+                let response =
+                    Response::error("431 Request Header Fields Too Large", "too many headers");
+                return write_response(&mut stream, response, false);
+            }
+        }
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Ok(());
+        };
+        let path = target.split(['?', '#']).next().unwrap_or(target);
+        let response = match method {
+            "GET" | "HEAD" => self.route(path),
+            _ => Response::error("405 Method Not Allowed", "only GET and HEAD are served"),
+        };
+        println!(
+            "{} {} {}",
+            paint(Style::Muted, method),
+            path,
+            paint(
+                if response.status.starts_with('2') || response.status.starts_with('3') {
+                    Style::Success
+                } else {
+                    Style::Error
+                },
+                response.status
+            )
+        );
+        write_response(&mut stream, response, method == "HEAD")
+    }
+
+    fn route(&self, path: &str) -> Response {
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        match segments.as_slice() {
+            [] => Response::redirect("/simple/".to_string()),
+            ["simple"] if path.ends_with('/') => self.project_list(),
+            ["simple"] => Response::redirect("/simple/".to_string()),
+            ["simple", project]
+                if !project
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b)) =>
+            {
+                Response::error("404 Not Found", "not a project name")
+            }
+            ["simple", project] => {
+                let canonical = normalize_dep_name(project);
+                if *project != canonical || !path.ends_with('/') {
+                    return Response::redirect(format!("/simple/{canonical}/"));
+                }
+                match self.project_page(&canonical) {
+                    Ok(response) => response,
+                    Err(err) => bad_gateway(&err),
+                }
+            }
+            ["files", sha, _filename] => match self.file(sha) {
+                Ok(response) => response,
+                Err(err) => bad_gateway(&err),
+            },
+            _ => Response::error("404 Not Found", "not found"),
+        }
+    }
+
+    /// The projects whose pages were fetched through this server.
+    fn project_list(&self) -> Response {
+        let mut names = fs::read_dir(&self.pages)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|entry| {
+                        let name = entry.file_name().to_string_lossy().to_string();
+                        name.strip_suffix(".html").map(str::to_string)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        names.sort();
+        let links = names
+            .iter()
+            .map(|name| format!("<a href=\"/simple/{name}/\">{name}</a><br/>\n"))
+            .collect::<String>();
+        Response::html(format!(
+            "<!DOCTYPE html>\n<html><body>\n{links}</body></html>\n"
+        ))
+    }
+
+    fn project_page(&self, project: &str) -> Result<Response> {
+        let url = format!("{}{project}/", self.upstream);
+        let cached = self.pages.join(format!("{project}.html"));
+        let upstream = http_send(http_get(&self.client, &url).header("Accept", "text/html"));
+        let html = match upstream {
+            Ok(resp) if resp.status() == reqwest::StatusCode::NOT_FOUND => {
+                return Ok(Response::error(
+                    "404 Not Found",
+                    &format!("{project} is not on {}", strip_credentials(&self.upstream)),
+                ));
+            }
+            Ok(resp) if resp.status().is_success() => {
+                let html = resp
+                    .text()
+                    .with_context(|| format!("failed to read {url}"))?;
+                // Best effort; a page that cannot be kept is still served.
+                let _ = fs::write(&cached, &html);
+                html
+            }
+            failed => {
+                let reason = match failed {
+                    Ok(resp) => format!("{url} returned {}", resp.status()),
+                    Err(err) => format!("failed to query {url}: {err}"),
+                };
+                let html = fs::read_to_string(&cached).map_err(|_| anyhow!(reason.clone()))?;
+                warning(&redact_urls_in(&format!(
+                    "{reason}; serving the page cached earlier"
+                )));
+                html
+            }
+        };
+        let base = Url::parse(&url).with_context(|| format!("invalid index URL {url}"))?;
+        let (page, files) = rewrite_page(&html, &base);
+        if let Ok(mut known) = self.files.lock() {
+            known.extend(files);
+        }
+        Ok(Response::html(page))
+    }
+
+    fn file(&self, sha: &str) -> Result<Response> {
+        if sha.len() != 64 || !sha.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Ok(Response::error("404 Not Found", "not found"));
+        }
+        let sha = sha.to_ascii_lowercase();
+        let blob = self.cas.blob_path(&sha);
+        if !blob.exists() {
+            let url = self
+                .files
+                .lock()
+                .ok()
+                .and_then(|known| known.get(&sha).cloned());
+            let Some(url) = url else {
+                return Ok(Response::error(
+                    "404 Not Found",
+                    "unknown file; fetch its project page first",
+                ));
+            };
+            self.cas.store_blob_from_url(&url, &sha)?;
+        }
+        let file =
+            File::open(&blob).with_context(|| format!("failed to open {}", blob.display()))?;
+        let size = file.metadata()?.len();
+        Ok(Response {
+            status: "200 OK",
+            content_type: "application/octet-stream",
+            body: Body::File(file, size),
+        })
+    }
+}
+
+/// Points every file link carrying a sha256 at `/files/<sha256>/<name>` and
+/// makes the rest absolute, returning the page and the upstream URL of each
+/// hash. Metadata attributes are dropped: the files they describe are not
+/// served, so pip reads metadata from the wheels it downloads instead.
+fn rewrite_page(html: &str, base: &Url) -> (String, Vec<(String, String)>) {
+    let mut out = String::with_capacity(html.len());
+    let mut files = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<a ") {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = &rest[start..start + len];
+        out.push_str(&rewrite_anchor(tag, base, &mut files));
+        rest = &rest[start + len..];
+    }
+    out.push_str(rest);
+    (out, files)
+}
+
+fn rewrite_anchor(tag: &str, base: &Url, files: &mut Vec<(String, String)>) -> String {
+    let mut tag = tag.to_string();
+    for attr in ["data-dist-info-metadata", "data-core-metadata"] {
+        while let Some(start) = tag.find(&format!(" {attr}=\"")) {
+            let value = start + attr.len() + 3;
+            let Some(end) = tag[value..].find('"') else {
+                break;
+            };
+            tag.replace_range(start..value + end + 1, "");
+        }
+    }
+    let Some(start) = tag.find("href=\"").map(|i| i + 6) else {
+        return tag;
+    };
+    let Some(end) = tag[start..].find('"').map(|i| start + i) else {
+        return tag;
+    };
+    let href = tag[start..end].replace("&amp;", "&");
+    let Ok(url) = base.join(&href) else {
+        return tag;
+    };
+    let sha = url
+        .fragment()
+        .and_then(|fragment| fragment.strip_prefix("sha256="))
+        .filter(|sha| sha.len() == 64 && sha.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase);
+    let new_href = match sha {
+        Some(sha) => {
+            let name = url
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .unwrap_or("file")
+                .to_string();
+            let mut upstream = url.clone();
+            upstream.set_fragment(None);
+            files.push((sha.clone(), upstream.to_string()));
+            format!("/files/{sha}/{name}#sha256={sha}")
+        }
+        // Upstream links go out to every client; its credentials do not.
+        None => strip_credentials(url.as_str()),
+    };
+    tag.replace_range(start..end, &new_href.replace('&', "&amp;"));
+    tag
+}
+
+/// A failure reaching the upstream, which clients may see but the
+/// credentials in its URL not.
+fn bad_gateway(err: &anyhow::Error) -> Response {
+    Response::error("502 Bad Gateway", &redact_urls_in(&format!("{err:#}")))
+}
+
+/// One line of at most [`MAX_LINE_BYTES`], or `None` when it is longer.
+fn read_line_limited(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_BYTES + 1).read_line(&mut line)?;
+    Ok((line.len() as u64 <= MAX_LINE_BYTES).then_some(line))
+}
+
+fn write_response(stream: &mut TcpStream, response: Response, head_only: bool) -> Result<()> {
+    let (length, location) = match &response.body {
+        Body::Bytes(bytes) => (bytes.len() as u64, None),
+        Body::File(_, size) => (*size, None),
+        Body::Redirect(location) => (0, Some(location.clone())),
+    };
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {length}\r\nConnection: close\r\n",
+        response.status, response.content_type
+    );
+    if let Some(location) = location {
+        head.push_str(&format!("Location: {location}\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if !head_only {
+        match response.body {
+            Body::Bytes(bytes) => stream.write_all(&bytes)?,
+            Body::File(mut file, _) => {
+                io::copy(&mut file, stream)?;
+            }
+            Body::Redirect(_) => {}
+        }
+    }
+    stream.flush()?;
+    Ok(())
+}
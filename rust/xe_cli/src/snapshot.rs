@@ -0,0 +1,628 @@
+//! `xe snapshot` and `xe restore`. A snapshot is either a zip of the xe home
+//! or, with `--incremental`, a manifest whose files point at 4 MiB chunks in
+//! a content-addressed store under `snaps/cas`, so repeated snapshots only
+//! add what changed.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+use xe_core::archive::{extract_zip, safe_relative};
+use xe_core::paths::{self, dir_size, xe_home};
+use xe_core::process::register_cleanup_path;
+use xe_core::profile::{profile_stamp, timestamp_iso8601};
+use xe_core::ui::{format_bytes, success, ProgressUnit, TransferProgress};
+use xe_core::Cas;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// Top-level entries of the xe home, grouped for `xe snapshot --include`
+/// and `--exclude`. Entries outside every group (profiles, crash reports)
+/// are kept unless `--include` narrows the snapshot.
+const SNAPSHOT_SCOPES: &[(&str, &[&str])] = &[
+    (
+        "config",
+        &["config.yaml", "credentials.json", "credentials"],
+    ),
+    ("venvs", &["venvs"]),
+    ("python", &["python"]),
+    ("cache", &["cache"]),
+    ("shims", &["bin"]),
+    ("plugins", &["plugins"]),
+];
+
+/// The CAS is large and refilled on demand, so it needs `--include cache`.
+const SNAPSHOT_DEFAULT_EXCLUDE: &[&str] = &["cache"];
+
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    name: String,
+    created_at: String,
+    entries: Vec<ManifestEntry>,
+}
+
+/// Paths are relative to the xe home, with `/` separators.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ManifestEntry {
+    Dir {
+        path: String,
+    },
+    File {
+        path: String,
+        size: u64,
+        /// Modification time in nanoseconds since the epoch; with `size` it
+        /// decides whether the next snapshot can reuse `chunks` unread.
+        mtime_ns: u64,
+        /// Unix permission bits, 0 elsewhere.
+        #[serde(default)]
+        mode: u32,
+        chunks: Vec<String>,
+    },
+    Symlink {
+        path: String,
+        target: String,
+    },
+}
+
+fn snaps_dir() -> PathBuf {
+    xe_home().join("snaps")
+}
+
+pub(crate) fn cmd_snapshot(args: &[String]) -> Result<()> {
+    let usage = "usage: xe snapshot <name> [--incremental] [--include <scopes>] [--exclude <scopes>] | xe snapshot gc";
+    if args.len() == 1 && args[0] == "gc" {
+        return gc_chunks();
+    }
+    let mut name = None;
+    let mut include = None;
+    let mut exclude = Vec::new();
+    let mut incremental = false;
+    let mut idx = 0usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            flag @ ("--include" | "--exclude") => {
+                let value = args.get(idx + 1).ok_or_else(|| anyhow!(usage))?;
+                let scopes = parse_snapshot_scopes(value)?;
+                if flag == "--include" {
+                    include = Some(scopes);
+                } else {
+                    exclude.extend(scopes);
+                }
+                idx += 2;
+            }
+            "--incremental" => {
+                incremental = true;
+                idx += 1;
+            }
+            value if !value.starts_with('-') && name.is_none() => {
+                name = Some(value.to_string());
+                idx += 1;
+            }
+            _ => bail!(usage),
+        }
+    }
+    let name = name.ok_or_else(|| anyhow!(usage))?;
+    let snap_path = create_snapshot(&name, include.as_deref(), &exclude, incremental)?;
+    println!(
+        "Snapshot '{}' created successfully at {}",
+        name,
+        snap_path.display()
+    );
+    Ok(())
+}
+
+pub(crate) fn cmd_restore(args: &[String]) -> Result<()> {
+    if args.len() != 1 {
+        bail!("usage: xe restore <name>");
+    }
+    let snap_path = restore_snapshot(&args[0])?;
+    println!(
+        "Successfully restored snapshot '{}' from {}",
+        args[0],
+        snap_path.display()
+    );
+    Ok(())
+}
+
+fn parse_snapshot_scopes(raw: &str) -> Result<Vec<String>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|scope| !scope.is_empty())
+        .map(|scope| {
+            if scope == "all" || SNAPSHOT_SCOPES.iter().any(|(name, _)| *name == scope) {
+                Ok(scope.to_string())
+            } else {
+                let known = SNAPSHOT_SCOPES
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Err(anyhow!(
+                    "unknown snapshot scope '{}' (expected all, {})",
+                    scope,
+                    known
+                ))
+            }
+        })
+        .collect()
+}
+
+fn create_snapshot(
+    name: &str,
+    include: Option<&[String]>,
+    exclude: &[String],
+    incremental: bool,
+) -> Result<PathBuf> {
+    let xe_dir = xe_home();
+    let snaps_dir = snaps_dir();
+    fs::create_dir_all(&snaps_dir)
+        .with_context(|| format!("failed to create {}", snaps_dir.display()))?;
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0))
+        .as_secs();
+
+    let selected = |scope: Option<&str>| {
+        let included = match (include, scope) {
+            (Some(list), _) if list.iter().any(|s| s == "all") => true,
+            (Some(list), Some(scope)) => list.iter().any(|s| s == scope),
+            (Some(_), None) => false,
+            (None, Some(scope)) => !SNAPSHOT_DEFAULT_EXCLUDE.contains(&scope),
+            (None, None) => true,
+        };
+        included && !scope.is_some_and(|scope| exclude.iter().any(|s| s == scope))
+    };
+    let mut entries = Vec::new();
+    for entry in
+        fs::read_dir(&xe_dir).with_context(|| format!("failed to read {}", xe_dir.display()))?
+    {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name == "snaps" {
+            continue;
+        }
+        let scope = SNAPSHOT_SCOPES
+            .iter()
+            .find(|(_, names)| names.contains(&file_name.as_str()))
+            .map(|(scope, _)| *scope);
+        if selected(scope) {
+            entries.push(entry.path());
+        }
+    }
+    entries.sort();
+
+    if incremental {
+        let snap_path = snaps_dir.join(format!("{name}_{ts}{MANIFEST_SUFFIX}"));
+        write_manifest(name, &xe_dir, &entries, &snap_path)?;
+        return Ok(snap_path);
+    }
+    let snap_path = snaps_dir.join(format!("{name}_{ts}.zip"));
+    zip_directory(&xe_dir, &entries, &snap_path)?;
+    Ok(snap_path)
+}
+
+/// Timestamp of a `<name>_<ts>.zip` or `<name>_<ts>.manifest.json` file
+/// when its name matches, and whether it is a manifest.
+fn snapshot_stamp(path: &Path, name: Option<&str>) -> Option<(u64, bool)> {
+    let file_name = path.file_name()?.to_str()?;
+    let (stem, manifest) = match file_name.strip_suffix(MANIFEST_SUFFIX) {
+        Some(stem) => (stem, true),
+        None => (file_name.strip_suffix(".zip")?, false),
+    };
+    let (snap_name, ts) = stem.rsplit_once('_')?;
+    if name.is_some_and(|name| name != snap_name) {
+        return None;
+    }
+    Some((ts.parse().ok()?, manifest))
+}
+
+fn list_snapshots(name: Option<&str>) -> Vec<(u64, bool, PathBuf)> {
+    let mut found = fs::read_dir(snaps_dir())
+        .ok()
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            let (ts, manifest) = snapshot_stamp(&path, name)?;
+            Some((ts, manifest, path))
+        })
+        .collect::<Vec<_>>();
+    found.sort();
+    found
+}
+
+fn load_manifest(path: &Path) -> Result<Manifest> {
+    let raw = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_slice(&raw).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+fn mtime_ns(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(_meta: &fs::Metadata) -> u32 {
+    0
+}
+
+/// Chunks every file under `entries` into the store and writes the
+/// manifest. Files whose size and mtime match the newest earlier manifest
+/// reuse its chunks without being read.
+fn write_manifest(name: &str, source: &Path, entries: &[PathBuf], target: &Path) -> Result<()> {
+    let cas = Cas::new(&snaps_dir())?;
+    // Until the manifest is written, nothing else says its chunks are used.
+    let _lock = cas.lock_chunks(false)?;
+    let mut previous = HashMap::new();
+    if let Some((_, _, path)) = list_snapshots(None)
+        .into_iter()
+        .rev()
+        .find(|(_, manifest, _)| *manifest)
+    {
+        for entry in load_manifest(&path)?.entries {
+            if let ManifestEntry::File {
+                path,
+                size,
+                mtime_ns,
+                chunks,
+                ..
+            } = entry
+            {
+                previous.insert(path, (size, mtime_ns, chunks));
+            }
+        }
+    }
+
+    let total = entries.iter().map(|path| dir_size(path)).sum();
+    let mut progress = TransferProgress::new("Snapshotting", Some(total), ProgressUnit::Bytes);
+    let mut manifest = Manifest {
+        name: name.to_string(),
+        created_at: timestamp_iso8601(),
+        entries: Vec::new(),
+    };
+    let (mut added, mut reused) = (0u64, 0u64);
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    for entry in entries
+        .iter()
+        .flat_map(|path| WalkDir::new(path).sort_by_file_name())
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let rel = path
+            .strip_prefix(source)
+            .with_context(|| format!("failed to strip prefix for {}", path.display()))?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if entry.file_type().is_dir() {
+            manifest.entries.push(ManifestEntry::Dir { path: rel });
+            continue;
+        }
+        // Windows links need a privilege to recreate; store their content.
+        if cfg!(unix) && entry.path_is_symlink() {
+            let target = fs::read_link(path)
+                .with_context(|| format!("failed to read link {}", path.display()))?;
+            manifest.entries.push(ManifestEntry::Symlink {
+                path: rel,
+                target: target.to_string_lossy().to_string(),
+            });
+            continue;
+        }
+
+        let meta =
+            fs::metadata(path).with_context(|| format!("failed to stat {}", path.display()))?;
+        let (size, mtime_ns, mode) = (meta.len(), mtime_ns(&meta), file_mode(&meta));
+        let unchanged = previous
+            .get(&rel)
+            .filter(|(prev_size, prev_mtime, chunks)| {
+                *prev_size == size
+                    && *prev_mtime == mtime_ns
+                    && chunks.iter().all(|sha| cas.chunk_path(sha).exists())
+            });
+        let chunks = match unchanged {
+            Some((_, _, chunks)) => {
+                reused += size;
+                progress.advance(size);
+                chunks.clone()
+            }
+            None => {
+                let mut input = File::open(path)
+                    .with_context(|| format!("failed to open {}", path.display()))?;
+                let mut chunks = Vec::new();
+                loop {
+                    let read = read_full(&mut input, &mut buffer)
+                        .with_context(|| format!("failed to read {}", path.display()))?;
+                    if read == 0 {
+                        break;
+                    }
+                    let (sha, new) = cas.store_chunk(&buffer[..read])?;
+                    if new {
+                        added += read as u64;
+                    } else {
+                        reused += read as u64;
+                    }
+                    chunks.push(sha);
+                    progress.advance(read as u64);
+                }
+                chunks
+            }
+        };
+        manifest.entries.push(ManifestEntry::File {
+            path: rel,
+            size,
+            mtime_ns,
+            mode,
+            chunks,
+        });
+    }
+    progress.finish();
+
+    let encoded = serde_json::to_vec_pretty(&manifest)?;
+    fs::write(target, encoded).with_context(|| format!("failed to write {}", target.display()))?;
+    success(&format!(
+        "Stored {} of new data, reused {}",
+        format_bytes(added),
+        format_bytes(reused)
+    ));
+    Ok(())
+}
+
+/// Fills `buffer` unless the reader ends first, so chunk boundaries depend
+/// only on file offsets.
+fn read_full(input: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Writes the files of a manifest below `target_dir`. Names are checked
+/// like archive entries, and symlinks are created last so nothing is
+/// written through one.
+fn materialize_manifest(path: &Path, target_dir: &Path) -> Result<()> {
+    let manifest = load_manifest(path)?;
+    let cas = Cas::new(&snaps_dir())?;
+    let total = manifest
+        .entries
+        .iter()
+        .map(|entry| match entry {
+            ManifestEntry::File { size, .. } => *size,
+            _ => 0,
+        })
+        .sum();
+    let mut progress = TransferProgress::new("Restoring", Some(total), ProgressUnit::Bytes);
+    let mut links = Vec::new();
+    for entry in &manifest.entries {
+        let rel = match entry {
+            ManifestEntry::Dir { path }
+            | ManifestEntry::File { path, .. }
+            | ManifestEntry::Symlink { path, .. } => path,
+        };
+        let out_path = safe_relative(rel)
+            .filter(|rel| !rel.as_os_str().is_empty())
+            .map(|rel| target_dir.join(rel))
+            .ok_or_else(|| anyhow!("unsafe path in snapshot manifest: {}", rel))?;
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        match entry {
+            ManifestEntry::Dir { .. } => {
+                fs::create_dir_all(&out_path)
+                    .with_context(|| format!("failed to create {}", out_path.display()))?;
+            }
+            ManifestEntry::Symlink { target, .. } => links.push((out_path, target)),
+            ManifestEntry::File {
+                size,
+                mtime_ns,
+                mode,
+                chunks,
+                ..
+            } => {
+                let mut out = File::create(&out_path)
+                    .with_context(|| format!("failed to create {}", out_path.display()))?;
+                let mut written = 0u64;
+                for sha in chunks {
+                    let data = cas.read_chunk(sha)?;
+                    out.write_all(&data)
+                        .with_context(|| format!("failed to write {}", out_path.display()))?;
+                    written += data.len() as u64;
+                    progress.advance(data.len() as u64);
+                }
+                if written != *size {
+                    bail!(
+                        "{} restored {} bytes, manifest says {}",
+                        out_path.display(),
+                        written,
+                        size
+                    );
+                }
+                // Keeping the mtime lets the next incremental snapshot skip it.
+                let _ = out.set_modified(UNIX_EPOCH + Duration::from_nanos(*mtime_ns));
+                set_mode(&out_path, *mode)?;
+            }
+        }
+    }
+    progress.finish();
+    for (link, target) in links {
+        create_symlink(target, &link)
+            .with_context(|| format!("failed to create link {}", link.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if mode != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("failed to set permissions on {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Manifests written on Windows never hold links; one from a Unix machine
+/// is restored without them.
+#[cfg(not(unix))]
+fn create_symlink(_target: &str, _link: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Restores the newest snapshot called `name`, zip or manifest. It is fully
+/// extracted into a staging directory first, so a damaged or hostile
+/// snapshot leaves the current state untouched; then each top-level entry
+/// replaces its counterpart in the xe home.
+fn restore_snapshot(name: &str) -> Result<PathBuf> {
+    let xe_dir = xe_home();
+    let snaps_dir = snaps_dir();
+    let (_, manifest, snap_path) = list_snapshots(Some(name))
+        .pop()
+        .ok_or_else(|| anyhow!("no snapshot named '{}' in {}", name, snaps_dir.display()))?;
+
+    let staging = snaps_dir.join(format!(".restore-{}", profile_stamp()));
+    register_cleanup_path(&staging);
+    let staged = if manifest {
+        materialize_manifest(&snap_path, &staging)
+    } else {
+        extract_zip(&snap_path, &staging).map_err(anyhow::Error::from)
+    };
+    if let Err(err) = staged {
+        let _ = paths::remove_dir_all(&staging);
+        return Err(err).with_context(|| format!("failed to restore {}", snap_path.display()));
+    }
+    fs::create_dir_all(&staging)
+        .with_context(|| format!("failed to create {}", staging.display()))?;
+    for entry in
+        fs::read_dir(&staging).with_context(|| format!("failed to read {}", staging.display()))?
+    {
+        let entry = entry?;
+        let target = xe_dir.join(entry.file_name());
+        if let Ok(meta) = fs::symlink_metadata(&target) {
+            if meta.is_dir() {
+                paths::remove_dir_all(&target)
+            } else {
+                paths::remove_file(&target)
+            }
+            .with_context(|| format!("failed to replace {}", target.display()))?;
+        }
+        fs::rename(entry.path(), &target)
+            .with_context(|| format!("failed to restore {}", target.display()))?;
+    }
+    let _ = paths::remove_dir_all(&staging);
+    Ok(snap_path)
+}
+
+/// Deletes chunks no manifest refers to. Stops if any manifest cannot be
+/// read, since its chunks would look unreferenced.
+fn gc_chunks() -> Result<()> {
+    let cas = Cas::new(&snaps_dir())?;
+    let _lock = cas.lock_chunks(true)?;
+    let mut keep = HashSet::new();
+    for (_, _, path) in list_snapshots(None)
+        .into_iter()
+        .filter(|(_, manifest, _)| *manifest)
+    {
+        for entry in load_manifest(&path)?.entries {
+            if let ManifestEntry::File { chunks, .. } = entry {
+                keep.extend(chunks);
+            }
+        }
+    }
+    let (removed, freed) = cas.gc_chunks(&keep)?;
+    success(&format!(
+        "Removed {} unreferenced chunk(s), freed {}",
+        removed,
+        format_bytes(freed)
+    ));
+    Ok(())
+}
+
+/// Zips `entries` (paths under `source`) into `target`, streaming each file
+/// through with a progress line on stderr.
+fn zip_directory(source: &Path, entries: &[PathBuf], target: &Path) -> Result<()> {
+    let total = entries
+        .iter()
+        .map(|path| {
+            if path.is_dir() {
+                dir_size(path)
+            } else {
+                fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)
+            }
+        })
+        .sum();
+    let file =
+        File::create(target).with_context(|| format!("failed to create {}", target.display()))?;
+    let mut writer = ZipWriter::new(file);
+    let options = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .large_file(true);
+    let mut progress = TransferProgress::new("Snapshotting", Some(total), ProgressUnit::Bytes);
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    for entry in entries.iter().flat_map(WalkDir::new) {
+        let entry = entry?;
+        let path = entry.path();
+        let rel = path
+            .strip_prefix(source)
+            .with_context(|| format!("failed to strip prefix for {}", path.display()))?;
+        let rel_str = rel.to_string_lossy().replace('\\', "/");
+
+        if entry.file_type().is_dir() {
+            writer
+                .add_directory(format!("{rel_str}/"), options)
+                .with_context(|| format!("failed to add dir {}", rel_str))?;
+            continue;
+        }
+        writer
+            .start_file(rel_str.clone(), options)
+            .with_context(|| format!("failed to add file {}", rel_str))?;
+        let mut input =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        loop {
+            let read = input
+                .read(&mut buffer)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            if read == 0 {
+                break;
+            }
+            writer
+                .write_all(&buffer[..read])
+                .with_context(|| format!("failed to write {}", rel_str))?;
+            progress.advance(read as u64);
+        }
+    }
+    writer.finish().context("failed to finalize snapshot zip")?;
+    progress.finish();
+    Ok(())
+}
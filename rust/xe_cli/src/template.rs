@@ -0,0 +1,261 @@
+//! `xe init --template`. A template is a directory or git repository whose
+//! files are copied into the new project with `{{ variable }}` placeholders
+//! filled in, in file names as well as text files. An optional
+//! `xe-template.toml` at its root declares extra variables and a
+//! post-generate hook. The hook runs in a throwaway venv rather than the
+//! project environment, but with the user's full rights, so it only runs
+//! when asked for.
+
+use crate::with_isolated_env;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+use xe_core::paths;
+use xe_core::process::{register_cleanup_path, run_foreground};
+use xe_core::profile::AppContext;
+use xe_core::runtime::apply_runtime_env;
+use xe_core::ui::info;
+
+/// Template settings at the template root; never copied into the project.
+const TEMPLATE_TOML: &str = "xe-template.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+struct TemplateConfig {
+    /// Extra variables and their defaults, overridable with `--var`.
+    variables: BTreeMap<String, String>,
+    /// Shell commands run in the new project once its files are written.
+    post_generate: Vec<String>,
+    /// Packages installed into the hook's venv.
+    requires: Vec<String>,
+}
+
+pub(crate) struct Template {
+    root: PathBuf,
+    config: TemplateConfig,
+    /// Temporary clone of a git template, removed on drop.
+    checkout: Option<PathBuf>,
+}
+
+impl Drop for Template {
+    fn drop(&mut self) {
+        if let Some(checkout) = &self.checkout {
+            let _ = paths::remove_dir_all(checkout);
+        }
+    }
+}
+
+impl Template {
+    /// Opens a template directory, or clones `source` with git when it is
+    /// not one.
+    pub(crate) fn open(source: &str) -> Result<Self> {
+        let local = Path::new(source);
+        let (root, checkout) = if local.is_dir() {
+            (local.to_path_buf(), None)
+        } else {
+            let clone = env::temp_dir().join(format!("xe-template-{}", std::process::id()));
+            register_cleanup_path(&clone);
+            info(&format!("Cloning template {source}..."));
+            let url = source.strip_prefix("git+").unwrap_or(source);
+            let status = Command::new("git")
+                .args(["clone", "--quiet", "--depth", "1", url])
+                .arg(&clone)
+                .status()
+                .context("failed to run git; is it installed?")?;
+            if !status.success() {
+                bail!("{source} is neither a directory nor a git repository git could clone");
+            }
+            (clone.clone(), Some(clone))
+        };
+        let config_path = root.join(TEMPLATE_TOML);
+        let config = if config_path.is_file() {
+            let text = fs::read_to_string(&config_path)
+                .with_context(|| format!("failed to read {}", config_path.display()))?;
+            toml::from_str(&text)
+                .with_context(|| format!("failed to parse {}", config_path.display()))?
+        } else {
+            TemplateConfig::default()
+        };
+        Ok(Self {
+            root,
+            config,
+            checkout,
+        })
+    }
+
+    /// Built-in variables, then the template's defaults, then `overrides`.
+    pub(crate) fn variables(
+        &self,
+        project_name: &str,
+        python_version: &str,
+        overrides: &BTreeMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        let mut vars = BTreeMap::new();
+        vars.insert("project_name".to_string(), project_name.to_string());
+        vars.insert(
+            "module_name".to_string(),
+            project_name.to_lowercase().replace(['-', '.', ' '], "_"),
+        );
+        vars.insert("python_version".to_string(), python_version.to_string());
+        vars.insert("author".to_string(), git_config("user.name"));
+        vars.insert("author_email".to_string(), git_config("user.email"));
+        vars.extend(self.config.variables.clone());
+        vars.extend(overrides.clone());
+        vars
+    }
+
+    /// Copies the template into `project_dir`, refusing to overwrite
+    /// anything. Files that are not UTF-8 are copied as they are.
+    pub(crate) fn render(
+        &self,
+        project_dir: &Path,
+        vars: &BTreeMap<String, String>,
+    ) -> Result<usize> {
+        let mut files = Vec::new();
+        for entry in WalkDir::new(&self.root)
+            .into_iter()
+            .filter_entry(|entry| entry.file_name() != ".git")
+        {
+            let entry = entry.context("failed to read template")?;
+            let relative = entry
+                .path()
+                .strip_prefix(&self.root)
+                .unwrap_or(entry.path());
+            if !entry.file_type().is_file() || relative == Path::new(TEMPLATE_TOML) {
+                continue;
+            }
+            let target = target_path(project_dir, &fill(&relative.to_string_lossy(), vars))?;
+            files.push((entry.path().to_path_buf(), target));
+        }
+        let existing = files
+            .iter()
+            .filter(|(_, target)| target.exists())
+            .map(|(_, target)| target.display().to_string())
+            .collect::<Vec<_>>();
+        if !existing.is_empty() {
+            bail!(
+                "the template would overwrite existing files: {}",
+                existing.join(", ")
+            );
+        }
+        for (source, target) in &files {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("failed to create {}", parent.display()))?;
+            }
+            let bytes =
+                fs::read(source).with_context(|| format!("failed to read {}", source.display()))?;
+            let bytes = match String::from_utf8(bytes) {
+                Ok(text) => fill(&text, vars).into_bytes(),
+                Err(err) => err.into_bytes(),
+            };
+            fs::write(target, bytes)
+                .with_context(|| format!("failed to write {}", target.display()))?;
+        }
+        Ok(files.len())
+    }
+
+    /// Runs the post-generate hook in the new project, inside a temporary
+    /// venv with the template's `requires`. Variables are passed as
+    /// `XE_VAR_<NAME>`.
+    pub(crate) fn run_post_generate(
+        &self,
+        ctx: &AppContext,
+        project_dir: &Path,
+        python_version: &str,
+        vars: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        if self.config.post_generate.is_empty() {
+            return Ok(());
+        }
+        with_isolated_env(
+            ctx,
+            Some(python_version.to_string()),
+            &self.config.requires,
+            |selection| {
+                for hook in &self.config.post_generate {
+                    info(&format!("Running post-generate hook: {hook}"));
+                    let mut command = if cfg!(windows) {
+                        let mut command = Command::new("cmd");
+                        command.arg("/C").arg(hook);
+                        command
+                    } else {
+                        let mut command = Command::new("sh");
+                        command.arg("-c").arg(hook);
+                        command
+                    };
+                    command.current_dir(project_dir);
+                    apply_runtime_env(&mut command, selection)?;
+                    command
+                        .env("XE_PYTHON", &selection.python_exe)
+                        .env("XE_PROJECT_DIR", project_dir)
+                        .env("XE_TEMPLATE_DIR", &self.root);
+                    for (name, value) in vars {
+                        command.env(format!("XE_VAR_{}", name.to_uppercase()), value);
+                    }
+                    let status = run_foreground(&mut command)
+                        .with_context(|| format!("failed to run post-generate hook: {hook}"))?;
+                    if !status.success() {
+                        bail!("post-generate hook failed ({status}): {hook}");
+                    }
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// The post-generate commands, empty when the template has none.
+    pub(crate) fn hooks(&self) -> &[String] {
+        &self.config.post_generate
+    }
+}
+
+/// Where the filled-in template path `relative` goes in `project_dir`. A
+/// variable can hold anything, so the result must stay a plain relative
+/// path: no `..`, root or drive prefix.
+fn target_path(project_dir: &Path, relative: &str) -> Result<PathBuf> {
+    let path = Path::new(relative);
+    if !path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        bail!("template path {relative} points outside the project directory");
+    }
+    Ok(project_dir.join(path))
+}
+
+/// Replaces `{{ name }}` with known variables; other braces, such as
+/// Jinja or GitHub Actions expressions, are left as they are.
+fn fill(text: &str, vars: &BTreeMap<String, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let key = rest[start + 2..start + len].trim();
+        out.push_str(&rest[..start]);
+        match vars.get(key) {
+            Some(value) => out.push_str(value),
+            None => out.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+pub(crate) fn git_config(key: &str) -> String {
+    Command::new("git")
+        .args(["config", "--get", key])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default()
+}
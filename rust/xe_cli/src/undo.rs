@@ -0,0 +1,318 @@
+//! `xe undo`. Before add, remove, sync and upgrade run, xe.toml, xe.lock and
+//! the installed distributions are recorded; when the operation changed any
+//! of them, the record goes on a per-project stack in the xe home, and
+//! `xe undo` puts the newest one back.
+
+use anyhow::{bail, Context, Result};
+use crate::print_planned_changes;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xe_core::config::{load_project, normalize_dep_name, Config, XE_TOML};
+use xe_core::install::{installed_distributions, Installer, Package, PlannedChange};
+use xe_core::lock::{LockFile, XE_LOCK};
+use xe_core::paths::xe_home;
+use xe_core::profile::AppContext;
+use xe_core::runtime::{existing_runtime_for_project, RuntimeSelection};
+use xe_core::ui::{info, success, warning};
+use xe_core::venv::project_path_hash;
+
+/// Operations kept per project; the oldest is dropped past this.
+const MAX_ENTRIES: usize = 10;
+
+/// The project as it was before one operation.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+struct Snapshot {
+    /// xe.toml, or `None` when the operation created it.
+    toml: Option<String>,
+    lock: Option<String>,
+    /// The environment the project used, if it had one.
+    site_packages: Option<PathBuf>,
+    /// Its distributions by normalized name: (name, version).
+    installed: BTreeMap<String, (String, String)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UndoEntry {
+    /// The command that ran, as in `xe add requests`.
+    command: String,
+    /// Unix seconds when it ran.
+    created_at: u64,
+    before: Snapshot,
+}
+
+fn stack_path(project_dir: &Path) -> PathBuf {
+    let name = project_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "project".to_string());
+    let hash = project_path_hash(project_dir);
+    xe_home()
+        .join("undo")
+        .join(format!("{name}-{}.json", &hash[..hash.len().min(12)]))
+}
+
+fn read_stack(path: &Path) -> Vec<UndoEntry> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn write_stack(path: &Path, entries: &[UndoEntry]) -> Result<()> {
+    if entries.is_empty() {
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(entries)?)
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// The environment of the project in `project_dir` as xe.toml currently
+/// selects it, without creating one.
+fn project_runtime(project_dir: &Path) -> Option<RuntimeSelection> {
+    project_environment(project_dir).map(|(_, runtime)| runtime)
+}
+
+/// The config and existing environment of the project in `project_dir`.
+fn project_environment(project_dir: &Path) -> Option<(Config, RuntimeSelection)> {
+    let cfg = load_project(&project_dir.join(XE_TOML)).ok()?;
+    let runtime = existing_runtime_for_project(&cfg)?;
+    Some((cfg, runtime))
+}
+
+fn snapshot(project_dir: &Path, runtime: Option<&RuntimeSelection>) -> Snapshot {
+    let installed = runtime
+        .and_then(|runtime| installed_distributions(&runtime.site_packages).ok())
+        .unwrap_or_default();
+    Snapshot {
+        toml: fs::read_to_string(project_dir.join(XE_TOML)).ok(),
+        lock: fs::read_to_string(project_dir.join(XE_LOCK)).ok(),
+        site_packages: runtime.map(|runtime| runtime.site_packages.clone()),
+        installed: installed.into_iter().collect(),
+    }
+}
+
+/// Runs `xe <command> <args>` through `run` and, when it changed the
+/// project, records how to undo it, even if it then failed. Failing to
+/// record never fails the command.
+pub(crate) fn recorded(
+    command: &str,
+    args: &[String],
+    run: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let Ok(project_dir) = env::current_dir() else {
+        return run();
+    };
+    let before = snapshot(&project_dir, project_runtime(&project_dir).as_ref());
+    let result = run();
+    let after = snapshot(&project_dir, project_runtime(&project_dir).as_ref());
+    if after != before {
+        let path = stack_path(&project_dir);
+        let mut entries = read_stack(&path);
+        entries.push(UndoEntry {
+            command: format!("xe {command} {}", args.join(" "))
+                .trim_end()
+                .to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            before,
+        });
+        let excess = entries.len().saturating_sub(MAX_ENTRIES);
+        entries.drain(..excess);
+        let _ = write_stack(&path, &entries);
+    }
+    result
+}
+
+pub(crate) fn cmd_undo(ctx: &AppContext, args: &[String]) -> Result<()> {
+    let dry_run = match args {
+        [] => false,
+        [flag] if flag == "--dry-run" => true,
+        _ => bail!("usage: xe undo [--dry-run]"),
+    };
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let path = stack_path(&wd);
+    let mut entries = read_stack(&path);
+    let Some(entry) = entries.pop() else {
+        bail!("nothing to undo in {}", wd.display());
+    };
+    info(&format!("Undoing `{}`", entry.command));
+
+    let files = [(XE_TOML, &entry.before.toml), (XE_LOCK, &entry.before.lock)];
+    if dry_run {
+        if let Some((cfg, runtime)) = project_environment(&wd) {
+            restore_environment(ctx, &cfg, &wd, &runtime, &entry.before, true)?;
+        }
+        for (file, recorded) in files {
+            let current = fs::read_to_string(wd.join(file)).ok();
+            match (recorded, current) {
+                (Some(recorded), Some(current)) if *recorded == current => {}
+                (Some(_), _) => info(&format!("Would restore {file}")),
+                (None, Some(_)) => info(&format!("Would delete {file}")),
+                (None, None) => {}
+            }
+        }
+        return Ok(());
+    }
+
+    // The files go back first, so the environment is restored with the
+    // indexes and interpreter the project had before.
+    for (file, recorded) in files {
+        let file_path = wd.join(file);
+        match recorded {
+            Some(text) => fs::write(&file_path, text)
+                .with_context(|| format!("failed to write {}", file_path.display()))?,
+            None if file_path.exists() => fs::remove_file(&file_path)
+                .with_context(|| format!("failed to remove {}", file_path.display()))?,
+            None => {}
+        }
+    }
+    match project_environment(&wd) {
+        Some((cfg, runtime)) => {
+            restore_environment(ctx, &cfg, &wd, &runtime, &entry.before, false)?
+        }
+        None => warning("The project has no environment; restored only xe.toml and xe.lock"),
+    }
+    write_stack(&path, &entries)?;
+    success(&format!("Undid `{}`", entry.command));
+    Ok(())
+}
+
+/// Uninstalls what was added since `before` and reinstalls what was
+/// removed or changed, from the recorded xe.lock when it pins the version.
+fn restore_environment(
+    ctx: &AppContext,
+    cfg: &Config,
+    project_dir: &Path,
+    runtime: &RuntimeSelection,
+    before: &Snapshot,
+    dry_run: bool,
+) -> Result<()> {
+    let installed = installed_distributions(&runtime.site_packages)?;
+    // An environment the operation created or switched to keeps only what
+    // it was created with.
+    let same_environment = before.site_packages.as_ref() == Some(&runtime.site_packages);
+    let seed = BTreeMap::new();
+    let recorded = if same_environment {
+        &before.installed
+    } else {
+        &seed
+    };
+    let removals = installed
+        .iter()
+        .filter(|(key, _)| {
+            !recorded.contains_key(*key)
+                && (same_environment || !matches!(key.as_str(), "pip" | "setuptools" | "wheel"))
+        })
+        .map(|(_, (name, version))| (name.clone(), version.clone()))
+        .collect::<BTreeSet<_>>();
+    let wanted = recorded
+        .iter()
+        .filter(|(key, (_, version))| {
+            installed
+                .get(*key)
+                .is_none_or(|(_, current)| current != version)
+        })
+        .map(|(key, (name, version))| (key.clone(), (name.clone(), version.clone())))
+        .collect::<BTreeMap<_, _>>();
+
+    let installer = Installer::new(cfg, project_dir)?;
+    let packages = if wanted.is_empty() {
+        Vec::new()
+    } else {
+        reinstall_plan(&installer, cfg, before, &wanted, &runtime.python_exe)?
+    };
+    let mut changes = installer.plan_changes(&packages, &runtime.site_packages)?;
+    changes.extend(
+        removals
+            .iter()
+            .map(|(name, version)| PlannedChange::remove(name, version)),
+    );
+    print_planned_changes(&changes, dry_run);
+    if dry_run {
+        return Ok(());
+    }
+
+    if !removals.is_empty() {
+        let mut command = Command::new(&runtime.python_exe);
+        command.arg("-m").arg("pip").arg("uninstall").arg("-y");
+        command.args(removals.iter().map(|(name, _)| name));
+        let status = command.status().context("failed to uninstall packages")?;
+        if !status.success() {
+            bail!("Failed to remove packages: {}", status);
+        }
+    }
+    installer.install_resolved(ctx, packages, &runtime.site_packages, &runtime.python_exe)?;
+    Ok(())
+}
+
+/// Artifacts for the `wanted` distributions: the recorded xe.lock entries
+/// when they have the same version, a fresh resolution of `name==version`
+/// for the rest.
+fn reinstall_plan(
+    installer: &Installer,
+    cfg: &Config,
+    before: &Snapshot,
+    wanted: &BTreeMap<String, (String, String)>,
+    python_exe: &Path,
+) -> Result<Vec<Package>> {
+    let lock = before
+        .lock
+        .as_deref()
+        .and_then(|text| toml::from_str::<LockFile>(text).ok());
+    let mut packages = Vec::new();
+    let mut pending = wanted.clone();
+    if let Some(lock) = lock {
+        let subset = LockFile {
+            packages: lock
+                .packages
+                .iter()
+                .filter(|pkg| {
+                    wanted
+                        .get(&normalize_dep_name(&pkg.name))
+                        .is_some_and(|(_, version)| *version == pkg.version)
+                })
+                .cloned()
+                .collect(),
+            ..lock.clone()
+        };
+        if !subset.packages.is_empty() {
+            for pkg in &subset.packages {
+                pending.remove(&normalize_dep_name(&pkg.name));
+            }
+            packages.extend(installer.locked_plan(&subset, python_exe)?);
+        }
+    }
+    if !pending.is_empty() {
+        let reqs = pending
+            .values()
+            .map(|(name, version)| format!("{name}=={version}"))
+            .collect::<Vec<_>>();
+        let graph = installer
+            .resolve(cfg, &reqs, python_exe)
+            .context("failed to resolve the versions to restore")?;
+        packages.extend(graph.packages.into_iter().filter(|pkg| {
+            pending
+                .get(&normalize_dep_name(&pkg.name))
+                .is_some_and(|(_, version)| *version == pkg.version)
+        }));
+    }
+    Ok(packages)
+}
@@ -0,0 +1,117 @@
+//! Once-a-day check for a newer xe release. The lookup runs on a
+//! background thread and only refreshes the cache; the hint printed after a
+//! command comes from the previous lookup, so no command waits on it.
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use xe_core::config::GLOBAL_CONFIG;
+use xe_core::net::{http_client, http_get, http_send};
+use xe_core::paths::xe_home;
+use xe_core::python::compare_version;
+use xe_core::ui::info;
+
+const RELEASES_URL: &str = "https://api.github.com/repos/aaravmaloo/xe/releases/latest";
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UpdateCache {
+    /// Unix seconds of the last lookup, successful or not.
+    checked_at: u64,
+    #[serde(default)]
+    latest: String,
+}
+
+fn cache_path() -> PathBuf {
+    xe_home().join("update-check.json")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Off with `update_check: false` in the global config or
+/// `XE_NO_UPDATE_CHECK`, and whenever stdout is not a terminal so scripts
+/// and CI logs never see the hint.
+fn enabled() -> bool {
+    let configured = GLOBAL_CONFIG
+        .get()
+        .and_then(|cfg| cfg.update_check)
+        .unwrap_or(true);
+    configured
+        && env::var_os("XE_NO_UPDATE_CHECK").is_none_or(|v| v.is_empty())
+        && io::stdout().is_terminal()
+}
+
+fn read_cache() -> UpdateCache {
+    fs::read(cache_path())
+        .ok()
+        .and_then(|raw| serde_json::from_slice(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn write_cache(cache: &UpdateCache) {
+    if let Ok(encoded) = serde_json::to_vec(cache) {
+        let _ = fs::create_dir_all(xe_home());
+        let _ = fs::write(cache_path(), encoded);
+    }
+}
+
+/// Starts a lookup when the cached one is older than a day. The attempt is
+/// recorded first, so an unreachable server is not retried on every run.
+pub(crate) fn spawn() {
+    if !enabled() {
+        return;
+    }
+    let mut cache = read_cache();
+    if now().saturating_sub(cache.checked_at) < CHECK_INTERVAL.as_secs() {
+        return;
+    }
+    cache.checked_at = now();
+    write_cache(&cache);
+    thread::spawn(move || {
+        if let Some(latest) = fetch_latest() {
+            cache.latest = latest;
+            write_cache(&cache);
+        }
+    });
+}
+
+fn fetch_latest() -> Option<String> {
+    #[derive(Deserialize)]
+    struct Release {
+        tag_name: String,
+    }
+    let client = http_client(Duration::from_secs(5)).ok()?;
+    let resp = http_send(
+        http_get(&client, RELEASES_URL)
+            .header("User-Agent", concat!("xe/", env!("CARGO_PKG_VERSION"))),
+    )
+        .ok()?;
+    if !resp.status().is_success() {
+        return None;
+    }
+    let release = resp.json::<Release>().ok()?;
+    Some(release.tag_name.trim_start_matches('v').to_string())
+}
+
+/// One-line hint when the last lookup found a newer release.
+pub(crate) fn print_hint() {
+    if !enabled() {
+        return;
+    }
+    let latest = read_cache().latest;
+    let current = env!("CARGO_PKG_VERSION");
+    if !latest.is_empty() && compare_version(current, &latest).is_lt() {
+        info(&format!(
+            "xe {latest} is available (you have {current}); run `xe self update`"
+        ));
+    }
+}
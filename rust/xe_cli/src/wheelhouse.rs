@@ -0,0 +1,272 @@
+//! `xe export --format wheelhouse` and its `xe import` counterpart. A
+//! wheelhouse is one archive holding every file of `xe.lock`, for all the
+//! platforms the lock covers, next to the lock and `xe.toml` themselves, so a
+//! machine without index access can load the files into its cache and sync.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use xe_core::archive::extract_zip;
+use xe_core::config::{load_or_create_project, save_project, XE_TOML};
+use xe_core::install::{Installer, LockedFile};
+use xe_core::lock::{load_lock, lock_fingerprint, save_lock, XE_LOCK};
+use xe_core::process::register_cleanup_path;
+use xe_core::profile::AppContext;
+use xe_core::runtime::ensure_runtime_for_project;
+use xe_core::ui::{format_bytes, info, success};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+const MANIFEST: &str = "manifest.json";
+const FORMAT: &str = "xe-wheelhouse";
+const VERSION: u32 = 1;
+const FILES_DIR: &str = "files";
+
+/// `manifest.json`: what the archive holds and the digest of every file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    version: u32,
+    python: String,
+    files: Vec<LockedFile>,
+}
+
+/// Whether `path` names an archive `xe import` treats as a wheelhouse.
+pub fn is_wheelhouse(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_lowercase();
+    name.ends_with(".zip") || is_tarball(&name)
+}
+
+fn is_tarball(name: &str) -> bool {
+    name.ends_with(".tar") || name.ends_with(".tgz") || name.contains(".tar.")
+}
+
+pub fn cmd_export_wheelhouse(output: &Path) -> Result<()> {
+    if !is_wheelhouse(output) {
+        bail!(
+            "unsupported wheelhouse archive {}; use .zip, .tar, .tar.gz or .tar.zst",
+            output.display()
+        );
+    }
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let (cfg, toml_path) = load_or_create_project(&wd)?;
+    let Some(mut lock) = load_lock(&wd)? else {
+        bail!("no {XE_LOCK} in {}; run `xe lock` first", wd.display());
+    };
+    if lock.fingerprint != lock_fingerprint(&cfg) {
+        bail!("{XE_LOCK} is out of date with {XE_TOML}; run `xe lock` first");
+    }
+    info(&format!(
+        "Fetching the files of {} locked package(s)...",
+        lock.packages.len()
+    ));
+    let installer = Installer::new(&cfg, &wd)?;
+    let files = installer.fetch_locked_files(&mut lock)?;
+    let file_count = files.len();
+
+    let staging = staging_dir("wheelhouse-export");
+    let result = (|| -> Result<()> {
+        let files_dir = staging.join(FILES_DIR);
+        fs::create_dir_all(&files_dir)
+            .with_context(|| format!("failed to create {}", files_dir.display()))?;
+        for file in &files {
+            let target = files_dir.join(bundled_name(file)?);
+            fs::copy(&file.blob, &target)
+                .with_context(|| format!("failed to copy {}", file.blob.display()))?;
+        }
+        fs::copy(&toml_path, staging.join(XE_TOML))
+            .with_context(|| format!("failed to copy {}", toml_path.display()))?;
+        save_lock(&staging, &lock)?;
+        let manifest = Manifest {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            python: lock.python.clone(),
+            files,
+        };
+        let encoded = serde_json::to_string_pretty(&manifest)?;
+        fs::write(staging.join(MANIFEST), encoded).context("failed to write manifest")?;
+        write_archive(&staging, &wd.join(output))
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    result?;
+
+    let size = fs::metadata(output).map(|meta| meta.len()).unwrap_or(0);
+    success(&format!(
+        "Exported {} file(s) of {} package(s) to {} ({})",
+        file_count,
+        lock.packages.len(),
+        output.display(),
+        format_bytes(size)
+    ));
+    Ok(())
+}
+
+/// Loads the files of a wheelhouse into the cache and syncs the project from
+/// its lock, without contacting any index.
+pub fn cmd_import_wheelhouse(ctx: &AppContext, archive: &Path) -> Result<()> {
+    let wd = env::current_dir().context("failed to get cwd")?;
+    let staging = staging_dir("wheelhouse-import");
+    let result = import_from(ctx, &wd, archive, &staging);
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn import_from(ctx: &AppContext, wd: &Path, archive: &Path, staging: &Path) -> Result<()> {
+    info(&format!("Unpacking {}...", archive.display()));
+    extract_archive(archive, staging)?;
+    let manifest_path = staging.join(MANIFEST);
+    let manifest: Manifest = serde_json::from_str(
+        &fs::read_to_string(&manifest_path)
+            .with_context(|| format!("{} has no {MANIFEST}", archive.display()))?,
+    )
+    .with_context(|| format!("failed to parse {MANIFEST} of {}", archive.display()))?;
+    if manifest.format != FORMAT {
+        bail!("{} is not an xe wheelhouse", archive.display());
+    }
+    if manifest.version > VERSION {
+        bail!(
+            "{} is wheelhouse version {}; this xe reads up to {}",
+            archive.display(),
+            manifest.version,
+            VERSION
+        );
+    }
+    let lock =
+        load_lock(staging)?.ok_or_else(|| anyhow!("{} has no {XE_LOCK}", archive.display()))?;
+
+    let toml_path = wd.join(XE_TOML);
+    if !toml_path.exists() {
+        fs::copy(staging.join(XE_TOML), &toml_path)
+            .with_context(|| format!("failed to write {}", toml_path.display()))?;
+        info(&format!("Created {XE_TOML} from the wheelhouse"));
+    }
+    let (mut cfg, toml_path) = load_or_create_project(wd)?;
+    let installer = Installer::new(&cfg, wd)?;
+    for file in &manifest.files {
+        let path = staging.join(FILES_DIR).join(bundled_name(file)?);
+        installer
+            .import_blob(&path, &file.sha256)
+            .with_context(|| format!("failed to load {} into the cache", file.filename))?;
+    }
+    info(&format!(
+        "Loaded {} file(s) into the cache",
+        manifest.files.len()
+    ));
+
+    let runtime = ensure_runtime_for_project(ctx, wd, &mut cfg)?;
+    if runtime.config_changed {
+        save_project(&toml_path, &cfg)?;
+    }
+    if lock.fingerprint != lock_fingerprint(&cfg) {
+        bail!(
+            "{} does not match the {XE_TOML} the wheelhouse was exported from",
+            toml_path.display()
+        );
+    }
+    crate::check_locked_interpreter(&lock, &runtime.selection.python_exe, false)?;
+    save_lock(wd, &lock)?;
+    let installed = installer.install_locked(
+        ctx,
+        &lock,
+        &runtime.selection.site_packages,
+        &runtime.selection.python_exe,
+    )?;
+    success(&format!(
+        "Project synced from {} ({} package(s))",
+        archive.display(),
+        installed.len()
+    ));
+    Ok(())
+}
+
+/// The manifest names files itself, so each name must stay one plain path
+/// component inside the archive.
+fn bundled_name(file: &LockedFile) -> Result<&str> {
+    let name = file.filename.as_str();
+    if name.is_empty() || Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name) {
+        bail!("invalid file name in wheelhouse: {name:?}");
+    }
+    Ok(name)
+}
+
+/// A fresh temp directory for `kind` of work, removed if xe is interrupted.
+pub(crate) fn staging_dir(kind: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("xe-{kind}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    register_cleanup_path(&dir);
+    dir
+}
+
+/// Zips with the zip crate; every other archive goes through the system
+/// `tar`, which picks the compression from the extension.
+pub(crate) fn write_archive(source: &Path, target: &Path) -> Result<()> {
+    let name = target.to_string_lossy().to_lowercase();
+    if !name.ends_with(".zip") {
+        let status = Command::new("tar")
+            .arg("-caf")
+            .arg(target)
+            .arg("-C")
+            .arg(source)
+            .arg(".")
+            .status()
+            .context("failed to run tar")?;
+        if !status.success() {
+            bail!("failed to write {}: tar {}", target.display(), status);
+        }
+        return Ok(());
+    }
+    let file =
+        File::create(target).with_context(|| format!("failed to create {}", target.display()))?;
+    let mut writer = ZipWriter::new(file);
+    // Wheels are zips already; deflating them again only costs time.
+    let options = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(true);
+    for entry in walkdir::WalkDir::new(source).min_depth(1) {
+        let entry = entry?;
+        let rel = entry
+            .path()
+            .strip_prefix(source)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if entry.file_type().is_dir() {
+            writer
+                .add_directory(format!("{rel}/"), options)
+                .with_context(|| format!("failed to add dir {rel}"))?;
+            continue;
+        }
+        writer
+            .start_file(rel.clone(), options)
+            .with_context(|| format!("failed to add file {rel}"))?;
+        let mut input = File::open(entry.path())
+            .with_context(|| format!("failed to open {}", entry.path().display()))?;
+        io::copy(&mut input, &mut writer).with_context(|| format!("failed to write {rel}"))?;
+    }
+    writer
+        .finish()
+        .with_context(|| format!("failed to finish {}", target.display()))?;
+    Ok(())
+}
+
+pub(crate) fn extract_archive(archive: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target).with_context(|| format!("failed to create {}", target.display()))?;
+    if archive.to_string_lossy().to_lowercase().ends_with(".zip") {
+        extract_zip(archive, target)?;
+        return Ok(());
+    }
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(archive)
+        .arg("-C")
+        .arg(target)
+        .status()
+        .context("failed to run tar")?;
+    if !status.success() {
+        bail!("failed to extract {}: tar {}", archive.display(), status);
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+//! Zip extraction shared by wheel installs, runtime downloads and snapshot
+//! restore. Keeps the executable bit and recreates symlinks, and treats the
+//! archive as hostile: names, links and sizes are all checked against the
+//! extraction root.
+
+use crate::error::{bail, ErrorKind, Result, ResultExt};
+use crate::paths::create_dir_all;
+use crate::paths::create_file;
+use crate::paths::remove_file;
+use anyhow::{anyhow, Context};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use zip::read::ZipFile;
+use zip::ZipArchive;
+
+/// Upper bounds for one archive, well above the largest wheels (CUDA
+/// builds) and runtime zips, but below what a zip bomb expands to.
+pub const MAX_ENTRIES: usize = 200_000;
+pub const MAX_EXTRACTED_BYTES: u64 = 16 * 1024 * 1024 * 1024;
+
+/// File type bits of a Unix mode, and the value marking a symlink.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+pub(crate) struct ZipExtractor {
+    root: PathBuf,
+    written: u64,
+    /// Created by `finish` once every regular entry is written, so no entry
+    /// is ever written through a link from the same archive.
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+impl ZipExtractor {
+    /// Fails up front when the archive has more than [`MAX_ENTRIES`].
+    pub(crate) fn new(root: &Path, entries: usize) -> Result<Self> {
+        if entries > MAX_ENTRIES {
+            bail!(
+                kind = ErrorKind::Integrity,
+                "archive has {} entries (limit {})",
+                entries,
+                MAX_ENTRIES
+            );
+        }
+        Ok(Self {
+            root: root.to_path_buf(),
+            written: 0,
+            links: Vec::new(),
+        })
+    }
+
+    /// Writes `entry` below the root.
+    pub(crate) fn extract(&mut self, entry: &mut ZipFile<'_>) -> Result<()> {
+        let relative = safe_relative(entry.name())
+            .ok_or_else(|| anyhow!("unsafe archive entry path: {}", entry.name()))
+            .kind(ErrorKind::Integrity)?;
+        if relative.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let relative = relative.as_path();
+        let out_path = self.root.join(relative);
+        if entry.name().ends_with('/') {
+            create_dir_all(&out_path)
+                .with_context(|| format!("failed to create {}", out_path.display()))?;
+            return Ok(());
+        }
+        if let Some(parent) = out_path.parent() {
+            create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        // A link left by an earlier install would otherwise be written through.
+        if fs::symlink_metadata(&out_path).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            remove_file(&out_path)
+                .with_context(|| format!("failed to replace {}", out_path.display()))?;
+        }
+
+        let mode = entry.unix_mode().unwrap_or(0);
+        if mode & S_IFMT == S_IFLNK {
+            let mut target = String::new();
+            entry
+                .by_ref()
+                .take(4096)
+                .read_to_string(&mut target)
+                .with_context(|| format!("failed to read link {}", entry.name()))?;
+            let target = PathBuf::from(target);
+            if escapes_root(relative, &target) {
+                bail!(
+                    kind = ErrorKind::Integrity,
+                    "unsafe symlink in archive: {} -> {}",
+                    entry.name(),
+                    target.display()
+                );
+            }
+            self.links.push((out_path, target));
+            return Ok(());
+        }
+
+        let mut out = create_file(&out_path)
+            .with_context(|| format!("failed to create {}", out_path.display()))?;
+        // The size in the header is the archive's claim; count real bytes.
+        let budget = MAX_EXTRACTED_BYTES - self.written;
+        let copied = io::copy(&mut entry.by_ref().take(budget + 1), &mut out)
+            .with_context(|| format!("failed to write {}", out_path.display()))?;
+        if copied > budget {
+            bail!(
+                kind = ErrorKind::Integrity,
+                "archive expands past the {} limit",
+                crate::ui::format_bytes(MAX_EXTRACTED_BYTES)
+            );
+        }
+        self.written += copied;
+        if mode & 0o111 != 0 {
+            set_executable(&out_path)
+                .with_context(|| format!("failed to set permissions on {}", out_path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Creates the collected symlinks and checks where they really point:
+    /// lexically safe targets can still escape through another link
+    /// (`a -> .` then `a/b -> ../x`).
+    pub(crate) fn finish(self) -> Result<()> {
+        if self.links.is_empty() {
+            return Ok(());
+        }
+        let root = fs::canonicalize(&self.root)
+            .with_context(|| format!("failed to resolve {}", self.root.display()))?;
+        for (link, target) in &self.links {
+            create_link(link, target)
+                .with_context(|| format!("failed to create link {}", link.display()))?;
+        }
+        for (link, target) in &self.links {
+            let inside = fs::canonicalize(link).is_ok_and(|resolved| resolved.starts_with(&root));
+            if !inside {
+                for (link, _) in &self.links {
+                    let _ = remove_file(link);
+                }
+                bail!(
+                    kind = ErrorKind::Integrity,
+                    "unsafe or dangling symlink in archive: {} -> {}",
+                    link.display(),
+                    target.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Normalizes a raw entry name, or `None` for absolute, drive and UNC
+/// paths and for `..` that climbs out of the root. Both separators count,
+/// whatever the OS.
+pub fn safe_relative(name: &str) -> Option<PathBuf> {
+    let bytes = name.as_bytes();
+    let drive = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    if name.starts_with(['/', '\\']) || drive || name.contains('\0') {
+        return None;
+    }
+    let mut relative = PathBuf::new();
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if !relative.pop() {
+                    return None;
+                }
+            }
+            part if part.contains(':') => return None,
+            part => relative.push(part),
+        }
+    }
+    Some(relative)
+}
+
+/// Whether `target`, read relative to the directory of `relative`, climbs
+/// above the root or is absolute.
+fn escapes_root(relative: &Path, target: &Path) -> bool {
+    let mut depth = relative
+        .parent()
+        .map(|parent| parent.components().count())
+        .unwrap_or(0);
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+    false
+}
+
+/// Extracts all of `zip_path` into `target_dir` with the checks above.
+pub fn extract_zip(zip_path: &Path, target_dir: &Path) -> Result<()> {
+    let file =
+        File::open(zip_path).with_context(|| format!("failed to open {}", zip_path.display()))?;
+    let mut archive =
+        ZipArchive::new(file).with_context(|| format!("failed to parse {}", zip_path.display()))?;
+    let mut extractor = ZipExtractor::new(target_dir, archive.len())?;
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .with_context(|| format!("failed to read entry {}", index))?;
+        extractor.extract(&mut entry)?;
+    }
+    extractor.finish()
+}
+
+/// Adds execute wherever read is allowed, like pip, so the umask the file
+/// was created with still applies.
+#[cfg(unix)]
+fn set_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut perms = fs::metadata(path)?.permissions();
+    let mode = perms.mode();
+    perms.set_mode(mode | ((mode & 0o444) >> 2));
+    fs::set_permissions(path, perms)
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_link(link: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Symlinks need a privilege on Windows, so the target is copied instead.
+#[cfg(not(unix))]
+fn create_link(link: &Path, target: &Path) -> io::Result<()> {
+    let source = link.parent().unwrap_or(Path::new("")).join(target);
+    if !source.is_dir() {
+        return fs::copy(&source, link).map(|_| ());
+    }
+    for entry in walkdir::WalkDir::new(&source) {
+        let entry = entry.map_err(io::Error::other)?;
+        let relative = entry.path().strip_prefix(&source).unwrap_or(entry.path());
+        let dest = link.join(relative);
+        if entry.file_type().is_dir() {
+            create_dir_all(&dest)?;
+        } else {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,473 @@
+//! Content-addressed store of downloaded and built artifacts.
+
+use crate::error::{bail, ErrorKind, Result, ResultExt};
+use crate::install::file_url_to_path;
+use crate::install::find_wheel_in;
+use crate::install::SDIST_BUILD_OPTIONS;
+use crate::net::apply_network_env;
+use crate::net::http_client;
+use crate::net::http_get;
+use crate::net::send_with_retry;
+use crate::paths::create_file;
+use crate::paths::remove_dir_all;
+use crate::paths::remove_file;
+use crate::paths::rename;
+use crate::paths::tempfile_path;
+use crate::paths::tempfile_path_in;
+use crate::process::unregister_cleanup_path;
+use crate::report::progress_event;
+use crate::ui::info;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha1::Digest as Sha1Digest;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+pub struct Cas {
+    pub(crate) root: PathBuf,
+}
+
+impl Cas {
+    pub fn new(root: &Path) -> Result<Self> {
+        let cas = Self {
+            root: root.to_path_buf(),
+        };
+        fs::create_dir_all(cas.blob_dir()).with_context(|| "failed to create CAS blob dir")?;
+        fs::create_dir_all(cas.solution_dir())
+            .with_context(|| "failed to create CAS solution dir")?;
+        Ok(cas)
+    }
+
+    pub fn store_blob_from_url(&self, url: &str, expected_sha256: &str) -> Result<PathBuf> {
+        self.store_blob_until(url, expected_sha256, &AtomicBool::new(false))
+    }
+
+    /// Like [`Cas::store_blob_from_url`], but gives up as soon as `cancel`
+    /// is set, between chunks of the download, leaving nothing behind.
+    pub(crate) fn store_blob_until(
+        &self,
+        url: &str,
+        expected_sha256: &str,
+        cancel: &AtomicBool,
+    ) -> Result<PathBuf> {
+        if !expected_sha256.trim().is_empty() {
+            let target = self.blob_path(expected_sha256);
+            if target.exists() {
+                return Ok(target);
+            }
+        }
+
+        // Local wheels (find-links directories) are copied in like downloads.
+        let (mut resp, total_bytes): (Box<dyn Read>, Option<u64>) =
+            if let Some(local) = file_url_to_path(url) {
+                let file = File::open(&local)
+                    .with_context(|| format!("failed to open {}", local.display()))?;
+                let size = file.metadata().ok().map(|meta| meta.len());
+                (Box::new(file), size)
+            } else {
+                let client = http_client(Duration::from_secs(120))?;
+                let resp = send_with_retry(http_get(&client, url), url)
+                    .with_context(|| format!("failed to download {}", url))
+                    .kind(ErrorKind::Network)?;
+                if !resp.status().is_success() {
+                    bail!(
+                        kind = ErrorKind::Network,
+                        "download failed: {}",
+                        resp.status()
+                    );
+                }
+                let size = resp.content_length();
+                (Box::new(resp), size)
+            };
+        let file_name = url
+            .split(['#', '?'])
+            .next()
+            .unwrap_or_default()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let mut done = 0u64;
+        let mut last_event: Option<Instant> = None;
+
+        fs::create_dir_all(&self.root)
+            .with_context(|| format!("failed to create {}", self.root.display()))?;
+        let tmp_path = tempfile_path_in(&self.root, "xe-download", "tmp");
+        let mut tmp_file = create_file(&tmp_path)
+            .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        let mut hasher = Sha256::new();
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            if cancel.load(AtomicOrdering::Relaxed) {
+                let _ = remove_file(&tmp_path);
+                bail!("download of {file_name} cancelled");
+            }
+            let read = resp
+                .read(&mut buffer)
+                .context("failed while downloading blob")?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            tmp_file
+                .write_all(&buffer[..read])
+                .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+            done += read as u64;
+            if last_event.is_none_or(|at| at.elapsed() >= Duration::from_millis(100)) {
+                last_event = Some(Instant::now());
+                progress_event(
+                    "download.progress",
+                    json!({"file": file_name, "bytes": done, "total_bytes": total_bytes}),
+                );
+            }
+        }
+        tmp_file.flush().ok();
+        let actual = hex::encode(hasher.finalize());
+        progress_event(
+            "download.finished",
+            json!({"file": file_name, "bytes": done, "sha256": actual}),
+        );
+
+        if !expected_sha256.trim().is_empty() && !expected_sha256.eq_ignore_ascii_case(&actual) {
+            let _ = remove_file(&tmp_path);
+            bail!(
+                kind = ErrorKind::Integrity,
+                "checksum mismatch: expected={} actual={}",
+                expected_sha256,
+                actual
+            );
+        }
+
+        let target = self.blob_path(&actual);
+        if target.exists() {
+            let _ = remove_file(&tmp_path);
+            return Ok(target);
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        match rename(&tmp_path, &target) {
+            Ok(_) => {}
+            Err(_) => {
+                fs::copy(&tmp_path, &target)
+                    .with_context(|| format!("failed to store blob at {}", target.display()))?;
+                let _ = remove_file(&tmp_path);
+            }
+        }
+        Ok(target)
+    }
+
+    pub(crate) fn save_solution<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let path = self.solution_dir().join(format!("{key}.json"));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+        serde_json::to_writer(file, value)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    pub(crate) fn load_solution<T: for<'de> Deserialize<'de>>(
+        &self,
+        key: &str,
+    ) -> Result<Option<T>> {
+        let path = self.solution_dir().join(format!("{key}.json"));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file =
+            File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+        let value = serde_json::from_reader(file)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(Some(value))
+    }
+
+    /// Drops every cached resolver solution; returns how many there were.
+    pub fn clear_solutions(&self) -> Result<usize> {
+        let dir = self.solution_dir();
+        let mut removed = 0usize;
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("failed to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                remove_file(&path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Returns a wheel built from the sdist blob, building it on first use.
+    /// Entries are keyed by source hash, interpreter tag and build options,
+    /// so a cache directory shared between machines never hands out a wheel
+    /// compiled for a different interpreter.
+    pub(crate) fn built_wheel(
+        &self,
+        sdist_blob: &Path,
+        source_url: &str,
+        interpreter_tag: &str,
+        python_exe: &Path,
+    ) -> Result<PathBuf> {
+        let source_sha = sdist_blob
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let mut hasher = Sha256::new();
+        hasher.update(source_sha.as_bytes());
+        hasher.update(b"|");
+        hasher.update(interpreter_tag.as_bytes());
+        for option in SDIST_BUILD_OPTIONS {
+            hasher.update(b"|");
+            hasher.update(option.as_bytes());
+        }
+        let key = hex::encode(hasher.finalize());
+        let entry_dir = self.built_dir().join(&key[..2]).join(&key);
+        if let Some(wheel) = find_wheel_in(&entry_dir)? {
+            return Ok(wheel);
+        }
+
+        let file_name = source_url
+            .split(['#', '?'])
+            .next()
+            .unwrap_or_default()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        info(&format!("Building wheel from {}...", file_name));
+        let work_dir = tempfile_path("xe-build", "dir");
+        let source = work_dir.join(file_name);
+        fs::create_dir_all(&work_dir)
+            .with_context(|| format!("failed to create {}", work_dir.display()))?;
+        fs::copy(sdist_blob, &source)
+            .with_context(|| format!("failed to stage {}", source.display()))?;
+        let built = pip_wheel(python_exe, source.as_os_str(), &work_dir, file_name);
+        let target = built.and_then(|built| store_built(&built, &entry_dir));
+        let _ = remove_dir_all(&work_dir);
+        target
+    }
+
+    /// Returns a wheel built from a VCS checkout or local directory. A
+    /// checkout pinned to a full commit hash is cached like an sdist build;
+    /// anything else is rebuilt every time, into a directory removed when xe
+    /// exits.
+    pub(crate) fn built_source_wheel(
+        &self,
+        source_url: &str,
+        interpreter_tag: &str,
+        python_exe: &Path,
+    ) -> Result<PathBuf> {
+        let local = file_url_to_path(source_url);
+        let pinned = local.is_none() && is_pinned_vcs_url(source_url);
+        let entry_dir = if pinned {
+            let mut hasher = Sha256::new();
+            hasher.update(source_url.as_bytes());
+            hasher.update(b"|");
+            hasher.update(interpreter_tag.as_bytes());
+            for option in SDIST_BUILD_OPTIONS {
+                hasher.update(b"|");
+                hasher.update(option.as_bytes());
+            }
+            let key = hex::encode(hasher.finalize());
+            let entry_dir = self.built_dir().join(&key[..2]).join(&key);
+            if let Some(wheel) = find_wheel_in(&entry_dir)? {
+                return Ok(wheel);
+            }
+            entry_dir
+        } else {
+            tempfile_path("xe-built", "dir")
+        };
+
+        info(&format!("Building wheel from {}...", source_url));
+        let work_dir = tempfile_path("xe-build", "dir");
+        fs::create_dir_all(&work_dir)
+            .with_context(|| format!("failed to create {}", work_dir.display()))?;
+        let target = match &local {
+            Some(path) => path.as_os_str().to_os_string(),
+            None => source_url.into(),
+        };
+        let built = pip_wheel(python_exe, &target, &work_dir, source_url);
+        let stored = built.and_then(|built| store_built(&built, &entry_dir));
+        let _ = remove_dir_all(&work_dir);
+        stored
+    }
+
+    /// Stores `data` under its SHA-256 unless it is already present, and
+    /// returns the digest and whether the chunk was new.
+    pub fn store_chunk(&self, data: &[u8]) -> Result<(String, bool)> {
+        let sha = hex::encode(Sha256::digest(data));
+        let path = self.chunk_path(&sha);
+        if path.exists() {
+            return Ok((sha, false));
+        }
+        let parent = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+        let tmp_path = tempfile_path_in(parent, "xe-chunk", "tmp");
+        fs::write(&tmp_path, data)
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        // Another writer may have stored the same chunk meanwhile.
+        if rename(&tmp_path, &path).is_err() && !path.exists() {
+            let _ = remove_file(&tmp_path);
+            bail!("failed to store chunk at {}", path.display());
+        }
+        unregister_cleanup_path(&tmp_path);
+        Ok((sha, true))
+    }
+
+    /// Reads a chunk back, failing if it no longer matches its digest.
+    pub fn read_chunk(&self, sha: &str) -> Result<Vec<u8>> {
+        let path = self.chunk_path(sha);
+        let data = fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        if hex::encode(Sha256::digest(&data)) != sha {
+            bail!(
+                kind = ErrorKind::Integrity,
+                "chunk {} is corrupt",
+                path.display()
+            );
+        }
+        Ok(data)
+    }
+
+    /// Locks the chunk store until the returned file is dropped: shared
+    /// while a snapshot stores chunks and writes the manifest naming them,
+    /// exclusive while [`Cas::gc_chunks`] decides what is unreferenced.
+    pub fn lock_chunks(&self, exclusive: bool) -> Result<File> {
+        let path = self.root.join("cas").join("chunks.lock");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        if exclusive {
+            file.lock()
+        } else {
+            file.lock_shared()
+        }
+        .with_context(|| format!("failed to lock {}", path.display()))?;
+        Ok(file)
+    }
+
+    /// Deletes every chunk not in `keep`; returns how many went and their
+    /// total size. Only files named by a digest count as chunks, so a chunk
+    /// still being written is left alone. Callers hold the exclusive
+    /// [`Cas::lock_chunks`].
+    pub fn gc_chunks(&self, keep: &HashSet<String>) -> Result<(usize, u64)> {
+        let mut removed = 0usize;
+        let mut freed = 0u64;
+        for entry in WalkDir::new(self.chunk_dir())
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+        {
+            let name = entry.file_name().to_string_lossy();
+            let is_chunk = name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit());
+            if !is_chunk || keep.contains(name.as_ref()) {
+                continue;
+            }
+            let size = entry.metadata().map(|meta| meta.len()).unwrap_or(0);
+            remove_file(entry.path())
+                .with_context(|| format!("failed to remove {}", entry.path().display()))?;
+            removed += 1;
+            freed += size;
+        }
+        Ok((removed, freed))
+    }
+
+    pub fn chunk_path(&self, sha: &str) -> PathBuf {
+        let prefix = if sha.len() >= 2 { &sha[..2] } else { "00" };
+        self.chunk_dir().join(prefix).join(sha)
+    }
+
+    pub(crate) fn chunk_dir(&self) -> PathBuf {
+        self.root.join("cas").join("chunks")
+    }
+
+    pub(crate) fn blob_dir(&self) -> PathBuf {
+        self.root.join("cas").join("blobs")
+    }
+
+    pub(crate) fn built_dir(&self) -> PathBuf {
+        self.root.join("cas").join("built")
+    }
+
+    pub(crate) fn solution_dir(&self) -> PathBuf {
+        self.root.join("cas").join("solutions")
+    }
+
+    pub fn blob_path(&self, sha: &str) -> PathBuf {
+        let prefix = if sha.len() >= 2 { &sha[..2] } else { "00" };
+        self.blob_dir().join(prefix).join(format!("{sha}.whl"))
+    }
+}
+
+/// Runs `pip wheel` on `target` into `work_dir/dist` and returns the wheel.
+fn pip_wheel(python_exe: &Path, target: &OsStr, work_dir: &Path, label: &str) -> Result<PathBuf> {
+    let out_dir = work_dir.join("dist");
+    fs::create_dir_all(&out_dir)
+        .with_context(|| format!("failed to create {}", out_dir.display()))?;
+    let mut command = Command::new(python_exe);
+    apply_network_env(&mut command);
+    let output = command
+        .args(["-m", "pip", "wheel"])
+        .args(SDIST_BUILD_OPTIONS)
+        .arg("--wheel-dir")
+        .arg(&out_dir)
+        .arg(target)
+        .output()
+        .context("failed to run pip wheel")?;
+    if !output.status.success() {
+        bail!(
+            "failed to build {}: {}\n{}{}",
+            label,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let Some(wheel) = find_wheel_in(&out_dir)? else {
+        bail!("building {} produced no wheel", label);
+    };
+    Ok(wheel)
+}
+
+/// Moves a freshly built wheel into `entry_dir`.
+fn store_built(built: &Path, entry_dir: &Path) -> Result<PathBuf> {
+    let built_name = built
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    fs::create_dir_all(entry_dir)
+        .with_context(|| format!("failed to create {}", entry_dir.display()))?;
+    let target = entry_dir.join(built_name);
+    if rename(built, &target).is_err() {
+        fs::copy(built, &target)
+            .with_context(|| format!("failed to write {}", target.display()))?;
+    }
+    Ok(target)
+}
+
+/// `vcs+url@<40 hex digits>`, optionally followed by `#subdirectory=...`.
+fn is_pinned_vcs_url(url: &str) -> bool {
+    let url = url.split('#').next().unwrap_or_default();
+    url.rsplit_once('@')
+        .is_some_and(|(_, rev)| rev.len() == 40 && rev.bytes().all(|b| b.is_ascii_hexdigit()))
+}
@@ -0,0 +1,1504 @@
+//! `xe.toml` and the global `config.yaml`.
+
+use crate::error::{bail, ErrorKind, Result, ResultExt};
+use crate::install::file_url_to_path;
+use crate::install::path_to_file_url;
+use crate::net::strip_credentials;
+use crate::paths::rename;
+use crate::paths::xe_cache_dir;
+use crate::ui::warning;
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fs::{self};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+
+pub const XE_TOML: &str = "xe.toml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub project: ProjectConfig,
+    #[serde(default)]
+    pub python: PythonConfig,
+    #[serde(default)]
+    pub deps: HashMap<String, DepSpec>,
+    /// Named package indexes, referenced by `[deps.<name>] index = "..."`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) indexes: HashMap<String, String>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub venv: VenvConfig,
+    #[serde(default)]
+    pub settings: SettingsConfig,
+    /// Editable dependencies: normalized name to a path relative to the
+    /// project (or absolute when it lives elsewhere).
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub editable: HashMap<String, String>,
+    /// Wheels installed from a URL or local file rather than an index.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub direct: HashMap<String, DirectReference>,
+    /// Optional dependency groups of this project: extra name to package
+    /// versions, installed by `xe sync --extra <name>`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extras: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "ResolutionConfig::is_empty")]
+    pub resolution: ResolutionConfig,
+    #[serde(default, skip_serializing_if = "NetworkConfig::is_empty")]
+    pub(crate) network: NetworkConfig,
+    /// Present on a workspace root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<WorkspaceConfig>,
+    #[serde(default, skip_serializing_if = "HooksConfig::is_empty")]
+    pub hooks: HooksConfig,
+    #[serde(default, skip_serializing_if = "UiConfig::is_empty")]
+    pub ui: UiConfig,
+    #[serde(default, skip_serializing_if = "NotificationsConfig::is_empty")]
+    pub notifications: NotificationsConfig,
+    /// `[profile.<name>]` tables, selected by `xe sync --profile <name>`.
+    #[serde(
+        default,
+        rename = "profile",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+/// `[profile.<name>]`: which `[extras]` groups `xe sync` installs and how.
+/// `default` applies when no profile is named, and under every other
+/// profile too.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProfileConfig {
+    /// `[extras]` groups installed on top of `[deps]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// Groups left out even when the default profile or `--extra` asks for
+    /// them.
+    #[serde(
+        default,
+        alias = "exclude_groups",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub exclude_groups: Vec<String>,
+    /// Compile the environment's packages to bytecode after syncing.
+    #[serde(
+        default,
+        alias = "compile_bytecode",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub compile_bytecode: Option<bool>,
+}
+
+/// `[hooks]`: shell commands run on lifecycle events, each a string or a
+/// list of strings.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct HooksConfig {
+    #[serde(
+        default,
+        deserialize_with = "string_or_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub(crate) pre_sync: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "string_or_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub(crate) post_sync: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "string_or_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub(crate) pre_build: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "string_or_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub(crate) post_add: Vec<String>,
+}
+
+impl HooksConfig {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn commands(&self, event: &str) -> &[String] {
+        match event {
+            "pre-sync" => &self.pre_sync,
+            "post-sync" => &self.post_sync,
+            "pre-build" => &self.pre_build,
+            "post-add" => &self.post_add,
+            _ => &[],
+        }
+    }
+}
+
+pub(crate) fn string_or_list<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(command) => vec![command],
+        OneOrMany::Many(commands) => commands,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct WorkspaceConfig {
+    /// Member project directories, relative to the workspace root.
+    #[serde(default)]
+    pub members: Vec<String>,
+    /// Shared dependency specs members opt into with `{ workspace = true }`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, DepSpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProjectConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// `Name <email>`, `Name` or `<email>`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    /// SPDX license expression.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub license: String,
+    /// Path of the readme, relative to the project.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub readme: String,
+}
+
+impl ProjectConfig {
+    /// The metadata set here as pyproject.toml `[project]` entries, each a
+    /// key and its TOML value in PEP 621 form.
+    pub fn pyproject_entries(&self) -> Vec<(&'static str, String)> {
+        let string = |value: &str| toml::Value::String(value.to_string()).to_string();
+        let mut entries = Vec::new();
+        if !self.description.is_empty() {
+            entries.push(("description", string(&self.description)));
+        }
+        if !self.authors.is_empty() {
+            let authors = self
+                .authors
+                .iter()
+                .map(|author| {
+                    let (name, email) = split_author(author);
+                    match (name.is_empty(), email.is_empty()) {
+                        (false, false) => {
+                            format!("{{ name = {}, email = {} }}", string(name), string(email))
+                        }
+                        (true, false) => format!("{{ email = {} }}", string(email)),
+                        _ => format!("{{ name = {} }}", string(name)),
+                    }
+                })
+                .collect::<Vec<_>>();
+            entries.push(("authors", format!("[{}]", authors.join(", "))));
+        }
+        if !self.license.is_empty() {
+            entries.push(("license", string(&self.license)));
+        }
+        if !self.readme.is_empty() {
+            entries.push(("readme", string(&self.readme)));
+        }
+        entries
+    }
+}
+
+/// `("Ada Lovelace", "ada@example.com")` out of `Ada Lovelace <ada@example.com>`.
+fn split_author(author: &str) -> (&str, &str) {
+    match author.split_once('<') {
+        Some((name, email)) => (name.trim(), email.trim_end().trim_end_matches('>').trim()),
+        None => (author.trim(), ""),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PythonConfig {
+    #[serde(default = "default_python_version")]
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_mode")]
+    pub mode: String,
+    /// Empty for the default shared cache, which is not written to xe.toml
+    /// so the file stays the same across machines and containers.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub global_dir: String,
+}
+
+impl CacheConfig {
+    /// Root of the blob and solution store: `global_dir` for `global-cas`,
+    /// `<project>/.xe/cache` for `project` so a checkout carries its own
+    /// cache. `XE_CACHE_DIR` overrides `global_dir`.
+    pub fn dir(&self, project_dir: &Path) -> Result<PathBuf> {
+        match self.mode.trim() {
+            "" | "global-cas" => {
+                let from_env = env::var_os("XE_CACHE_DIR").is_some_and(|dir| !dir.is_empty());
+                if from_env || self.global_dir.trim().is_empty() {
+                    Ok(xe_cache_dir())
+                } else {
+                    Ok(PathBuf::from(&self.global_dir))
+                }
+            }
+            "project" => Ok(project_dir.join(".xe").join("cache")),
+            other => bail!(
+                kind = ErrorKind::Config,
+                "unknown cache.mode '{}' (expected 'global-cas' or 'project')",
+                other
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct VenvConfig {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SettingsConfig {
+    #[serde(default)]
+    pub autovenv: bool,
+    /// Packages managed outside xe (system packages, pieces of a monorepo
+    /// installed by other means). xe never installs, upgrades or removes
+    /// them, even when another package depends on them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Backend of `xe format`: `black` (the default) or `ruff`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub formatter: String,
+    /// Name the package to add when `xe run` fails on a missing module.
+    /// Off by default, since the command's stderr then goes through xe.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub run_hints: bool,
+}
+
+impl SettingsConfig {
+    pub fn is_ignored(&self, name: &str) -> bool {
+        let name = normalize_dep_name(name);
+        self.ignore
+            .iter()
+            .any(|ignored| normalize_dep_name(ignored) == name)
+    }
+}
+
+/// `[network]` in `xe.toml` or `network:` in the global config. Unset fields
+/// fall back to the global config, then to the built-in default.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub(crate) struct NetworkConfig {
+    /// Send `~/.netrc` credentials to matching hosts (default on).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) netrc: Option<bool>,
+}
+
+impl NetworkConfig {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `[ui]` in `xe.toml` or `ui:` in the global config; the project wins.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct UiConfig {
+    /// `default`, `high-contrast` or `mono`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub theme: String,
+}
+
+impl UiConfig {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `[notifications]` in `xe.toml` or `notifications:` in the global config;
+/// the project wins.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct NotificationsConfig {
+    /// Webhook that receives a JSON summary after each sync, lock and
+    /// publish.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+}
+
+impl NotificationsConfig {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A `[deps]` entry: either a bare version string or a table with options.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DepSpec {
+    Version(String),
+    Table(DepTable),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepTable {
+    #[serde(
+        default = "default_dep_version",
+        skip_serializing_if = "is_any_version"
+    )]
+    pub version: String,
+    /// Name of the only index this package may be resolved from; `source`
+    /// is accepted as an alias.
+    #[serde(default, alias = "source", skip_serializing_if = "String::is_empty")]
+    pub index: String,
+    /// Build the package from this git repository instead of an index.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub git: String,
+    /// Commit, tag or branch of `git`; the default branch when empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub rev: String,
+    /// Build or install the package from a local directory, wheel or sdist,
+    /// relative to the project.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+    /// Take version and index from the root's `[workspace.dependencies]`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub workspace: bool,
+    /// Version and index filled in from the workspace root at load time.
+    #[serde(skip)]
+    pub(crate) inherited: Option<(String, String)>,
+}
+
+pub(crate) fn default_dep_version() -> String {
+    "*".to_string()
+}
+
+pub(crate) fn is_any_version(version: &String) -> bool {
+    version == "*"
+}
+
+impl DepSpec {
+    pub fn version(&self) -> &str {
+        match self {
+            DepSpec::Version(version) => version,
+            DepSpec::Table(DepTable {
+                inherited: Some((version, _)),
+                ..
+            }) => version,
+            DepSpec::Table(table) => &table.version,
+        }
+    }
+
+    pub fn index(&self) -> &str {
+        match self {
+            DepSpec::Version(_) => "",
+            DepSpec::Table(table) if table.index.is_empty() => table
+                .inherited
+                .as_ref()
+                .map(|(_, index)| index.as_str())
+                .unwrap_or_default(),
+            DepSpec::Table(table) => &table.index,
+        }
+    }
+
+    pub fn inherits_workspace(&self) -> bool {
+        matches!(self, DepSpec::Table(table) if table.workspace)
+    }
+
+    /// The git repository or local path the package comes from, if it does
+    /// not come from an index.
+    pub fn source_location(&self) -> Option<&str> {
+        match self {
+            DepSpec::Table(table) if !table.git.is_empty() => Some(&table.git),
+            DepSpec::Table(table) if !table.path.is_empty() => Some(&table.path),
+            _ => None,
+        }
+    }
+
+    /// `name @ <url>` for git and path dependencies, with `rev` appended to
+    /// the git URL and `path` made absolute.
+    pub(crate) fn source_requirement(&self, name: &str, project_dir: &Path) -> Option<String> {
+        let DepSpec::Table(table) = self else {
+            return None;
+        };
+        if !table.git.is_empty() {
+            let url = if table.git.starts_with("git+") {
+                table.git.clone()
+            } else {
+                format!("git+{}", table.git)
+            };
+            return Some(if table.rev.is_empty() {
+                format!("{name} @ {url}")
+            } else {
+                format!("{name} @ {url}@{}", table.rev)
+            });
+        }
+        if !table.path.is_empty() {
+            let path = project_dir.join(&table.path);
+            let path = fs::canonicalize(&path).unwrap_or(path);
+            return Some(format!("{name} @ {}", path_to_file_url(&path)));
+        }
+        None
+    }
+
+    /// Rejects tables that name more than one place to get the package from.
+    fn check(&self, name: &str) -> Result<()> {
+        let DepSpec::Table(table) = self else {
+            return Ok(());
+        };
+        let sources = [&table.index, &table.git, &table.path]
+            .iter()
+            .filter(|value| !value.is_empty())
+            .count();
+        if sources > 1 {
+            bail!(
+                kind = ErrorKind::Config,
+                "dependency {} sets more than one of index/source, git and path",
+                name
+            );
+        }
+        if !table.rev.is_empty() && table.git.is_empty() {
+            bail!(
+                kind = ErrorKind::Config,
+                "dependency {} sets rev without git",
+                name
+            );
+        }
+        if table.workspace && sources > 0 {
+            bail!(
+                kind = ErrorKind::Config,
+                "dependency {} inherits from the workspace and cannot set its own source",
+                name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A wheel pinned by location. Exactly one of `url` and `path` is set; `path`
+/// is relative to the project when the wheel lives inside it.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct DirectReference {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) url: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) path: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) sha256: String,
+}
+
+impl DirectReference {
+    /// PEP 508 URL for `name @ <url>`, carrying the recorded hash so the
+    /// artifact is verified and served from the CAS.
+    pub fn requirement_url(&self, project_dir: &Path) -> String {
+        let base = if self.url.is_empty() {
+            path_to_file_url(&project_dir.join(&self.path))
+        } else {
+            self.url.clone()
+        };
+        if self.sha256.is_empty() {
+            base
+        } else {
+            format!("{base}#sha256={}", self.sha256)
+        }
+    }
+}
+
+/// Index and constraint options handed to the resolver, typically captured
+/// from a requirements file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ResolutionConfig {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) index_url: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) extra_index_urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) find_links: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) no_index: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) constraints: Vec<String>,
+}
+
+impl ResolutionConfig {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Folds `other` in; a later `--index-url` replaces an earlier one, the
+    /// list options accumulate.
+    pub fn merge(&mut self, other: ResolutionConfig) {
+        if !other.index_url.is_empty() {
+            self.index_url = other.index_url;
+        }
+        for url in other.extra_index_urls {
+            if !self.extra_index_urls.contains(&url) {
+                self.extra_index_urls.push(url);
+            }
+        }
+        for link in other.find_links {
+            if !self.find_links.contains(&link) {
+                self.find_links.push(link);
+            }
+        }
+        self.no_index |= other.no_index;
+        for constraint in other.constraints {
+            if !self.constraints.contains(&constraint) {
+                self.constraints.push(constraint);
+            }
+        }
+    }
+
+    /// Removes credentials from the index and find-links URLs before they
+    /// are saved, saying whether there were any; requests pick them up
+    /// from netrc instead.
+    pub fn strip_credentials(&mut self) -> bool {
+        let before = self.clone();
+        if !self.index_url.is_empty() {
+            self.index_url = strip_credentials(&self.index_url);
+        }
+        for url in self.extra_index_urls.iter_mut().chain(&mut self.find_links) {
+            *url = strip_credentials(url);
+        }
+        *self != before
+    }
+
+    /// pip options for the index settings. Constraints are passed separately
+    /// through a temporary constraints file.
+    pub(crate) fn pip_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.index_url.is_empty() {
+            args.push("--index-url".to_string());
+            args.push(self.index_url.clone());
+        }
+        for url in &self.extra_index_urls {
+            args.push("--extra-index-url".to_string());
+            args.push(url.clone());
+        }
+        for link in &self.find_links {
+            args.push("--find-links".to_string());
+            args.push(link.clone());
+        }
+        if self.no_index {
+            args.push("--no-index".to_string());
+        }
+        args
+    }
+}
+
+impl Default for PythonConfig {
+    fn default() -> Self {
+        Self {
+            version: default_python_version(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            mode: default_cache_mode(),
+            global_dir: String::new(),
+        }
+    }
+}
+
+impl Config {
+    /// A fresh project config, starting from the global `defaults:`.
+    pub fn new_default(project_dir: &Path) -> Self {
+        let name = project_dir
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("project")
+            .to_string();
+        let mut cfg = Self {
+            project: ProjectConfig {
+                name,
+                ..ProjectConfig::default()
+            },
+            python: PythonConfig::default(),
+            deps: HashMap::new(),
+            indexes: HashMap::new(),
+            cache: CacheConfig::default(),
+            venv: VenvConfig::default(),
+            settings: SettingsConfig::default(),
+            editable: HashMap::new(),
+            direct: HashMap::new(),
+            extras: BTreeMap::new(),
+            resolution: ResolutionConfig::default(),
+            network: NetworkConfig::default(),
+            workspace: None,
+            hooks: HooksConfig::default(),
+            ui: UiConfig::default(),
+            notifications: NotificationsConfig::default(),
+            profiles: BTreeMap::new(),
+        };
+        global_config().defaults.apply(&mut cfg);
+        cfg
+    }
+
+    /// Records a dependency version, keeping any table options already set
+    /// for it.
+    pub fn set_dep(&mut self, name: &str, version: &str) {
+        match self.deps.get_mut(name) {
+            // The workspace root owns inherited versions.
+            Some(DepSpec::Table(table)) if table.workspace => {}
+            // The checkout or directory decides the version.
+            Some(spec) if spec.source_location().is_some() => {}
+            Some(DepSpec::Table(table)) => table.version = version.to_string(),
+            _ => {
+                self.deps
+                    .insert(name.to_string(), DepSpec::Version(version.to_string()));
+            }
+        }
+    }
+
+    /// Depends on the project at `path`, relative to this project.
+    pub fn set_path_dep(&mut self, name: &str, path: &str) {
+        self.deps.insert(
+            name.to_string(),
+            DepSpec::Table(DepTable {
+                version: default_dep_version(),
+                index: String::new(),
+                git: String::new(),
+                rev: String::new(),
+                path: path.to_string(),
+                workspace: false,
+                inherited: None,
+            }),
+        );
+    }
+
+    pub fn set_extra_dep(&mut self, extra: &str, name: &str, version: &str) {
+        self.extras
+            .entry(extra.to_string())
+            .or_default()
+            .insert(name.to_string(), version.to_string());
+    }
+
+    /// The profile `xe sync` runs with: `name` on top of
+    /// `[profile.default]`, or the default profile alone.
+    pub fn sync_profile(&self, name: Option<&str>) -> Result<ProfileConfig> {
+        let default = self.profiles.get("default").cloned().unwrap_or_default();
+        let Some(name) = name else {
+            return Ok(default);
+        };
+        let Some(profile) = self.profiles.get(name) else {
+            let known = self.profiles.keys().cloned().collect::<Vec<_>>();
+            bail!(
+                kind = ErrorKind::Config,
+                "unknown profile '{}' (defined: {})",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            );
+        };
+        let mut merged = profile.clone();
+        for group in default.groups.iter().rev() {
+            if !merged.groups.contains(group) {
+                merged.groups.insert(0, group.clone());
+            }
+        }
+        for group in &default.exclude_groups {
+            if !merged.exclude_groups.contains(group) {
+                merged.exclude_groups.push(group.clone());
+            }
+        }
+        merged.compile_bytecode = profile.compile_bytecode.or(default.compile_bytecode);
+        Ok(merged)
+    }
+
+    /// Requirement lines of one `[extras]` group.
+    pub fn extra_requirements(&self, extra: &str) -> Result<Vec<String>> {
+        let Some(deps) = self.extras.get(extra) else {
+            let known = self.extras.keys().cloned().collect::<Vec<_>>();
+            bail!(
+                kind = ErrorKind::Config,
+                "unknown extra '{}' (defined: {})",
+                extra,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            );
+        };
+        Ok(deps
+            .iter()
+            .map(|(name, version)| {
+                if version.is_empty() || version == "*" {
+                    name.clone()
+                } else {
+                    format!("{name}=={version}")
+                }
+            })
+            .collect())
+    }
+
+    /// Packages pinned to a named index, with that index's URL. Project
+    /// `[indexes]` take precedence over the global `indexes` map.
+    pub(crate) fn index_pins(&self) -> Result<BTreeMap<String, (String, String)>> {
+        let mut pins = BTreeMap::new();
+        for (name, spec) in &self.deps {
+            let index = spec.index();
+            if index.is_empty() {
+                continue;
+            }
+            let url = lookup_index_url(self, index).ok_or_else(|| {
+                anyhow!(
+                    "dependency {} is pinned to unknown index '{}'; define it under [indexes] in xe.toml or `indexes` in config.yaml",
+                    name,
+                    index
+                )
+            })?;
+            pins.insert(normalize_dep_name(name), (index.to_string(), url));
+        }
+        Ok(pins)
+    }
+
+    /// Requirement strings for every declared dependency, with editable
+    /// entries expanded to `-e <absolute path>` and git and path
+    /// dependencies to `name @ <url>`.
+    pub fn requirements(&self, project_dir: &Path) -> Vec<String> {
+        let mut reqs = self
+            .deps
+            .iter()
+            .filter(|(name, _)| {
+                !self.editable.contains_key(*name)
+                    && !self.direct.contains_key(*name)
+                    && !self.settings.is_ignored(name)
+            })
+            .map(|(name, spec)| {
+                if let Some(requirement) = spec.source_requirement(name, project_dir) {
+                    return requirement;
+                }
+                let version = spec.version();
+                if version.is_empty() || version == "*" {
+                    name.clone()
+                } else {
+                    format!("{name}=={version}")
+                }
+            })
+            .collect::<Vec<_>>();
+        for (name, target) in &self.editable {
+            if self.settings.is_ignored(name) {
+                continue;
+            }
+            let path = project_dir.join(target);
+            reqs.push(format!("-e {}", path.display()));
+        }
+        for (name, reference) in &self.direct {
+            if self.settings.is_ignored(name) {
+                continue;
+            }
+            reqs.push(format!(
+                "{name} @ {}",
+                reference.requirement_url(project_dir)
+            ));
+        }
+        reqs
+    }
+
+    pub(crate) fn normalize(&mut self, project_dir: &Path) {
+        if self.project.name.trim().is_empty() {
+            self.project.name = project_dir
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("project")
+                .to_string();
+        }
+        if self.python.version.trim().is_empty() {
+            self.python.version = default_python_version();
+        }
+        if self.cache.mode.trim().is_empty() {
+            self.cache.mode = default_cache_mode();
+        }
+    }
+}
+
+pub fn default_python_version() -> String {
+    "3.12".to_string()
+}
+
+pub(crate) fn default_cache_mode() -> String {
+    "global-cas".to_string()
+}
+
+pub fn load_or_create_project(project_dir: &Path) -> Result<(Config, PathBuf)> {
+    let toml_path = project_dir.join(XE_TOML);
+    if !toml_path.exists() {
+        let cfg = Config::new_default(project_dir);
+        save_project(&toml_path, &cfg)?;
+        return Ok((cfg, toml_path));
+    }
+    let cfg = load_project(&toml_path)?;
+    Ok((cfg, toml_path))
+}
+
+pub fn load_project(path: &Path) -> Result<Config> {
+    let mut cfg = read_project_file(path)?;
+    for (name, spec) in &cfg.deps {
+        spec.check(name)
+            .with_context(|| format!("invalid [deps] in {}", path.display()))?;
+    }
+    if cfg.deps.values().any(DepSpec::inherits_workspace) {
+        let project_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let shared = if cfg.workspace.is_some() {
+            cfg.workspace.clone().unwrap_or_default().dependencies
+        } else {
+            let (_, _, root) = find_workspace_root(project_dir).with_context(|| {
+                format!(
+                    "{} inherits workspace dependencies but is not inside a workspace",
+                    path.display()
+                )
+            })?;
+            root.workspace.unwrap_or_default().dependencies
+        };
+        for (name, spec) in cfg.deps.iter_mut() {
+            let DepSpec::Table(table) = spec else {
+                continue;
+            };
+            if !table.workspace {
+                continue;
+            }
+            let root_spec = shared
+                .iter()
+                .find(|(dep, _)| normalize_dep_name(dep) == normalize_dep_name(name))
+                .map(|(_, spec)| spec)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{}: {} = {{ workspace = true }} but [workspace.dependencies] has no {}",
+                        path.display(),
+                        name,
+                        name
+                    )
+                })?;
+            table.inherited = Some((
+                root_spec.version().to_string(),
+                root_spec.index().to_string(),
+            ));
+        }
+    }
+    Ok(cfg)
+}
+
+/// Nearest directory at or above `start` whose `xe.toml` has a
+/// `[workspace]` table.
+pub fn find_workspace_root(start: &Path) -> Result<(PathBuf, PathBuf, Config)> {
+    for dir in start.ancestors() {
+        let toml_path = dir.join(XE_TOML);
+        if !toml_path.exists() {
+            continue;
+        }
+        let cfg = read_project_file(&toml_path)?;
+        if cfg.workspace.is_some() {
+            let root = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+            return Ok((root, toml_path, cfg));
+        }
+    }
+    bail!("no xe workspace found; run `xe workspace init` at the repository root")
+}
+
+/// Parses an `xe.toml` without resolving workspace inheritance.
+pub(crate) fn read_project_file(path: &Path) -> Result<Config> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut cfg: Config = toml::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))
+        .kind(ErrorKind::Config)?;
+    let project_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    cfg.normalize(project_dir);
+    cfg.deps = canonical_keys(cfg.deps, "deps");
+    cfg.editable = canonical_keys(cfg.editable, "editable");
+    cfg.direct = canonical_keys(cfg.direct, "direct");
+    for (extra, deps) in cfg.extras.iter_mut() {
+        *deps = canonical_keys(std::mem::take(deps), &format!("extras.{extra}"));
+    }
+    if let Some(workspace) = cfg.workspace.as_mut() {
+        workspace.dependencies = canonical_keys(
+            std::mem::take(&mut workspace.dependencies),
+            "workspace.dependencies",
+        );
+    }
+    Ok(cfg)
+}
+
+pub fn save_project(path: &Path, cfg: &Config) -> Result<()> {
+    let mut normalized = cfg.clone();
+    let project_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    normalized.normalize(project_dir);
+    let mut encoded = toml::to_string_pretty(&normalized).context("failed to encode xe.toml")?;
+    if let Ok(existing) = fs::read_to_string(path) {
+        if let Some(edited) = apply_to_document(&existing, &encoded) {
+            encoded = edited;
+        }
+    }
+    // Written beside and renamed over, so an interrupted write never leaves
+    // a truncated xe.toml.
+    let tmp = path.with_extension(format!("toml.tmp-{}", std::process::id()));
+    fs::write(&tmp, encoded).with_context(|| format!("failed to write {}", tmp.display()))?;
+    rename(&tmp, path).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Edits the document in `existing` into `encoded` key by key, so comments,
+/// ordering and formatting survive everywhere a value did not change.
+/// `None` when either side does not parse.
+fn apply_to_document(existing: &str, encoded: &str) -> Option<String> {
+    let mut doc = existing.parse::<DocumentMut>().ok()?;
+    let target = encoded.parse::<DocumentMut>().ok()?;
+    let mut next_position = last_position(doc.as_table()) + 1;
+    merge_table(doc.as_table_mut(), target.as_table(), &mut next_position);
+    Some(doc.to_string())
+}
+
+/// Highest position among `table` and the tables in it; positions order
+/// the table headers in the file.
+fn last_position(table: &Table) -> isize {
+    table
+        .iter()
+        .filter_map(|(_, item)| item.as_table())
+        .map(last_position)
+        .chain(table.position())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Moves the tables in a new `item` after those already in the file.
+fn reposition(item: &mut Item, next: &mut isize) {
+    let Some(table) = item.as_table_mut() else {
+        return;
+    };
+    table.set_position(*next);
+    *next += 1;
+    for (_, item) in table.iter_mut() {
+        reposition(item, next);
+    }
+}
+
+fn merge_table(current: &mut dyn TableLike, target: &dyn TableLike, next: &mut isize) {
+    let stale = current
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !target.contains_key(key))
+        .collect::<Vec<_>>();
+    for key in stale {
+        current.remove(&key);
+    }
+    for (key, item) in target.iter() {
+        match current.get_mut(key) {
+            Some(existing) => merge_item(existing, item, next),
+            None => {
+                let mut item = item.clone();
+                reposition(&mut item, next);
+                current.insert(key, item);
+            }
+        }
+    }
+}
+
+fn merge_item(current: &mut Item, target: &Item, next: &mut isize) {
+    if let (Some(current), Some(target)) = (current.as_table_like_mut(), target.as_table_like()) {
+        merge_table(current, target, next);
+        return;
+    }
+    match (current, target) {
+        (Item::Value(current), Item::Value(target)) => {
+            if !same_value(current, target) {
+                let decor = current.decor().clone();
+                *current = target.clone();
+                *current.decor_mut() = decor;
+            }
+        }
+        (current, target) => {
+            if current.to_string().trim() != target.to_string().trim() {
+                *current = target.clone();
+            }
+        }
+    }
+}
+
+/// Equality of TOML values, ignoring how they are written.
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Float(a), Value::Float(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Datetime(a), Value::Datetime(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same_value(a, b))
+        }
+        (Value::InlineTable(a), Value::InlineTable(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same_value(a, b)))
+        }
+        _ => false,
+    }
+}
+
+/// The PEP 503 form of a project name: lowercase, with each run of `-`,
+/// `_` and `.` turned into one `-`. xe keys, compares and records package
+/// names only in this form.
+pub fn normalize_dep_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.trim().chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !out.ends_with('-') {
+                out.push('-');
+            }
+        } else {
+            out.extend(c.to_lowercase());
+        }
+    }
+    out
+}
+
+static DUPLICATE_WARNINGS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Re-keys package tables by [`normalize_dep_name`], so files written
+/// before names were canonical (`ruamel.yaml` next to `ruamel-yaml`) load as
+/// one entry. The canonical spelling wins over the others.
+fn canonical_keys<M, V>(map: M, table: &str) -> M
+where
+    M: IntoIterator<Item = (String, V)> + FromIterator<(String, V)>,
+{
+    let mut entries = map.into_iter().collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| {
+        (normalize_dep_name(a) != *a, a).cmp(&(normalize_dep_name(b) != *b, b))
+    });
+    let mut kept: HashMap<String, String> = HashMap::new();
+    let mut out = Vec::with_capacity(entries.len());
+    for (key, value) in entries {
+        let name = normalize_dep_name(&key);
+        match kept.get(&name) {
+            Some(first) => {
+                let message = format!(
+                    "[{table}] lists {first} and {key}, which are the same package; keeping {first}"
+                );
+                // A command may read the same xe.toml more than once.
+                if DUPLICATE_WARNINGS
+                    .lock()
+                    .map(|mut warned| warned.insert(message.clone()))
+                    .unwrap_or(true)
+                {
+                    warning(&message);
+                }
+            }
+            None => {
+                kept.insert(name.clone(), key);
+                out.push((name, value));
+            }
+        }
+    }
+    out.into_iter().collect()
+}
+
+pub fn requirement_to_dep_name(requirement: &str) -> Option<String> {
+    let mut name = requirement.trim().to_string();
+    if name.is_empty() {
+        return None;
+    }
+    if let Some(idx) = name.find('[') {
+        name = name[..idx].to_string();
+    }
+    if let Some(idx) = name.find(|c: char| " <>=!~;".contains(c)) {
+        name = name[..idx].to_string();
+    }
+    let name = name.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(normalize_dep_name(name))
+    }
+}
+
+pub const PYPROJECT_TOML: &str = "pyproject.toml";
+
+/// Mirrors `[extras]` into `[project.optional-dependencies]` of the
+/// project's pyproject.toml, versions as lower bounds. Only that table is
+/// rewritten, and groups xe does not manage are kept. Returns `false` when
+/// there is no pyproject.toml with a `[project]` table.
+pub fn export_extras_to_pyproject(project_dir: &Path, cfg: &Config) -> Result<bool> {
+    const HEADER: &str = "[project.optional-dependencies]";
+    let path = project_dir.join(PYPROJECT_TOML);
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(false);
+    };
+    let doc: toml::Table = toml::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))
+        .kind(ErrorKind::Config)?;
+    let Some(project) = doc.get("project").and_then(|p| p.as_table()) else {
+        return Ok(false);
+    };
+    let mut groups = BTreeMap::new();
+    if let Some(existing) = project
+        .get("optional-dependencies")
+        .and_then(|t| t.as_table())
+    {
+        for (extra, reqs) in existing {
+            let reqs = reqs
+                .as_array()
+                .map(|reqs| {
+                    reqs.iter()
+                        .filter_map(|r| r.as_str().map(str::to_string))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            groups.insert(extra.clone(), reqs);
+        }
+    }
+    for (extra, deps) in &cfg.extras {
+        let reqs = deps
+            .iter()
+            .map(|(name, version)| {
+                if version.is_empty() || version == "*" {
+                    name.clone()
+                } else {
+                    format!("{name}>={version}")
+                }
+            })
+            .collect();
+        groups.insert(extra.clone(), reqs);
+    }
+
+    let mut section = format!("{HEADER}\n");
+    for (extra, reqs) in &groups {
+        let bare = extra
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        let key = if bare {
+            extra.clone()
+        } else {
+            toml::Value::String(extra.clone()).to_string()
+        };
+        section.push_str(&format!("{key} = [\n"));
+        for req in reqs {
+            section.push_str(&format!("    {},\n", toml::Value::String(req.clone())));
+        }
+        section.push_str("]\n");
+    }
+
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+    let updated = match lines.iter().position(|line| line.trim() == HEADER) {
+        Some(start) => {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|line| line.trim_start().starts_with('['))
+                .map_or(lines.len(), |offset| start + 1 + offset);
+            let mut tail = lines[end..].concat();
+            if !tail.is_empty() {
+                tail.insert(0, '\n');
+            }
+            format!("{}{section}{tail}", lines[..start].concat())
+        }
+        None if project.contains_key("optional-dependencies") => bail!(
+            kind = ErrorKind::Config,
+            "{} defines project.optional-dependencies inline; move it to a {} table",
+            path.display(),
+            HEADER
+        ),
+        None => {
+            let separator = if text.ends_with('\n') { "\n" } else { "\n\n" };
+            format!("{text}{separator}{section}")
+        }
+    };
+    fs::write(&path, updated).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(true)
+}
+
+/// Adds the `[project]` metadata of xe.toml that `pyproject.toml` lacks, so
+/// builds carry it. Fields pyproject.toml sets or lists in `dynamic` are
+/// left alone. Returns the keys added.
+pub fn export_metadata_to_pyproject(project_dir: &Path, cfg: &Config) -> Result<Vec<&'static str>> {
+    let path = project_dir.join(PYPROJECT_TOML);
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    let doc: toml::Table = toml::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))
+        .kind(ErrorKind::Config)?;
+    let Some(project) = doc.get("project").and_then(|p| p.as_table()) else {
+        return Ok(Vec::new());
+    };
+    let dynamic = project
+        .get("dynamic")
+        .and_then(|d| d.as_array())
+        .map(|d| d.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let missing = cfg
+        .project
+        .pyproject_entries()
+        .into_iter()
+        .filter(|(key, _)| !project.contains_key(*key) && !dynamic.contains(key))
+        .collect::<Vec<_>>();
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+    let Some(header) = lines.iter().position(|line| line.trim() == "[project]") else {
+        // An inline `project = {...}` table; not worth rewriting.
+        return Ok(Vec::new());
+    };
+    if missing.is_empty() {
+        return Ok(Vec::new());
+    }
+    let end = lines[header + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map_or(lines.len(), |offset| header + 1 + offset);
+    // After the last entry, before the blank lines ending the table.
+    let at = (header + 1..end)
+        .rev()
+        .find(|idx| !lines[*idx].trim().is_empty())
+        .map_or(header + 1, |idx| idx + 1);
+    let mut updated = lines[..at].concat();
+    if !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    for (key, value) in &missing {
+        updated.push_str(&format!("{key} = {value}\n"));
+    }
+    updated.push_str(&lines[at..].concat());
+    fs::write(&path, updated).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(missing.into_iter().map(|(key, _)| key).collect())
+}
+
+/// The static `version` of pyproject.toml `[project]`. Fails when there is
+/// no pyproject.toml or the version is dynamic, since then the build
+/// backend reads it from somewhere xe does not know about.
+pub fn pyproject_version(project_dir: &Path) -> Result<String> {
+    let (doc, path) = read_pyproject(project_dir)?;
+    let project = doc.get("project").and_then(Item::as_table_like);
+    if let Some(version) = project
+        .and_then(|p| p.get("version"))
+        .and_then(Item::as_str)
+    {
+        return Ok(version.to_string());
+    }
+    let dynamic = project
+        .and_then(|p| p.get("dynamic"))
+        .and_then(Item::as_array)
+        .is_some_and(|d| d.iter().any(|v| v.as_str() == Some("version")));
+    if dynamic {
+        bail!(
+            kind = ErrorKind::Config,
+            "the version in {} is dynamic; set it where the build backend reads it",
+            path.display()
+        );
+    }
+    bail!(
+        kind = ErrorKind::Config,
+        "{} has no [project] version",
+        path.display()
+    )
+}
+
+/// Rewrites the `version` of pyproject.toml `[project]`, keeping the rest
+/// of the file as written.
+pub fn set_pyproject_version(project_dir: &Path, version: &str) -> Result<()> {
+    let (mut doc, path) = read_pyproject(project_dir)?;
+    let Some(project) = doc.get_mut("project").and_then(Item::as_table_like_mut) else {
+        bail!(
+            kind = ErrorKind::Config,
+            "{} has no [project] table",
+            path.display()
+        );
+    };
+    let decor = project
+        .get("version")
+        .and_then(Item::as_value)
+        .map(|v| v.decor().clone());
+    let mut value = Value::from(version);
+    if let Some(decor) = decor {
+        *value.decor_mut() = decor;
+    }
+    project.insert("version", Item::Value(value));
+    fs::write(&path, doc.to_string())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+fn read_pyproject(project_dir: &Path) -> Result<(DocumentMut, PathBuf)> {
+    let path = project_dir.join(PYPROJECT_TOML);
+    let text =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    let doc = text
+        .parse::<DocumentMut>()
+        .with_context(|| format!("failed to parse {}", path.display()))
+        .kind(ErrorKind::Config)?;
+    Ok((doc, path))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct GlobalConfig {
+    #[serde(default)]
+    pub default_python: String,
+    /// Index used when a project sets no `index_url`; a URL or a name from
+    /// `indexes`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) default_index: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) indexes: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "NetworkConfig::is_empty")]
+    pub(crate) network: NetworkConfig,
+    #[serde(default, skip_serializing_if = "UiConfig::is_empty")]
+    pub ui: UiConfig,
+    #[serde(default, skip_serializing_if = "NotificationsConfig::is_empty")]
+    pub notifications: NotificationsConfig,
+    /// Check once a day for a newer xe release (default on).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_check: Option<bool>,
+    /// Record every command in `history.jsonl` for `xe history` (default
+    /// on).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<bool>,
+    /// Download the current module-to-distribution mapping once a week
+    /// (default on).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module_map_update: Option<bool>,
+    #[serde(default, skip_serializing_if = "ProjectDefaults::is_empty")]
+    pub defaults: ProjectDefaults,
+}
+
+/// `defaults:` in the global config: what every new `xe.toml` starts with.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ProjectDefaults {
+    /// `[python] version`; `default_python` still applies without it.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub python: String,
+    /// `[settings] autovenv`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autovenv: Option<bool>,
+    /// `[extras]` groups created empty and installed by a plain `xe sync`
+    /// through `[profile.default]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// `[resolution] index_url`: a URL or a name from `indexes`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub index: String,
+}
+
+impl ProjectDefaults {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Writes the defaults into a fresh project config.
+    fn apply(&self, cfg: &mut Config) {
+        if !self.python.trim().is_empty() {
+            cfg.python.version = self.python.trim().to_string();
+        }
+        if let Some(autovenv) = self.autovenv {
+            cfg.settings.autovenv = autovenv;
+        }
+        if !self.groups.is_empty() {
+            let groups = self
+                .groups
+                .iter()
+                .map(|group| normalize_dep_name(group))
+                .collect::<Vec<_>>();
+            for group in &groups {
+                cfg.extras.entry(group.clone()).or_default();
+            }
+            cfg.profiles
+                .entry("default".to_string())
+                .or_default()
+                .groups = groups;
+        }
+        if !self.index.is_empty() {
+            match lookup_index_url(cfg, &self.index) {
+                Some(url) => cfg.resolution.index_url = url,
+                None => warning(&format!(
+                    "defaults.index '{}' is not a URL or a known index; leaving it out of the new project",
+                    self.index
+                )),
+            }
+        }
+    }
+}
+
+/// Global config as loaded at startup, for code paths without an
+/// `AppContext`.
+pub static GLOBAL_CONFIG: OnceLock<GlobalConfig> = OnceLock::new();
+
+pub(crate) fn global_config() -> &'static GlobalConfig {
+    GLOBAL_CONFIG.get_or_init(GlobalConfig::default)
+}
+
+pub(crate) fn lookup_index_url(cfg: &Config, name: &str) -> Option<String> {
+    if name.contains("://") {
+        return Some(name.to_string());
+    }
+    cfg.indexes
+        .get(name)
+        .or_else(|| global_config().indexes.get(name))
+        .cloned()
+}
+
+/// Whether an artifact URL was served by `index_url`. Indexes commonly
+/// host files elsewhere on the same host; PyPI serves them from its file
+/// host.
+pub(crate) fn artifact_from_index(artifact_url: &str, index_url: &str) -> bool {
+    let host = |url: &str| {
+        url.split_once("://")
+            .map(|(_, rest)| rest.split(['/', '?', '#']).next().unwrap_or_default())
+            .map(|authority| {
+                authority
+                    .rsplit('@')
+                    .next()
+                    .unwrap_or(authority)
+                    .to_lowercase()
+            })
+            .unwrap_or_default()
+    };
+    if let (Some(artifact), Some(index)) =
+        (file_url_to_path(artifact_url), file_url_to_path(index_url))
+    {
+        return artifact.starts_with(index);
+    }
+    let artifact_host = host(artifact_url);
+    let index_host = host(index_url);
+    if artifact_host.is_empty() || index_host.is_empty() {
+        return false;
+    }
+    artifact_host == index_host
+        || (index_host == "pypi.org" && artifact_host == "files.pythonhosted.org")
+}
+
+pub fn load_global_config(path: &Path) -> Result<GlobalConfig> {
+    if !path.exists() {
+        return Ok(GlobalConfig::default());
+    }
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let cfg = serde_yaml::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))
+        .kind(ErrorKind::Config)?;
+    Ok(cfg)
+}
+
+pub fn save_global_config(path: &Path, cfg: &GlobalConfig) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let text = serde_yaml::to_string(cfg).context("failed to encode config")?;
+    fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! Error type returned by the public API.
+
+use std::fmt;
+
+/// Broad class of a failure, for callers that react to some of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// `xe.toml`, `xe.lock` or the global config is unreadable or invalid.
+    Config,
+    /// A Python runtime is missing or could not be installed.
+    Python,
+    /// Dependency resolution failed or broke an index pin.
+    Resolve,
+    /// An index, download or runtime server could not be reached or
+    /// answered with an error.
+    Network,
+    /// An artifact did not match its recorded hash.
+    Integrity,
+    /// A bug in xe: an invariant it relies on did not hold.
+    Internal,
+    Other,
+}
+
+/// An [`ErrorKind`] plus the chain of messages that led to it. `{:#}`
+/// prints the whole chain.
+pub struct Error {
+    kind: ErrorKind,
+    inner: anyhow::Error,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn new(kind: ErrorKind, error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            kind,
+            inner: error.into(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Messages from the outermost context down to the root cause.
+    pub fn chain(&self) -> anyhow::Chain<'_> {
+        self.inner.chain()
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    /// Keeps the kind of an `Error` that picked up context on the way.
+    fn from(inner: anyhow::Error) -> Self {
+        let kind = inner
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Error>())
+            .map_or(ErrorKind::Other, Error::kind);
+        Self { kind, inner }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Self::new(ErrorKind::Other, error)
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{:#}", self.inner)
+        } else {
+            write!(f, "{}", self.inner)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+/// Tags a failure with an [`ErrorKind`].
+pub(crate) trait ResultExt<T> {
+    fn kind(self, kind: ErrorKind) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+    fn kind(self, kind: ErrorKind) -> Result<T> {
+        self.map_err(|error| {
+            let mut error = error.into();
+            error.kind = kind;
+            error
+        })
+    }
+}
+
+/// `anyhow::bail!` for functions returning [`Result`]; a leading
+/// `kind = ErrorKind::..,` tags the error.
+macro_rules! bail {
+    (kind = $kind:expr, $($arg:tt)*) => {
+        return Err($crate::error::Error::new($kind, anyhow::anyhow!($($arg)*)))
+    };
+    ($($arg:tt)*) => {
+        return Err($crate::error::Error::from(anyhow::anyhow!($($arg)*)))
+    };
+}
+
+pub(crate) use bail;
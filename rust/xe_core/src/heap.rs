@@ -0,0 +1,471 @@
+//! Heap profiling for `--profile`. Built with the `heap-profile` feature,
+//! [`TrackingAllocator`] counts every allocation while a profile runs and
+//! samples one allocation per 512 KiB allocated with its call stack;
+//! the samples become the `heap-*.pprof` of the run. Peak RSS is read from
+//! the OS either way.
+
+use crate::error::Result;
+use std::fs;
+use std::path::Path;
+
+/// Peak resident set size of this process, where the OS reports it.
+pub fn peak_rss() -> Option<u64> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// Bytes and number of allocations made since the profile started, or
+/// `None` when allocations are not tracked.
+pub(crate) fn counters() -> Option<(u64, u64)> {
+    #[cfg(feature = "heap-profile")]
+    {
+        tracking::counters()
+    }
+    #[cfg(not(feature = "heap-profile"))]
+    {
+        None
+    }
+}
+
+/// Whether this build can write a heap profile.
+pub(crate) fn supported() -> bool {
+    cfg!(feature = "heap-profile")
+}
+
+/// Starts tracking allocations.
+pub(crate) fn start() {
+    #[cfg(feature = "heap-profile")]
+    tracking::start();
+}
+
+/// Stops tracking and writes what was sampled to `path` as a pprof heap
+/// profile. Returns the peak of bytes in use while tracking.
+pub(crate) fn write_profile(path: &Path) -> Result<Option<u64>> {
+    #[cfg(feature = "heap-profile")]
+    {
+        tracking::write_profile(path).map(Some)
+    }
+    #[cfg(not(feature = "heap-profile"))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "heap-profile")]
+pub use tracking::{TrackingAllocator, SAMPLE_RATE};
+
+#[cfg(feature = "heap-profile")]
+mod tracking {
+    use super::peak_rss;
+    use crate::error::Result;
+    use anyhow::Context;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::ffi::c_void;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+    use std::sync::Mutex;
+    use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+    /// Average number of bytes allocated between two sampled allocations.
+    pub const SAMPLE_RATE: u64 = 512 * 1024;
+
+    /// Deepest call stack kept for a sample.
+    const MAX_DEPTH: usize = 64;
+
+    /// Symbols of the global allocator shim; frames up to the first of them
+    /// are the tracking itself and are dropped from each stack.
+    const ALLOCATOR_ENTRY: &[&str] = &["__rust_alloc", "__rust_realloc"];
+
+    /// The system allocator, counting and sampling allocations while a
+    /// profile runs. Install it with `#[global_allocator]`.
+    pub struct TrackingAllocator;
+
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+    static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+    static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+    static IN_USE: AtomicI64 = AtomicI64::new(0);
+    static PEAK_IN_USE: AtomicI64 = AtomicI64::new(0);
+    /// Bytes allocated towards the next sample.
+    static UNSAMPLED: AtomicU64 = AtomicU64::new(0);
+    static SAMPLES: Mutex<Option<Samples>> = Mutex::new(None);
+    static STARTED: Mutex<Option<(Instant, u64)>> = Mutex::new(None);
+
+    thread_local! {
+        /// Set while this thread records a sample, whose own allocations
+        /// must not be tracked.
+        static RECORDING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    #[derive(Default)]
+    struct Site {
+        allocs: u64,
+        bytes: u64,
+        live_allocs: u64,
+        live_bytes: u64,
+    }
+
+    #[derive(Default)]
+    struct Samples {
+        sites: Vec<(Vec<usize>, Site)>,
+        by_stack: HashMap<Vec<usize>, usize>,
+        /// Sampled allocations not yet freed: address to site and the
+        /// allocations and bytes the sample stands for.
+        live: HashMap<usize, (usize, u64, u64)>,
+    }
+
+    unsafe impl GlobalAlloc for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() && ENABLED.load(Ordering::Relaxed) {
+                on_alloc(ptr, layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc_zeroed(layout);
+            if !ptr.is_null() && ENABLED.load(Ordering::Relaxed) {
+                on_alloc(ptr, layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            if ENABLED.load(Ordering::Relaxed) {
+                on_dealloc(ptr, layout.size());
+            }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() && ENABLED.load(Ordering::Relaxed) {
+                on_dealloc(ptr, layout.size());
+                on_alloc(new_ptr, new_size);
+            }
+            new_ptr
+        }
+    }
+
+    fn on_alloc(ptr: *mut u8, size: usize) {
+        let size = size as u64;
+        ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        let in_use = IN_USE.fetch_add(size as i64, Ordering::Relaxed) + size as i64;
+        PEAK_IN_USE.fetch_max(in_use, Ordering::Relaxed);
+        let before = UNSAMPLED.fetch_add(size, Ordering::Relaxed);
+        if before % SAMPLE_RATE + size < SAMPLE_RATE {
+            return;
+        }
+        let _ = RECORDING.try_with(|recording| {
+            if recording.replace(true) {
+                return;
+            }
+            record_sample(ptr as usize, size);
+            recording.set(false);
+        });
+    }
+
+    fn on_dealloc(ptr: *mut u8, size: usize) {
+        IN_USE.fetch_sub(size as i64, Ordering::Relaxed);
+        let _ = RECORDING.try_with(|recording| {
+            if recording.replace(true) {
+                return;
+            }
+            if let Ok(mut samples) = SAMPLES.lock() {
+                if let Some(samples) = samples.as_mut() {
+                    if let Some((site, allocs, bytes)) = samples.live.remove(&(ptr as usize)) {
+                        let site = &mut samples.sites[site].1;
+                        site.live_allocs = site.live_allocs.saturating_sub(allocs);
+                        site.live_bytes = site.live_bytes.saturating_sub(bytes);
+                    }
+                }
+            }
+            recording.set(false);
+        });
+    }
+
+    /// Records the allocation at `ptr` as standing for [`SAMPLE_RATE`]
+    /// bytes, or its own size when larger.
+    fn record_sample(ptr: usize, size: u64) {
+        let mut stack = Vec::with_capacity(MAX_DEPTH);
+        backtrace::trace(|frame| {
+            stack.push(frame.ip() as usize);
+            stack.len() < MAX_DEPTH
+        });
+        let bytes = size.max(SAMPLE_RATE);
+        let allocs = bytes.div_ceil(size.max(1));
+        let Ok(mut samples) = SAMPLES.lock() else {
+            return;
+        };
+        let samples = samples.get_or_insert_with(Samples::default);
+        let site = match samples.by_stack.get(&stack) {
+            Some(site) => *site,
+            None => {
+                samples.sites.push((stack.clone(), Site::default()));
+                samples.by_stack.insert(stack, samples.sites.len() - 1);
+                samples.sites.len() - 1
+            }
+        };
+        let entry = &mut samples.sites[site].1;
+        entry.allocs += allocs;
+        entry.bytes += bytes;
+        entry.live_allocs += allocs;
+        entry.live_bytes += bytes;
+        samples.live.insert(ptr, (site, allocs, bytes));
+    }
+
+    pub(super) fn start() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+        if let Ok(mut started) = STARTED.lock() {
+            *started = Some((Instant::now(), now));
+        }
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+
+    pub(super) fn counters() -> Option<(u64, u64)> {
+        ENABLED.load(Ordering::Relaxed).then(|| {
+            (
+                ALLOCATED.load(Ordering::Relaxed),
+                ALLOCATIONS.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    pub(super) fn write_profile(path: &Path) -> Result<u64> {
+        ENABLED.store(false, Ordering::Relaxed);
+        let samples = SAMPLES
+            .lock()
+            .ok()
+            .and_then(|mut samples| samples.take())
+            .unwrap_or_default();
+        let (started, time_nanos) = STARTED
+            .lock()
+            .ok()
+            .and_then(|started| *started)
+            .unwrap_or((Instant::now(), 0));
+        let peak = PEAK_IN_USE.load(Ordering::Relaxed).max(0) as u64;
+        let mut comments = vec![
+            format!("allocated_bytes={}", ALLOCATED.load(Ordering::Relaxed)),
+            format!("allocations={}", ALLOCATIONS.load(Ordering::Relaxed)),
+            format!("peak_heap_bytes={peak}"),
+        ];
+        if let Some(rss) = peak_rss() {
+            comments.push(format!("peak_rss_bytes={rss}"));
+        }
+        let profile = encode_profile(
+            &samples,
+            &comments,
+            time_nanos,
+            started.elapsed().as_nanos() as u64,
+        );
+        fs::write(path, profile).with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(peak)
+    }
+
+    /// A function of the profile, as the pprof `Function` message keys it.
+    type FunctionKey = (String, String);
+
+    /// Encodes `samples` as a pprof `Profile` message.
+    fn encode_profile(
+        samples: &Samples,
+        comments: &[String],
+        time_nanos: u64,
+        duration_nanos: u64,
+    ) -> Vec<u8> {
+        let mut strings = Strings::default();
+        let mut out = Vec::new();
+        for (kind, unit) in [
+            ("alloc_objects", "count"),
+            ("alloc_space", "bytes"),
+            ("inuse_objects", "count"),
+            ("inuse_space", "bytes"),
+        ] {
+            let mut value_type = Vec::new();
+            put_int(&mut value_type, 1, strings.id(kind));
+            put_int(&mut value_type, 2, strings.id(unit));
+            put_bytes(&mut out, 1, &value_type);
+        }
+
+        let mut locations: HashMap<usize, u64> = HashMap::new();
+        let mut location_lines: Vec<(usize, Vec<(u64, u64)>)> = Vec::new();
+        let mut functions: HashMap<FunctionKey, u64> = HashMap::new();
+        let mut symbols: HashMap<usize, Vec<(String, String, u64)>> = HashMap::new();
+        for (stack, site) in &samples.sites {
+            for &ip in stack {
+                symbols.entry(ip).or_insert_with(|| symbolize(ip));
+            }
+            // The stack starts inside the allocator; keep what called it.
+            let callers = stack
+                .iter()
+                .position(|ip| {
+                    symbols[ip].iter().any(|(name, _, _)| {
+                        ALLOCATOR_ENTRY.iter().any(|entry| name.contains(entry))
+                    })
+                })
+                .map_or(0, |entry| entry + 1);
+            let mut ids = Vec::new();
+            for &ip in &stack[callers..] {
+                if let Some(&id) = locations.get(&ip) {
+                    ids.push(id);
+                    continue;
+                }
+                let lines = symbols[&ip]
+                    .iter()
+                    .map(|(name, file, line)| {
+                        let next = functions.len() as u64 + 1;
+                        let function = *functions
+                            .entry((name.clone(), file.clone()))
+                            .or_insert(next);
+                        (function, *line)
+                    })
+                    .collect();
+                let id = location_lines.len() as u64 + 1;
+                location_lines.push((ip, lines));
+                locations.insert(ip, id);
+                ids.push(id);
+            }
+            let mut sample = Vec::new();
+            put_packed(&mut sample, 1, &ids);
+            put_packed(
+                &mut sample,
+                2,
+                &[site.allocs, site.bytes, site.live_allocs, site.live_bytes],
+            );
+            put_bytes(&mut out, 2, &sample);
+        }
+        for (i, (ip, lines)) in location_lines.iter().enumerate() {
+            let mut location = Vec::new();
+            put_int(&mut location, 1, i as u64 + 1);
+            put_int(&mut location, 3, *ip as u64);
+            for (function, line) in lines {
+                let mut entry = Vec::new();
+                put_int(&mut entry, 1, *function);
+                put_int(&mut entry, 2, *line);
+                put_bytes(&mut location, 4, &entry);
+            }
+            put_bytes(&mut out, 4, &location);
+        }
+        let mut functions = functions.into_iter().collect::<Vec<_>>();
+        functions.sort_by_key(|(_, id)| *id);
+        for ((name, file), id) in functions {
+            let mut function = Vec::new();
+            put_int(&mut function, 1, id);
+            put_int(&mut function, 2, strings.id(&name));
+            put_int(&mut function, 3, strings.id(&name));
+            put_int(&mut function, 4, strings.id(&file));
+            put_bytes(&mut out, 5, &function);
+        }
+
+        let mut period_type = Vec::new();
+        put_int(&mut period_type, 1, strings.id("space"));
+        put_int(&mut period_type, 2, strings.id("bytes"));
+        let comment_ids = comments
+            .iter()
+            .map(|comment| strings.id(comment))
+            .collect::<Vec<_>>();
+        for text in &strings.table {
+            put_bytes(&mut out, 6, text.as_bytes());
+        }
+        put_int(&mut out, 9, time_nanos);
+        put_int(&mut out, 10, duration_nanos);
+        put_bytes(&mut out, 11, &period_type);
+        put_int(&mut out, 12, SAMPLE_RATE);
+        put_packed(&mut out, 13, &comment_ids);
+        out
+    }
+
+    /// `(function, file, line)` of each frame at `ip`, innermost inlined
+    /// frame first.
+    fn symbolize(ip: usize) -> Vec<(String, String, u64)> {
+        let mut lines = Vec::new();
+        // A return address points past the call; step back into it.
+        backtrace::resolve(ip.saturating_sub(1) as *mut c_void, |symbol| {
+            let name = symbol
+                .name()
+                .map(|name| format!("{name:#}"))
+                .unwrap_or_else(|| format!("{ip:#x}"));
+            let file = symbol
+                .filename()
+                .map(|file| file.display().to_string())
+                .unwrap_or_default();
+            lines.push((name, file, u64::from(symbol.lineno().unwrap_or(0))));
+        });
+        if lines.is_empty() {
+            lines.push((format!("{ip:#x}"), String::new(), 0));
+        }
+        lines
+    }
+
+    /// The pprof string table; entry 0 is always the empty string.
+    struct Strings {
+        table: Vec<String>,
+        ids: HashMap<String, u64>,
+    }
+
+    impl Default for Strings {
+        fn default() -> Self {
+            Self {
+                table: vec![String::new()],
+                ids: HashMap::from([(String::new(), 0)]),
+            }
+        }
+    }
+
+    impl Strings {
+        fn id(&mut self, text: &str) -> u64 {
+            if let Some(id) = self.ids.get(text) {
+                return *id;
+            }
+            let id = self.table.len() as u64;
+            self.table.push(text.to_string());
+            self.ids.insert(text.to_string(), id);
+            id
+        }
+    }
+
+    fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn put_int(out: &mut Vec<u8>, field: u64, value: u64) {
+        put_varint(out, field << 3);
+        put_varint(out, value);
+    }
+
+    fn put_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        put_varint(out, (field << 3) | 2);
+        put_varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+
+    fn put_packed(out: &mut Vec<u8>, field: u64, values: &[u64]) {
+        let mut packed = Vec::new();
+        for value in values {
+            put_varint(&mut packed, *value);
+        }
+        put_bytes(out, field, &packed);
+    }
+}
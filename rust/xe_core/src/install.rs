@@ -0,0 +1,2286 @@
+//! Resolution through pip and wheel installation from the CAS.
+
+use crate::archive::ZipExtractor;
+use crate::config::artifact_from_index;
+use crate::config::global_config;
+use crate::config::lookup_index_url;
+use crate::config::normalize_dep_name;
+use crate::config::requirement_to_dep_name;
+use crate::config::DirectReference;
+use crate::error::{bail, ErrorKind, Result, ResultExt};
+use crate::installed::metadata_dir_project;
+use crate::installed::parse_record;
+use crate::installed::record_entries;
+use crate::installed::FileCollision;
+use crate::installed::DIRECT_URL_FILE;
+use crate::installed::INSTALLER_FILE;
+use crate::installed::SOURCE_FILE;
+use crate::lock::LockFile;
+use crate::modules::ModuleMap;
+use crate::net::apply_network_env;
+use crate::net::http_client;
+use crate::net::http_get;
+use crate::net::http_head;
+use crate::net::http_send;
+use crate::net::send_with_retry;
+use crate::net::strip_credentials;
+use crate::paths::remove_dir_all;
+use crate::paths::tempfile_path;
+use crate::paths::xe_cache_dir;
+use crate::process::PIPE_DRAIN_TIMEOUT;
+use crate::profile::span;
+use crate::profile::AppContext;
+use crate::python::compare_version;
+use crate::python::specifier_allows;
+use crate::python::PythonSpec;
+use crate::report::progress_event;
+use crate::scripts::install_console_scripts;
+use crate::tags::TagSet;
+use crate::tags::TargetEnv;
+use crate::ui::info;
+use crate::ui::warning;
+use crate::Cas;
+use crate::Config;
+use anyhow::{anyhow, Context};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha1::{Digest as Sha1Digest, Sha1};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use zip::ZipArchive;
+
+/// Recognizes a wheel given by URL (`https://host/pkg.whl#sha256=...`), by
+/// path (`./dist/pkg.whl`) or as `name @ url`, stores it in the CAS and
+/// returns the entry to record. Anything else is an index requirement.
+pub fn prepare_direct_reference(
+    arg: &str,
+    project_dir: &Path,
+    cas: &Cas,
+) -> Result<Option<(String, DirectReference)>> {
+    let location = match arg.split_once(" @ ") {
+        Some((_, url)) => url.trim(),
+        None => arg.trim(),
+    };
+    let is_url = location.starts_with("https://") || location.starts_with("http://");
+    let is_file_url = location.starts_with("file://");
+    if !is_url && !is_file_url && !location.to_lowercase().ends_with(".whl") {
+        return Ok(None);
+    }
+
+    let (location, fragment) = location.split_once('#').unwrap_or((location, ""));
+    let expected_sha = fragment
+        .strip_prefix("sha256=")
+        .unwrap_or("")
+        .to_lowercase();
+    let file_name = location
+        .split('?')
+        .next()
+        .unwrap_or(location)
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(location);
+    let (name, _) = parse_wheel_filename(file_name).ok_or_else(|| {
+        anyhow!(
+            "only wheel files can be added by location, got {}",
+            location
+        )
+    })?;
+
+    let mut reference = DirectReference::default();
+    let blob = if is_url {
+        info(&format!("Fetching {}...", location));
+        reference.url = location.to_string();
+        cas.store_blob_from_url(location, &expected_sha)?
+    } else {
+        let local = match file_url_to_path(location) {
+            Some(path) => path,
+            None => project_dir.join(location),
+        };
+        let local = fs::canonicalize(&local)
+            .with_context(|| format!("wheel not found: {}", local.display()))?;
+        reference.path = local
+            .strip_prefix(project_dir)
+            .unwrap_or(&local)
+            .to_string_lossy()
+            .replace('\\', "/");
+        cas.store_blob_from_url(&path_to_file_url(&local), &expected_sha)?
+    };
+    reference.sha256 = blob
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string();
+    Ok(Some((normalize_dep_name(&name), reference)))
+}
+
+/// Splits `name-version(-build)?-python-abi-platform.whl` into name and version.
+pub(crate) fn parse_wheel_filename(file_name: &str) -> Option<(String, String)> {
+    let stem = file_name.strip_suffix(".whl")?;
+    let parts = stem.split('-').collect::<Vec<_>>();
+    if parts.len() < 5 || parts[0].is_empty() || parts[1].is_empty() {
+        return None;
+    }
+    Some((parts[0].to_string(), parts[1].to_string()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Package {
+    #[serde(alias = "Name")]
+    pub name: String,
+    #[serde(alias = "Version")]
+    pub version: String,
+    #[serde(default, alias = "DownloadURL")]
+    pub(crate) download_url: String,
+    #[serde(default, alias = "Hash")]
+    pub(crate) hash: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub editable: bool,
+    /// Every wheel published for this version, recorded at lock time so the
+    /// lock can be installed on other platforms.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<Artifact>,
+    /// URL of the index this package is pinned to, if any.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) index: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    pub(crate) filename: String,
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) sha256: String,
+}
+
+/// A file of a locked package, held in the cache as `blob`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedFile {
+    pub package: String,
+    pub version: String,
+    pub filename: String,
+    pub sha256: String,
+    #[serde(skip)]
+    pub blob: PathBuf,
+}
+
+impl Package {
+    /// Source directory of an editable package.
+    pub fn editable_path(&self) -> Option<PathBuf> {
+        if !self.editable {
+            return None;
+        }
+        file_url_to_path(&self.download_url)
+    }
+}
+
+pub fn path_to_file_url(path: &Path) -> String {
+    let text = path.to_string_lossy().replace('\\', "/");
+    let mut out = String::from("file://");
+    if !text.starts_with('/') {
+        out.push('/');
+    }
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+pub(crate) fn file_url_to_path(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file://")?;
+    let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+    let bytes = rest.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0usize;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(&rest[idx + 1..idx + 3], 16) {
+                decoded.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[idx]);
+        idx += 1;
+    }
+    let path = String::from_utf8(decoded).ok()?;
+    // `file:///C:/dir` carries a leading slash before the drive letter.
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => path[1..].to_string(),
+        _ => path,
+    };
+    Some(PathBuf::from(path))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SolveGraph {
+    #[serde(alias = "PythonVersion")]
+    pub(crate) python_version: String,
+    #[serde(default, alias = "Requirements")]
+    pub(crate) requirements: Vec<String>,
+    #[serde(default, alias = "Packages")]
+    pub packages: Vec<Package>,
+}
+
+pub struct Installer {
+    pub cas: Cas,
+    pub(crate) report: Mutex<InstallReport>,
+    /// Install even when a wheel would overwrite files of another package.
+    pub allow_collisions: bool,
+    /// `[settings] ignore`, normalized; dropped from every plan.
+    ignored: HashSet<String>,
+    /// Packages the next resolution will probably pick, fetched while it
+    /// runs; see [`Installer::expect_lock`].
+    expected: Mutex<Vec<Package>>,
+    /// Bounds on each pip resolution.
+    pub limits: ResolveLimits,
+    /// Resolve for this interpreter and platform instead of the one running
+    /// pip, as `xe lock --python-platform` does.
+    pub target: Option<TargetEnv>,
+    /// The primary index, then the extra ones, to record which one each
+    /// installed package came from.
+    indexes: Vec<String>,
+    /// `[direct]` names, normalized; installed from their own URL rather
+    /// than an index.
+    pub direct: HashSet<String>,
+}
+
+/// When to give up on a pip resolution instead of letting a pathological
+/// graph backtrack for hours.
+#[derive(Debug, Clone, Copy)]
+pub struct ResolveLimits {
+    /// Wall time per requirement; `None` waits as long as pip takes.
+    pub timeout: Option<Duration>,
+    /// Candidates pip may reject per requirement; 0 disables the budget.
+    pub max_backtracks: usize,
+}
+
+impl Default for ResolveLimits {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            max_backtracks: DEFAULT_MAX_BACKTRACKS,
+        }
+    }
+}
+
+/// Machine-readable account of one install run, written by `--report`.
+#[derive(Debug, Default, Serialize)]
+pub struct InstallReport {
+    /// `lockfile`, `solve-cache` or `resolver`.
+    pub resolution_source: String,
+    pub resolution_ms: u128,
+    pub requirements: Vec<String>,
+    pub packages: Vec<PackageReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageReport {
+    pub name: String,
+    pub(crate) version: String,
+    /// `installed`, `skipped` (already present), `editable`, or `cached`
+    /// for `xe cache warm`.
+    pub action: String,
+    pub(crate) url: String,
+    pub(crate) sha256: String,
+    /// `hit` or `miss` for the artifact blob; empty when nothing was fetched.
+    pub cache: String,
+    pub(crate) built_from_sdist: bool,
+    pub(crate) duration_ms: u128,
+}
+
+/// What fetching left to put into site-packages for one package.
+enum Fetched {
+    Wheel(PathBuf),
+    /// The source tree of an editable install.
+    Editable(PathBuf),
+}
+
+impl Installer {
+    /// Opens the cache selected by `cfg.cache.mode` for the project at
+    /// `project_dir`.
+    pub fn new(cfg: &Config, project_dir: &Path) -> Result<Self> {
+        Ok(Self {
+            cas: Cas::new(&cfg.cache.dir(project_dir)?)?,
+            report: Mutex::new(InstallReport::default()),
+            allow_collisions: false,
+            ignored: cfg
+                .settings
+                .ignore
+                .iter()
+                .map(|name| normalize_dep_name(name))
+                .collect(),
+            expected: Mutex::new(Vec::new()),
+            limits: ResolveLimits::default(),
+            target: None,
+            indexes: primary_index_url(cfg)
+                .into_iter()
+                .chain(cfg.resolution.extra_index_urls.iter().cloned())
+                .collect(),
+            direct: cfg
+                .direct
+                .keys()
+                .map(|name| normalize_dep_name(name))
+                .collect(),
+        })
+    }
+
+    /// Marks the packages of a previous lock as likely: the next
+    /// [`Installer::install`] downloads their artifacts for `python_exe` into
+    /// the cache while the resolver runs, so those it picks again install
+    /// from cache. Only a hint; nothing is installed from it.
+    pub fn expect_lock(&self, lock: &LockFile, python_exe: &Path) {
+        let Ok(target) = TargetEnv::probe(python_exe) else {
+            return;
+        };
+        let tag_set = TagSet::new(&target);
+        let likely = lock
+            .packages
+            .iter()
+            .filter(|pkg| !pkg.editable && !is_source_tree_url(&pkg.download_url))
+            .filter(|pkg| !self.ignored.contains(&normalize_dep_name(&pkg.name)))
+            .filter_map(|pkg| {
+                let mut pkg = pkg.clone();
+                if !pkg.artifacts.is_empty() {
+                    let best = best_artifact(&pkg.artifacts, &tag_set)?;
+                    pkg.download_url = best.url.clone();
+                    pkg.hash = best.sha256.clone();
+                }
+                Some(pkg)
+            })
+            .collect();
+        if let Ok(mut expected) = self.expected.lock() {
+            *expected = likely;
+        }
+    }
+
+    /// Downloads expected artifacts that are neither cached nor installed,
+    /// a few at a time, until `stop` is set; downloads still running then
+    /// are abandoned. Failures are ignored: the real install fetches
+    /// whatever it needs anyway.
+    fn prefetch_expected(&self, site_packages: &Path, stop: &AtomicBool) {
+        let expected = self
+            .expected
+            .lock()
+            .map(|mut expected| std::mem::take(&mut *expected))
+            .unwrap_or_default();
+        let installed = installed_package_key_set(site_packages).unwrap_or_default();
+        let pending = expected
+            .into_iter()
+            .filter(|pkg| !pkg.download_url.is_empty())
+            .filter(|pkg| !installed.contains(&package_identity_key(&pkg.name, &pkg.version)))
+            .filter(|pkg| pkg.hash.is_empty() || !self.cas.blob_path(&pkg.hash).exists())
+            .collect::<Vec<_>>();
+        if pending.is_empty() {
+            return;
+        }
+        let Ok(pool) = rayon::ThreadPoolBuilder::new()
+            .num_threads(SPECULATIVE_FETCHES)
+            .build()
+        else {
+            return;
+        };
+        pool.install(|| {
+            pending.par_iter().for_each(|pkg| {
+                if !stop.load(AtomicOrdering::Relaxed) {
+                    let _ = self
+                        .cas
+                        .store_blob_until(&pkg.download_url, &pkg.hash, stop);
+                }
+            })
+        });
+    }
+
+    pub fn take_report(&self) -> InstallReport {
+        self.report
+            .lock()
+            .map(|mut report| std::mem::take(&mut *report))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn record_resolution(
+        &self,
+        source: &str,
+        started: Instant,
+        requirements: &[String],
+    ) {
+        if let Ok(mut report) = self.report.lock() {
+            report.resolution_source = source.to_string();
+            report.resolution_ms = started.elapsed().as_millis();
+            report.requirements = requirements.to_vec();
+        }
+    }
+
+    pub(crate) fn record_package(&self, entry: PackageReport) {
+        if let Ok(mut report) = self.report.lock() {
+            report.packages.push(entry);
+        }
+    }
+
+    fn reported_packages(&self) -> usize {
+        self.report
+            .lock()
+            .map(|report| report.packages.len())
+            .unwrap_or(0)
+    }
+
+    /// Writes an `install.package` trace event for each package reported
+    /// since `first`; `xe cache warm` counts them to find what this machine
+    /// installs most.
+    fn trace_packages(&self, ctx: &AppContext, first: usize) {
+        let Some(profiler) = ctx.profiler.as_ref() else {
+            return;
+        };
+        if let Ok(report) = self.report.lock() {
+            for pkg in report.packages.iter().skip(first) {
+                profiler.event(
+                    "install.package",
+                    json!({
+                        "name": pkg.name,
+                        "version": pkg.version,
+                        "action": pkg.action,
+                        "cache": pkg.cache,
+                    }),
+                );
+            }
+        }
+    }
+
+    pub fn install(
+        &self,
+        ctx: &AppContext,
+        cfg: &Config,
+        requirements: &[String],
+        project_dir: &Path,
+        install_site_packages: &Path,
+        python_exe: &Path,
+    ) -> Result<Vec<Package>> {
+        let _span = span(
+            ctx,
+            "install.total",
+            json!({"python_version": cfg.python.version, "raw_requirements": requirements.len()}),
+        );
+        let target_site_packages = if install_site_packages.as_os_str().is_empty() {
+            project_dir.join("xe").join("site-packages")
+        } else {
+            install_site_packages.to_path_buf()
+        };
+        let graph =
+            self.resolve_prefetching(cfg, requirements, &target_site_packages, python_exe)?;
+        self.install_resolved(ctx, graph.packages, &target_site_packages, python_exe)
+    }
+
+    /// Resolves like [`Installer::resolve`]. The resolver spends most of its
+    /// time waiting on the index, so what the last lock used is fetched in
+    /// the meantime; whatever is still downloading when the resolution
+    /// returns is cancelled rather than waited for.
+    pub fn resolve_prefetching(
+        &self,
+        cfg: &Config,
+        requirements: &[String],
+        site_packages: &Path,
+        python_exe: &Path,
+    ) -> Result<SolveGraph> {
+        let stop = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| self.prefetch_expected(site_packages, &stop));
+            let graph = self.resolve(cfg, requirements, python_exe);
+            stop.store(true, AtomicOrdering::Relaxed);
+            graph
+        })
+    }
+
+    /// Installs packages resolved earlier without resolving them again.
+    pub fn install_resolved(
+        &self,
+        ctx: &AppContext,
+        mut packages: Vec<Package>,
+        site_packages: &Path,
+        python_exe: &Path,
+    ) -> Result<Vec<Package>> {
+        if packages.is_empty() {
+            return Ok(packages);
+        }
+        let first_report = self.reported_packages();
+
+        self.install_packages(&packages, site_packages, python_exe)?;
+        self.trace_packages(ctx, first_report);
+
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(packages)
+    }
+
+    /// Resolves requirements to a package graph, from the solve cache when
+    /// possible. Nothing is installed.
+    pub fn resolve(
+        &self,
+        cfg: &Config,
+        requirements: &[String],
+        python_exe: &Path,
+    ) -> Result<SolveGraph> {
+        let reqs = normalize_requirements(requirements);
+        if reqs.is_empty() {
+            return Ok(SolveGraph {
+                python_version: cfg.python.version.clone(),
+                requirements: reqs,
+                packages: Vec::new(),
+            });
+        }
+
+        progress_event("resolve.started", json!({"requirements": reqs}));
+        let pins = cfg.index_pins()?;
+        let mut pip_options = resolver_index_args(cfg, &pins)?;
+        let markers = match &self.target {
+            Some(target) => {
+                pip_options.extend(target.pip_args());
+                Some(target.marker_environment().to_string())
+            }
+            None => None,
+        };
+        let cache_key = solve_key(
+            &solve_python_tag(&cfg.python.version),
+            &solve_key_parts(cfg, &reqs, &pip_options),
+        );
+        let resolve_started = Instant::now();
+        let (mut graph, source) = if let Some(cached) =
+            self.cas.load_solution::<SolveGraph>(&cache_key)?
+        {
+            self.record_resolution("solve-cache", resolve_started, &reqs);
+            (cached, "solve-cache")
+        } else {
+            if !cfg.resolution.constraints.is_empty() {
+                let constraints_file = tempfile_path("xe-constraints", "txt");
+                fs::write(&constraints_file, cfg.resolution.constraints.join("\n"))
+                    .with_context(|| format!("failed to write {}", constraints_file.display()))?;
+                pip_options.push("--constraint".to_string());
+                pip_options.push(constraints_file.display().to_string());
+            }
+            let solved = reqs
+                .par_iter()
+                .map(|req| {
+                    resolve_requirement(
+                        req,
+                        python_exe,
+                        &pip_options,
+                        markers.as_deref(),
+                        &self.limits,
+                    )
+                })
+                .collect::<Result<Vec<Vec<Package>>>>()?
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+
+            let solved = dedupe_packages(solved);
+            let graph = SolveGraph {
+                python_version: cfg.python.version.clone(),
+                requirements: reqs.clone(),
+                packages: solved,
+            };
+            self.cas.save_solution(&cache_key, &graph)?;
+            self.record_resolution("resolver", resolve_started, &reqs);
+            (graph, "resolver")
+        };
+        graph
+            .packages
+            .retain(|pkg| !self.ignored.contains(&normalize_dep_name(&pkg.name)));
+        for pkg in &mut graph.packages {
+            let Some((index_name, index_url)) = pins.get(&normalize_dep_name(&pkg.name)) else {
+                continue;
+            };
+            if !artifact_from_index(&pkg.download_url, index_url) {
+                bail!(
+                    kind = ErrorKind::Resolve,
+                    "{} {} resolved to {}, but it is pinned to index '{}' ({}); refusing a possible dependency-confusion substitute",
+                    pkg.name,
+                    pkg.version,
+                    pkg.download_url,
+                    index_name,
+                    index_url
+                );
+            }
+            pkg.index = index_url.clone();
+        }
+        progress_event(
+            "resolve.finished",
+            json!({
+                "source": source,
+                "packages": graph.packages.len(),
+                "duration_ms": resolve_started.elapsed().as_millis(),
+            }),
+        );
+        Ok(graph)
+    }
+
+    /// Installs the packages of a lockfile, picking for each one the wheel
+    /// that best matches the receiving interpreter and platform.
+    pub fn install_locked(
+        &self,
+        ctx: &AppContext,
+        lock: &LockFile,
+        target_site_packages: &Path,
+        python_exe: &Path,
+    ) -> Result<Vec<Package>> {
+        let _span = span(
+            ctx,
+            "install.locked",
+            json!({"packages": lock.packages.len()}),
+        );
+        let plan = self.locked_plan(lock, python_exe)?;
+        let first_report = self.reported_packages();
+        self.install_packages(&plan, target_site_packages, python_exe)?;
+        self.trace_packages(ctx, first_report);
+        Ok(plan)
+    }
+
+    /// Plans the part of `lock` that `packages` and `extra_reqs` need. The
+    /// lock records no dependency edges, so the roots are resolved with every
+    /// locked version as a constraint; the closure that comes back is then
+    /// installed from the locked entries. Editable and direct packages are
+    /// taken as they are, without their dependencies, and packages the lock
+    /// lacks, such as those of an `[extras]` group, come from the resolution.
+    pub fn locked_subset(
+        &self,
+        cfg: &Config,
+        lock: &LockFile,
+        packages: &[String],
+        extra_reqs: &[String],
+        python_exe: &Path,
+    ) -> Result<Vec<Package>> {
+        let pinnable = |pkg: &Package| {
+            !pkg.editable && !cfg.direct.contains_key(&normalize_dep_name(&pkg.name))
+        };
+        let mut constrained = cfg.clone();
+        let mut roots = extra_reqs.to_vec();
+        let mut needed = HashSet::new();
+        for name in packages {
+            let name = normalize_dep_name(name);
+            let Some(pkg) = lock
+                .packages
+                .iter()
+                .find(|pkg| normalize_dep_name(&pkg.name) == name)
+            else {
+                bail!(
+                    kind = ErrorKind::Config,
+                    "{} is not in xe.lock; add it with `xe add {}`",
+                    name,
+                    name
+                );
+            };
+            if pinnable(pkg) {
+                roots.push(format!("{}=={}", pkg.name, pkg.version));
+            } else {
+                needed.insert(name);
+            }
+        }
+        for pkg in lock.packages.iter().filter(|pkg| pinnable(pkg)) {
+            constrained
+                .resolution
+                .constraints
+                .push(format!("{}=={}", pkg.name, pkg.version));
+        }
+        let graph = self.resolve(&constrained, &roots, python_exe)?;
+        let locked = lock
+            .packages
+            .iter()
+            .map(|pkg| normalize_dep_name(&pkg.name))
+            .collect::<HashSet<_>>();
+        let mut unlocked = Vec::new();
+        for pkg in graph.packages {
+            let name = normalize_dep_name(&pkg.name);
+            if locked.contains(&name) {
+                needed.insert(name);
+            } else {
+                unlocked.push(pkg);
+            }
+        }
+        let subset = LockFile {
+            packages: lock
+                .packages
+                .iter()
+                .filter(|pkg| needed.contains(&normalize_dep_name(&pkg.name)))
+                .cloned()
+                .collect(),
+            ..lock.clone()
+        };
+        let mut plan = self.locked_plan(&subset, python_exe)?;
+        plan.extend(unlocked);
+        Ok(plan)
+    }
+
+    /// Compares a resolved package set against what `site_packages` holds,
+    /// sizing the downloads that installing it would need.
+    pub fn plan_changes(
+        &self,
+        packages: &[Package],
+        site_packages: &Path,
+    ) -> Result<Vec<PlannedChange>> {
+        let installed = installed_distributions(site_packages)?;
+        let mut changes = packages
+            .par_iter()
+            .filter_map(|pkg| {
+                let current = installed.get(&normalize_dep_name(&pkg.name));
+                let action = match current {
+                    Some((_, version)) if *version == pkg.version => return None,
+                    Some((_, version))
+                        if compare_version(&pkg.version, version) == Ordering::Less =>
+                    {
+                        PlannedAction::Downgrade
+                    }
+                    Some(_) => PlannedAction::Upgrade,
+                    None => PlannedAction::Install,
+                };
+                let cached = !pkg.hash.is_empty() && self.cas.blob_path(&pkg.hash).exists();
+                let size = if cached || pkg.editable {
+                    Some(0)
+                } else {
+                    artifact_download_size(&pkg.download_url)
+                };
+                Some(PlannedChange {
+                    action,
+                    name: pkg.name.clone(),
+                    from: current.map(|(_, v)| v.clone()).unwrap_or_default(),
+                    to: pkg.version.clone(),
+                    download_bytes: size,
+                })
+            })
+            .collect::<Vec<_>>();
+        changes.sort_by_key(|c| c.name.to_lowercase());
+        Ok(changes)
+    }
+
+    /// Lock packages with the artifact chosen for the receiving interpreter.
+    pub fn locked_plan(&self, lock: &LockFile, python_exe: &Path) -> Result<Vec<Package>> {
+        let resolve_started = Instant::now();
+        let target = TargetEnv::probe(python_exe)?;
+        let tag_set = TagSet::new(&target);
+        let mut plan = Vec::with_capacity(lock.packages.len());
+        for pkg in &lock.packages {
+            if self.ignored.contains(&normalize_dep_name(&pkg.name)) {
+                continue;
+            }
+            let mut pkg = pkg.clone();
+            if !pkg.artifacts.is_empty() {
+                let Some(best) = best_artifact(&pkg.artifacts, &tag_set).cloned() else {
+                    let files = pkg
+                        .artifacts
+                        .iter()
+                        .map(|a| a.filename.as_str())
+                        .collect::<Vec<_>>();
+                    bail!(
+                        "no locked wheel of {} {} is compatible with {} ({}); available: {}",
+                        pkg.name,
+                        pkg.version,
+                        python_exe.display(),
+                        target
+                            .platform_tags()
+                            .first()
+                            .map(String::as_str)
+                            .unwrap_or("unknown"),
+                        files.join(", ")
+                    );
+                };
+                pkg.download_url = best.url;
+                pkg.hash = best.sha256;
+            }
+            if !pkg.index.is_empty() && !artifact_from_index(&pkg.download_url, &pkg.index) {
+                bail!(
+                    "locked artifact {} of {} does not come from its pinned index {}",
+                    pkg.download_url,
+                    pkg.name,
+                    pkg.index
+                );
+            }
+            plan.push(pkg);
+        }
+        self.record_resolution("lockfile", resolve_started, &[]);
+        progress_event(
+            "resolve.finished",
+            json!({
+                "source": "lockfile",
+                "packages": plan.len(),
+                "duration_ms": resolve_started.elapsed().as_millis(),
+            }),
+        );
+        Ok(plan)
+    }
+
+    /// Downloads (and for sdists, builds) the artifacts of `packages` into
+    /// the cache without installing them; each is reported as `cached`.
+    pub fn prefetch(&self, packages: &[Package], python_exe: &Path) -> Result<()> {
+        let build_tag = build_tag(packages, python_exe)?;
+        packages.par_iter().try_for_each(|pkg| -> Result<()> {
+            if pkg.editable
+                || pkg.download_url.trim().is_empty()
+                || is_source_tree_url(&pkg.download_url)
+            {
+                return Ok(());
+            }
+            let started = Instant::now();
+            let cached = !pkg.hash.trim().is_empty() && self.cas.blob_path(&pkg.hash).exists();
+            let blob = self
+                .cas
+                .store_blob_from_url(&pkg.download_url, pkg.hash.as_str())?;
+            if is_sdist_url(&pkg.download_url) {
+                self.cas
+                    .built_wheel(&blob, &pkg.download_url, &build_tag, python_exe)?;
+            }
+            self.record_package(PackageReport {
+                name: pkg.name.clone(),
+                version: pkg.version.clone(),
+                action: "cached".to_string(),
+                url: pkg.download_url.clone(),
+                sha256: blob
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                cache: if cached { "hit" } else { "miss" }.to_string(),
+                built_from_sdist: is_sdist_url(&pkg.download_url),
+                duration_ms: started.elapsed().as_millis(),
+            });
+            Ok(())
+        })
+    }
+
+    /// The wheel each of `packages` installs from, downloaded (and built,
+    /// for sdists and source trees) into the cache; `None` for editable
+    /// packages.
+    pub fn wheels_of(
+        &self,
+        packages: &[Package],
+        python_exe: &Path,
+    ) -> Result<Vec<Option<PathBuf>>> {
+        let build_tag = build_tag(packages, python_exe)?;
+        packages
+            .par_iter()
+            .map(|pkg| -> Result<Option<PathBuf>> {
+                if pkg.editable || pkg.download_url.trim().is_empty() {
+                    return Ok(None);
+                }
+                if is_source_tree_url(&pkg.download_url) {
+                    return self
+                        .cas
+                        .built_source_wheel(&pkg.download_url, &build_tag, python_exe)
+                        .map(Some);
+                }
+                let blob = self
+                    .cas
+                    .store_blob_from_url(&pkg.download_url, pkg.hash.as_str())?;
+                if is_sdist_url(&pkg.download_url) {
+                    return self
+                        .cas
+                        .built_wheel(&blob, &pkg.download_url, &build_tag, python_exe)
+                        .map(Some);
+                }
+                Ok(Some(blob))
+            })
+            .collect()
+    }
+
+    /// Downloads every file `lock` records, wheels for other platforms
+    /// included, into the cache and fills in digests the lock is missing.
+    /// Editable and source-tree packages have no file and are skipped.
+    pub fn fetch_locked_files(&self, lock: &mut LockFile) -> Result<Vec<LockedFile>> {
+        let fetched = lock
+            .packages
+            .par_iter_mut()
+            .map(|pkg| -> Result<Vec<LockedFile>> {
+                if pkg.editable
+                    || pkg.download_url.trim().is_empty()
+                    || is_source_tree_url(&pkg.download_url)
+                {
+                    warning(&format!(
+                        "{} {} is installed from source and is not bundled",
+                        pkg.name, pkg.version
+                    ));
+                    return Ok(Vec::new());
+                }
+                let mut files = Vec::new();
+                if pkg.artifacts.is_empty() {
+                    let filename = pkg
+                        .download_url
+                        .split(['#', '?'])
+                        .next()
+                        .unwrap_or_default()
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or_default()
+                        .to_string();
+                    let blob = self.cas.store_blob_from_url(&pkg.download_url, &pkg.hash)?;
+                    pkg.hash = blob_digest(&blob);
+                    files.push(LockedFile {
+                        package: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        filename,
+                        sha256: pkg.hash.clone(),
+                        blob,
+                    });
+                }
+                for artifact in &mut pkg.artifacts {
+                    let blob = self
+                        .cas
+                        .store_blob_from_url(&artifact.url, &artifact.sha256)?;
+                    artifact.sha256 = blob_digest(&blob);
+                    if pkg.hash.is_empty() && artifact.url == pkg.download_url {
+                        pkg.hash = artifact.sha256.clone();
+                    }
+                    files.push(LockedFile {
+                        package: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        filename: artifact.filename.clone(),
+                        sha256: artifact.sha256.clone(),
+                        blob,
+                    });
+                }
+                Ok(files)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut seen = HashSet::new();
+        Ok(fetched
+            .into_iter()
+            .flatten()
+            .filter(|file| seen.insert(file.sha256.clone()))
+            .collect())
+    }
+
+    /// Copies `path` into the cache, failing unless its digest is `sha256`.
+    pub fn import_blob(&self, path: &Path, sha256: &str) -> Result<PathBuf> {
+        if sha256.trim().is_empty() {
+            bail!("no digest given for {}", path.display());
+        }
+        self.cas
+            .store_blob_from_url(&path_to_file_url(path), sha256)
+    }
+
+    pub(crate) fn install_packages(
+        &self,
+        packages: &[Package],
+        target_site_packages: &Path,
+        python_exe: &Path,
+    ) -> Result<()> {
+        let mut download_plan = packages.to_vec();
+        download_plan.sort_by(|a, b| a.name.cmp(&b.name));
+
+        fs::create_dir_all(target_site_packages)
+            .with_context(|| format!("failed to create {}", target_site_packages.display()))?;
+        let build_tag = build_tag(&download_plan, python_exe)?;
+
+        let installed_set = Arc::new(Mutex::new(installed_package_key_set(target_site_packages)?));
+        // Everything is fetched (and built) before anything is unpacked or
+        // linked, so a collision stops the install with the environment
+        // untouched.
+        let fetched = download_plan
+            .par_iter()
+            .map(
+                |pkg| -> Result<Option<(&Package, Fetched, PackageReport, Instant)>> {
+                    let started = Instant::now();
+                    let mut entry = PackageReport {
+                        name: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        action: "skipped".to_string(),
+                        url: pkg.download_url.clone(),
+                        sha256: pkg.hash.clone(),
+                        cache: String::new(),
+                        built_from_sdist: false,
+                        duration_ms: 0,
+                    };
+                    let key = package_identity_key(&pkg.name, &pkg.version);
+                    // A local directory may have changed under the same version.
+                    let local_tree =
+                        file_url_to_path(&pkg.download_url).is_some_and(|path| path.is_dir());
+                    {
+                        let mut guard = installed_set
+                            .lock()
+                            .map_err(|_| anyhow!("install state poisoned"))
+                            .kind(ErrorKind::Internal)?;
+                        if !guard.insert(key) && !local_tree {
+                            self.record_package(entry);
+                            return Ok(None);
+                        }
+                    }
+                    if let Some(source) = pkg.editable_path() {
+                        return Ok(Some((pkg, Fetched::Editable(source), entry, started)));
+                    }
+                    if pkg.download_url.trim().is_empty() {
+                        self.record_package(entry);
+                        return Ok(None);
+                    }
+                    if is_source_tree_url(&pkg.download_url) {
+                        let wheel = self.cas.built_source_wheel(
+                            &pkg.download_url,
+                            &build_tag,
+                            python_exe,
+                        )?;
+                        entry.built_from_sdist = true;
+                        return Ok(Some((pkg, Fetched::Wheel(wheel), entry, started)));
+                    }
+
+                    let cached =
+                        !pkg.hash.trim().is_empty() && self.cas.blob_path(&pkg.hash).exists();
+                    let blob = self
+                        .cas
+                        .store_blob_from_url(&pkg.download_url, pkg.hash.as_str())?;
+                    entry.cache = if cached { "hit" } else { "miss" }.to_string();
+                    entry.sha256 = blob
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .unwrap_or_default()
+                        .to_string();
+                    entry.built_from_sdist = is_sdist_url(&pkg.download_url);
+                    let wheel = if is_sdist_url(&pkg.download_url) {
+                        self.cas
+                            .built_wheel(&blob, &pkg.download_url, &build_tag, python_exe)?
+                    } else {
+                        blob
+                    };
+                    Ok(Some((pkg, Fetched::Wheel(wheel), entry, started)))
+                },
+            )
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        if !self.allow_collisions {
+            let wheels: Vec<(&Package, &Path)> = fetched
+                .iter()
+                .filter_map(|(pkg, fetched, _, _)| match fetched {
+                    Fetched::Wheel(wheel) => Some((*pkg, wheel.as_path())),
+                    Fetched::Editable(_) => None,
+                })
+                .collect();
+            let collisions = find_collisions(target_site_packages, &wheels)?;
+            if !collisions.is_empty() {
+                let listed = collisions
+                    .iter()
+                    .map(|c| format!("  {} ({})", c.path, c.owners.join(", ")))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                bail!(
+                    kind = ErrorKind::Resolve,
+                    "{} file(s) would be overwritten by another package:\n{}\nnothing was installed; pass --allow-collisions to install anyway",
+                    collisions.len(),
+                    listed
+                );
+            }
+        }
+
+        let total = fetched.len();
+        let installed = AtomicUsize::new(0);
+        fetched.into_par_iter().try_for_each(
+            |(pkg, fetched, mut entry, started)| -> Result<()> {
+                let (dist_info, action) = match fetched {
+                    Fetched::Wheel(wheel) => (
+                        install_wheel_blob(&wheel, target_site_packages)?,
+                        "installed",
+                    ),
+                    Fetched::Editable(source) => (
+                        Some(install_editable(
+                            &pkg.name,
+                            &pkg.version,
+                            &source,
+                            target_site_packages,
+                        )?),
+                        "editable",
+                    ),
+                };
+                if let Some(dist_info) = dist_info {
+                    record_source(target_site_packages, &dist_info, &self.source_files(pkg))?;
+                }
+                progress_event(
+                    "install.progress",
+                    json!({
+                        "package": entry.name,
+                        "version": entry.version,
+                        "installed": installed.fetch_add(1, AtomicOrdering::Relaxed) + 1,
+                        "total": total,
+                    }),
+                );
+                entry.action = action.to_string();
+                entry.duration_ms = started.elapsed().as_millis();
+                self.record_package(entry);
+                Ok(())
+            })?;
+
+        if let Err(err) = install_console_scripts(target_site_packages, python_exe) {
+            warning(&format!(
+                "Failed to generate console script launchers: {err}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// The files that record in `pkg`'s `.dist-info` where it came from: a
+    /// PEP 610 `direct_url.json` for a VCS checkout, local tree or
+    /// `[direct]` URL, and xe's own note of the index or find-links
+    /// location otherwise. URLs are recorded without their credentials.
+    fn source_files(&self, pkg: &Package) -> Vec<(&'static str, String)> {
+        let url = strip_credentials(&pkg.download_url);
+        let (base, fragment) = url.split_once('#').unwrap_or((&url, ""));
+        let scheme = base.split("://").next().unwrap_or_default();
+        let vcs = scheme
+            .split_once('+')
+            .map(|(vcs, _)| vcs)
+            .filter(|vcs| ["git", "hg", "svn", "bzr"].contains(vcs));
+        let direct_url = if let Some(vcs) = vcs {
+            let repository = &base[vcs.len() + 1..];
+            let (repository, commit) = repository
+                .rsplit_once('@')
+                .filter(|(_, rev)| !rev.contains('/'))
+                .unwrap_or((repository, ""));
+            let mut direct_url = json!({
+                "url": repository,
+                "vcs_info": {"vcs": vcs, "commit_id": commit},
+            });
+            if let Some(subdirectory) = fragment.strip_prefix("subdirectory=") {
+                direct_url["subdirectory"] = json!(subdirectory);
+            }
+            Some(direct_url)
+        } else if pkg.editable {
+            Some(json!({"url": base, "dir_info": {"editable": true}}))
+        } else if file_url_to_path(base).is_some_and(|path| path.is_dir()) {
+            Some(json!({"url": base, "dir_info": {}}))
+        } else if self.direct.contains(&normalize_dep_name(&pkg.name)) {
+            let mut direct_url = json!({"url": base, "archive_info": {}});
+            if !pkg.hash.is_empty() {
+                direct_url["archive_info"]["hashes"] = json!({"sha256": pkg.hash});
+            }
+            Some(direct_url)
+        } else {
+            None
+        };
+        let mut files = vec![(INSTALLER_FILE, "xe\n".to_string())];
+        match direct_url {
+            Some(direct_url) => files.push((DIRECT_URL_FILE, direct_url.to_string())),
+            None => {
+                let origin = if !pkg.index.is_empty() {
+                    Some(format!("index {}", strip_credentials(&pkg.index)))
+                } else if let Some(path) = file_url_to_path(base) {
+                    let dir = path.parent().unwrap_or(&path).display().to_string();
+                    Some(format!("find-links {dir}"))
+                } else {
+                    let host = url_host(base);
+                    self.indexes
+                        .iter()
+                        .skip(1)
+                        .find(|index| url_host(index) == host)
+                        .or(self.indexes.first())
+                        .map(|index| format!("index {}", strip_credentials(index)))
+                };
+                if let Some(origin) = origin {
+                    files.push((SOURCE_FILE, format!("{origin}\n")));
+                }
+            }
+        }
+        files
+    }
+}
+
+/// The host of `url`, empty when it has none.
+fn url_host(url: &str) -> &str {
+    let rest = url
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or_default();
+    let authority = rest.split('/').next().unwrap_or_default();
+    authority.rsplit('@').next().unwrap_or_default()
+}
+
+/// Writes `files` into the `dist_info` directory of `site_packages` and
+/// lists them in its RECORD, if it has one, so uninstalling the package
+/// removes them too.
+fn record_source(
+    site_packages: &Path,
+    dist_info: &str,
+    files: &[(&'static str, String)],
+) -> Result<()> {
+    let dir = site_packages.join(dist_info);
+    let mut record = String::new();
+    for (name, contents) in files {
+        let path = dir.join(name);
+        fs::write(&path, contents)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        record.push_str(&format!("{dist_info}/{name},,\n"));
+    }
+    let record_path = dir.join("RECORD");
+    if !record_path.exists() {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(&record_path)
+        .with_context(|| format!("failed to open {}", record_path.display()))?;
+    file.write_all(record.as_bytes())
+        .with_context(|| format!("failed to write {}", record_path.display()))?;
+    Ok(())
+}
+
+/// How many artifacts [`Installer::expect_lock`] fetches at once, leaving
+/// bandwidth and connections to the resolver.
+const SPECULATIVE_FETCHES: usize = 4;
+
+/// The locked wheel that best matches the receiving interpreter.
+fn best_artifact<'a>(artifacts: &'a [Artifact], tag_set: &TagSet) -> Option<&'a Artifact> {
+    artifacts
+        .iter()
+        .filter_map(|a| tag_set.rank(&a.filename).map(|rank| (rank, a)))
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, a)| a)
+}
+
+/// Built wheels are only valid for the interpreter and platform they were
+/// compiled on, so the most specific supported tag keys them. Empty when
+/// nothing needs building.
+fn build_tag(packages: &[Package], python_exe: &Path) -> Result<String> {
+    if !packages
+        .iter()
+        .any(|p| is_sdist_url(&p.download_url) || is_source_tree_url(&p.download_url))
+    {
+        return Ok(String::new());
+    }
+    let target = TargetEnv::probe(python_exe)?;
+    Ok(target
+        .supported_tags()
+        .into_iter()
+        .next()
+        .unwrap_or_default())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlannedAction {
+    Install,
+    Upgrade,
+    Downgrade,
+    Remove,
+}
+
+/// One line of a `--dry-run` plan.
+#[derive(Debug, Clone)]
+pub struct PlannedChange {
+    pub action: PlannedAction,
+    pub name: String,
+    pub from: String,
+    pub to: String,
+    /// `Some(0)` when the artifact is already cached, `None` when unknown.
+    pub download_bytes: Option<u64>,
+}
+
+impl PlannedChange {
+    pub fn remove(name: &str, version: &str) -> Self {
+        Self {
+            action: PlannedAction::Remove,
+            name: name.to_string(),
+            from: version.to_string(),
+            to: String::new(),
+            download_bytes: None,
+        }
+    }
+}
+
+/// Installed distributions by normalized identity: (name, version).
+pub fn installed_distributions(site_packages: &Path) -> Result<HashMap<String, (String, String)>> {
+    let mut out = HashMap::new();
+    if !site_packages.exists() {
+        return Ok(out);
+    }
+    for entry in fs::read_dir(site_packages)
+        .with_context(|| format!("failed to read {}", site_packages.display()))?
+    {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        let Some(base) = name.strip_suffix(".dist-info") else {
+            continue;
+        };
+        if let Some((dist, version)) = base.rsplit_once('-') {
+            out.insert(
+                normalize_dep_name(dist),
+                (dist.to_string(), version.to_string()),
+            );
+        }
+    }
+    Ok(out)
+}
+
+pub(crate) fn artifact_download_size(url: &str) -> Option<u64> {
+    if is_source_tree_url(url) {
+        return None;
+    }
+    if let Some(path) = file_url_to_path(url) {
+        return fs::metadata(path).ok().map(|m| m.len());
+    }
+    if url.trim().is_empty() {
+        return None;
+    }
+    let client = http_client(Duration::from_secs(30)).ok()?;
+    let resp = http_send(http_head(&client, url)).ok()?;
+    if !resp.status().is_success() {
+        return None;
+    }
+    resp.content_length().filter(|len| *len > 0)
+}
+
+pub const PYPI_SIMPLE_URL: &str = "https://pypi.org/simple";
+
+/// Index searched first: the project's `[resolution] index_url`, else the
+/// global default index, else PyPI.
+pub fn primary_index_url(cfg: &Config) -> Result<String> {
+    if !cfg.resolution.index_url.is_empty() {
+        return Ok(cfg.resolution.index_url.clone());
+    }
+    let default_index = &global_config().default_index;
+    if default_index.is_empty() {
+        return Ok(PYPI_SIMPLE_URL.to_string());
+    }
+    Ok(lookup_index_url(cfg, default_index).ok_or_else(|| {
+        anyhow!(
+            "default_index '{}' is not a URL or a known index",
+            default_index
+        )
+    })?)
+}
+
+/// Every index `cfg` installs from, the primary one first; none under
+/// `no_index`.
+pub fn index_urls(cfg: &Config) -> Result<Vec<String>> {
+    if cfg.resolution.no_index {
+        return Ok(Vec::new());
+    }
+    let mut urls = vec![primary_index_url(cfg)?];
+    for url in &cfg.resolution.extra_index_urls {
+        if !urls.contains(url) {
+            urls.push(url.clone());
+        }
+    }
+    Ok(urls)
+}
+
+/// Index options for pip: the project's `[resolution]`, the global default
+/// index when the project names none, and every index a dependency is
+/// pinned to.
+pub(crate) fn resolver_index_args(
+    cfg: &Config,
+    pins: &BTreeMap<String, (String, String)>,
+) -> Result<Vec<String>> {
+    let mut resolution = cfg.resolution.clone();
+    let primary = primary_index_url(cfg)?;
+    if resolution.index_url.is_empty() && !global_config().default_index.is_empty() {
+        resolution.index_url = primary.clone();
+    }
+    for (_, url) in pins.values() {
+        if *url != primary && !resolution.extra_index_urls.contains(url) {
+            resolution.extra_index_urls.push(url.clone());
+        }
+    }
+    Ok(resolution.pip_args())
+}
+
+/// How long the project list of an index is reused before it is fetched
+/// again.
+const INDEX_PROJECTS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Project names on the primary index matching `query`: exact match first,
+/// then prefix matches, then other substring matches, shortest first.
+pub fn search_index(cfg: &Config, query: &str, limit: usize) -> Result<Vec<String>> {
+    let index_url = primary_index_url(cfg)?;
+    let projects = index_projects(&index_url)?;
+    let needle = normalize_dep_name(query);
+    let mut hits = projects
+        .into_iter()
+        .filter_map(|name| {
+            let normalized = normalize_dep_name(&name);
+            let rank = if normalized == needle {
+                0
+            } else if normalized.starts_with(&needle) {
+                1
+            } else if normalized.contains(&needle) {
+                2
+            } else {
+                return None;
+            };
+            Some((rank, name.len(), name))
+        })
+        .collect::<Vec<_>>();
+    hits.sort();
+    Ok(hits
+        .into_iter()
+        .take(limit)
+        .map(|(_, _, name)| name)
+        .collect())
+}
+
+/// Every project name listed at the root of a simple index (PEP 691 JSON,
+/// or the PEP 503 HTML page), cached under the xe cache for a day.
+fn index_projects(index_url: &str) -> Result<Vec<String>> {
+    let cache_file = xe_cache_dir().join("index-projects").join(format!(
+        "{}.json",
+        hex::encode(Sha1::digest(index_url.as_bytes()))
+    ));
+    let fresh = fs::metadata(&cache_file)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < INDEX_PROJECTS_TTL);
+    if fresh {
+        if let Some(names) = fs::read(&cache_file)
+            .ok()
+            .and_then(|raw| serde_json::from_slice(&raw).ok())
+        {
+            return Ok(names);
+        }
+    }
+
+    info(&format!("Fetching project list from {index_url}..."));
+    let client = http_client(Duration::from_secs(120))?;
+    let url = format!("{}/", index_url.trim_end_matches('/'));
+    let request = http_get(&client, &url).header(
+        "Accept",
+        "application/vnd.pypi.simple.v1+json, text/html;q=0.1",
+    );
+    let resp = send_with_retry(request, &url)
+        .with_context(|| format!("failed to query {url}"))
+        .kind(ErrorKind::Network)?;
+    if !resp.status().is_success() {
+        bail!(
+            kind = ErrorKind::Network,
+            "{} returned {}",
+            url,
+            resp.status()
+        );
+    }
+    let is_json = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("json"));
+    let body = resp
+        .text()
+        .with_context(|| format!("failed to read {url}"))
+        .kind(ErrorKind::Network)?;
+    let names = if is_json {
+        let parsed: Value =
+            serde_json::from_str(&body).with_context(|| format!("invalid index page at {url}"))?;
+        parsed["projects"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|project| project["name"].as_str().map(str::to_string))
+            .collect::<Vec<_>>()
+    } else {
+        body.split("</a>")
+            .filter_map(|chunk| chunk.rsplit('>').next())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty() && !name.contains('<'))
+            .collect()
+    };
+
+    if let Some(parent) = cache_file.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let encoded = serde_json::to_vec(&names).context("failed to encode project list")?;
+    fs::write(&cache_file, encoded)
+        .with_context(|| format!("failed to write {}", cache_file.display()))?;
+    Ok(names)
+}
+
+/// pip arguments used for every sdist build; part of the built-wheel key.
+pub(crate) const SDIST_BUILD_OPTIONS: &[&str] = &["--no-deps", "--no-cache-dir"];
+
+/// A VCS checkout (`git+https://...@<commit>`) or a local directory, built
+/// into a wheel before installing.
+pub(crate) fn is_source_tree_url(url: &str) -> bool {
+    let scheme = url.split("://").next().unwrap_or_default();
+    ["git+", "hg+", "svn+", "bzr+"]
+        .iter()
+        .any(|prefix| scheme.starts_with(prefix))
+        || file_url_to_path(url).is_some_and(|path| path.is_dir())
+}
+
+/// The digest a CAS blob is stored under.
+fn blob_digest(blob: &Path) -> String {
+    blob.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub(crate) fn is_sdist_url(url: &str) -> bool {
+    let path = url
+        .split(['#', '?'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    path.ends_with(".tar.gz")
+        || path.ends_with(".tar.bz2")
+        || path.ends_with(".zip")
+        || path.ends_with(".tgz")
+}
+
+pub(crate) fn find_wheel_in(dir: &Path) -> Result<Option<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("whl") {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+pub(crate) fn normalize_requirements(reqs: &[String]) -> Vec<String> {
+    let mut out = reqs
+        .iter()
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty())
+        .collect::<Vec<_>>();
+    out.sort();
+    out
+}
+
+/// Identifies the interpreter for solution caching: wheel tags differ between
+/// CPython, free-threaded CPython and PyPy even at the same version number.
+pub(crate) fn solve_python_tag(python_version: &str) -> String {
+    match PythonSpec::parse(python_version) {
+        Ok(spec) => format!("{}-{}", spec.interpreter_tag(), spec.numeric()),
+        Err(_) => python_version.trim().to_string(),
+    }
+}
+
+/// How solutions are produced. Bump it when the resolver changes, so
+/// graphs cached by the old one are not reused.
+const SOLVE_STRATEGY: &str = "pip-report-v2";
+
+/// Everything besides the Python version that can change a solution: the
+/// requirements, index options, constraints, platform and strategy.
+fn solve_key_parts(cfg: &Config, reqs: &[String], pip_options: &[String]) -> Vec<String> {
+    let mut parts = reqs.to_vec();
+    parts.extend(pip_options.iter().map(|o| format!("index:{o}")));
+    parts.extend(
+        cfg.resolution
+            .constraints
+            .iter()
+            .map(|c| format!("constraint:{c}")),
+    );
+    parts.push(format!(
+        "platform:{}-{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    ));
+    parts.push(format!("strategy:{SOLVE_STRATEGY}"));
+    parts
+}
+
+pub(crate) fn solve_key(python_version: &str, reqs: &[String]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(python_version.as_bytes());
+    hasher.update(b"|");
+    for req in reqs {
+        hasher.update(req.as_bytes());
+        hasher.update(b"|");
+    }
+    hex::encode(hasher.finalize())
+}
+
+pub(crate) fn dedupe_packages(pkgs: Vec<Package>) -> Vec<Package> {
+    let mut seen = BTreeMap::new();
+    for pkg in pkgs {
+        let key = package_identity_key(&pkg.name, &pkg.version);
+        seen.insert(key, pkg);
+    }
+    seen.into_values().collect()
+}
+
+pub(crate) fn package_identity_key(name: &str, version: &str) -> String {
+    format!("{}=={}", normalize_dep_name(name), version.trim())
+}
+
+pub(crate) fn installed_package_key_set(site_packages: &Path) -> Result<HashSet<String>> {
+    let mut out = HashSet::new();
+    if !site_packages.exists() {
+        return Ok(out);
+    }
+    for entry in fs::read_dir(site_packages)
+        .with_context(|| format!("failed to read {}", site_packages.display()))?
+    {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.to_lowercase().ends_with(".dist-info") {
+            continue;
+        }
+        let base = name.trim_end_matches(".dist-info");
+        if let Some(idx) = base.rfind('-') {
+            if idx > 0 && idx + 1 < base.len() {
+                let key = package_identity_key(&base[..idx], &base[idx + 1..]);
+                out.insert(key);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Unpacks a wheel into `site_packages` and returns the `.dist-info`
+/// directory it installed.
+pub(crate) fn install_wheel_blob(blob_path: &Path, site_packages: &Path) -> Result<Option<String>> {
+    fs::create_dir_all(site_packages)
+        .with_context(|| format!("failed to create {}", site_packages.display()))?;
+    let file =
+        File::open(blob_path).with_context(|| format!("failed to open {}", blob_path.display()))?;
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("failed to parse {}", blob_path.display()))?;
+    let dist_info = wheel_dist_info(&archive);
+    let mut extractor = ZipExtractor::new(site_packages, archive.len())?;
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .with_context(|| format!("failed to read entry {}", index))?;
+        extractor.extract(&mut entry)?;
+    }
+    extractor.finish()?;
+    if let Some(dist_info) = &dist_info {
+        remove_stale_dist_info(site_packages, dist_info)?;
+    }
+    Ok(dist_info)
+}
+
+/// The `*.dist-info` directory a wheel installs.
+fn wheel_dist_info(archive: &ZipArchive<File>) -> Option<String> {
+    archive.file_names().find_map(|name| {
+        let (first, _) = name.split_once('/')?;
+        first.ends_with(".dist-info").then(|| first.to_string())
+    })
+}
+
+/// Removes the `.dist-info` of other versions of the project `dist_info`
+/// belongs to, so an upgrade does not leave the project installed twice.
+fn remove_stale_dist_info(site_packages: &Path, dist_info: &str) -> Result<()> {
+    let Some(project) = metadata_dir_project(dist_info) else {
+        return Ok(());
+    };
+    for entry in fs::read_dir(site_packages)
+        .with_context(|| format!("failed to read {}", site_packages.display()))?
+    {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name != dist_info
+            && name.ends_with(".dist-info")
+            && metadata_dir_project(&name).as_deref() == Some(project.as_str())
+        {
+            remove_dir_all(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Files that `wheels` would write over a different copy owned by another
+/// project, whether installed already or in the same batch. Files a project
+/// replaces in its own upgrade, and identical copies, do not count.
+fn find_collisions(
+    site_packages: &Path,
+    wheels: &[(&Package, &Path)],
+) -> Result<Vec<FileCollision>> {
+    let mut owners: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    let upgraded: HashSet<String> = wheels
+        .iter()
+        .map(|(pkg, _)| normalize_dep_name(&pkg.name))
+        .collect();
+    for (project, path, hash) in record_entries(site_packages)? {
+        // Superseded by the new version's RECORD below.
+        if !upgraded.contains(&project) {
+            owners.entry(path).or_default().push((project, hash));
+        }
+    }
+    for (pkg, wheel) in wheels {
+        let file =
+            File::open(wheel).with_context(|| format!("failed to open {}", wheel.display()))?;
+        let mut archive =
+            ZipArchive::new(file).with_context(|| format!("failed to parse {}", wheel.display()))?;
+        let Some(dist_info) = wheel_dist_info(&archive) else {
+            continue;
+        };
+        let mut record = String::new();
+        match archive.by_name(&format!("{dist_info}/RECORD")) {
+            Ok(mut entry) => {
+                entry
+                    .read_to_string(&mut record)
+                    .with_context(|| format!("failed to read RECORD of {}", wheel.display()))?;
+            }
+            Err(_) => continue,
+        }
+        let project = normalize_dep_name(&pkg.name);
+        for (path, hash) in parse_record(&record) {
+            owners.entry(path).or_default().push((project.clone(), hash));
+        }
+    }
+
+    let mut collisions: Vec<FileCollision> = owners
+        .into_iter()
+        .filter_map(|(path, claims)| {
+            let projects: BTreeSet<&str> = claims.iter().map(|(p, _)| p.as_str()).collect();
+            let hashes: HashSet<&str> = claims.iter().map(|(_, h)| h.as_str()).collect();
+            (projects.len() > 1 && hashes.len() > 1).then(|| FileCollision {
+                owners: projects.into_iter().map(str::to_string).collect(),
+                path,
+            })
+        })
+        .collect();
+    collisions.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(collisions)
+}
+
+/// Makes a local source tree importable from `site_packages` through a `.pth`
+/// file, preferring a `src/` layout when the project has one, next to a
+/// `.dist-info` that lists the package and lets pip uninstall it. Returns
+/// that directory's name.
+pub(crate) fn install_editable(
+    name: &str,
+    version: &str,
+    source: &Path,
+    site_packages: &Path,
+) -> Result<String> {
+    // The import name, which unlike the project name cannot hold dashes.
+    let module = normalize_dep_name(name).replace('-', "_");
+    let root = if source.join("src").is_dir() && !source.join(&module).is_dir() {
+        source.join("src")
+    } else {
+        source.to_path_buf()
+    };
+    let pth_name = format!("__editable__.{module}.pth");
+    let pth = site_packages.join(&pth_name);
+    fs::write(&pth, format!("{}\n", root.display()))
+        .with_context(|| format!("failed to write {}", pth.display()))?;
+
+    let dist_info = format!("{module}-{version}.dist-info");
+    let dir = site_packages.join(&dist_info);
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let metadata = dir.join("METADATA");
+    fs::write(
+        &metadata,
+        format!("Metadata-Version: 2.1\nName: {name}\nVersion: {version}\n"),
+    )
+    .with_context(|| format!("failed to write {}", metadata.display()))?;
+    let record = dir.join("RECORD");
+    fs::write(
+        &record,
+        format!("{pth_name},,\n{dist_info}/METADATA,,\n{dist_info}/RECORD,,\n"),
+    )
+    .with_context(|| format!("failed to write {}", record.display()))?;
+    remove_stale_dist_info(site_packages, &dist_info)?;
+    Ok(dist_info)
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PipReport {
+    #[serde(default)]
+    pub(crate) install: Vec<PipInstallItem>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PipInstallItem {
+    pub(crate) metadata: PipMetadata,
+    #[serde(default)]
+    pub(crate) download_info: PipDownloadInfo,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PipMetadata {
+    pub(crate) name: String,
+    pub(crate) version: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub(crate) struct PipDownloadInfo {
+    #[serde(default)]
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) archive_info: PipArchiveInfo,
+    #[serde(default)]
+    pub(crate) dir_info: PipDirInfo,
+    #[serde(default)]
+    pub(crate) vcs_info: Option<PipVcsInfo>,
+    #[serde(default)]
+    pub(crate) subdirectory: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PipVcsInfo {
+    pub(crate) vcs: String,
+    pub(crate) commit_id: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub(crate) struct PipDirInfo {
+    #[serde(default)]
+    pub(crate) editable: bool,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub(crate) struct PipArchiveInfo {
+    #[serde(default)]
+    pub(crate) hashes: HashMap<String, String>,
+}
+
+pub(crate) fn resolve_requirement(
+    requirement: &str,
+    python_exe: &Path,
+    pip_options: &[String],
+    markers: Option<&str>,
+    limits: &ResolveLimits,
+) -> Result<Vec<Package>> {
+    let report_file = tempfile_path("xe-report", "json");
+    let mut command = Command::new(python_exe);
+    command.arg("-c").arg(PIP_RESOLVE_SCRIPT).arg("install");
+    if let Some(markers) = markers {
+        // pip before 23.1 takes platform options only next to --target;
+        // with --dry-run nothing is written there.
+        command
+            .env(MARKER_ENV_VAR, markers)
+            .arg("--target")
+            .arg(std::env::temp_dir().join("xe-cross-target"));
+    }
+    match requirement.strip_prefix("-e ") {
+        Some(target) => command.arg("--editable").arg(target.trim()),
+        None => command.arg(requirement),
+    };
+    apply_network_env(&mut command);
+    let child = command
+        .args(pip_options)
+        .arg("--dry-run")
+        // Report the whole graph, not just what the interpreter lacks.
+        .arg("--ignore-installed")
+        .arg("--report")
+        .arg(&report_file)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("dependency resolution failed for {requirement}"))?;
+    let run = watch_resolver(child, limits)
+        .with_context(|| format!("dependency resolution failed for {requirement}"))?;
+    let status = match run.status {
+        Some(status) => status,
+        None => {
+            let _ = fs::remove_file(&report_file);
+            let hint = if run.backtracks.is_empty() {
+                "raise the limit with --resolution-timeout if the index is just slow"
+            } else {
+                "constrain the packages above (a version range in [deps] or [resolution] constraints) or raise the limit with --max-backtracks / --resolution-timeout"
+            };
+            bail!(
+                kind = ErrorKind::Resolve,
+                "dependency resolution for {} {}{}\n{}",
+                requirement,
+                run.stopped,
+                backtrack_summary(&run.backtracks),
+                hint
+            );
+        }
+    };
+    if !status.success() {
+        let hint = if run.stderr.contains("No matching distribution found") {
+            requires_python_hint(requirement, python_exe, pip_options)
+                .or_else(|| {
+                    let name = requirement_to_dep_name(requirement)?;
+                    ModuleMap::load().missing_package_hint(&name)
+                })
+                .map(|hint| format!("\n{hint}"))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        bail!(
+            kind = ErrorKind::Resolve,
+            "dependency resolution failed for {}: {}\n{}{}{}{}",
+            requirement,
+            status,
+            run.stdout,
+            run.stderr,
+            hint,
+            backtrack_summary(&run.backtracks)
+        );
+    }
+    let report_data = fs::read(&report_file)
+        .with_context(|| format!("failed to read pip report {}", report_file.display()))?;
+    let _ = fs::remove_file(&report_file);
+    let sanitized = sanitize_json(&report_data);
+    let report: PipReport = serde_json::from_slice(&sanitized)
+        .with_context(|| format!("failed to parse pip report for {}", requirement))?;
+    let mut packages = Vec::with_capacity(report.install.len());
+    for item in report.install {
+        let hash = item
+            .download_info
+            .archive_info
+            .hashes
+            .get("sha256")
+            .cloned()
+            .unwrap_or_default();
+        let info = item.download_info;
+        // Pin VCS checkouts to the commit pip resolved, so the lock and the
+        // build cache never follow a moving branch.
+        let download_url = match &info.vcs_info {
+            Some(vcs) => {
+                let mut url = format!("{}+{}@{}", vcs.vcs, info.url, vcs.commit_id);
+                if !info.subdirectory.is_empty() {
+                    url.push_str(&format!("#subdirectory={}", info.subdirectory));
+                }
+                url
+            }
+            None => info.url.clone(),
+        };
+        packages.push(Package {
+            name: item.metadata.name,
+            version: item.metadata.version,
+            download_url,
+            hash,
+            editable: info.dir_info.editable,
+            artifacts: Vec::new(),
+            index: String::new(),
+        });
+    }
+    Ok(packages)
+}
+
+/// Marker environment the resolver evaluates requirements against, as
+/// JSON, when it resolves for another target.
+const MARKER_ENV_VAR: &str = "XE_MARKER_ENVIRONMENT";
+
+/// Runs pip with its resolver reporter patched to print one marker line to
+/// stderr per rejected candidate, which is how [`watch_resolver`] counts
+/// backtracking. Pips without the hook just run unpatched.
+const PIP_RESOLVE_SCRIPT: &str = r#"
+import json, os, sys
+_markers = os.environ.get("XE_MARKER_ENVIRONMENT")
+if _markers:
+    from pip._vendor.packaging import markers as _packaging_markers
+    _environment = json.loads(_markers)
+    _packaging_markers.default_environment = lambda: dict(_environment)
+try:
+    from pip._internal.resolution.resolvelib.reporter import PipReporter
+
+    def _counted(hook):
+        def wrapped(self, *args):
+            sys.stderr.write("xe-backtrack %s\n" % args[-1].name)
+            sys.stderr.flush()
+            return hook(self, *args)
+        return wrapped
+
+    for _name in ("rejecting_candidate", "backtracking"):
+        if _name in vars(PipReporter):
+            setattr(PipReporter, _name, _counted(vars(PipReporter)[_name]))
+            break
+except Exception:
+    pass
+from pip._internal.cli.main import main
+sys.exit(main())
+"#;
+
+const BACKTRACK_MARKER: &str = "xe-backtrack ";
+
+/// Rejected candidates allowed per requirement by default. Ordinary graphs
+/// stay in the tens; thousands means pip is walking release histories.
+const DEFAULT_MAX_BACKTRACKS: usize = 2000;
+
+/// How a watched pip resolution ended.
+struct ResolverRun {
+    /// `None` when pip was stopped for exceeding a limit.
+    status: Option<ExitStatus>,
+    /// Why pip was stopped, e.g. "timed out after 60s".
+    stopped: String,
+    stdout: String,
+    stderr: String,
+    /// Rejected candidates per package.
+    backtracks: HashMap<String, usize>,
+}
+
+/// Waits for `child` while counting its backtracking markers, killing it
+/// once `limits` are exceeded.
+fn watch_resolver(mut child: Child, limits: &ResolveLimits) -> Result<ResolverRun> {
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let total = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let (stdout_tx, stdout_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut text = String::new();
+        if let Some(mut pipe) = stdout {
+            let _ = pipe.read_to_string(&mut text);
+        }
+        let _ = stdout_tx.send(text);
+    });
+    let (stderr_tx, stderr_rx) = mpsc::channel();
+    let counted = Arc::clone(&total);
+    std::thread::spawn(move || {
+        let mut text = String::new();
+        let mut backtracks = HashMap::<String, usize>::new();
+        if let Some(pipe) = stderr {
+            for line in BufReader::new(pipe).lines().map_while(|line| line.ok()) {
+                match line.strip_prefix(BACKTRACK_MARKER) {
+                    Some(name) => {
+                        *backtracks.entry(name.trim().to_string()).or_default() += 1;
+                        counted.fetch_add(1, AtomicOrdering::Relaxed);
+                    }
+                    None => {
+                        text.push_str(&line);
+                        text.push('\n');
+                    }
+                }
+            }
+        }
+        let _ = stderr_tx.send((text, backtracks));
+    });
+
+    let mut stopped = String::new();
+    let status = loop {
+        if let Some(status) = child.try_wait().context("failed to wait for pip")? {
+            break Some(status);
+        }
+        let rejected = total.load(AtomicOrdering::Relaxed);
+        if limits.max_backtracks > 0 && rejected > limits.max_backtracks {
+            stopped = format!(
+                "gave up after {} backtracks (limit {})",
+                rejected, limits.max_backtracks
+            );
+        } else if let Some(timeout) = limits.timeout.filter(|t| started.elapsed() > *t) {
+            stopped = format!("timed out after {}s", timeout.as_secs());
+        }
+        if !stopped.is_empty() {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    // A build backend may leave a process behind that holds the pipes open;
+    // its readers are abandoned rather than waited for.
+    let deadline = Instant::now() + PIPE_DRAIN_TIMEOUT;
+    let (stderr, backtracks) = stderr_rx
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .unwrap_or_default();
+    Ok(ResolverRun {
+        status,
+        stopped,
+        stdout: stdout_rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .unwrap_or_default(),
+        stderr,
+        backtracks,
+    })
+}
+
+/// The packages pip backtracked on most, as a line to append to an error.
+fn backtrack_summary(backtracks: &HashMap<String, usize>) -> String {
+    if backtracks.is_empty() {
+        return String::new();
+    }
+    let mut ranked = backtracks.iter().collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let top = ranked
+        .iter()
+        .take(5)
+        .map(|(name, count)| format!("{name} ({count})"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "\nmost backtracked ({} candidates rejected): {}",
+        backtracks.values().sum::<usize>(),
+        top
+    )
+}
+
+/// pip skips releases whose Requires-Python excludes the interpreter
+/// without saying so, which makes "no matching distribution" look like a
+/// missing package. Looks the project up on the index and, when releases
+/// matching `requirement` were skipped for that reason, says which and
+/// what the newest usable one is.
+fn requires_python_hint(
+    requirement: &str,
+    python_exe: &Path,
+    pip_options: &[String],
+) -> Option<String> {
+    if requirement.starts_with("-e ")
+        || requirement.contains('@')
+        || pip_options.iter().any(|option| option == "--no-index")
+    {
+        return None;
+    }
+    let name = requirement_to_dep_name(requirement)?;
+    let requirement = requirement.split(';').next().unwrap_or_default();
+    let specifier = requirement
+        .find(|c: char| "<>=!~".contains(c))
+        .map_or("", |idx| &requirement[idx..]);
+    let index_url = pip_options
+        .iter()
+        .position(|option| option == "--index-url")
+        .and_then(|idx| pip_options.get(idx + 1))
+        .map_or(PYPI_SIMPLE_URL, String::as_str);
+    let python = TargetEnv::probe(python_exe).ok()?.full_version;
+
+    // Version to (some file admits the interpreter, a Requires-Python that
+    // shut a file out).
+    let mut releases: HashMap<String, (bool, String)> = HashMap::new();
+    for (filename, requires_python) in index_files(index_url, &name).ok()? {
+        let Some(version) = release_version(&filename) else {
+            continue;
+        };
+        let release = releases.entry(version).or_default();
+        if specifier_allows(&requires_python, &python) {
+            release.0 = true;
+        } else if release.1.is_empty() {
+            release.1 = requires_python;
+        }
+    }
+    let mut skipped = releases
+        .iter()
+        .filter(|(version, (usable, _))| !usable && specifier_allows(specifier, version))
+        .collect::<Vec<_>>();
+    skipped.sort_by(|a, b| compare_version(b.0, a.0));
+    let (newest, (_, requires_python)) = skipped.first()?;
+    let mut hint = format!(
+        "{} release(s) of {} were skipped because their Requires-Python excludes Python {}; {} {} requires Python {}",
+        skipped.len(),
+        name,
+        python,
+        name,
+        newest,
+        requires_python
+    );
+    // Possibly outside the requested range; relaxing the pin is one fix.
+    match releases
+        .iter()
+        .filter(|(_, (usable, _))| *usable)
+        .map(|(version, _)| version)
+        .max_by(|a, b| compare_version(a, b))
+    {
+        Some(version) => hint.push_str(&format!(
+            ". The newest release that supports Python {python} is {version}"
+        )),
+        None => hint.push_str(&format!(
+            ". No release supports Python {python}; change [python] version in xe.toml"
+        )),
+    }
+    Some(hint)
+}
+
+/// `(filename, requires-python)` of every file the index lists for
+/// `project`, from the PEP 691 JSON page or the PEP 503 HTML one.
+fn index_files(index_url: &str, project: &str) -> Result<Vec<(String, String)>> {
+    let client = http_client(Duration::from_secs(30))?;
+    let url = format!("{}/{}/", index_url.trim_end_matches('/'), project);
+    let request = http_get(&client, &url).header(
+        "Accept",
+        "application/vnd.pypi.simple.v1+json, text/html;q=0.1",
+    );
+    let resp = send_with_retry(request, &url)
+        .with_context(|| format!("failed to query {url}"))
+        .kind(ErrorKind::Network)?;
+    if !resp.status().is_success() {
+        bail!(
+            kind = ErrorKind::Network,
+            "{} returned {}",
+            url,
+            resp.status()
+        );
+    }
+    let is_json = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("json"));
+    let body = resp
+        .text()
+        .with_context(|| format!("failed to read {url}"))
+        .kind(ErrorKind::Network)?;
+    if is_json {
+        let parsed: Value =
+            serde_json::from_str(&body).with_context(|| format!("invalid index page at {url}"))?;
+        return Ok(parsed["files"]
+            .as_array()
+            .map(|files| {
+                files
+                    .iter()
+                    .filter_map(|file| {
+                        let filename = file["filename"].as_str()?.to_string();
+                        let requires = file["requires-python"].as_str().unwrap_or_default();
+                        Some((filename, requires.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default());
+    }
+    Ok(body
+        .split("<a ")
+        .skip(1)
+        .filter_map(|anchor| {
+            let (attrs, rest) = anchor.split_once('>')?;
+            let filename = rest.split("</a>").next()?.trim().to_string();
+            let requires = attrs
+                .split_once("data-requires-python=\"")
+                .and_then(|(_, value)| value.split_once('"'))
+                .map(|(value, _)| {
+                    value
+                        .replace("&gt;", ">")
+                        .replace("&lt;", "<")
+                        .replace("&amp;", "&")
+                })
+                .unwrap_or_default();
+            Some((filename, requires))
+        })
+        .collect())
+}
+
+/// `(version, requires-python)` of every file the index lists for
+/// `project`; a release appears once per file.
+pub fn index_releases(index_url: &str, project: &str) -> Result<Vec<(String, String)>> {
+    Ok(index_files(index_url, project)?
+        .into_iter()
+        .filter_map(|(filename, requires)| Some((release_version(&filename)?, requires)))
+        .collect())
+}
+
+/// Version of a wheel or sdist from its file name.
+fn release_version(filename: &str) -> Option<String> {
+    if let Some((_, version)) = parse_wheel_filename(filename) {
+        return Some(version);
+    }
+    let stem = [".tar.gz", ".tar.bz2", ".tgz", ".zip"]
+        .iter()
+        .find_map(|ext| filename.strip_suffix(ext))?;
+    stem.rsplit_once('-')
+        .map(|(_, version)| version.to_string())
+}
+
+pub(crate) fn sanitize_json(data: &[u8]) -> Vec<u8> {
+    let trimmed = trim_json_start(data);
+    if trimmed.is_empty() {
+        return data.to_vec();
+    }
+    let mut de = serde_json::Deserializer::from_slice(trimmed);
+    match Value::deserialize(&mut de) {
+        Ok(value) => serde_json::to_vec(&value).unwrap_or_else(|_| trimmed.to_vec()),
+        Err(_) => trimmed.to_vec(),
+    }
+}
+
+pub(crate) fn trim_json_start(data: &[u8]) -> &[u8] {
+    match data.iter().position(|b| *b == b'{' || *b == b'[') {
+        Some(idx) => &data[idx..],
+        None => data,
+    }
+}
@@ -0,0 +1,541 @@
+//! Metadata of the distributions installed in an environment, read from
+//! `*.dist-info` / `*.egg-info` without starting Python and cached per
+//! site-packages directory.
+//!
+//! Installing, upgrading or removing a distribution adds, renames or
+//! deletes its metadata directory, which changes the mtime of
+//! site-packages; the cache is only reused while that mtime matches.
+
+use crate::config::normalize_dep_name;
+use crate::error::Result;
+use crate::install::file_url_to_path;
+use crate::paths::create_dir_all;
+use crate::paths::remove_file;
+use crate::paths::rename;
+use crate::paths::tempfile_path;
+use crate::paths::xe_cache_dir;
+use crate::runtime::apply_runtime_env;
+use crate::runtime::RuntimeSelection;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::UNIX_EPOCH;
+
+const CACHE_VERSION: u32 = 4;
+
+/// Who installed a distribution, per the packaging spec.
+pub(crate) const INSTALLER_FILE: &str = "INSTALLER";
+/// PEP 610 record of a distribution installed from a URL, VCS or local
+/// tree rather than an index.
+pub(crate) const DIRECT_URL_FILE: &str = "direct_url.json";
+/// xe's record of the index or find-links location a distribution came
+/// from, which PEP 610 leaves out: `index <url>` or `find-links <url>`.
+pub(crate) const SOURCE_FILE: &str = "XE_SOURCE";
+
+/// Imports each module named in the JSON list at argv[1] and appends one
+/// JSON line per module to argv[2], flushed before the next import so a
+/// crash still leaves the earlier results.
+const IMPORT_SCRIPT: &str = r#"
+import importlib, json, sys
+with open(sys.argv[1], encoding="utf-8") as f:
+    modules = json.load(f)
+with open(sys.argv[2], "a", encoding="utf-8") as out:
+    for name in modules:
+        try:
+            importlib.import_module(name)
+            error = None
+        except BaseException as exc:
+            error = f"{type(exc).__name__}: {exc}"
+        out.write(json.dumps(error) + "\n")
+        out.flush()
+"#;
+
+/// One installed distribution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledDist {
+    pub name: String,
+    pub version: String,
+    /// `Requires-Dist` lines, markers included.
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Entry point group to `name = object reference` entries.
+    #[serde(default)]
+    pub entry_points: BTreeMap<String, BTreeMap<String, String>>,
+    /// Importable top-level modules and packages.
+    #[serde(default)]
+    pub top_level: Vec<String>,
+    /// `Requires-Python`, empty when the release does not declare one.
+    #[serde(default)]
+    pub requires_python: String,
+    #[serde(default)]
+    pub source: DistSource,
+}
+
+/// Where an installed distribution came from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DistSource {
+    /// `index`, `find-links`, `url`, `path`, `editable` or the VCS, such as
+    /// `git`; empty when nothing recorded it.
+    #[serde(default)]
+    pub kind: String,
+    /// The index, artifact URL, `repository@commit` or directory.
+    #[serde(default)]
+    pub location: String,
+    /// What `INSTALLER` names, such as `xe` or `pip`.
+    #[serde(default)]
+    pub installer: String,
+}
+
+impl DistSource {
+    /// `<kind> <location>`, or who installed it when that is all there is.
+    pub fn describe(&self) -> String {
+        match (self.kind.as_str(), self.installer.as_str()) {
+            ("", "") => "unknown".to_string(),
+            ("", installer) => format!("installed by {installer}"),
+            (kind, _) if self.location.is_empty() => kind.to_string(),
+            (kind, _) => format!("{kind} {}", self.location),
+        }
+    }
+}
+
+/// A project with more than one metadata directory, usually left behind by
+/// another installer.
+#[derive(Debug, Clone)]
+pub struct DuplicateDist {
+    pub name: String,
+    /// Directory names, sorted.
+    pub dirs: Vec<String>,
+}
+
+/// A file that several projects ship with different contents; whichever
+/// was installed last won.
+#[derive(Debug, Clone)]
+pub struct FileCollision {
+    /// Relative to site-packages, as written in RECORD.
+    pub path: String,
+    /// Normalized project names, sorted.
+    pub owners: Vec<String>,
+}
+
+/// A module of an installed distribution that could not be imported.
+#[derive(Debug, Clone)]
+pub struct ImportFailure {
+    pub package: String,
+    pub module: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedEnv {
+    version: u32,
+    site_packages: String,
+    mtime_ns: u128,
+    dists: Vec<InstalledDist>,
+}
+
+/// Distributions in `site_packages`, sorted by name; from the cache when
+/// nothing was installed or removed since it was written.
+pub fn installed_metadata(site_packages: &Path) -> Result<Vec<InstalledDist>> {
+    let Some(mtime_ns) = dir_mtime_ns(site_packages) else {
+        return Ok(Vec::new());
+    };
+    let cache_file = xe_cache_dir().join("env-metadata").join(format!(
+        "{}.json",
+        hex::encode(Sha1::digest(site_packages.to_string_lossy().as_bytes()))
+    ));
+    let cached = fs::read(&cache_file)
+        .ok()
+        .and_then(|raw| serde_json::from_slice::<CachedEnv>(&raw).ok())
+        .filter(|cached| cached.version == CACHE_VERSION && cached.mtime_ns == mtime_ns);
+    if let Some(cached) = cached {
+        return Ok(cached.dists);
+    }
+
+    let dists = scan_site_packages(site_packages)?;
+    let entry = CachedEnv {
+        version: CACHE_VERSION,
+        site_packages: site_packages.display().to_string(),
+        mtime_ns,
+        dists,
+    };
+    // A cache that cannot be written only costs the next call a rescan.
+    let _ = write_cache(&cache_file, &entry);
+    Ok(entry.dists)
+}
+
+fn dir_mtime_ns(path: &Path) -> Option<u128> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+fn write_cache(cache_file: &Path, entry: &CachedEnv) -> Result<()> {
+    if let Some(parent) = cache_file.parent() {
+        create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let tmp = cache_file.with_extension(format!("tmp-{}", std::process::id()));
+    let encoded = serde_json::to_vec(entry).context("failed to encode metadata cache")?;
+    fs::write(&tmp, encoded).with_context(|| format!("failed to write {}", tmp.display()))?;
+    rename(&tmp, cache_file)
+        .with_context(|| format!("failed to write {}", cache_file.display()))?;
+    Ok(())
+}
+
+fn scan_site_packages(site_packages: &Path) -> Result<Vec<InstalledDist>> {
+    let mut dists = BTreeMap::new();
+    for entry in fs::read_dir(site_packages)
+        .with_context(|| format!("failed to read {}", site_packages.display()))?
+    {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let metadata_file = if name.ends_with(".dist-info") {
+            path.join("METADATA")
+        } else if name.ends_with(".egg-info") && path.is_dir() {
+            path.join("PKG-INFO")
+        } else if name.ends_with(".egg-info") {
+            path.clone()
+        } else {
+            continue;
+        };
+        let Ok(text) = fs::read_to_string(&metadata_file) else {
+            continue;
+        };
+        let Some(mut dist) = parse_metadata(&text) else {
+            continue;
+        };
+        if let Ok(text) = fs::read_to_string(path.join("entry_points.txt")) {
+            dist.entry_points = parse_entry_points(&text);
+        }
+        dist.top_level = top_level_modules(&path);
+        dist.source = read_dist_source(&path);
+        dists.insert(normalize_dep_name(&dist.name), dist);
+    }
+    Ok(dists.into_values().collect())
+}
+
+/// The normalized project of a `*.dist-info` or `*.egg-info` directory
+/// name.
+pub(crate) fn metadata_dir_project(dir_name: &str) -> Option<String> {
+    let base = dir_name
+        .strip_suffix(".dist-info")
+        .or_else(|| dir_name.strip_suffix(".egg-info"))?;
+    let name = base.split('-').next().filter(|name| !name.is_empty())?;
+    Some(normalize_dep_name(name))
+}
+
+/// Metadata directories in `site_packages` with their normalized project.
+fn metadata_dirs(site_packages: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut dirs = Vec::new();
+    if !site_packages.is_dir() {
+        return Ok(dirs);
+    }
+    for entry in fs::read_dir(site_packages)
+        .with_context(|| format!("failed to read {}", site_packages.display()))?
+    {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some(project) = metadata_dir_project(&name) {
+            dirs.push((project, path));
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Projects in `site_packages` with several `.dist-info`/`.egg-info`
+/// directories, e.g. after pip or a manual copy installed a second version
+/// next to the first.
+pub fn duplicate_distributions(site_packages: &Path) -> Result<Vec<DuplicateDist>> {
+    let mut by_project: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (project, path) in metadata_dirs(site_packages)? {
+        let dir = path.file_name().unwrap_or_default().to_string_lossy();
+        by_project.entry(project).or_default().push(dir.to_string());
+    }
+    Ok(by_project
+        .into_iter()
+        .filter(|(_, dirs)| dirs.len() > 1)
+        .map(|(name, dirs)| DuplicateDist { name, dirs })
+        .collect())
+}
+
+/// `(project, path, hash)` for every file listed in a RECORD in
+/// `site_packages`.
+pub(crate) fn record_entries(site_packages: &Path) -> Result<Vec<(String, String, String)>> {
+    let mut entries = Vec::new();
+    for (project, path) in metadata_dirs(site_packages)? {
+        let Ok(text) = fs::read_to_string(path.join("RECORD")) else {
+            continue;
+        };
+        for (file, hash) in parse_record(&text) {
+            entries.push((project.clone(), file, hash));
+        }
+    }
+    Ok(entries)
+}
+
+/// `(path, hash)` pairs of a RECORD, without the metadata directory's own
+/// files, which are never shared.
+pub(crate) fn parse_record(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(',');
+            let path = fields.next()?.trim_matches('"');
+            let hash = fields.next().unwrap_or_default();
+            let first = path.split('/').next().unwrap_or_default();
+            if path.is_empty() || first.ends_with(".dist-info") || first.ends_with(".egg-info") {
+                return None;
+            }
+            Some((path.to_string(), hash.to_string()))
+        })
+        .collect()
+}
+
+/// Files that more than one project lists in RECORD with different
+/// hashes. Identical copies, such as the `__init__.py` of a namespace
+/// package shared by several distributions, are not collisions.
+pub fn file_collisions(site_packages: &Path) -> Result<Vec<FileCollision>> {
+    let mut by_path: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for (project, path, hash) in record_entries(site_packages)? {
+        by_path.entry(path).or_default().push((project, hash));
+    }
+    Ok(by_path
+        .into_iter()
+        .filter_map(|(path, claims)| {
+            let owners: BTreeSet<String> =
+                claims.iter().map(|(project, _)| project.clone()).collect();
+            let hashes: BTreeSet<&str> =
+                claims.iter().map(|(_, hash)| hash.as_str()).collect();
+            (owners.len() > 1 && hashes.len() > 1).then(|| FileCollision {
+                path,
+                owners: owners.into_iter().collect(),
+            })
+        })
+        .collect())
+}
+
+/// Reads `Name`, `Version`, `Requires-Dist` and `Requires-Python` from the
+/// headers of a core metadata file.
+fn parse_metadata(text: &str) -> Option<InstalledDist> {
+    let mut name = None;
+    let mut version = None;
+    let mut requires = Vec::new();
+    let mut requires_python = String::new();
+    for line in text.lines() {
+        if line.is_empty() {
+            break;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().to_string();
+        match key {
+            "Name" => name = Some(value),
+            "Version" => version = Some(value),
+            "Requires-Dist" => requires.push(value),
+            "Requires-Python" => requires_python = value,
+            _ => {}
+        }
+    }
+    Some(InstalledDist {
+        name: name?,
+        version: version?,
+        requires,
+        entry_points: BTreeMap::new(),
+        top_level: Vec::new(),
+        requires_python,
+        source: DistSource::default(),
+    })
+}
+
+/// Reads `INSTALLER` and `direct_url.json` from a metadata directory, or
+/// the index xe recorded when the distribution came from one.
+fn read_dist_source(metadata_dir: &Path) -> DistSource {
+    let mut source = DistSource {
+        installer: fs::read_to_string(metadata_dir.join(INSTALLER_FILE))
+            .map(|text| text.trim().to_string())
+            .unwrap_or_default(),
+        ..DistSource::default()
+    };
+    let direct_url = fs::read(metadata_dir.join(DIRECT_URL_FILE))
+        .ok()
+        .and_then(|raw| serde_json::from_slice::<Value>(&raw).ok());
+    if let Some(direct_url) = direct_url {
+        (source.kind, source.location) = parse_direct_url(&direct_url);
+    } else if let Ok(text) = fs::read_to_string(metadata_dir.join(SOURCE_FILE)) {
+        if let Some((kind, location)) = text.trim().split_once(' ') {
+            source.kind = kind.to_string();
+            source.location = location.trim().to_string();
+        }
+    }
+    source
+}
+
+/// Kind and location of a PEP 610 `direct_url.json`.
+fn parse_direct_url(direct_url: &Value) -> (String, String) {
+    let url = direct_url["url"].as_str().unwrap_or_default();
+    let path = file_url_to_path(url);
+    if let Some(vcs) = direct_url["vcs_info"].as_object() {
+        let kind = vcs.get("vcs").and_then(Value::as_str).unwrap_or("vcs");
+        let mut location = url.to_string();
+        if let Some(commit) = vcs.get("commit_id").and_then(Value::as_str) {
+            location.push_str(&format!("@{commit}"));
+        }
+        if let Some(subdirectory) = direct_url["subdirectory"].as_str() {
+            location.push_str(&format!("#subdirectory={subdirectory}"));
+        }
+        return (kind.to_string(), location);
+    }
+    let kind = if direct_url["dir_info"]["editable"].as_bool() == Some(true) {
+        "editable"
+    } else if direct_url["dir_info"].is_object() || path.is_some() {
+        "path"
+    } else {
+        "url"
+    };
+    let location = match path {
+        Some(path) => path.display().to_string(),
+        None => url.to_string(),
+    };
+    (kind.to_string(), location)
+}
+
+/// Names from `top_level.txt`, or else the first component of every path
+/// in RECORD, skipping metadata, scripts and private helpers such as the
+/// finders of editable installs.
+fn top_level_modules(metadata_dir: &Path) -> Vec<String> {
+    let mut modules = BTreeSet::new();
+    if let Ok(text) = fs::read_to_string(metadata_dir.join("top_level.txt")) {
+        for line in text.lines().map(str::trim) {
+            let name = line.replace('/', ".");
+            if name.split('.').all(is_identifier) {
+                modules.insert(name);
+            }
+        }
+        return modules.into_iter().collect();
+    }
+    let Ok(text) = fs::read_to_string(metadata_dir.join("RECORD")) else {
+        return Vec::new();
+    };
+    for line in text.lines() {
+        let path = line.split(',').next().unwrap_or_default().trim_matches('"');
+        let (first, is_dir) = match path.split_once('/') {
+            Some((first, _)) => (first, true),
+            None => (path, false),
+        };
+        if first.ends_with(".dist-info") || first.ends_with(".data") || first.starts_with("__") {
+            continue;
+        }
+        let name = if is_dir {
+            first
+        } else if [".py", ".so", ".pyd"].iter().any(|ext| first.ends_with(ext)) {
+            first.split('.').next().unwrap_or_default()
+        } else {
+            continue;
+        };
+        if is_identifier(name) {
+            modules.insert(name.to_string());
+        }
+    }
+    modules.into_iter().collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Imports every top-level module of `dists` in the selected interpreter.
+/// An import that kills the interpreter, as a native extension built for
+/// another ABI can, is reported as a failure and the rest run in a fresh
+/// process.
+pub fn verify_imports(
+    selection: &RuntimeSelection,
+    dists: &[InstalledDist],
+) -> Result<Vec<ImportFailure>> {
+    let mut pending: Vec<(&str, &str)> = dists
+        .iter()
+        .flat_map(|dist| {
+            dist.top_level
+                .iter()
+                .map(move |module| (dist.name.as_str(), module.as_str()))
+        })
+        .collect();
+    let mut failures = Vec::new();
+    let failure = |(package, module): (&str, &str), error: String| ImportFailure {
+        package: package.to_string(),
+        module: module.to_string(),
+        error,
+    };
+    while !pending.is_empty() {
+        let modules_file = tempfile_path("xe-imports", "json");
+        let results_file = tempfile_path("xe-imports", "jsonl");
+        let modules: Vec<&str> = pending.iter().map(|(_, module)| *module).collect();
+        let encoded = serde_json::to_vec(&modules).context("failed to encode module list")?;
+        fs::write(&modules_file, encoded)
+            .with_context(|| format!("failed to write {}", modules_file.display()))?;
+        let mut command = Command::new(&selection.python_exe);
+        command
+            .arg("-c")
+            .arg(IMPORT_SCRIPT)
+            .arg(&modules_file)
+            .arg(&results_file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        apply_runtime_env(&mut command, selection)?;
+        let status = command
+            .status()
+            .with_context(|| format!("failed to run {}", selection.python_exe.display()))?;
+        let results = fs::read_to_string(&results_file).unwrap_or_default();
+        let _ = remove_file(&modules_file);
+        let _ = remove_file(&results_file);
+
+        let mut done = 0;
+        for line in results.lines() {
+            let Ok(error) = serde_json::from_str::<Option<String>>(line) else {
+                break;
+            };
+            if let Some(error) = error {
+                failures.push(failure(pending[done], error));
+            }
+            done += 1;
+        }
+        if status.success() || done >= pending.len() {
+            break;
+        }
+        failures.push(failure(
+            pending[done],
+            format!("interpreter crashed while importing ({status})"),
+        ));
+        pending.drain(..=done);
+    }
+    Ok(failures)
+}
+
+fn parse_entry_points(text: &str) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut groups: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let mut group = None;
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            group = Some(name.trim().to_string());
+            continue;
+        }
+        if let (Some(group), Some((name, value))) = (&group, line.split_once('=')) {
+            groups
+                .entry(group.clone())
+                .or_default()
+                .insert(name.trim().to_string(), value.trim().to_string());
+        }
+    }
+    groups
+}
@@ -0,0 +1,32 @@
+//! Core of xe: project configuration, Python runtimes, venvs, dependency
+//! resolution and the artifact cache, for embedding without the CLI.
+
+pub mod archive;
+pub mod cas;
+pub mod config;
+pub mod error;
+pub mod heap;
+pub mod install;
+pub mod installed;
+pub mod lock;
+pub mod modules;
+pub mod net;
+pub mod paths;
+pub mod process;
+pub mod profile;
+pub mod python;
+pub mod report;
+pub mod requirements;
+pub mod runtime;
+pub mod scripts;
+pub mod shell;
+pub mod tags;
+pub mod ui;
+pub mod venv;
+
+pub use cas::Cas;
+pub use config::Config;
+pub use error::{Error, ErrorKind, Result};
+pub use install::Installer;
+pub use python::PythonManager;
+pub use venv::VenvManager;
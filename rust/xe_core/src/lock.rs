@@ -0,0 +1,297 @@
+//! `xe.lock` reading, writing and per-platform artifacts.
+
+use crate::config::normalize_dep_name;
+use crate::config::DepSpec;
+use crate::config::ResolutionConfig;
+use crate::error::{bail, ErrorKind, Result, ResultExt};
+use crate::install::Artifact;
+use crate::install::Package;
+use crate::net::http_client;
+use crate::net::http_get;
+use crate::net::send_with_retry;
+use crate::tags::TargetEnv;
+use crate::Config;
+use anyhow::Context;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha1::Digest as Sha1Digest;
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self};
+use std::path::Path;
+use std::time::Duration;
+
+pub const XE_LOCK: &str = "xe.lock";
+pub const LOCK_VERSION: u32 = 1;
+
+/// `xe.lock`: the resolved graph plus every platform's artifacts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockFile {
+    pub version: u32,
+    pub python: String,
+    /// Hash of the xe.toml inputs the lock was resolved from.
+    pub fingerprint: String,
+    /// Interpreter the lock was resolved with; absent in older locks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interpreter: Option<LockedInterpreter>,
+    #[serde(default, rename = "package")]
+    pub packages: Vec<Package>,
+}
+
+impl LockFile {
+    /// The lock of `resolved` for `cfg`: packages sorted by name, each with
+    /// the artifacts of every platform on its index, and `target` as the
+    /// interpreter they were resolved for. Both `xe lock` and the Python
+    /// bindings write what this returns.
+    pub fn new(cfg: &Config, resolved: &[Package], target: &TargetEnv) -> Result<Self> {
+        let mut packages = resolved
+            .par_iter()
+            .map(|p| -> Result<Package> {
+                let mut pkg = p.clone();
+                if !pkg.editable {
+                    pkg.artifacts = locked_artifacts(&pkg, &cfg.resolution)?;
+                }
+                Ok(pkg)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self {
+            version: LOCK_VERSION,
+            python: cfg.python.version.clone(),
+            fingerprint: lock_fingerprint(cfg),
+            interpreter: Some(LockedInterpreter::from_target(target)),
+            packages,
+        })
+    }
+}
+
+/// `[interpreter]` in `xe.lock`: the exact Python that evaluated the markers.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LockedInterpreter {
+    pub implementation: String,
+    pub version: String,
+    /// Hash of `sys.version`; differs between builds of the same version.
+    pub build: String,
+    pub abi: String,
+    pub platform: String,
+}
+
+impl LockedInterpreter {
+    pub fn from_target(target: &TargetEnv) -> Self {
+        Self {
+            implementation: target.implementation.clone(),
+            version: target.full_version.clone(),
+            build: target.build.clone(),
+            abi: target.abi_tag(),
+            platform: target.platform_name(),
+        }
+    }
+
+    /// Differences from `target` that can change marker results or which
+    /// wheels are compatible. Patch releases and rebuilds are left out.
+    pub fn mismatches(&self, target: &TargetEnv) -> Vec<String> {
+        let current = Self::from_target(target);
+        let mut found = Vec::new();
+        if self.implementation != current.implementation {
+            found.push(format!(
+                "implementation {} (locked with {})",
+                current.implementation, self.implementation
+            ));
+        }
+        if minor_version(&self.version) != minor_version(&current.version) {
+            found.push(format!(
+                "Python {} (locked with {})",
+                current.version, self.version
+            ));
+        }
+        if self.abi != current.abi {
+            found.push(format!("ABI {} (locked with {})", current.abi, self.abi));
+        }
+        if self.platform != current.platform {
+            found.push(format!(
+                "platform {} (locked with {})",
+                current.platform, self.platform
+            ));
+        }
+        found
+    }
+}
+
+fn minor_version(version: &str) -> &str {
+    match version.match_indices('.').nth(1) {
+        Some((end, _)) => &version[..end],
+        None => version,
+    }
+}
+
+pub fn load_lock(project_dir: &Path) -> Result<Option<LockFile>> {
+    let path = project_dir.join(XE_LOCK);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    let lock: LockFile = toml::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))
+        .kind(ErrorKind::Config)?;
+    if lock.version > LOCK_VERSION {
+        bail!(
+            "{} was written by a newer xe (lock version {}); upgrade xe to use it",
+            path.display(),
+            lock.version
+        );
+    }
+    Ok(Some(lock))
+}
+
+pub fn save_lock(project_dir: &Path, lock: &LockFile) -> Result<()> {
+    let path = project_dir.join(XE_LOCK);
+    let encoded = toml::to_string_pretty(lock).context("failed to encode xe.lock")?;
+    fs::write(&path, encoded).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Hashes the parts of xe.toml that feed resolution, in a stable order.
+pub fn lock_fingerprint(cfg: &Config) -> String {
+    let deps = cfg
+        .deps
+        .iter()
+        .map(|(name, spec)| match spec {
+            // Only git and path deps carry the extra fields, so locks of
+            // index-only projects keep their fingerprint.
+            DepSpec::Table(table) if spec.source_location().is_some() => (
+                name,
+                json!([
+                    spec.version(),
+                    spec.index(),
+                    table.git,
+                    table.rev,
+                    table.path
+                ]),
+            ),
+            _ => (name, json!([spec.version(), spec.index()])),
+        })
+        .collect::<BTreeMap<_, _>>();
+    let indexes = cfg.indexes.iter().collect::<BTreeMap<_, _>>();
+    let editable = cfg.editable.iter().collect::<BTreeMap<_, _>>();
+    let direct = cfg
+        .direct
+        .iter()
+        .map(|(name, r)| (name, (&r.url, &r.path, &r.sha256)))
+        .collect::<BTreeMap<_, _>>();
+    let mut inputs = json!({
+        "python": cfg.python.version,
+        "deps": deps,
+        "indexes": indexes,
+        "editable": editable,
+        "direct": direct,
+        "resolution": cfg.resolution,
+    });
+    // Only present when set, so locks written before `ignore` existed stay
+    // current.
+    if !cfg.settings.ignore.is_empty() {
+        let ignore = cfg
+            .settings
+            .ignore
+            .iter()
+            .map(|name| normalize_dep_name(name))
+            .collect::<BTreeSet<_>>();
+        inputs["ignore"] = json!(ignore);
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(inputs.to_string().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Digest of the environment a lock installs on one machine: every package
+/// with its version and the file picked for that machine (from
+/// [`crate::install::Installer::locked_plan`]), and the interpreter. Two
+/// machines that would install the same files into the same Python get the
+/// same digest, so CI can key caches of the environment on it.
+pub fn environment_digest(plan: &[Package], interpreter: &LockedInterpreter) -> String {
+    let packages = plan
+        .iter()
+        .map(|pkg| {
+            // Editable sources live at a machine-specific path and change
+            // without the lock noticing; only their presence counts.
+            let file = if pkg.editable {
+                "editable"
+            } else if pkg.hash.is_empty() {
+                pkg.download_url.as_str()
+            } else {
+                pkg.hash.as_str()
+            };
+            (normalize_dep_name(&pkg.name), json!([pkg.version, file]))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let inputs = json!({
+        "interpreter": interpreter,
+        "packages": packages,
+    });
+    let mut hasher = Sha256::new();
+    hasher.update(inputs.to_string().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PypiReleaseFiles {
+    #[serde(default)]
+    pub(crate) urls: Vec<PypiReleaseFile>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PypiReleaseFile {
+    pub(crate) filename: String,
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) digests: HashMap<String, String>,
+}
+
+/// Wheels to record for a resolved package: all of the release's wheels on
+/// PyPI, or just the resolved artifact for custom indexes and direct URLs.
+pub fn locked_artifacts(pkg: &Package, resolution: &ResolutionConfig) -> Result<Vec<Artifact>> {
+    let resolved_name = pkg
+        .download_url
+        .split(['#', '?'])
+        .next()
+        .unwrap_or_default()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let mut artifacts = Vec::new();
+    let from_pypi = resolution.index_url.is_empty()
+        && !resolution.no_index
+        && pkg
+            .download_url
+            .starts_with("https://files.pythonhosted.org/");
+    if from_pypi {
+        let url = format!("https://pypi.org/pypi/{}/{}/json", pkg.name, pkg.version);
+        let client = http_client(Duration::from_secs(30))?;
+        let resp = send_with_retry(http_get(&client, &url), &url)
+            .with_context(|| format!("failed to request PyPI files for {}", pkg.name))?;
+        if resp.status().is_success() {
+            let release = resp
+                .json::<PypiReleaseFiles>()
+                .with_context(|| format!("failed to parse PyPI files for {}", pkg.name))?;
+            for file in release.urls {
+                if file.filename.ends_with(".whl") {
+                    artifacts.push(Artifact {
+                        sha256: file.digests.get("sha256").cloned().unwrap_or_default(),
+                        filename: file.filename,
+                        url: file.url,
+                    });
+                }
+            }
+        }
+    }
+    if resolved_name.ends_with(".whl") && !artifacts.iter().any(|a| a.filename == resolved_name) {
+        artifacts.push(Artifact {
+            filename: resolved_name,
+            url: pkg.download_url.clone(),
+            sha256: pkg.hash.clone(),
+        });
+    }
+    Ok(artifacts)
+}
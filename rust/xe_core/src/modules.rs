@@ -0,0 +1,266 @@
+//! Which PyPI distribution provides an importable module, for the names
+//! that differ (`cv2` is opencv-python, `yaml` is PyYAML). A mapping ships
+//! with xe; `xe modules update`, and once a week a background refresh,
+//! download the one attached to the latest xe release into the xe home.
+//! The download only adds modules the shipped mapping lacks; it never
+//! changes where a shipped module points.
+
+use crate::config::{normalize_dep_name, GLOBAL_CONFIG};
+use crate::error::{bail, ErrorKind, Result, ResultExt};
+use crate::net::{http_client, http_get, http_send, send_with_retry};
+use crate::paths::{rename, tempfile_path_in, xe_home};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where the current mapping is published: an asset of the latest
+/// release, not whatever the main branch holds. `XE_MODULE_MAP_URL`
+/// points elsewhere, e.g. at an internal mirror.
+pub const MODULE_MAP_URL: &str =
+    "https://github.com/aaravmaloo/xe/releases/latest/download/modules.txt";
+
+const BUILTIN: &str = include_str!("modules.txt");
+const REFRESH_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Default)]
+pub struct ModuleMap {
+    /// Module, dotted for namespace packages, to distribution.
+    entries: BTreeMap<String, String>,
+}
+
+impl ModuleMap {
+    /// The mapping shipped with this xe.
+    pub fn builtin() -> Self {
+        Self {
+            entries: parse(BUILTIN),
+        }
+    }
+
+    /// The shipped mapping plus the modules only the downloaded one lists.
+    /// `xe add --infer` installs what the mapping names, so a download
+    /// cannot redirect a module the shipped mapping already knows.
+    pub fn load() -> Self {
+        let mut map = Self::builtin();
+        if let Ok(text) = fs::read_to_string(downloaded_path()) {
+            for (module, dist) in parse(&text) {
+                map.entries.entry(module).or_insert(dist);
+            }
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The distribution that provides `module`, as in `import module`,
+    /// going by the longest listed prefix of a dotted name.
+    pub fn distribution(&self, module: &str) -> Option<&str> {
+        let mut prefix = module.trim();
+        loop {
+            if let Some(dist) = self.entries.get(prefix) {
+                return Some(dist);
+            }
+            prefix = prefix.rsplit_once('.')?.0;
+        }
+    }
+
+    /// A listed distribution within two edits of the package `name` that
+    /// is not one itself, for a misspelled `xe add`.
+    pub fn closest_distribution(&self, name: &str) -> Option<&str> {
+        let wanted = normalize_dep_name(name);
+        if wanted.len() < 4 {
+            return None;
+        }
+        let mut best: Option<(usize, &str)> = None;
+        for dist in self.entries.values() {
+            let candidate = normalize_dep_name(dist);
+            if candidate == wanted {
+                return None;
+            }
+            let distance = edit_distance(&wanted, &candidate);
+            if distance <= 2 && best.is_none_or(|(d, _)| distance < d) {
+                best = Some((distance, dist));
+            }
+        }
+        best.map(|(_, dist)| dist)
+    }
+
+    /// Why `name` found no distribution on the index, when the mapping
+    /// knows: it is the import name of another distribution, or a near
+    /// miss of a listed one. Names are compared as package names, so `pil`
+    /// finds `PIL`.
+    pub fn missing_package_hint(&self, name: &str) -> Option<String> {
+        let wanted = normalize_dep_name(name);
+        let import = self.entries.iter().find(|(module, dist)| {
+            normalize_dep_name(module) == wanted && normalize_dep_name(dist) != wanted
+        });
+        if let Some((module, dist)) = import {
+            return Some(format!(
+                "`{module}` is the import name of {dist}; add `{dist}` instead"
+            ));
+        }
+        self.closest_distribution(name)
+            .map(|dist| format!("no package is named {name}; did you mean `{dist}`?"))
+    }
+}
+
+/// The module of the last `ModuleNotFoundError: No module named 'x'` in
+/// the output of a failed Python run.
+pub fn missing_module(stderr: &str) -> Option<&str> {
+    let (_, rest) = stderr.rsplit_once("ModuleNotFoundError: No module named ")?;
+    let quoted = rest.lines().next()?.trim();
+    let module = quoted.trim_matches(|c| c == '\'' || c == '"');
+    (!module.is_empty() && module.split('.').all(|part| !part.is_empty())).then_some(module)
+}
+
+/// `<module> <distribution>` lines; `#` starts a comment.
+fn parse(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(module), Some(dist), None) => Some((module.to_string(), dist.to_string())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Levenshtein distance over bytes; names are ASCII.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.as_bytes();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.bytes().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// The downloaded mapping.
+pub fn downloaded_path() -> PathBuf {
+    xe_home().join("modules.txt")
+}
+
+fn stamp_path() -> PathBuf {
+    xe_home().join("modules-checked")
+}
+
+fn map_url() -> String {
+    env::var("XE_MODULE_MAP_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())
+        .unwrap_or_else(|| MODULE_MAP_URL.to_string())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Downloads the current mapping into the xe home and returns how many
+/// entries it has.
+pub fn update() -> Result<usize> {
+    let url = map_url();
+    let client = http_client(Duration::from_secs(30))?;
+    let resp = send_with_retry(http_get(&client, &url), &url)
+        .with_context(|| format!("failed to download {url}"))
+        .kind(ErrorKind::Network)?;
+    if !resp.status().is_success() {
+        bail!(
+            kind = ErrorKind::Network,
+            "{} returned {}",
+            url,
+            resp.status()
+        );
+    }
+    let text = resp
+        .text()
+        .with_context(|| format!("failed to read {url}"))
+        .kind(ErrorKind::Network)?;
+    let count = parse(&text).len();
+    if count == 0 {
+        bail!("{url} has no module mappings");
+    }
+    save(&text)?;
+    Ok(count)
+}
+
+fn save(text: &str) -> Result<()> {
+    let home = xe_home();
+    fs::create_dir_all(&home).with_context(|| format!("failed to create {}", home.display()))?;
+    let tmp = tempfile_path_in(&home, "xe-modules", "txt");
+    fs::write(&tmp, text).with_context(|| format!("failed to write {}", tmp.display()))?;
+    let path = downloaded_path();
+    rename(&tmp, &path).with_context(|| format!("failed to replace {}", path.display()))?;
+    let _ = fs::write(stamp_path(), now().to_string());
+    Ok(())
+}
+
+/// Off with `module_map_update: false` in the global config or
+/// `XE_NO_MODULE_MAP_UPDATE`.
+fn refresh_enabled() -> bool {
+    let configured = GLOBAL_CONFIG
+        .get()
+        .and_then(|cfg| cfg.module_map_update)
+        .unwrap_or(true);
+    configured && env::var_os("XE_NO_MODULE_MAP_UPDATE").is_none_or(|v| v.is_empty())
+}
+
+/// Downloads the mapping on a background thread when the last attempt is
+/// more than a week old. The attempt is recorded first, so an unreachable
+/// server is not retried on every run; nothing waits on the download.
+pub fn refresh_in_background() {
+    if !refresh_enabled() {
+        return;
+    }
+    let checked_at = fs::read_to_string(stamp_path())
+        .ok()
+        .and_then(|text| text.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    if now().saturating_sub(checked_at) < REFRESH_INTERVAL.as_secs() {
+        return;
+    }
+    if fs::create_dir_all(xe_home()).is_err() || fs::write(stamp_path(), now().to_string()).is_err()
+    {
+        return;
+    }
+    thread::spawn(|| {
+        let url = map_url();
+        let Ok(client) = http_client(Duration::from_secs(10)) else {
+            return;
+        };
+        let Ok(resp) = http_send(http_get(&client, &url)) else {
+            return;
+        };
+        if !resp.status().is_success() {
+            return;
+        }
+        if let Ok(text) = resp.text() {
+            if !parse(&text).is_empty() {
+                let _ = save(&text);
+            }
+        }
+    });
+}
@@ -0,0 +1,626 @@
+//! HTTP clients that send netrc credentials and wait out index throttling.
+
+use crate::config::global_config;
+use crate::config::load_project;
+use crate::config::XE_TOML;
+use crate::error::{bail, ErrorKind, Result, ResultExt};
+use crate::paths::tempfile_path;
+use crate::paths::xe_home;
+use crate::profile::global_span;
+use crate::ui::debug;
+use crate::ui::format_bytes;
+use crate::ui::warning;
+use crate::ui::ProgressUnit;
+use crate::ui::TransferProgress;
+use anyhow::Context;
+use regex::{Captures, Regex};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH, COOKIE, PROXY_AUTHORIZATION, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{LazyLock, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+/// Retries of a request the server throttles before xe gives up.
+const THROTTLE_RETRIES: u32 = 4;
+/// Longest `Retry-After` xe waits out; a server asking for more is
+/// reported instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// Login and password for one `machine` (or the `default` entry) of a
+/// netrc file.
+#[derive(Debug, Clone)]
+pub(crate) struct NetrcEntry {
+    pub(crate) login: String,
+    pub(crate) password: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Netrc {
+    pub(crate) machines: HashMap<String, NetrcEntry>,
+    pub(crate) default: Option<NetrcEntry>,
+}
+
+impl Netrc {
+    pub(crate) fn parse(text: &str) -> Self {
+        let mut netrc = Netrc::default();
+        // `None` while reading the `default` entry.
+        let mut current: Option<Option<String>> = None;
+        let mut entry = NetrcEntry {
+            login: String::new(),
+            password: String::new(),
+        };
+        let finish =
+            |netrc: &mut Netrc, current: Option<Option<String>>, entry: NetrcEntry| match current {
+                Some(Some(machine)) => {
+                    netrc.machines.entry(machine).or_insert(entry);
+                }
+                Some(None) => {
+                    netrc.default.get_or_insert(entry);
+                }
+                None => {}
+            };
+
+        let mut lines = text.lines();
+        while let Some(line) = lines.next() {
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                match token {
+                    "machine" | "default" => {
+                        let next = std::mem::replace(
+                            &mut entry,
+                            NetrcEntry {
+                                login: String::new(),
+                                password: String::new(),
+                            },
+                        );
+                        finish(&mut netrc, current.take(), next);
+                        current = Some(if token == "machine" {
+                            tokens.next().map(str::to_lowercase)
+                        } else {
+                            None
+                        });
+                    }
+                    "login" => entry.login = tokens.next().unwrap_or_default().to_string(),
+                    "password" => entry.password = tokens.next().unwrap_or_default().to_string(),
+                    "account" => {
+                        tokens.next();
+                    }
+                    // A macro body runs until the next empty line.
+                    "macdef" => {
+                        for body in lines.by_ref() {
+                            if body.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        finish(&mut netrc, current, entry);
+        netrc
+    }
+
+    pub(crate) fn lookup(&self, host: &str) -> Option<&NetrcEntry> {
+        self.machines
+            .get(&host.to_lowercase())
+            .or(self.default.as_ref())
+    }
+}
+
+/// `$NETRC`, else `~/.netrc` (`~/_netrc` on Windows when that exists).
+pub(crate) fn netrc_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("NETRC").filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let home = dirs::home_dir()?;
+    if cfg!(windows) && !home.join(".netrc").exists() {
+        return Some(home.join("_netrc"));
+    }
+    Some(home.join(".netrc"))
+}
+
+/// Whether netrc credentials are in use: the project's `[network]` wins over
+/// the global config; both default to on.
+pub(crate) fn netrc_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        let local = env::current_dir()
+            .ok()
+            .map(|wd| wd.join(XE_TOML))
+            .filter(|path| path.exists())
+            .and_then(|path| load_project(&path).ok())
+            .and_then(|cfg| cfg.network.netrc);
+        local.or(global_config().network.netrc).unwrap_or(true)
+    })
+}
+
+pub(crate) fn netrc() -> Option<&'static Netrc> {
+    static NETRC: OnceLock<Option<Netrc>> = OnceLock::new();
+    NETRC
+        .get_or_init(|| {
+            if !netrc_enabled() {
+                return None;
+            }
+            let path = netrc_path()?;
+            let text = fs::read_to_string(&path).ok()?;
+            Some(Netrc::parse(&text))
+        })
+        .as_ref()
+}
+
+pub(crate) fn url_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://")?.1;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority.rsplit('@').next()?;
+    let host = if let Some(v6) = host_port.strip_prefix('[') {
+        v6.split(']').next()?
+    } else {
+        host_port.split(':').next()?
+    };
+    (!host.is_empty()).then(|| host.to_lowercase())
+}
+
+pub fn http_client(timeout: Duration) -> Result<Client> {
+    Ok(Client::builder()
+        .timeout(timeout)
+        .build()
+        .context("failed to build HTTP client")?)
+}
+
+/// Attaches netrc credentials for the URL's host unless the URL carries its
+/// own.
+pub(crate) fn with_netrc_auth(request: RequestBuilder, url: &str) -> RequestBuilder {
+    let has_userinfo = url
+        .split_once("://")
+        .and_then(|(_, rest)| rest.split(['/', '?', '#']).next())
+        .is_some_and(|authority| authority.contains('@'));
+    if has_userinfo {
+        return request;
+    }
+    match (netrc(), url_host(url)) {
+        (Some(netrc), Some(host)) => match netrc.lookup(&host) {
+            Some(entry) => request.basic_auth(&entry.login, Some(&entry.password)),
+            None => request,
+        },
+        _ => request,
+    }
+}
+
+pub fn http_get(client: &Client, url: &str) -> RequestBuilder {
+    with_netrc_auth(client.get(url), url)
+}
+
+pub(crate) fn http_head(client: &Client, url: &str) -> RequestBuilder {
+    with_netrc_auth(client.head(url), url)
+}
+
+/// Sends `request` as an `http.request` profiler span with its method,
+/// URL, status, duration and announced size. `-v` prints the same as one
+/// line on stderr, and `-vv` adds the request headers. Credentials, in
+/// the URL or in headers, are never logged.
+pub fn http_send(request: RequestBuilder) -> reqwest::Result<Response> {
+    send_logged(request, false)
+}
+
+/// [`http_send`] for a URL that is a secret as a whole, such as a chat
+/// webhook: only its scheme and host are logged, and errors leave the URL
+/// out.
+pub fn http_send_secret(request: RequestBuilder) -> reqwest::Result<Response> {
+    send_logged(request, true)
+}
+
+fn send_logged(request: RequestBuilder, secret_url: bool) -> reqwest::Result<Response> {
+    let hide_url = |err: reqwest::Error| if secret_url { err.without_url() } else { err };
+    let (client, request) = request.build_split();
+    let request = request.map_err(hide_url)?;
+    let method = request.method().to_string();
+    let url = if secret_url {
+        redact_url_path(request.url().as_str())
+    } else {
+        redact_url(request.url().as_str())
+    };
+    let headers = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = if [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE].contains(name) {
+                "<redacted>"
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            (name.to_string(), value.to_string())
+        })
+        .collect::<Vec<_>>();
+    let mut span = global_span(
+        "http.request",
+        json!({"method": method, "url": url, "headers": headers}),
+    );
+    let started = Instant::now();
+    let result = client.execute(request).map_err(hide_url);
+    let elapsed_ms = started.elapsed().as_millis();
+    match &result {
+        Ok(resp) => {
+            // The header rather than `content_length`, which is 0 for HEAD.
+            let bytes = resp
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+            span.record("status", json!(resp.status().as_u16()));
+            span.record("bytes", json!(bytes));
+            let size = bytes.map(|b| format!(", {}", format_bytes(b)));
+            debug(
+                1,
+                &format!(
+                    "{method} {url} -> {} in {elapsed_ms} ms{}",
+                    resp.status(),
+                    size.unwrap_or_default()
+                ),
+            );
+        }
+        Err(err) => {
+            span.record("error", json!(err.to_string()));
+            debug(
+                1,
+                &format!("{method} {url} failed after {elapsed_ms} ms: {err}"),
+            );
+        }
+    }
+    for (name, value) in &headers {
+        debug(2, &format!("  {name}: {value}"));
+    }
+    result
+}
+
+/// `url` without the secret in its userinfo: the password, or all of it
+/// when it is a bare token such as `https://ghp_xxx@host/`.
+pub fn redact_url(url: &str) -> String {
+    let Some((scheme, userinfo, host, path)) = split_userinfo(url) else {
+        return url.to_string();
+    };
+    match userinfo.split_once(':') {
+        Some((user, _)) => format!("{scheme}://{user}:<redacted>@{host}{path}"),
+        None => format!("{scheme}://<redacted>@{host}{path}"),
+    }
+}
+
+/// `text` with [`redact_url`] applied to every URL in it, for arguments,
+/// error messages and reports that quote URLs.
+pub fn redact_urls_in(text: &str) -> String {
+    static URL: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"[A-Za-z][A-Za-z0-9+.-]*://[^\s"'<>]+"#).expect("valid URL pattern")
+    });
+    URL.replace_all(text, |caps: &Captures| redact_url(&caps[0]))
+        .into_owned()
+}
+
+/// `url` down to its scheme and host.
+pub fn redact_url_path(url: &str) -> String {
+    match (url.split_once("://"), url_host(url)) {
+        (Some((scheme, _)), Some(host)) => format!("{scheme}://{host}/<redacted>"),
+        _ => "<redacted>".to_string(),
+    }
+}
+
+/// `url` with its userinfo removed, for URLs that are written to files;
+/// credentials belong in netrc or the credential store. `git@`, the usual
+/// SSH user rather than a credential, stays.
+pub fn strip_credentials(url: &str) -> String {
+    match split_userinfo(url) {
+        Some((scheme, userinfo, host, path)) if userinfo != "git" => {
+            format!("{scheme}://{host}{path}")
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// `(scheme, userinfo, host, rest)` of a URL that has userinfo.
+fn split_userinfo(url: &str) -> Option<(&str, &str, &str, &str)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(authority_end);
+    let (userinfo, host) = authority.rsplit_once('@')?;
+    Some((scheme, userinfo, host, path))
+}
+
+/// Sends `request`, which goes to `url`, and while the server answers 429
+/// Too Many Requests or 503 Service Unavailable waits as long as its
+/// `Retry-After` asks, or backs off exponentially when it does not say,
+/// then tries again. Fails with an "index throttling" error once the
+/// retries run out or the server asks for too long a pause. Any other
+/// response is returned as is.
+pub fn send_with_retry(request: RequestBuilder, url: &str) -> Result<Response> {
+    let host = url_host(url).unwrap_or_else(|| url.to_string());
+    let mut attempt = 0;
+    loop {
+        // Only a request with a streaming body cannot be cloned; it is sent
+        // once.
+        let Some(current) = request.try_clone() else {
+            return http_send(request)
+                .with_context(|| format!("no response from {host}"))
+                .kind(ErrorKind::Network);
+        };
+        let resp = http_send(current)
+            .with_context(|| format!("no response from {host}"))
+            .kind(ErrorKind::Network)?;
+        let status = resp.status();
+        if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+            return Ok(resp);
+        }
+        if attempt == THROTTLE_RETRIES {
+            bail!(
+                kind = ErrorKind::Network,
+                "index throttling: {host} still answered {status} after {} attempts; try again later or use a mirror",
+                attempt + 1
+            );
+        }
+        let delay = match retry_after(&resp) {
+            Some(delay) if delay > MAX_RETRY_AFTER => bail!(
+                kind = ErrorKind::Network,
+                "index throttling: {host} answered {status} and asks to wait {}s; try again later or use a mirror",
+                delay.as_secs()
+            ),
+            Some(delay) => delay,
+            None => Duration::from_secs(1 << attempt),
+        };
+        let delay = with_jitter(delay);
+        warning(&format!(
+            "{host} is throttling requests ({status}); retrying in {:.1}s",
+            delay.as_secs_f64()
+        ));
+        std::thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+/// The pause a `Retry-After` header asks for, given in seconds or as an
+/// HTTP date.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = parse_http_date(value)?;
+    let wait = at - OffsetDateTime::now_utc();
+    Some(Duration::try_from(wait).unwrap_or_default())
+}
+
+/// An IMF-fixdate such as `Sun, 06 Nov 1994 08:49:37 GMT`, the form HTTP
+/// servers send.
+fn parse_http_date(value: &str) -> Option<OffsetDateTime> {
+    let [_, day, month, year, clock, "GMT"] = value.split_whitespace().collect::<Vec<_>>()[..]
+    else {
+        return None;
+    };
+    let month = match month {
+        "Jan" => Month::January,
+        "Feb" => Month::February,
+        "Mar" => Month::March,
+        "Apr" => Month::April,
+        "May" => Month::May,
+        "Jun" => Month::June,
+        "Jul" => Month::July,
+        "Aug" => Month::August,
+        "Sep" => Month::September,
+        "Oct" => Month::October,
+        "Nov" => Month::November,
+        "Dec" => Month::December,
+        _ => return None,
+    };
+    let mut clock = clock.split(':').map(|part| part.parse::<u8>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    let date = Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()?;
+    let time = Time::from_hms(hour, minute, second).ok()?;
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+/// `delay` plus up to a quarter more, so parallel downloads throttled
+/// together do not all retry at the same instant.
+fn with_jitter(delay: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|now| now.subsec_nanos())
+        .unwrap_or_default();
+    delay + delay.mul_f64(f64::from(nanos % 1000) / 4000.0)
+}
+
+/// pip reads `~/.netrc` on its own; point it at a missing file when netrc is
+/// turned off so resolver and download behave the same.
+pub(crate) fn apply_network_env(command: &mut Command) {
+    if !netrc_enabled() {
+        command.env("NETRC", xe_home().join("netrc-disabled"));
+    }
+}
+
+pub(crate) fn download_file(url: &str, prefix: &str, ext: &str) -> Result<PathBuf> {
+    let client = http_client(Duration::from_secs(180))?;
+    let mut resp = send_with_retry(http_get(&client, url), url)
+        .with_context(|| format!("failed to download {}", url))
+        .kind(ErrorKind::Network)?;
+    if !resp.status().is_success() {
+        bail!(
+            kind = ErrorKind::Network,
+            "failed to download {}: {}",
+            url,
+            resp.status()
+        );
+    }
+    let path = tempfile_path(prefix, ext);
+    let mut out =
+        File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+    let label = url.rsplit('/').next().unwrap_or(url).to_string();
+    let mut progress = TransferProgress::new(&label, resp.content_length(), ProgressUnit::Bytes);
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = resp
+            .read(&mut buffer)
+            .with_context(|| format!("failed while downloading {}", url))?;
+        if read == 0 {
+            break;
+        }
+        out.write_all(&buffer[..read])
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        progress.advance(read as u64);
+    }
+    progress.finish();
+    Ok(path)
+}
+
+/// How a probe of one endpoint by [`probe_endpoint`] went.
+pub enum ProbeOutcome {
+    /// The server answered with `status` after `latency`.
+    Answered { status: u16, latency: Duration },
+    /// The host name did not resolve.
+    Dns(String),
+    /// The connection failed during the TLS handshake, usually over the
+    /// certificate.
+    Tls(String),
+    /// The proxy could not be reached or refused the request.
+    Proxy(String),
+    /// No answer within the timeout.
+    Timeout,
+    /// Any other failure to connect.
+    Connect(String),
+}
+
+pub struct EndpointProbe {
+    pub outcome: ProbeOutcome,
+    /// Time the host name took to resolve, when it did.
+    pub dns: Option<Duration>,
+    /// The proxy the request was sent through, from the environment.
+    pub proxy: Option<String>,
+}
+
+/// Resolves the host of `url` and sends it a HEAD request, telling DNS,
+/// TLS and proxy failures apart from the rest. Behind a proxy a name that
+/// does not resolve locally is not a failure; the proxy resolves it.
+pub fn probe_endpoint(url: &str, timeout: Duration) -> EndpointProbe {
+    let proxy = env_proxy(url);
+    let dns = match resolve_host(url) {
+        Ok(elapsed) => Some(elapsed),
+        Err(err) if proxy.is_none() => {
+            return EndpointProbe {
+                outcome: ProbeOutcome::Dns(err),
+                dns: None,
+                proxy,
+            };
+        }
+        Err(_) => None,
+    };
+    let client = match http_client(timeout) {
+        Ok(client) => client,
+        Err(err) => {
+            return EndpointProbe {
+                outcome: ProbeOutcome::Connect(format!("{err:#}")),
+                dns,
+                proxy,
+            };
+        }
+    };
+    let started = Instant::now();
+    let outcome = match http_send(http_head(&client, url)) {
+        Ok(resp) if resp.status() == StatusCode::PROXY_AUTHENTICATION_REQUIRED => {
+            ProbeOutcome::Proxy("the proxy requires authentication".to_string())
+        }
+        Ok(resp) => ProbeOutcome::Answered {
+            status: resp.status().as_u16(),
+            latency: started.elapsed(),
+        },
+        Err(err) if err.is_timeout() => ProbeOutcome::Timeout,
+        Err(err) => {
+            let chain = error_chain(&err);
+            let lower = chain.to_lowercase();
+            // A failure to connect over HTTPS after the TCP connection
+            // (or proxy tunnel) stood is the TLS handshake failing.
+            let handshake = url.starts_with("https://")
+                && err.is_connect()
+                && !["tcp connect", "dns error", "tunnel"]
+                    .iter()
+                    .any(|stage| lower.contains(stage));
+            if handshake
+                || ["certificate", "tls", "handshake"]
+                    .iter()
+                    .any(|word| lower.contains(word))
+            {
+                ProbeOutcome::Tls(chain)
+            } else if proxy.is_some() && err.is_connect() {
+                ProbeOutcome::Proxy(chain)
+            } else {
+                ProbeOutcome::Connect(chain)
+            }
+        }
+    };
+    EndpointProbe {
+        outcome,
+        dns,
+        proxy,
+    }
+}
+
+/// Time taken to resolve the host of `url`, or why it failed.
+fn resolve_host(url: &str) -> std::result::Result<Duration, String> {
+    let host = url_host(url).ok_or_else(|| format!("{url} has no host"))?;
+    let default_port = if url.starts_with("http://") { 80 } else { 443 };
+    let started = Instant::now();
+    match (host.as_str(), default_port).to_socket_addrs() {
+        Ok(addrs) if addrs.len() > 0 => Ok(started.elapsed()),
+        Ok(_) => Err(format!("{host} has no addresses")),
+        Err(err) => Err(format!("{host}: {err}")),
+    }
+}
+
+/// The proxy `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY` name for `url`,
+/// unless `NO_PROXY` exempts its host.
+fn env_proxy(url: &str) -> Option<String> {
+    let var = |name: &str| {
+        env::var(name)
+            .or_else(|_| env::var(name.to_lowercase()))
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+    };
+    let host = url_host(url)?;
+    if let Some(no_proxy) = var("NO_PROXY") {
+        let exempt = no_proxy.split(',').map(str::trim).any(|entry| {
+            let entry = entry.trim_start_matches('.').to_lowercase();
+            entry == "*" || host == entry || host.ends_with(&format!(".{entry}"))
+        });
+        if exempt {
+            return None;
+        }
+    }
+    let scheme_var = if url.starts_with("https://") {
+        "HTTPS_PROXY"
+    } else {
+        "HTTP_PROXY"
+    };
+    var(scheme_var)
+        .or_else(|| var("ALL_PROXY"))
+        .map(|proxy| redact_url(&proxy))
+}
+
+/// The errors beneath `err`, which only repeats the URL, joined by `: `.
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let Some(first) = err.source() else {
+        return err.to_string();
+    };
+    let mut text = first.to_string();
+    let mut source = first.source();
+    while let Some(cause) = source {
+        let message = cause.to_string();
+        if !text.contains(&message) {
+            text.push_str(&format!(": {message}"));
+        }
+        source = cause.source();
+    }
+    text
+}
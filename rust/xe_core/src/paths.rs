@@ -0,0 +1,187 @@
+//! Locations of xe's home, cache, venv and shim directories, and the file
+//! operations that have to work on Windows too.
+
+use crate::process::register_cleanup_path;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+pub fn xe_home() -> PathBuf {
+    if cfg!(windows) {
+        if let Ok(local) = env::var("LOCALAPPDATA") {
+            return PathBuf::from(local).join("xe");
+        }
+        return dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("AppData")
+            .join("Local")
+            .join("xe");
+    }
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".local")
+        .join("share")
+        .join("xe")
+}
+
+pub fn xe_config_file() -> PathBuf {
+    xe_home().join("config.yaml")
+}
+
+/// The shared cache: `XE_CACHE_DIR` when set, so containers and CI can put
+/// it on a fixed path or a cache mount, else `cache` in the xe home.
+pub fn xe_cache_dir() -> PathBuf {
+    match env::var_os("XE_CACHE_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => xe_home().join("cache"),
+    }
+}
+
+pub(crate) fn xe_venv_dir() -> PathBuf {
+    xe_home().join("venvs")
+}
+
+pub fn xe_shim_dir() -> PathBuf {
+    xe_home().join("bin")
+}
+
+pub fn xe_plugin_dir() -> PathBuf {
+    xe_home().join("plugins")
+}
+
+pub(crate) fn tempfile_path(prefix: &str, ext: &str) -> PathBuf {
+    tempfile_path_in(&env::temp_dir(), prefix, ext)
+}
+
+pub(crate) fn tempfile_path_in(dir: &Path, prefix: &str, ext: &str) -> PathBuf {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0))
+        .as_nanos();
+    let pid = std::process::id();
+    let path = dir.join(format!("{prefix}-{pid}-{stamp}.{ext}"));
+    register_cleanup_path(&path);
+    path
+}
+
+pub fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// Attempts made by the retrying file operations below.
+const FS_ATTEMPTS: u32 = 6;
+
+/// `\\?\` form of `path` on Windows, so paths past `MAX_PATH` (260 chars,
+/// common in deep wheel layouts) can be opened. Other platforms get `path`
+/// back unchanged.
+pub fn long_path(path: &Path) -> PathBuf {
+    if !cfg!(windows) {
+        return path.to_path_buf();
+    }
+    let raw = path.as_os_str().to_string_lossy();
+    if raw.starts_with(r"\\?\") {
+        return path.to_path_buf();
+    }
+    // The prefix turns off normalization, so resolve `..` and `/` first.
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let absolute = absolute.to_string_lossy().into_owned();
+    match absolute.strip_prefix(r"\\") {
+        Some(unc) => PathBuf::from(format!(r"\\?\UNC\{unc}")),
+        None => PathBuf::from(format!(r"\\?\{absolute}")),
+    }
+}
+
+/// Clears the read-only attribute of `path`, and of everything below it when
+/// it is a directory. Windows refuses to overwrite or delete read-only files.
+fn make_writable(path: &Path) {
+    for entry in WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+    {
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        let mut perms = meta.permissions();
+        if !perms.readonly() {
+            continue;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            perms.set_mode(perms.mode() | 0o200);
+        }
+        #[cfg(not(unix))]
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        let _ = fs::set_permissions(entry.path(), perms);
+    }
+}
+
+/// Sharing and lock violations: another process (an antivirus scanner,
+/// the search indexer, a running interpreter) briefly holds the file.
+fn is_sharing_violation(err: &io::Error) -> bool {
+    cfg!(windows) && matches!(err.raw_os_error(), Some(32 | 33))
+}
+
+/// Runs `op` on the long form of `path`, clearing read-only attributes after
+/// a permission error and backing off while the file is held by another
+/// process.
+fn retry_fs<T>(path: &Path, mut op: impl FnMut(&Path) -> io::Result<T>) -> io::Result<T> {
+    let path = long_path(path);
+    let mut attempt = 0;
+    loop {
+        match op(&path) {
+            Err(err) if attempt + 1 < FS_ATTEMPTS => {
+                let denied = err.kind() == io::ErrorKind::PermissionDenied;
+                // Windows also reports files pending deletion as access
+                // denied, so only there is it worth asking more than once.
+                let retry =
+                    is_sharing_violation(&err) || (denied && (cfg!(windows) || attempt == 0));
+                if !retry {
+                    return Err(err);
+                }
+                if denied {
+                    make_writable(&path);
+                }
+                thread::sleep(Duration::from_millis(20 << attempt));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// `fs::remove_dir_all` that also removes read-only files.
+pub fn remove_dir_all(path: &Path) -> io::Result<()> {
+    retry_fs(path, |path| fs::remove_dir_all(path))
+}
+
+pub fn remove_file(path: &Path) -> io::Result<()> {
+    retry_fs(path, |path| fs::remove_file(path))
+}
+
+pub(crate) fn create_dir_all(path: &Path) -> io::Result<()> {
+    fs::create_dir_all(long_path(path))
+}
+
+/// `File::create` that replaces a read-only file instead of failing.
+pub(crate) fn create_file(path: &Path) -> io::Result<File> {
+    retry_fs(path, |path| File::create(path))
+}
+
+pub(crate) fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    let to = long_path(to);
+    retry_fs(from, |from| fs::rename(from, &to))
+}
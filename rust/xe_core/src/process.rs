@@ -0,0 +1,211 @@
+//! Temp-path cleanup on interrupt, sweeping of what killed runs left
+//! behind, and foreground child tracking.
+
+use crate::paths::dir_size;
+use crate::paths::remove_dir_all;
+use crate::paths::remove_file;
+use crate::paths::xe_cache_dir;
+use crate::paths::xe_home;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Temp files, staging dirs and half-built trees that must not outlive an
+/// interrupted run.
+pub(crate) static CLEANUP_PATHS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Set while a user command (`xe run`, `xe shell`) owns the terminal; Ctrl+C
+/// then belongs to that child and xe just waits for it.
+pub static CHILD_IN_FOREGROUND: AtomicBool = AtomicBool::new(false);
+
+pub fn register_cleanup_path(path: &Path) {
+    if let Ok(mut paths) = CLEANUP_PATHS.lock() {
+        paths.push(path.to_path_buf());
+    }
+}
+
+/// Marks `path` as finished so it survives both interruption and exit.
+pub(crate) fn unregister_cleanup_path(path: &Path) {
+    if let Ok(mut paths) = CLEANUP_PATHS.lock() {
+        paths.retain(|p| p != path);
+    }
+}
+
+pub fn cleanup_registered_paths() {
+    let paths = match CLEANUP_PATHS.lock() {
+        Ok(mut paths) => std::mem::take(&mut *paths),
+        Err(_) => return,
+    };
+    for path in paths.iter().rev() {
+        if path.is_dir() {
+            let _ = remove_dir_all(path);
+        } else if path.exists() {
+            let _ = remove_file(path);
+        }
+    }
+}
+
+/// Temp entries younger than this are never swept, whoever owns them.
+pub const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// How often startup sweeps the temp dir and the global cache.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long the pipes of an exited child are still read. A background
+/// process it started keeps them open for as long as it runs, and xe must
+/// not wait on that.
+pub(crate) const PIPE_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+const SWEEP_STAMP: &str = "last-temp-sweep";
+
+/// Removes the `xe-*` temp files and dirs in `dirs` that runs killed too
+/// hard to clean up left behind: those older than `max_age` whose name
+/// carries the pid of a process that is gone, as `xe-download-<pid>-<stamp>.tmp`
+/// does. Returns how many entries went and their size.
+pub fn sweep_stale_temp(dirs: &[PathBuf], max_age: Duration) -> (usize, u64) {
+    let now = SystemTime::now();
+    let mut removed = 0usize;
+    let mut bytes = 0u64;
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(pid) = temp_owner_pid(&name) else {
+                continue;
+            };
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let age = meta
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+            if age < max_age || pid == std::process::id() || process_alive(pid) {
+                continue;
+            }
+            let path = entry.path();
+            let (size, result) = if meta.is_dir() {
+                (dir_size(&path), remove_dir_all(&path))
+            } else {
+                (meta.len(), remove_file(&path))
+            };
+            if result.is_ok() {
+                removed += 1;
+                bytes += size;
+            }
+        }
+    }
+    (removed, bytes)
+}
+
+/// Sweeps the temp dir, the global cache and `extra` dirs at most once a
+/// day, so startup stays cheap on a busy temp dir.
+pub fn sweep_stale_temp_periodically(extra: &[PathBuf]) {
+    let stamp = xe_home().join(SWEEP_STAMP);
+    let recent = fs::metadata(&stamp)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < SWEEP_INTERVAL);
+    if recent || fs::create_dir_all(xe_home()).is_err() || fs::write(&stamp, b"").is_err() {
+        return;
+    }
+    let mut dirs = vec![env::temp_dir(), xe_cache_dir()];
+    dirs.extend(extra.iter().cloned());
+    sweep_stale_temp(&dirs, STALE_TEMP_AGE);
+}
+
+/// The pid in an xe temp name: its first all-digit `-` component, as in
+/// `xe-build-<pid>` or `xe-report-<pid>-<stamp>.json`. Other names are not
+/// xe's to remove.
+fn temp_owner_pid(name: &str) -> Option<u32> {
+    let rest = name.strip_prefix("xe-")?;
+    rest.split(['-', '.'])
+        .find(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))?
+        .parse()
+        .ok()
+}
+
+/// Whether a process with this pid is running. When that cannot be told,
+/// the process counts as alive, so nothing of it is swept.
+pub fn process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        return Path::new("/proc").join(pid.to_string()).exists();
+    }
+    if cfg!(windows) {
+        return Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+            .output()
+            .map(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .split_whitespace()
+                    .any(|word| word == pid.to_string())
+            })
+            .unwrap_or(true);
+    }
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(true)
+}
+
+/// Runs a foreground child that should receive Ctrl+C itself.
+pub fn run_foreground(command: &mut Command) -> io::Result<std::process::ExitStatus> {
+    CHILD_IN_FOREGROUND.store(true, AtomicOrdering::SeqCst);
+    let status = command.status();
+    CHILD_IN_FOREGROUND.store(false, AtomicOrdering::SeqCst);
+    status
+}
+
+/// [`run_foreground`] with the child's stderr piped through xe, which
+/// returns the last `keep` bytes of it with the exit status.
+pub fn run_foreground_tee_stderr(
+    command: &mut Command,
+    keep: usize,
+) -> io::Result<(std::process::ExitStatus, Vec<u8>)> {
+    command.stderr(Stdio::piped());
+    CHILD_IN_FOREGROUND.store(true, AtomicOrdering::SeqCst);
+    let result = (|| {
+        let mut child = command.spawn()?;
+        let mut pipe = child.stderr.take().expect("stderr is piped");
+        let tail = Arc::new(Mutex::new(Vec::new()));
+        let (done, drained) = mpsc::channel();
+        let reader_tail = Arc::clone(&tail);
+        thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            let mut stderr = io::stderr();
+            while let Ok(n) = pipe.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                let _ = stderr.write_all(&buf[..n]);
+                let _ = stderr.flush();
+                if let Ok(mut tail) = reader_tail.lock() {
+                    tail.extend_from_slice(&buf[..n]);
+                    let excess = tail.len().saturating_sub(keep);
+                    tail.drain(..excess);
+                }
+            }
+            let _ = done.send(());
+        });
+        let status = child.wait()?;
+        // A background process the child started may still hold the pipe;
+        // what it writes later is passed through but not returned.
+        let _ = drained.recv_timeout(PIPE_DRAIN_TIMEOUT);
+        let tail = tail.lock().map(|tail| tail.clone()).unwrap_or_default();
+        Ok((status, tail))
+    })();
+    CHILD_IN_FOREGROUND.store(false, AtomicOrdering::SeqCst);
+    result
+}
@@ -0,0 +1,243 @@
+//! Command context and the `--profile` span recorder.
+
+use crate::error::Result;
+use crate::heap;
+use anyhow::Context;
+use serde_json::{json, Map, Value};
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use time::format_description::well_known::Iso8601;
+use time::OffsetDateTime;
+
+#[derive(Clone)]
+pub struct AppContext {
+    pub config_file: PathBuf,
+    pub profiler: Option<Profiler>,
+}
+
+#[derive(Clone)]
+pub struct Profiler {
+    pub(crate) inner: Arc<ProfilerInner>,
+}
+
+/// The profiler of this run, for spans recorded where no [`AppContext`]
+/// reaches, such as HTTP requests.
+static ACTIVE: OnceLock<Profiler> = OnceLock::new();
+
+pub(crate) struct ProfilerInner {
+    pub(crate) info: ProfileInfo,
+    pub(crate) file: Mutex<File>,
+    pub(crate) started: Instant,
+}
+
+#[derive(Clone)]
+pub struct ProfileInfo {
+    pub log_path: PathBuf,
+    pub cpu_path: PathBuf,
+    /// Unset unless xe was built with the `heap-profile` feature.
+    pub heap_path: Option<PathBuf>,
+}
+
+impl Profiler {
+    pub fn start(profile_dir: &Path) -> Result<(Self, ProfileInfo)> {
+        fs::create_dir_all(profile_dir)
+            .with_context(|| format!("failed to create {}", profile_dir.display()))?;
+        let stamp = profile_stamp();
+        let info = ProfileInfo {
+            log_path: profile_dir.join(format!("trace-{stamp}.jsonl")),
+            cpu_path: profile_dir.join(format!("cpu-{stamp}.pprof")),
+            heap_path: heap::supported().then(|| profile_dir.join(format!("heap-{stamp}.pprof"))),
+        };
+        let log_file = File::create(&info.log_path)
+            .with_context(|| format!("failed to create {}", info.log_path.display()))?;
+        File::create(&info.cpu_path)
+            .with_context(|| format!("failed to create {}", info.cpu_path.display()))?;
+        if let Some(heap_path) = &info.heap_path {
+            File::create(heap_path)
+                .with_context(|| format!("failed to create {}", heap_path.display()))?;
+        }
+
+        let profiler = Self {
+            inner: Arc::new(ProfilerInner {
+                info: info.clone(),
+                file: Mutex::new(log_file),
+                started: Instant::now(),
+            }),
+        };
+        let _ = ACTIVE.set(profiler.clone());
+        heap::start();
+        profiler.event(
+            "profile.session_start",
+            json!({
+                "log_path": profiler.inner.info.log_path.display().to_string(),
+                "cpu_profile_path": profiler.inner.info.cpu_path.display().to_string(),
+                "heap_profile_path": profiler.inner.info.heap_path.as_ref().map(|path| path.display().to_string()),
+                "pid": std::process::id(),
+                "os": env::consts::OS,
+                "arch": env::consts::ARCH
+            }),
+        );
+        Ok((profiler, info))
+    }
+
+    pub fn event(&self, name: &str, fields: Value) {
+        let object = event_object(name, fields);
+        if let Ok(mut file) = self.inner.file.lock() {
+            if let Ok(line) = serde_json::to_string(&object) {
+                let _ = writeln!(file, "{line}");
+            }
+        }
+    }
+
+    /// Records the end of the session and writes the heap profile.
+    pub fn stop(&self) -> Result<()> {
+        // Before symbolizing the heap profile inflates it.
+        let peak_rss = heap::peak_rss();
+        let peak_heap = match &self.inner.info.heap_path {
+            Some(path) => heap::write_profile(path)?,
+            None => None,
+        };
+        self.event(
+            "profile.session_stop",
+            json!({
+                "elapsed_ms": self.inner.started.elapsed().as_millis(),
+                "peak_rss_bytes": peak_rss,
+                "peak_heap_bytes": peak_heap,
+            }),
+        );
+        Ok(())
+    }
+}
+
+fn event_object(name: &str, fields: Value) -> Value {
+    let mut object = Map::new();
+    object.insert("ts".to_string(), json!(timestamp_iso8601()));
+    object.insert("event".to_string(), json!(name));
+    if let Value::Object(map) = fields {
+        for (key, value) in map {
+            object.insert(key, value);
+        }
+    }
+    Value::Object(object)
+}
+
+/// Span events kept in memory, with or without `--profile`, so a crash
+/// report can show what xe was doing.
+static RECENT_SPANS: Mutex<VecDeque<Value>> = Mutex::new(VecDeque::new());
+const RECENT_SPANS_KEPT: usize = 32;
+
+fn remember_span(name: &str, fields: Value) {
+    if let Ok(mut recent) = RECENT_SPANS.lock() {
+        if recent.len() == RECENT_SPANS_KEPT {
+            recent.pop_front();
+        }
+        recent.push_back(event_object(name, fields));
+    }
+}
+
+/// The last span start/done events, oldest first.
+pub fn recent_spans() -> Vec<Value> {
+    RECENT_SPANS
+        .lock()
+        .map(|recent| recent.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+pub(crate) struct SpanGuard {
+    pub(crate) profiler: Option<Profiler>,
+    pub(crate) name: String,
+    pub(crate) started: Instant,
+    pub(crate) fields: Value,
+    /// Allocation counters when the span started, while they are tracked.
+    pub(crate) allocated: Option<(u64, u64)>,
+}
+
+impl SpanGuard {
+    /// Adds a field to the `.done` event, for what is only known at the end.
+    pub(crate) fn record(&mut self, key: &str, value: Value) {
+        if let Value::Object(map) = &mut self.fields {
+            map.insert(key.to_string(), value);
+        }
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let mut fields = match self.fields.clone() {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        };
+        fields.insert(
+            "duration_ms".to_string(),
+            json!(self.started.elapsed().as_millis()),
+        );
+        // Process-wide, so other threads' allocations count too.
+        if let Some(((bytes, allocs), (bytes_before, allocs_before))) =
+            heap::counters().zip(self.allocated)
+        {
+            fields.insert("alloc_bytes".to_string(), json!(bytes - bytes_before));
+            fields.insert("allocs".to_string(), json!(allocs - allocs_before));
+        }
+        let name = format!("{}.done", self.name);
+        remember_span(&name, Value::Object(fields.clone()));
+        if let Some(profiler) = self.profiler.as_ref() {
+            profiler.event(&name, Value::Object(fields));
+        }
+    }
+}
+
+pub(crate) fn span(ctx: &AppContext, name: &str, fields: Value) -> SpanGuard {
+    start_span(ctx.profiler.clone(), name, fields)
+}
+
+/// A span recorded by the profiler of this run, if it has one.
+pub(crate) fn global_span(name: &str, fields: Value) -> SpanGuard {
+    start_span(ACTIVE.get().cloned(), name, fields)
+}
+
+fn start_span(profiler: Option<Profiler>, name: &str, mut fields: Value) -> SpanGuard {
+    if let Value::Object(map) = &mut fields {
+        map.insert("thread".to_string(), json!(thread_index()));
+    }
+    remember_span(&format!("{}.start", name), fields.clone());
+    if let Some(profiler) = profiler.as_ref() {
+        profiler.event(&format!("{}.start", name), fields.clone());
+    }
+    SpanGuard {
+        profiler,
+        name: name.to_string(),
+        started: Instant::now(),
+        fields,
+        allocated: heap::counters(),
+    }
+}
+
+/// A small number for the current thread, stable for its lifetime, so
+/// `xe profile export` can nest the spans of each thread.
+fn thread_index() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static INDEX: u64 = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    INDEX.with(|index| *index)
+}
+
+pub fn profile_stamp() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0))
+        .as_millis();
+    format!("{millis}")
+}
+
+pub fn timestamp_iso8601() -> String {
+    OffsetDateTime::now_utc()
+        .format(&Iso8601::DEFAULT)
+        .unwrap_or_else(|_| "1970-01-01T00:00:00Z".to_string())
+}
@@ -0,0 +1,1280 @@
+//! Managed CPython and PyPy runtimes.
+
+use crate::archive::ZipExtractor;
+use crate::error::{bail, ErrorKind, Result, ResultExt};
+use crate::net::download_file;
+use crate::net::http_client;
+use crate::net::http_get;
+use crate::net::http_head;
+use crate::net::http_send;
+use crate::paths::remove_dir_all;
+use crate::paths::rename;
+use crate::paths::tempfile_path;
+use crate::paths::tempfile_path_in;
+use crate::paths::xe_home;
+use crate::process::register_cleanup_path;
+use crate::process::unregister_cleanup_path;
+use crate::profile::span;
+use crate::profile::AppContext;
+use crate::shell::add_to_path;
+use crate::ui::info;
+use crate::ui::success;
+use crate::ui::warning;
+use crate::ui::ProgressUnit;
+use crate::ui::TransferProgress;
+use anyhow::{anyhow, Context};
+use regex::Regex;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use zip::ZipArchive;
+
+/// python.org's release directory, listing every CPython version and its
+/// installers.
+pub const PYTHON_DOWNLOADS_URL: &str = "https://www.python.org/ftp/python/";
+
+/// Runtime directories xe installed under `base_dir`. On Windows that is
+/// the standard per-user Python location, which may also hold Pythons
+/// installed by other means, so only these are xe's to remove.
+const RUNTIMES_MANIFEST: &str = "xe-runtimes.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RuntimesManifest {
+    #[serde(default)]
+    runtimes: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PythonManager {
+    pub base_dir: PathBuf,
+    /// The build to install; the host's when `None`.
+    pub arch: Option<PythonArch>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PythonArch {
+    Amd64,
+    Arm64,
+    Win32,
+}
+
+impl PythonArch {
+    pub(crate) fn detect() -> Self {
+        // PROCESSOR_ARCHITEW6432 is only set for emulated processes, so it wins
+        // over PROCESSOR_ARCHITECTURE when an x64 xe runs on an ARM64 host.
+        for var in ["PROCESSOR_ARCHITEW6432", "PROCESSOR_ARCHITECTURE"] {
+            if let Ok(value) = env::var(var) {
+                if let Ok(arch) = Self::parse(&value) {
+                    return arch;
+                }
+            }
+        }
+        match env::consts::ARCH {
+            "aarch64" => Self::Arm64,
+            "x86" => Self::Win32,
+            _ => Self::Amd64,
+        }
+    }
+
+    pub fn parse(raw: &str) -> Result<Self> {
+        match raw.trim().to_lowercase().as_str() {
+            "amd64" | "x64" | "x86_64" | "x86-64" => Ok(Self::Amd64),
+            "arm64" | "aarch64" => Ok(Self::Arm64),
+            "win32" | "x86" | "i386" | "i686" => Ok(Self::Win32),
+            other => bail!("unsupported architecture {other} (expected amd64, arm64 or win32)"),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Amd64 => "amd64",
+            Self::Arm64 => "arm64",
+            Self::Win32 => "win32",
+        }
+    }
+
+    pub(crate) fn installer_url(&self, full_version: &str) -> String {
+        match self {
+            Self::Win32 => format!(
+                "https://www.python.org/ftp/python/{0}/python-{0}.exe",
+                full_version
+            ),
+            _ => format!(
+                "https://www.python.org/ftp/python/{0}/python-{0}-{1}.exe",
+                full_version,
+                self.as_str()
+            ),
+        }
+    }
+
+    pub(crate) fn embeddable_url(&self, full_version: &str) -> String {
+        format!(
+            "https://www.python.org/ftp/python/{0}/python-{0}-embed-{1}.zip",
+            full_version,
+            self.as_str()
+        )
+    }
+}
+
+impl PythonManager {
+    pub fn new() -> Result<Self> {
+        let base_dir = if cfg!(windows) {
+            let local = env::var("LOCALAPPDATA")
+                .ok()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| {
+                    dirs::home_dir()
+                        .unwrap_or_else(|| PathBuf::from("."))
+                        .join("AppData")
+                        .join("Local")
+                });
+            local.join("Programs").join("Python")
+        } else {
+            xe_home().join("python")
+        };
+        fs::create_dir_all(&base_dir)
+            .with_context(|| format!("failed to create {}", base_dir.display()))?;
+        Ok(Self {
+            base_dir,
+            arch: None,
+        })
+    }
+
+    fn arch(&self) -> PythonArch {
+        self.arch.unwrap_or_else(PythonArch::detect)
+    }
+
+    pub fn get_python_path(&self, version: &str) -> Result<PathBuf> {
+        let spec = PythonSpec::parse(version)?;
+        Ok(self.base_dir.join(spec.dir_name()))
+    }
+
+    pub fn get_python_exe(&self, version: &str) -> Result<PathBuf> {
+        let spec = PythonSpec::parse(version)?;
+        python_exe_in(&spec, &self.get_python_path(version)?)
+    }
+
+    pub fn install(&self, version: &str, ctx: &AppContext) -> Result<()> {
+        let _span = span(ctx, "python.install", json!({"version": version}));
+        let spec = PythonSpec::parse(version)?;
+        let mut needs_cleanup = false;
+
+        if let Ok(exe) = self.get_python_exe(version) {
+            // One runtime directory per version: another build of it would
+            // be skipped as installed, or replace the one in use.
+            let installed = runtime_arch(&exe);
+            if let (Some(wanted), Some(installed)) = (self.arch, installed) {
+                if wanted != installed {
+                    bail!(
+                        kind = ErrorKind::Python,
+                        "Python {} is installed at {} as the {} build; remove that directory before installing the {} build",
+                        version,
+                        self.get_python_path(version)?.display(),
+                        installed.as_str(),
+                        wanted.as_str()
+                    );
+                }
+            }
+            // The py launcher only knows about CPython, so PyPy runtimes are
+            // judged healthy on the interpreter alone.
+            let launcher_ok = !cfg!(windows)
+                || spec.implementation == PythonImpl::PyPy
+                || is_windows_launcher_version_available(version);
+            if is_python_runtime_healthy(&exe) && launcher_ok {
+                success(&format!(
+                    "Python {} already installed at {}",
+                    version,
+                    exe.display()
+                ));
+                return Ok(());
+            }
+            needs_cleanup = true;
+            warning(&format!(
+                "Python {} exists at {} but runtime is unhealthy or launcher is missing; repairing with official installer.",
+                version,
+                exe.display()
+            ));
+        }
+
+        if spec.implementation == PythonImpl::PyPy {
+            self.install_pypy(&spec, version)?;
+            return self.record_installed(version);
+        }
+
+        if !cfg!(windows) {
+            bail!("automatic Python installation is currently supported on Windows only");
+        }
+        if spec.freethreaded && (spec.major, spec.minor) < (3, 13) {
+            bail!("free-threaded builds are only available for Python 3.13 and newer");
+        }
+
+        let target_dir = self.get_python_path(version)?;
+        info(&format!(
+            "Installing Python {} to {}...",
+            version,
+            target_dir.display()
+        ));
+        if cfg!(windows) && needs_cleanup && target_dir.exists() {
+            fs::remove_dir_all(&target_dir)
+                .with_context(|| format!("failed to remove {}", target_dir.display()))?;
+        }
+
+        let full_version = resolve_latest_windows_installer_version(&spec.numeric(), self.arch())?;
+        // An interrupted install must not leave a half-populated runtime
+        // that later looks installed.
+        register_cleanup_path(&target_dir);
+        let result = self.run_windows_installer(&spec, version, &full_version, &target_dir);
+        unregister_cleanup_path(&target_dir);
+        result?;
+        self.record_installed(version)
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.base_dir.join(RUNTIMES_MANIFEST)
+    }
+
+    fn read_manifest(&self) -> Result<RuntimesManifest> {
+        let path = self.manifest_path();
+        if !path.exists() {
+            return Ok(RuntimesManifest::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?)
+    }
+
+    /// Adds the runtime of `version` to the manifest of xe-installed runtimes.
+    fn record_installed(&self, version: &str) -> Result<()> {
+        let spec = PythonSpec::parse(version)?;
+        let mut manifest = self.read_manifest()?;
+        if !manifest.runtimes.insert(spec.dir_name()) {
+            return Ok(());
+        }
+        let path = self.manifest_path();
+        let encoded =
+            serde_json::to_string_pretty(&manifest).context("failed to encode runtime manifest")?;
+        fs::write(&path, encoded).with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Directories of the runtimes xe installed that still exist. Runtimes
+    /// from before the manifest existed are not listed.
+    pub fn runtimes_installed_by_xe(&self) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .read_manifest()?
+            .runtimes
+            .into_iter()
+            .map(|name| self.base_dir.join(name))
+            .filter(|dir| dir.is_dir())
+            .collect())
+    }
+
+    /// Forgets every runtime recorded as installed by xe.
+    pub fn clear_manifest(&self) -> Result<()> {
+        let path = self.manifest_path();
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn run_windows_installer(
+        &self,
+        spec: &PythonSpec,
+        version: &str,
+        full_version: &str,
+        target_dir: &Path,
+    ) -> Result<()> {
+        let url = self.arch().installer_url(full_version);
+        info(&format!(
+            "Downloading official Python installer ({}) from {}...",
+            self.arch().as_str(),
+            url
+        ));
+        let tmp_installer = download_file(&url, "python-installer", "exe")?;
+
+        if let Some(parent) = target_dir.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+
+        // Kept out of the temp cleanup so the failure messages below can
+        // point at it; removed only once the install succeeds.
+        let log_dir = xe_home().join("logs");
+        fs::create_dir_all(&log_dir)
+            .with_context(|| format!("failed to create {}", log_dir.display()))?;
+        let log_path = log_dir.join(format!("python-installer-{full_version}.log"));
+        let mut args = vec![
+            "/quiet".to_string(),
+            "/log".to_string(),
+            log_path.display().to_string(),
+            "InstallAllUsers=0".to_string(),
+            "Include_pip=1".to_string(),
+            "Include_launcher=1".to_string(),
+            "PrependPath=1".to_string(),
+            format!("TargetDir={}", target_dir.display()),
+        ];
+        if spec.freethreaded {
+            args.push("Include_freethreaded=1".to_string());
+        }
+
+        let output = Command::new(&tmp_installer)
+            .args(&args)
+            .output()
+            .context("failed to run python installer")?;
+        if !output.status.success() {
+            if spec.freethreaded {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                bail!(
+                    "official installer failed ({}) and no embeddable build exists for free-threaded Python\ninstaller log: {}\n{}",
+                    output.status,
+                    log_path.display(),
+                    stderr
+                );
+            }
+            warning(&format!(
+                "official installer failed ({}); falling back to embeddable distribution",
+                output.status
+            ));
+            info(&format!("Installer log: {}", log_path.display()));
+            self.install_windows_embeddable(full_version, target_dir)?;
+            let exe = self.get_python_exe(version)?;
+            if !is_python_runtime_healthy(&exe) {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                bail!(
+                    "installer fallback completed but runtime is unhealthy at {}\ninstaller log: {}\ninstaller output:\n{}{}",
+                    exe.display(),
+                    log_path.display(),
+                    stdout,
+                    stderr
+                );
+            }
+            success(&format!(
+                "Python {} installed at {}",
+                version,
+                target_dir.display()
+            ));
+            add_to_path(target_dir)?;
+            add_to_path(&target_dir.join("Scripts"))?;
+            success(&format!("Added Python {} to PATH.", version));
+            return Ok(());
+        }
+
+        let exe = self.get_python_exe(version)?;
+        if !is_python_runtime_healthy(&exe) {
+            bail!(
+                "python installer completed but runtime is unhealthy at {}\ninstaller log: {}",
+                exe.display(),
+                log_path.display()
+            );
+        }
+        let _ = fs::remove_file(&log_path);
+        success(&format!(
+            "Python {} installed at {}",
+            version,
+            target_dir.display()
+        ));
+        info("Ensuring Python install directories are in system PATH...");
+        add_to_path(target_dir)?;
+        add_to_path(&target_dir.join("Scripts"))?;
+        success(&format!("Added Python {} to PATH.", version));
+        Ok(())
+    }
+
+    /// Versions of every runtime xe manages under `base_dir`, derived from the
+    /// `python312` / `python313t` / `pypy310` directory layout.
+    pub fn installed_versions(&self) -> Result<Vec<String>> {
+        let mut out = Vec::new();
+        for entry in fs::read_dir(&self.base_dir)
+            .with_context(|| format!("failed to read {}", self.base_dir.display()))?
+        {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            let (prefix, digits) = if let Some(rest) = name.strip_prefix("pypy") {
+                ("pypy", rest)
+            } else if let Some(rest) = name.strip_prefix("python") {
+                ("", rest)
+            } else {
+                continue;
+            };
+            let (digits, suffix) = match digits.strip_suffix('t') {
+                Some(d) => (d, "t"),
+                None => (digits, ""),
+            };
+            if digits.len() < 2 || !digits.chars().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            let version = format!("{prefix}{}.{}{suffix}", &digits[..1], &digits[1..]);
+            if self.get_python_exe(&version).is_ok() {
+                out.push(version);
+            }
+        }
+        out.sort_by(|a, b| compare_version(a, b));
+        Ok(out)
+    }
+
+    /// Moves an installed minor version to its newest patch release in place.
+    /// Returns what changed when an upgrade happened.
+    pub fn upgrade(&self, version: &str, ctx: &AppContext) -> Result<Option<PythonUpgrade>> {
+        let _span = span(ctx, "python.upgrade", json!({"version": version}));
+        let spec = PythonSpec::parse(version)?;
+        let exe = self.get_python_exe(version)?;
+        // The build that is installed is upgraded, whatever the host's.
+        let pm = Self {
+            base_dir: self.base_dir.clone(),
+            arch: runtime_arch(&exe).or(self.arch),
+        };
+        let current =
+            python_full_version(&exe, "import platform; print(platform.python_version())")?;
+        if spec.implementation == PythonImpl::PyPy {
+            let current_pypy = python_full_version(
+                &exe,
+                "import sys; print('.'.join(map(str, sys.pypy_version_info[:3])))",
+            )?;
+            let release = resolve_pypy_release(&spec, pm.arch())?;
+            if compare_version(&release.pypy_version, &current_pypy) != Ordering::Greater {
+                return Ok(None);
+            }
+            pm.install_pypy(&spec, version)?;
+            return Ok(Some(PythonUpgrade {
+                old: current_pypy,
+                new: release.pypy_version,
+                old_python: current,
+            }));
+        }
+        if !cfg!(windows) {
+            bail!("automatic Python upgrades are currently supported on Windows only");
+        }
+        let latest = resolve_latest_windows_installer_version(
+            &format!("{}.{}", spec.major, spec.minor),
+            pm.arch(),
+        )?;
+        if compare_version(&latest, &current) != Ordering::Greater {
+            return Ok(None);
+        }
+        let target_dir = self.get_python_path(version)?;
+        info(&format!(
+            "Upgrading Python {} from {} to {}...",
+            version, current, latest
+        ));
+        pm.run_windows_installer(&spec, version, &latest, &target_dir)?;
+        Ok(Some(PythonUpgrade {
+            old: current.clone(),
+            new: latest,
+            old_python: current,
+        }))
+    }
+
+    pub(crate) fn install_windows_embeddable(
+        &self,
+        full_version: &str,
+        target_dir: &Path,
+    ) -> Result<()> {
+        let url = self.arch().embeddable_url(full_version);
+        info(&format!("Downloading embeddable Python from {}...", url));
+        let zip_path = download_file(&url, "python-embed", "zip")?;
+        // Whatever is in `target_dir`, perhaps what a failed installer left,
+        // stays until the embeddable build is known to run.
+        let parent = target_dir.parent().unwrap_or(&self.base_dir);
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+        let staging = tempfile_path_in(parent, "python-embed", "dir");
+        let staged = (|| {
+            fs::create_dir_all(&staging)
+                .with_context(|| format!("failed to create {}", staging.display()))?;
+            extract_zip_to_dir(&zip_path, &staging)?;
+            patch_embeddable_pth(&staging)?;
+            let exe = staging.join("python.exe");
+            if !is_python_runtime_healthy(&exe) {
+                bail!(
+                    "embeddable Python {} does not run on this machine ({})",
+                    full_version,
+                    exe.display()
+                );
+            }
+            swap_in_runtime(&staging, target_dir)
+        })();
+        let _ = fs::remove_file(&zip_path);
+        if staged.is_err() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+        unregister_cleanup_path(&staging);
+        staged?;
+
+        let exe = target_dir.join("python.exe");
+        if exe.exists() {
+            if let Err(err) = bootstrap_pip(&exe) {
+                warning(&format!("Pip bootstrap failed: {err}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs the newest PyPy release for `spec`. It is unpacked and
+    /// checked next to the runtime it replaces, which stays in place when
+    /// any step up to the swap fails.
+    pub(crate) fn install_pypy(&self, spec: &PythonSpec, version: &str) -> Result<()> {
+        let target_dir = self.get_python_path(version)?;
+        let release = resolve_pypy_release(spec, self.arch())?;
+        info(&format!(
+            "Installing PyPy {} (Python {}) to {}...",
+            release.pypy_version,
+            release.python_version,
+            target_dir.display()
+        ));
+        info(&format!(
+            "Downloading PyPy from {}...",
+            release.download_url
+        ));
+        let ext = if release.download_url.ends_with(".zip") {
+            "zip"
+        } else {
+            "tar.bz2"
+        };
+        let archive = download_file(&release.download_url, "pypy", ext)?;
+
+        // PyPy archives wrap everything in a single versioned top-level
+        // directory; unpack next to the target and move that directory in.
+        let staging = tempfile_path_in(&self.base_dir, "pypy-staging", "dir");
+        let staged = (|| {
+            fs::create_dir_all(&staging)
+                .with_context(|| format!("failed to create {}", staging.display()))?;
+            if ext == "zip" {
+                extract_zip_to_dir(&archive, &staging)?;
+            } else {
+                let status = Command::new("tar")
+                    .arg("-xjf")
+                    .arg(&archive)
+                    .arg("-C")
+                    .arg(&staging)
+                    .status()
+                    .context("failed to run tar")?;
+                if !status.success() {
+                    bail!("failed to extract {}: {}", archive.display(), status);
+                }
+            }
+            let mut roots = fs::read_dir(&staging)
+                .with_context(|| format!("failed to read {}", staging.display()))?
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_dir())
+                .map(|e| e.path())
+                .collect::<Vec<_>>();
+            let root = if roots.len() == 1 {
+                roots.remove(0)
+            } else {
+                staging.clone()
+            };
+            let exe = python_exe_in(spec, &root)?;
+            if !is_python_runtime_healthy(&exe) {
+                bail!(
+                    "PyPy {} does not run on this machine ({})",
+                    release.pypy_version,
+                    exe.display()
+                );
+            }
+            swap_in_runtime(&root, &target_dir)
+        })();
+        let _ = fs::remove_file(&archive);
+        let _ = fs::remove_dir_all(&staging);
+        unregister_cleanup_path(&staging);
+        staged?;
+
+        // pip goes in only now: its scripts record the interpreter's path.
+        let exe = self.get_python_exe(version)?;
+        let ensurepip = Command::new(&exe)
+            .args(["-m", "ensurepip", "--default-pip"])
+            .output()
+            .context("failed to run ensurepip")?;
+        if !ensurepip.status.success() {
+            if let Err(err) = bootstrap_pip(&exe) {
+                warning(&format!("Pip bootstrap failed: {err}"));
+            }
+        }
+        success(&format!(
+            "PyPy {} installed at {}",
+            version,
+            target_dir.display()
+        ));
+        Ok(())
+    }
+
+    pub(crate) fn get_site_packages_dir(&self, version: &str) -> Result<PathBuf> {
+        let spec = PythonSpec::parse(version)?;
+        let python_dir = self.get_python_path(version)?;
+        if cfg!(windows) {
+            let tools_lib = python_dir.join("tools").join("Lib");
+            let lib_root = if tools_lib.exists() {
+                python_dir.join("tools")
+            } else {
+                python_dir
+            };
+            let site = lib_root.join("Lib").join("site-packages");
+            fs::create_dir_all(&site)
+                .with_context(|| format!("failed to create {}", site.display()))?;
+            return Ok(site);
+        }
+        let site = python_dir
+            .join("lib")
+            .join(spec.lib_dir_name())
+            .join("site-packages");
+        fs::create_dir_all(&site)
+            .with_context(|| format!("failed to create {}", site.display()))?;
+        Ok(site)
+    }
+}
+
+/// A runtime [`PythonManager::upgrade`] replaced in place.
+#[derive(Debug, Clone)]
+pub struct PythonUpgrade {
+    /// Releases before and after; for PyPy these are PyPy's own versions.
+    pub old: String,
+    pub new: String,
+    /// The Python version the old runtime implemented, as venvs built from
+    /// it record it in `pyvenv.cfg`.
+    pub old_python: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PythonImpl {
+    CPython,
+    PyPy,
+}
+
+/// A parsed interpreter request such as `3.12`, `3.13t`, `cpython3.11.9` or
+/// `pypy3.10`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PythonSpec {
+    pub(crate) implementation: PythonImpl,
+    pub(crate) major: u32,
+    pub(crate) minor: u32,
+    pub(crate) patch: Option<u32>,
+    pub(crate) freethreaded: bool,
+}
+
+impl PythonSpec {
+    pub fn parse(version: &str) -> Result<Self> {
+        let raw = version.trim().to_lowercase();
+        let (implementation, rest) = if let Some(rest) = raw.strip_prefix("pypy") {
+            (PythonImpl::PyPy, rest)
+        } else if let Some(rest) = raw.strip_prefix("cpython") {
+            (PythonImpl::CPython, rest)
+        } else if let Some(rest) = raw.strip_prefix("python") {
+            (PythonImpl::CPython, rest)
+        } else {
+            (PythonImpl::CPython, raw.as_str())
+        };
+        let (rest, freethreaded) = match rest.strip_suffix('t') {
+            Some(stripped) => (stripped, true),
+            None => (rest, false),
+        };
+        if freethreaded && implementation == PythonImpl::PyPy {
+            bail!("free-threaded builds are not available for PyPy ({version})");
+        }
+        let (major, minor) =
+            parse_major_minor(rest).with_context(|| format!("invalid python version {version}"))?;
+        let patch = rest
+            .split('.')
+            .nth(2)
+            .map(|p| p.parse::<u32>())
+            .transpose()
+            .with_context(|| format!("invalid python patch version in {version}"))?;
+        Ok(Self {
+            implementation,
+            major,
+            minor,
+            patch,
+            freethreaded,
+        })
+    }
+
+    pub(crate) fn numeric(&self) -> String {
+        match self.patch {
+            Some(patch) => format!("{}.{}.{}", self.major, self.minor, patch),
+            None => format!("{}.{}", self.major, self.minor),
+        }
+    }
+
+    pub(crate) fn dir_name(&self) -> String {
+        match self.implementation {
+            PythonImpl::PyPy => format!("pypy{}{}", self.major, self.minor),
+            PythonImpl::CPython if self.freethreaded => {
+                format!("python{}{}t", self.major, self.minor)
+            }
+            PythonImpl::CPython => format!("python{}{}", self.major, self.minor),
+        }
+    }
+
+    pub(crate) fn lib_dir_name(&self) -> String {
+        match self.implementation {
+            PythonImpl::PyPy => format!("pypy{}.{}", self.major, self.minor),
+            PythonImpl::CPython if self.freethreaded => {
+                format!("python{}.{}t", self.major, self.minor)
+            }
+            PythonImpl::CPython => format!("python{}.{}", self.major, self.minor),
+        }
+    }
+
+    /// The interpreter part of a wheel tag (`cp312`, `cp313t`, `pp310`).
+    pub(crate) fn interpreter_tag(&self) -> String {
+        let prefix = match self.implementation {
+            PythonImpl::CPython => "cp",
+            PythonImpl::PyPy => "pp",
+        };
+        let suffix = if self.freethreaded { "t" } else { "" };
+        format!("{prefix}{}{}{suffix}", self.major, self.minor)
+    }
+
+    pub fn shim_name(&self) -> String {
+        match self.implementation {
+            PythonImpl::PyPy => format!("pypy{}{}", self.major, self.minor),
+            PythonImpl::CPython => self.dir_name(),
+        }
+    }
+}
+
+impl std::fmt::Display for PythonSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.implementation {
+            PythonImpl::PyPy => write!(f, "PyPy {}", self.numeric()),
+            PythonImpl::CPython if self.freethreaded => {
+                write!(f, "CPython {} (free-threaded)", self.numeric())
+            }
+            PythonImpl::CPython => write!(f, "CPython {}", self.numeric()),
+        }
+    }
+}
+
+pub(crate) fn parse_major_minor(version: &str) -> Result<(u32, u32)> {
+    let parts: Vec<&str> = version.split('.').collect();
+    if parts.len() < 2 {
+        bail!("invalid python version {}", version);
+    }
+    let major = parts[0]
+        .parse::<u32>()
+        .with_context(|| format!("invalid python major version in {version}"))?;
+    let minor = parts[1]
+        .parse::<u32>()
+        .with_context(|| format!("invalid python minor version in {version}"))?;
+    Ok((major, minor))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PypyRelease {
+    pub(crate) pypy_version: String,
+    pub(crate) python_version: String,
+    #[serde(default)]
+    pub(crate) stable: bool,
+    #[serde(default)]
+    pub(crate) files: Vec<PypyFile>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PypyFile {
+    #[serde(default)]
+    pub(crate) arch: String,
+    #[serde(default)]
+    pub(crate) platform: String,
+    pub(crate) download_url: String,
+}
+
+pub(crate) struct PypyDownload {
+    pub(crate) pypy_version: String,
+    pub(crate) python_version: String,
+    pub(crate) download_url: String,
+}
+
+pub(crate) fn resolve_pypy_release(spec: &PythonSpec, arch: PythonArch) -> Result<PypyDownload> {
+    let client = http_client(Duration::from_secs(30))?;
+    let releases = http_send(http_get(
+        &client,
+        "https://downloads.python.org/pypy/versions.json",
+    ))
+        .context("failed to request PyPy release index")?
+        .error_for_status()
+        .context("PyPy release index request failed")?
+        .json::<Vec<PypyRelease>>()
+        .context("failed to parse PyPy release index")?;
+
+    let platform = match env::consts::OS {
+        "windows" => "win64",
+        "macos" => "darwin",
+        _ => "linux",
+    };
+    let arch_names: &[&str] = match arch {
+        PythonArch::Arm64 => &["aarch64", "arm64"],
+        PythonArch::Win32 => &["i686", "x86"],
+        PythonArch::Amd64 => &["x64", "x86_64"],
+    };
+    let prefix = format!("{}.", spec.numeric());
+    let mut best: Option<PypyDownload> = None;
+    for release in releases {
+        if !release.stable || !release.python_version.starts_with(&prefix) {
+            continue;
+        }
+        let file = release
+            .files
+            .iter()
+            .find(|f| f.platform == platform && arch_names.contains(&f.arch.as_str()));
+        let Some(file) = file else {
+            continue;
+        };
+        let newer = best
+            .as_ref()
+            .map(|b| compare_version(&release.pypy_version, &b.pypy_version) == Ordering::Greater)
+            .unwrap_or(true);
+        if newer {
+            best = Some(PypyDownload {
+                pypy_version: release.pypy_version.clone(),
+                python_version: release.python_version.clone(),
+                download_url: file.download_url.clone(),
+            });
+        }
+    }
+    best.ok_or_else(|| {
+        anyhow!(
+            "no stable PyPy build for Python {} on {}-{}",
+            spec.numeric(),
+            platform,
+            arch.as_str()
+        )
+    })
+    .kind(ErrorKind::Python)
+}
+
+pub(crate) fn resolve_latest_windows_installer_version(
+    version: &str,
+    arch: PythonArch,
+) -> Result<String> {
+    let parts: Vec<&str> = version.split('.').collect();
+    if parts.len() >= 3 {
+        return Ok(version.to_string());
+    }
+    if parts.len() != 2 {
+        return Ok(version.to_string());
+    }
+
+    let latest = resolve_latest_patch_version(version)?;
+    if windows_installer_exists(&latest, arch) {
+        return Ok(latest);
+    }
+    if let Some(fallback) = windows_installer_fallback(version) {
+        if windows_installer_exists(fallback, arch) {
+            return Ok(fallback.to_string());
+        }
+    }
+
+    let mut candidates = list_patch_versions(version)?;
+    candidates.sort_by(|a, b| compare_version(a, b).reverse());
+    for candidate in &candidates {
+        if windows_installer_exists(candidate, arch) {
+            return Ok(candidate.clone());
+        }
+    }
+
+    if let Some(fallback) = windows_installer_fallback(version) {
+        return Ok(fallback.to_string());
+    }
+    Ok(format!("{version}.0"))
+}
+
+pub(crate) fn resolve_latest_patch_version(version: &str) -> Result<String> {
+    let parts: Vec<&str> = version.split('.').collect();
+    if parts.len() >= 3 {
+        return Ok(version.to_string());
+    }
+    if parts.len() != 2 {
+        return Ok(version.to_string());
+    }
+    let mut candidates = list_patch_versions(version)?;
+    if candidates.is_empty() {
+        return Ok(format!("{version}.0"));
+    }
+    candidates.sort_by(|a, b| compare_version(a, b).reverse());
+    Ok(candidates[0].clone())
+}
+
+pub(crate) fn list_patch_versions(version: &str) -> Result<Vec<String>> {
+    let client = http_client(Duration::from_secs(30))?;
+    let body = http_send(http_get(&client, PYTHON_DOWNLOADS_URL))
+        .context("failed to request python FTP listing")?
+        .error_for_status()
+        .context("python FTP listing request failed")?
+        .text()
+        .context("failed to decode python FTP response")?;
+    let re = Regex::new(r#"href="(\d+\.\d+\.\d+)/""#).unwrap();
+    let prefix = format!("{version}.");
+    let mut out = Vec::new();
+    for cap in re.captures_iter(&body) {
+        let value = cap.get(1).map(|m| m.as_str()).unwrap_or_default();
+        if value.starts_with(&prefix) {
+            out.push(value.to_string());
+        }
+    }
+    Ok(out)
+}
+
+pub(crate) fn windows_installer_fallback(version: &str) -> Option<&'static str> {
+    match version {
+        "3.9" => Some("3.9.13"),
+        "3.10" => Some("3.10.11"),
+        "3.11" => Some("3.11.9"),
+        "3.12" => Some("3.12.3"),
+        "3.13" => Some("3.13.0"),
+        _ => None,
+    }
+}
+
+pub(crate) fn windows_installer_exists(version: &str, arch: PythonArch) -> bool {
+    if version.trim().is_empty() {
+        return false;
+    }
+    let url = arch.installer_url(version);
+    let client = match http_client(Duration::from_secs(20)) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    match http_send(http_head(&client, &url)) {
+        Ok(resp) => {
+            if resp.status() == StatusCode::METHOD_NOT_ALLOWED {
+                http_send(http_get(&client, &url).header("Range", "bytes=0-0"))
+                    .map(|r| r.status().is_success() || r.status() == StatusCode::PARTIAL_CONTENT)
+                    .unwrap_or(false)
+            } else {
+                resp.status().is_success()
+            }
+        }
+        Err(_) => false,
+    }
+}
+
+pub fn compare_version(a: &str, b: &str) -> Ordering {
+    let pa: Vec<u32> = a
+        .split('.')
+        .map(|s| s.parse::<u32>().unwrap_or(0))
+        .collect();
+    let pb: Vec<u32> = b
+        .split('.')
+        .map(|s| s.parse::<u32>().unwrap_or(0))
+        .collect();
+    let n = pa.len().max(pb.len());
+    for i in 0..n {
+        let va = *pa.get(i).unwrap_or(&0);
+        let vb = *pb.get(i).unwrap_or(&0);
+        if va > vb {
+            return Ordering::Greater;
+        }
+        if va < vb {
+            return Ordering::Less;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Whether a version specifier such as the `Requires-Python` value
+/// `>=3.9, !=3.9.1, <4` admits `version`. Only release numbers are
+/// compared; clauses that do not parse admit everything, as pip treats
+/// malformed metadata.
+pub fn specifier_allows(specifier: &str, version: &str) -> bool {
+    specifier
+        .split(',')
+        .map(str::trim)
+        .filter(|clause| !clause.is_empty())
+        .all(|clause| clause_allows(clause, version))
+}
+
+fn clause_allows(clause: &str, version: &str) -> bool {
+    const OPERATORS: [&str; 8] = ["===", "==", "!=", "~=", ">=", "<=", ">", "<"];
+    let Some(op) = OPERATORS.iter().find(|op| clause.starts_with(**op)) else {
+        return true;
+    };
+    let target = clause[op.len()..].trim();
+    if let Some(prefix) = target.strip_suffix(".*") {
+        let matches = version_has_prefix(version, prefix);
+        return match *op {
+            "==" => matches,
+            "!=" => !matches,
+            _ => true,
+        };
+    }
+    if target.is_empty() || !target.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return true;
+    }
+    let ord = compare_version(version, target);
+    match *op {
+        "==" | "===" => ord == Ordering::Equal,
+        "!=" => ord != Ordering::Equal,
+        ">=" => ord != Ordering::Less,
+        "<=" => ord != Ordering::Greater,
+        ">" => ord == Ordering::Greater,
+        "<" => ord == Ordering::Less,
+        // `~=3.8` is `>=3.8, ==3.*`.
+        _ => {
+            ord != Ordering::Less
+                && target
+                    .rsplit_once('.')
+                    .is_none_or(|(prefix, _)| version_has_prefix(version, prefix))
+        }
+    }
+}
+
+/// `3.11.4` has the prefix `3.11` and `3`, but not `3.1`.
+fn version_has_prefix(version: &str, prefix: &str) -> bool {
+    let mut parts = version.split('.');
+    prefix
+        .split('.')
+        .all(|want| parts.next().unwrap_or("0").parse::<u32>().ok() == want.parse::<u32>().ok())
+}
+
+pub(crate) fn is_windows_launcher_version_available(version: &str) -> bool {
+    if !cfg!(windows) {
+        return false;
+    }
+    let Ok(spec) = PythonSpec::parse(version) else {
+        return false;
+    };
+    let selector = format!(
+        "{}.{}{}",
+        spec.major,
+        spec.minor,
+        if spec.freethreaded { "t" } else { "" }
+    );
+    let mut candidates = vec![PathBuf::from("py")];
+    if let Ok(win_dir) = env::var("WINDIR") {
+        candidates.push(PathBuf::from(win_dir).join("py.exe"));
+    }
+    if let Ok(local_app_data) = env::var("LOCALAPPDATA") {
+        candidates.push(
+            PathBuf::from(local_app_data)
+                .join("Programs")
+                .join("Python")
+                .join("Launcher")
+                .join("py.exe"),
+        );
+    }
+    for candidate in candidates {
+        let status = Command::new(&candidate)
+            .arg(format!("-{}", selector))
+            .arg("-V")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if let Ok(status) = status {
+            if status.success() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+pub fn python_full_version(exe: &Path, script: &str) -> Result<String> {
+    let output = Command::new(exe)
+        .args(["-c", script])
+        .output()
+        .with_context(|| format!("failed to run {}", exe.display()))?;
+    if !output.status.success() {
+        bail!("failed to query version of {}", exe.display());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The architecture `exe` was built for, from its `sysconfig` platform
+/// (`win-arm64`, `win32`, `linux-x86_64`); `None` for universal builds.
+pub(crate) fn runtime_arch(exe: &Path) -> Option<PythonArch> {
+    let platform =
+        python_full_version(exe, "import sysconfig; print(sysconfig.get_platform())").ok()?;
+    PythonArch::parse(platform.rsplit('-').next()?).ok()
+}
+
+/// Moves the runtime checked at `staged` into `target_dir`. A runtime
+/// already there is set aside first and put back if the move fails.
+fn swap_in_runtime(staged: &Path, target_dir: &Path) -> Result<()> {
+    let previous = match target_dir.parent() {
+        Some(parent) if target_dir.exists() => {
+            let previous = tempfile_path_in(parent, "python-previous", "dir");
+            // An interrupt between the two renames must not delete it.
+            unregister_cleanup_path(&previous);
+            rename(target_dir, &previous)
+                .with_context(|| format!("failed to move {} aside", target_dir.display()))?;
+            Some(previous)
+        }
+        _ => None,
+    };
+    let moved = rename(staged, target_dir);
+    if let (Err(_), Some(previous)) = (&moved, &previous) {
+        let _ = rename(previous, target_dir);
+    }
+    moved.with_context(|| {
+        format!(
+            "failed to move the new runtime into {}",
+            target_dir.display()
+        )
+    })?;
+    if let Some(previous) = previous {
+        let _ = remove_dir_all(&previous);
+    }
+    Ok(())
+}
+
+/// The interpreter of the `spec` runtime installed in `python_dir`.
+pub(crate) fn python_exe_in(spec: &PythonSpec, python_dir: &Path) -> Result<PathBuf> {
+    let candidates: Vec<PathBuf> = match (spec.implementation, spec.freethreaded) {
+        (PythonImpl::PyPy, _) if cfg!(windows) => {
+            vec![python_dir.join("pypy3.exe"), python_dir.join("python.exe")]
+        }
+        (PythonImpl::PyPy, _) => vec![
+            python_dir.join("bin").join("pypy3"),
+            python_dir.join("bin").join("python"),
+        ],
+        (PythonImpl::CPython, true) if cfg!(windows) => {
+            vec![python_dir.join(format!("python{}.{}t.exe", spec.major, spec.minor))]
+        }
+        (PythonImpl::CPython, true) => vec![python_dir
+            .join("bin")
+            .join(format!("python{}.{}t", spec.major, spec.minor))],
+        (PythonImpl::CPython, false) => Vec::new(),
+    };
+    if !candidates.is_empty() {
+        if let Some(found) = candidates.iter().find(|p| p.exists()) {
+            return Ok(found.clone());
+        }
+        bail!(
+            kind = ErrorKind::Python,
+            "{} interpreter not found in {}",
+            spec,
+            python_dir.display()
+        );
+    }
+    if cfg!(windows) {
+        let tools = python_dir.join("tools").join("python.exe");
+        if tools.exists() {
+            return Ok(tools);
+        }
+        let root = python_dir.join("python.exe");
+        if root.exists() {
+            return Ok(root);
+        }
+        bail!(
+            kind = ErrorKind::Python,
+            "python.exe not found in {}",
+            python_dir.display()
+        );
+    }
+    let py3 = python_dir.join("bin").join("python3");
+    if py3.exists() {
+        return Ok(py3);
+    }
+    let py = python_dir.join("bin").join("python");
+    if py.exists() {
+        return Ok(py);
+    }
+    bail!(
+        kind = ErrorKind::Python,
+        "python/python3 not found in {}",
+        python_dir.join("bin").display()
+    );
+}
+
+pub(crate) fn is_python_runtime_healthy(exe: &Path) -> bool {
+    let output = Command::new(exe)
+        .args(["-c", "import encodings,site; print('ok')"])
+        .output();
+    match output {
+        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout).contains("ok"),
+        _ => false,
+    }
+}
+
+pub(crate) fn extract_zip_to_dir(zip_path: &Path, target_dir: &Path) -> Result<()> {
+    let file =
+        File::open(zip_path).with_context(|| format!("failed to open {}", zip_path.display()))?;
+    let mut archive =
+        ZipArchive::new(file).with_context(|| format!("failed to parse {}", zip_path.display()))?;
+    let mut progress = TransferProgress::new(
+        "Extracting",
+        Some(archive.len() as u64),
+        ProgressUnit::Items,
+    );
+    let mut extractor = ZipExtractor::new(target_dir, archive.len())?;
+    for index in 0..archive.len() {
+        progress.advance(1);
+        let mut entry = archive
+            .by_index(index)
+            .with_context(|| format!("failed to read entry {}", index))?;
+        extractor.extract(&mut entry)?;
+    }
+    extractor.finish()?;
+    progress.finish();
+    Ok(())
+}
+
+pub(crate) fn patch_embeddable_pth(python_dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(python_dir)
+        .with_context(|| format!("failed to read {}", python_dir.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.to_lowercase().ends_with("._pth") {
+            continue;
+        }
+        let path = entry.path();
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if content.contains("\nimport site") || content.starts_with("import site") {
+            continue;
+        }
+        let updated = if content.contains("#import site") {
+            content.replace("#import site", "import site")
+        } else {
+            format!("{content}\nimport site\n")
+        };
+        fs::write(&path, updated).with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn bootstrap_pip(python_exe: &Path) -> Result<()> {
+    info("Bootstrapping pip...");
+    let client = http_client(Duration::from_secs(120))?;
+    let mut resp = http_send(http_get(&client, "https://bootstrap.pypa.io/get-pip.py"))
+        .context("failed to download get-pip.py")?;
+    if !resp.status().is_success() {
+        bail!("failed to download get-pip.py: {}", resp.status());
+    }
+    let script_path = tempfile_path("xe-get-pip", "py");
+    let mut script_file = File::create(&script_path)
+        .with_context(|| format!("failed to create {}", script_path.display()))?;
+    io::copy(&mut resp, &mut script_file)
+        .with_context(|| format!("failed to write {}", script_path.display()))?;
+
+    let output = Command::new(python_exe)
+        .arg(&script_path)
+        .output()
+        .context("failed to bootstrap pip")?;
+    let _ = fs::remove_file(&script_path);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        bail!(
+            "failed to bootstrap pip: {}\n{}{}",
+            output.status,
+            stdout,
+            stderr
+        );
+    }
+    Ok(())
+}
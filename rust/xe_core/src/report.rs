@@ -0,0 +1,296 @@
+//! Reporters: where progress events, transfer progress and the outcome of
+//! long operations go. The global `--progress` flag picks one of the
+//! built-in progress reporters; `[notifications] url` adds a webhook that
+//! is told how each sync, lock and publish went.
+
+use crate::error::{bail, Result};
+use crate::net::http_client;
+use crate::net::{http_send_secret, url_host};
+use crate::ui::format_bytes;
+use crate::ui::warning;
+use crate::ui::ProgressUnit;
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Receives what xe reports while it works. Every method defaults to doing
+/// nothing, so a reporter only implements what it cares about.
+pub trait Reporter: Send + Sync {
+    /// A progress event such as `resolve.finished`; `fields` is an object.
+    fn event(&self, _event: &str, _fields: &Value) {}
+
+    /// A [`crate::ui::TransferProgress`] moved on, at most every 100 ms.
+    fn transfer(&self, _update: &TransferUpdate<'_>) {}
+
+    /// A long operation finished, successfully or not.
+    fn finished(&self, _summary: &Summary) {}
+}
+
+/// State of one transfer, as passed to [`Reporter::transfer`].
+pub struct TransferUpdate<'a> {
+    pub label: &'a str,
+    pub unit: ProgressUnit,
+    pub done: u64,
+    pub total: Option<u64>,
+    pub elapsed: Duration,
+    /// Set on the last update of the transfer.
+    pub finished: bool,
+}
+
+/// Outcome of a sync, lock or publish.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub operation: String,
+    pub project: String,
+    pub ok: bool,
+    pub duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The built-in progress reporters, from the global `--progress` flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// `fancy` when stderr is a terminal, `plain` otherwise.
+    Auto,
+    /// Progress bars on stderr.
+    Fancy,
+    /// Status lines only.
+    Plain,
+    /// One JSON event per line on stderr, for IDEs and wrappers.
+    Json,
+}
+
+impl ProgressFormat {
+    pub fn parse(raw: &str) -> Result<Self> {
+        Ok(match raw {
+            "auto" => Self::Auto,
+            "fancy" => Self::Fancy,
+            "plain" | "never" => Self::Plain,
+            "json" => Self::Json,
+            _ => bail!("invalid --progress value '{raw}'; expected auto, fancy, plain or json"),
+        })
+    }
+
+    pub fn reporter(self) -> Box<dyn Reporter> {
+        match self {
+            Self::Auto if io::stderr().is_terminal() => Box::new(FancyReporter),
+            Self::Auto | Self::Plain => Box::new(PlainReporter),
+            Self::Fancy => Box::new(FancyReporter),
+            Self::Json => Box::new(JsonReporter),
+        }
+    }
+}
+
+static REPORTERS: OnceLock<Vec<Box<dyn Reporter>>> = OnceLock::new();
+
+/// Fixes the reporters for the rest of the process. Until this is called,
+/// `--progress auto` applies.
+pub fn init_reporters(reporters: Vec<Box<dyn Reporter>>) {
+    let _ = REPORTERS.set(reporters);
+}
+
+fn reporters() -> &'static [Box<dyn Reporter>] {
+    REPORTERS.get_or_init(|| vec![ProgressFormat::Auto.reporter()])
+}
+
+pub fn progress_event(event: &str, fields: Value) {
+    for reporter in reporters() {
+        reporter.event(event, &fields);
+    }
+}
+
+pub fn report_transfer(update: &TransferUpdate<'_>) {
+    for reporter in reporters() {
+        reporter.transfer(update);
+    }
+}
+
+pub fn report_finished(summary: &Summary) {
+    for reporter in reporters() {
+        reporter.finished(summary);
+    }
+}
+
+/// Leaves the terminal to the status lines the commands print.
+pub struct PlainReporter;
+
+impl Reporter for PlainReporter {}
+
+/// A single-line bar on stderr for each transfer.
+pub struct FancyReporter;
+
+impl Reporter for FancyReporter {
+    fn transfer(&self, update: &TransferUpdate<'_>) {
+        let elapsed = update.elapsed.as_secs_f64().max(0.001);
+        let rate = update.done as f64 / elapsed;
+        let amount = |v: u64| match update.unit {
+            ProgressUnit::Bytes => format_bytes(v),
+            ProgressUnit::Items => v.to_string(),
+        };
+        let line = match update.total {
+            Some(total) => {
+                let ratio = (update.done as f64 / total as f64).min(1.0);
+                let filled = (ratio * 24.0) as usize;
+                let remaining = total.saturating_sub(update.done) as f64;
+                let eta = if rate > 0.0 {
+                    format!("ETA {}s", (remaining / rate).ceil() as u64)
+                } else {
+                    "ETA --".to_string()
+                };
+                let speed = match update.unit {
+                    ProgressUnit::Bytes => format!("{}/s", format_bytes(rate as u64)),
+                    ProgressUnit::Items => format!("{:.0}/s", rate),
+                };
+                format!(
+                    "{} [{}{}] {}/{} {} {}",
+                    update.label,
+                    "#".repeat(filled),
+                    " ".repeat(24 - filled),
+                    amount(update.done),
+                    amount(total),
+                    speed,
+                    eta
+                )
+            }
+            None => format!("{} {}", update.label, amount(update.done)),
+        };
+        eprint!("\r  {line:<100}");
+        if update.finished {
+            eprintln!();
+        }
+        io::stderr().flush().ok();
+    }
+}
+
+/// Every event, transfer and summary as one JSON object per line on stderr.
+pub struct JsonReporter;
+
+impl JsonReporter {
+    fn write(event: &str, fields: &Value) {
+        let mut object = Map::new();
+        object.insert("event".to_string(), json!(event));
+        if let Value::Object(fields) = fields {
+            object.extend(fields.clone());
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = writeln!(stderr, "{}", Value::Object(object));
+    }
+}
+
+impl Reporter for JsonReporter {
+    fn event(&self, event: &str, fields: &Value) {
+        Self::write(event, fields);
+    }
+
+    fn transfer(&self, update: &TransferUpdate<'_>) {
+        let unit = match update.unit {
+            ProgressUnit::Bytes => "bytes",
+            ProgressUnit::Items => "items",
+        };
+        Self::write(
+            "transfer.progress",
+            &json!({
+                "label": update.label,
+                "unit": unit,
+                "done": update.done,
+                "total": update.total,
+            }),
+        );
+    }
+
+    fn finished(&self, summary: &Summary) {
+        Self::write("operation.finished", &json!(summary));
+    }
+}
+
+/// Posts the summary of each finished operation to `url` as JSON. The
+/// `text` field reads as a chat message, so Slack-style incoming webhooks
+/// can take the payload as is.
+pub struct WebhookReporter {
+    url: String,
+    /// Packages installed and bytes downloaded so far.
+    tally: Mutex<(usize, u64)>,
+}
+
+impl WebhookReporter {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            tally: Mutex::new((0, 0)),
+        }
+    }
+
+    /// The webhook's host; the rest of a webhook URL is its secret.
+    fn host(&self) -> String {
+        url_host(&self.url).unwrap_or_else(|| "<redacted>".to_string())
+    }
+
+    fn post(&self, payload: &Value) -> Result<()> {
+        let client = http_client(Duration::from_secs(10))?;
+        let resp = http_send_secret(
+            client
+                .post(&self.url)
+                .header("Content-Type", "application/json")
+                .body(payload.to_string()),
+        )
+        .with_context(|| format!("failed to reach the webhook at {}", self.host()))?;
+        if !resp.status().is_success() {
+            bail!("the webhook at {} answered {}", self.host(), resp.status());
+        }
+        Ok(())
+    }
+}
+
+impl Reporter for WebhookReporter {
+    fn event(&self, event: &str, fields: &Value) {
+        let Ok(mut tally) = self.tally.lock() else {
+            return;
+        };
+        match event {
+            "install.progress" => tally.0 += 1,
+            "download.finished" => tally.1 += fields["bytes"].as_u64().unwrap_or(0),
+            _ => {}
+        }
+    }
+
+    fn finished(&self, summary: &Summary) {
+        let (installed, downloaded) = self.tally.lock().map(|t| *t).unwrap_or_default();
+        let seconds = summary.duration_ms as f64 / 1000.0;
+        let text = if summary.ok {
+            let mut details = Vec::new();
+            if installed > 0 {
+                details.push(format!("{installed} package(s) installed"));
+            }
+            if downloaded > 0 {
+                details.push(format!("{} downloaded", format_bytes(downloaded)));
+            }
+            let details = if details.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", details.join(", "))
+            };
+            format!(
+                "xe {} of {} succeeded in {seconds:.1}s{details}",
+                summary.operation, summary.project
+            )
+        } else {
+            format!(
+                "xe {} of {} failed after {seconds:.1}s: {}",
+                summary.operation,
+                summary.project,
+                summary.error.as_deref().unwrap_or("unknown error")
+            )
+        };
+        let mut payload = json!(summary);
+        payload["installed"] = json!(installed);
+        payload["downloaded_bytes"] = json!(downloaded);
+        payload["text"] = json!(text);
+        if let Err(err) = self.post(&payload) {
+            warning(&format!("Failed to send notification: {err:#}"));
+        }
+    }
+}
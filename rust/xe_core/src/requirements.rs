@@ -0,0 +1,168 @@
+//! `requirements.txt` parsing, including nested files and options.
+
+use crate::config::ResolutionConfig;
+use crate::error::{bail, Result};
+use crate::ui::warning;
+use anyhow::Context;
+use std::fs::{self};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct RequirementsFile {
+    pub requirements: Vec<String>,
+    /// Absolute paths of `-e` entries.
+    pub editables: Vec<PathBuf>,
+    pub resolution: ResolutionConfig,
+}
+
+/// Reads a pip requirements file, following `-r` includes and `-c`
+/// constraint files relative to the file that names them.
+pub fn parse_requirements(path: &Path) -> Result<RequirementsFile> {
+    let mut out = RequirementsFile::default();
+    let mut visiting = Vec::new();
+    parse_requirements_into(path, false, &mut visiting, &mut out)?;
+    Ok(out)
+}
+
+pub(crate) fn parse_requirements_into(
+    path: &Path,
+    as_constraints: bool,
+    visiting: &mut Vec<PathBuf>,
+    out: &mut RequirementsFile,
+) -> Result<()> {
+    let canonical =
+        fs::canonicalize(path).with_context(|| format!("failed to open {}", path.display()))?;
+    if visiting.contains(&canonical) {
+        bail!("requirements file includes itself: {}", path.display());
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let base_dir = canonical
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    visiting.push(canonical);
+
+    for line in join_continuation_lines(&content) {
+        let line = strip_requirement_comment(&line);
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with('-') {
+            // Per-requirement options such as `--hash` are pip's business.
+            let req = line.split(" --").next().unwrap_or(&line).trim().to_string();
+            if as_constraints {
+                out.resolution.constraints.push(req);
+            } else {
+                out.requirements.push(req);
+            }
+            continue;
+        }
+
+        let (option, value) = split_requirement_option(&line);
+        match option {
+            "-r" | "--requirement" => {
+                let include = requirement_file_path(&base_dir, value, &line)?;
+                parse_requirements_into(&include, as_constraints, visiting, out)?;
+            }
+            "-c" | "--constraint" => {
+                let include = requirement_file_path(&base_dir, value, &line)?;
+                parse_requirements_into(&include, true, visiting, out)?;
+            }
+            "-e" | "--editable" => {
+                if value.is_empty() {
+                    bail!("{}: missing target for {}", path.display(), option);
+                }
+                if value.contains("://") {
+                    bail!(
+                        "{}: editable VCS requirements are not supported, clone the repository and use a local path: {}",
+                        path.display(),
+                        value
+                    );
+                }
+                if !as_constraints {
+                    out.editables.push(base_dir.join(value));
+                }
+            }
+            "-i" | "--index-url" => out.resolution.index_url = value.to_string(),
+            "--extra-index-url" => out.resolution.extra_index_urls.push(value.to_string()),
+            "-f" | "--find-links" => {
+                let link = if value.contains("://") {
+                    value.to_string()
+                } else {
+                    let dir = base_dir.join(value);
+                    fs::canonicalize(&dir).unwrap_or(dir).display().to_string()
+                };
+                out.resolution.find_links.push(link);
+            }
+            "--no-index" => out.resolution.no_index = true,
+            _ => warning(&format!(
+                "{}: ignoring unsupported option {}",
+                path.display(),
+                option
+            )),
+        }
+    }
+    visiting.pop();
+    Ok(())
+}
+
+/// Joins lines ending in a backslash, as pip does before parsing.
+pub(crate) fn join_continuation_lines(content: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    for raw in content.lines() {
+        if let Some(head) = raw.strip_suffix('\\') {
+            current.push_str(head);
+            current.push(' ');
+            continue;
+        }
+        current.push_str(raw);
+        out.push(std::mem::take(&mut current));
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+pub(crate) fn strip_requirement_comment(line: &str) -> String {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return String::new();
+    }
+    match line.find(" #").or_else(|| line.find("\t#")) {
+        Some(idx) => line[..idx].trim().to_string(),
+        None => line.to_string(),
+    }
+}
+
+/// Splits `-r file`, `-rfile` and `--requirement=file` into option and value.
+pub(crate) fn split_requirement_option(line: &str) -> (&str, &str) {
+    if line.starts_with("--") {
+        if let Some((opt, value)) = line.split_once('=') {
+            if !opt.contains(char::is_whitespace) {
+                return (opt, value.trim());
+            }
+        }
+        return match line.split_once(char::is_whitespace) {
+            Some((opt, value)) => (opt, value.trim()),
+            None => (line, ""),
+        };
+    }
+    match line.split_once(char::is_whitespace) {
+        Some((opt, value)) => (opt, value.trim()),
+        None if line.len() > 2 => (&line[..2], line[2..].trim()),
+        None => (line, ""),
+    }
+}
+
+pub(crate) fn requirement_file_path(base_dir: &Path, value: &str, line: &str) -> Result<PathBuf> {
+    if value.is_empty() {
+        bail!("missing file name in requirements line: {}", line);
+    }
+    if value.contains("://") {
+        bail!("remote requirements files are not supported: {}", value);
+    }
+    Ok(base_dir.join(value))
+}
@@ -0,0 +1,224 @@
+//! Picking the interpreter and site-packages a project runs with.
+
+use crate::config::default_python_version;
+use crate::config::load_global_config;
+use crate::config::load_project;
+use crate::config::XE_TOML;
+use crate::error::{bail, Result};
+use crate::profile::span;
+use crate::profile::AppContext;
+use crate::scripts::scripts_dir_for;
+use crate::venv::detect_venv_site_packages;
+use crate::venv::normalize_venv_name;
+use crate::venv::project_path_hash;
+use crate::Config;
+use crate::PythonManager;
+use crate::VenvManager;
+use anyhow::Context;
+use serde_json::json;
+use std::env;
+use std::fs::{self};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub fn get_preferred_python_version(ctx: &AppContext) -> Result<String> {
+    if let Ok(wd) = env::current_dir() {
+        let local = wd.join(XE_TOML);
+        if local.exists() {
+            if let Ok(cfg) = load_project(&local) {
+                if !cfg.python.version.trim().is_empty() {
+                    return Ok(cfg.python.version);
+                }
+            }
+        }
+    }
+    let global_cfg = load_global_config(&ctx.config_file)?;
+    if !global_cfg.default_python.trim().is_empty() {
+        return Ok(global_cfg.default_python);
+    }
+    Ok(default_python_version())
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeSelection {
+    pub python_exe: PathBuf,
+    pub site_packages: PathBuf,
+    pub activation_path: PathBuf,
+    pub venv_name: String,
+    pub is_venv: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeResult {
+    pub selection: RuntimeSelection,
+    pub config_changed: bool,
+}
+
+pub fn ensure_runtime_for_project(
+    ctx: &AppContext,
+    wd: &Path,
+    cfg: &mut Config,
+) -> Result<RuntimeResult> {
+    let _span = span(
+        ctx,
+        "runtime.ensure",
+        json!({"working_dir": wd.display().to_string(), "python_version": cfg.python.version}),
+    );
+    let pm = PythonManager::new()?;
+
+    if cfg.python.version.trim().is_empty() {
+        cfg.python.version = get_preferred_python_version(ctx)?;
+    }
+
+    let python_exe = match pm.get_python_exe(&cfg.python.version) {
+        Ok(path) => path,
+        Err(_) => {
+            pm.install(&cfg.python.version, ctx)?;
+            pm.get_python_exe(&cfg.python.version)?
+        }
+    };
+
+    let vm = VenvManager::new()?;
+    let mut config_changed = false;
+    let mut venv_name = cfg.venv.name.trim().to_string();
+    if venv_name.is_empty() && cfg.settings.autovenv {
+        venv_name = auto_venv_name(cfg, wd);
+        cfg.venv.name = venv_name.clone();
+        config_changed = true;
+    }
+
+    if !venv_name.is_empty() {
+        if !vm.exists(&venv_name) {
+            vm.create(&venv_name, &python_exe, Some(wd))?;
+        }
+        return Ok(RuntimeResult {
+            selection: venv_selection(&vm, &venv_name)?,
+            config_changed,
+        });
+    }
+
+    Ok(RuntimeResult {
+        selection: interpreter_selection(&pm, &cfg.python.version, python_exe)?,
+        config_changed,
+    })
+}
+
+/// The name `autovenv` gives the venv of the project in `wd`.
+pub fn auto_venv_name(cfg: &Config, wd: &Path) -> String {
+    let mut name = cfg.project.name.trim().to_string();
+    if name.is_empty() {
+        name = wd
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("default")
+            .to_string();
+    }
+    name = normalize_venv_name(&name);
+    if name.is_empty() {
+        name = "default".to_string();
+    }
+    format!("auto-{name}-{}", project_path_hash(wd))
+}
+
+/// The venv `name` xe manages, for commands that look at an environment
+/// other than the project's. Nothing is created.
+pub fn venv_runtime(name: &str) -> Result<RuntimeSelection> {
+    let vm = VenvManager::new()?;
+    if !vm.exists(name) {
+        bail!("no venv named '{name}'; see `xe venv list`");
+    }
+    venv_selection(&vm, name)
+}
+
+/// The managed interpreter `version` itself, outside any venv. Unlike the
+/// project runtime, a missing interpreter is an error, not an install.
+pub fn python_runtime(version: &str) -> Result<RuntimeSelection> {
+    let pm = PythonManager::new()?;
+    let Ok(python_exe) = pm.get_python_exe(version) else {
+        bail!("Python {version} is not installed; run `xe python install {version}`");
+    };
+    interpreter_selection(&pm, version, python_exe)
+}
+
+/// The project's environment if it exists already, for commands that only
+/// look at what is installed there. Unlike [`ensure_runtime_for_project`],
+/// nothing is installed or created.
+pub fn existing_runtime_for_project(cfg: &Config) -> Option<RuntimeSelection> {
+    let venv_name = cfg.venv.name.trim();
+    if !venv_name.is_empty() {
+        return venv_runtime(venv_name).ok();
+    }
+    match cfg.python.version.trim() {
+        "" => None,
+        version => python_runtime(version).ok(),
+    }
+}
+
+fn venv_selection(vm: &VenvManager, name: &str) -> Result<RuntimeSelection> {
+    let python_exe = vm.get_python_exe(name);
+    if !python_exe.exists() {
+        bail!("venv python not found: {}", python_exe.display());
+    }
+    let mut site_packages = vm.get_site_packages_dir(name);
+    if site_packages
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.eq_ignore_ascii_case("lib"))
+        .unwrap_or(false)
+    {
+        if let Ok(detected) = detect_venv_site_packages(&python_exe) {
+            site_packages = detected;
+        }
+    }
+    fs::create_dir_all(&site_packages)
+        .with_context(|| format!("failed to create {}", site_packages.display()))?;
+    Ok(RuntimeSelection {
+        activation_path: python_exe
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+        python_exe,
+        site_packages,
+        venv_name: name.to_string(),
+        is_venv: true,
+    })
+}
+
+fn interpreter_selection(
+    pm: &PythonManager,
+    version: &str,
+    python_exe: PathBuf,
+) -> Result<RuntimeSelection> {
+    let site_packages = pm.get_site_packages_dir(version)?;
+    Ok(RuntimeSelection {
+        activation_path: python_exe
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+        python_exe,
+        site_packages,
+        venv_name: String::new(),
+        is_venv: false,
+    })
+}
+
+pub fn apply_runtime_env(command: &mut Command, selection: &RuntimeSelection) -> Result<()> {
+    let python_root = selection.activation_path.clone();
+    let scripts_dir = scripts_dir_for(&selection.python_exe);
+    let current_path = env::var_os("PATH").unwrap_or_default();
+    let mut entries = vec![scripts_dir, python_root];
+    entries.extend(env::split_paths(&current_path));
+    let new_path = env::join_paths(entries).context("failed to build PATH for runtime")?;
+    command.env("PATH", new_path);
+    if selection.is_venv {
+        if let Some(root) = selection
+            .python_exe
+            .parent()
+            .and_then(|p| p.parent())
+            .map(Path::to_path_buf)
+        {
+            command.env("VIRTUAL_ENV", root);
+        }
+    }
+    Ok(())
+}
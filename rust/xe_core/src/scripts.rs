@@ -0,0 +1,302 @@
+//! Console-script entry points and their launchers.
+
+use crate::config::PYPROJECT_TOML;
+use crate::error::Result;
+use crate::python::PythonArch;
+use crate::venv::{is_relocatable, relocatable_shebang};
+use anyhow::Context;
+use std::fs::{self};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+#[derive(Debug, Clone)]
+pub struct EntryPoint {
+    pub name: String,
+    pub(crate) module: String,
+    pub(crate) attr: String,
+    pub(crate) gui: bool,
+}
+
+impl EntryPoint {
+    /// Body of a launcher script that imports and calls the entry point.
+    pub(crate) fn script_source(&self) -> String {
+        let head = self.attr.split('.').next().unwrap_or(&self.attr);
+        format!(
+            "# -*- coding: utf-8 -*-\n\
+             import re\n\
+             import sys\n\
+             from {module} import {head}\n\
+             if __name__ == \"__main__\":\n    \
+             sys.argv[0] = re.sub(r\"(-script\\.pyw|\\.exe)?$\", \"\", sys.argv[0])\n    \
+             sys.exit({attr}())\n",
+            module = self.module,
+            head = head,
+            attr = self.attr
+        )
+    }
+
+    /// Single-line equivalent for `python -c` when no launcher exists.
+    pub fn inline_source(&self) -> String {
+        let head = self.attr.split('.').next().unwrap_or(&self.attr);
+        format!(
+            "import sys; sys.argv[0] = {name:?}; from {module} import {head}; sys.exit({attr}())",
+            name = self.name,
+            module = self.module,
+            head = head,
+            attr = self.attr
+        )
+    }
+}
+
+pub(crate) fn parse_entry_points(text: &str) -> Vec<EntryPoint> {
+    let mut out = Vec::new();
+    let mut section = String::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+        let gui = match section.as_str() {
+            "console_scripts" => false,
+            "gui_scripts" => true,
+            _ => continue,
+        };
+        let Some((name, target)) = line.split_once('=') else {
+            continue;
+        };
+        out.extend(entry_point(name, target, gui));
+    }
+    out
+}
+
+/// `name = module:attr [extras]`, as written in `entry_points.txt` and
+/// `[project.scripts]`.
+fn entry_point(name: &str, target: &str, gui: bool) -> Option<EntryPoint> {
+    let target = target.split('[').next().unwrap_or(target).trim();
+    let (module, attr) = target.split_once(':')?;
+    Some(EntryPoint {
+        name: name.trim().to_string(),
+        module: module.trim().to_string(),
+        attr: attr.trim().to_string(),
+        gui,
+    })
+}
+
+/// `[project.scripts]` and `[project.gui-scripts]` of the project's own
+/// `pyproject.toml`, so they can run from source before it is installed.
+pub fn project_entry_points(project_dir: &Path) -> Result<Vec<EntryPoint>> {
+    let path = project_dir.join(PYPROJECT_TOML);
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    let doc: toml::Table =
+        toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+    let mut out = Vec::new();
+    for (table, gui) in [("scripts", false), ("gui-scripts", true)] {
+        let Some(scripts) = doc
+            .get("project")
+            .and_then(|project| project.get(table))
+            .and_then(|scripts| scripts.as_table())
+        else {
+            continue;
+        };
+        for (name, target) in scripts {
+            if let Some(target) = target.as_str() {
+                out.extend(entry_point(name, target, gui));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Console and GUI scripts declared by every distribution in `site_packages`.
+pub fn read_entry_points(site_packages: &Path) -> Result<Vec<EntryPoint>> {
+    let mut out = Vec::new();
+    if !site_packages.exists() {
+        return Ok(out);
+    }
+    for entry in fs::read_dir(site_packages)
+        .with_context(|| format!("failed to read {}", site_packages.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        if !name.ends_with(".dist-info") {
+            continue;
+        }
+        let path = entry.path().join("entry_points.txt");
+        if let Ok(text) = fs::read_to_string(&path) {
+            out.extend(parse_entry_points(&text));
+        }
+    }
+    Ok(out)
+}
+
+/// Directory holding launchers for an interpreter: the venv `Scripts`/`bin`
+/// dir itself, or `Scripts` next to a base Windows `python.exe`.
+pub fn scripts_dir_for(python_exe: &Path) -> PathBuf {
+    let parent = python_exe.parent().unwrap_or_else(|| Path::new("."));
+    let parent_name = parent
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    if parent_name == "scripts" || parent_name == "bin" {
+        parent.to_path_buf()
+    } else if cfg!(windows) {
+        parent.join("Scripts")
+    } else {
+        parent.join("bin")
+    }
+}
+
+pub fn find_runtime_script(python_exe: &Path, name: &str) -> Option<PathBuf> {
+    let dir = scripts_dir_for(python_exe);
+    let candidates: &[&str] = if cfg!(windows) {
+        &[".exe", ".cmd", ".bat"]
+    } else {
+        &[""]
+    };
+    candidates
+        .iter()
+        .map(|ext| dir.join(format!("{name}{ext}")))
+        .find(|p| p.is_file())
+}
+
+/// Writes launchers for entry points that do not have one yet. Windows gets
+/// real `.exe` launchers built from pip's vendored distlib stubs.
+pub fn install_console_scripts(site_packages: &Path, python_exe: &Path) -> Result<usize> {
+    let entry_points = read_entry_points(site_packages)?;
+    if entry_points.is_empty() {
+        return Ok(0);
+    }
+    let scripts_dir = scripts_dir_for(python_exe);
+    fs::create_dir_all(&scripts_dir)
+        .with_context(|| format!("failed to create {}", scripts_dir.display()))?;
+    let distlib_dir = if cfg!(windows) {
+        find_distlib_dir(python_exe)
+    } else {
+        None
+    };
+    let mut written = 0usize;
+    for ep in entry_points {
+        if find_runtime_script(python_exe, &ep.name).is_some() {
+            continue;
+        }
+        write_script_launcher(&scripts_dir, &ep, python_exe, distlib_dir.as_deref())?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+pub(crate) fn write_script_launcher(
+    scripts_dir: &Path,
+    ep: &EntryPoint,
+    python_exe: &Path,
+    distlib_dir: Option<&Path>,
+) -> Result<()> {
+    if !cfg!(windows) {
+        let path = scripts_dir.join(&ep.name);
+        let shebang = if is_relocatable(python_exe) {
+            let interpreter = python_exe
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "python".to_string());
+            relocatable_shebang(&interpreter)
+        } else {
+            format!("#!{}\n", python_exe.display())
+        };
+        let content = format!("{shebang}{}", ep.script_source());
+        fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+        return Ok(());
+    }
+
+    let interpreter = if ep.gui {
+        let pythonw = python_exe.with_file_name("pythonw.exe");
+        if pythonw.exists() {
+            pythonw
+        } else {
+            python_exe.to_path_buf()
+        }
+    } else {
+        python_exe.to_path_buf()
+    };
+    let stub_name = match (PythonArch::detect(), ep.gui) {
+        (PythonArch::Arm64, false) => "t64-arm.exe",
+        (PythonArch::Arm64, true) => "w64-arm.exe",
+        (PythonArch::Win32, false) => "t32.exe",
+        (PythonArch::Win32, true) => "w32.exe",
+        (PythonArch::Amd64, false) => "t64.exe",
+        (PythonArch::Amd64, true) => "w64.exe",
+    };
+    if let Some(stub) = distlib_dir
+        .map(|d| d.join(stub_name))
+        .filter(|p| p.exists())
+    {
+        // distlib launchers run the zip appended after a `#!` line naming the
+        // interpreter, which is exactly how pip builds Scripts/*.exe.
+        let mut data =
+            fs::read(&stub).with_context(|| format!("failed to read {}", stub.display()))?;
+        let shebang = match interpreter.file_name() {
+            // The launcher resolves `<launcher_dir>` to its own directory.
+            Some(file) if is_relocatable(python_exe) => {
+                format!("#!\"<launcher_dir>\\{}\"\r\n", file.to_string_lossy())
+            }
+            _ => format!("#!\"{}\"\r\n", interpreter.display()),
+        };
+        data.extend_from_slice(shebang.as_bytes());
+        let mut zip = ZipWriter::new(io::Cursor::new(Vec::new()));
+        zip.start_file("__main__.py", FileOptions::default())
+            .context("failed to build launcher archive")?;
+        zip.write_all(ep.script_source().as_bytes())?;
+        data.extend_from_slice(
+            &zip.finish()
+                .context("failed to build launcher archive")?
+                .into_inner(),
+        );
+        let path = scripts_dir.join(format!("{}.exe", ep.name));
+        fs::write(&path, data).with_context(|| format!("failed to write {}", path.display()))?;
+        return Ok(());
+    }
+
+    let script = scripts_dir.join(format!("{}-script.py", ep.name));
+    fs::write(&script, ep.script_source())
+        .with_context(|| format!("failed to write {}", script.display()))?;
+    let cmd = scripts_dir.join(format!("{}.cmd", ep.name));
+    let interpreter = match interpreter.file_name() {
+        Some(file) if is_relocatable(python_exe) => format!("%~dp0{}", file.to_string_lossy()),
+        _ => interpreter.display().to_string(),
+    };
+    let content = format!(
+        "@echo off\r\n\"{}\" \"%~dp0{}-script.py\" %*\r\n",
+        interpreter, ep.name
+    );
+    fs::write(&cmd, content).with_context(|| format!("failed to write {}", cmd.display()))?;
+    Ok(())
+}
+
+pub(crate) fn find_distlib_dir(python_exe: &Path) -> Option<PathBuf> {
+    let output = Command::new(python_exe)
+        .args([
+            "-c",
+            "import os, pip._vendor.distlib as d; print(os.path.dirname(d.__file__))",
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    dir.exists().then_some(dir)
+}
@@ -0,0 +1,349 @@
+//! PATH entries, shell profile blocks and shims.
+
+use crate::error::{bail, Result};
+use crate::paths::xe_shim_dir;
+use crate::ui::info;
+use crate::ui::success;
+use anyhow::Context;
+use std::env;
+use std::fs::{self};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Comment line [`create_shim`] puts in every shim, so xe can tell its own
+/// files in the shim dir from anything else kept there.
+const SHIM_MARKER: &str = "xe shim";
+
+/// Whether `path` is a shim xe wrote: it carries the marker, or has the
+/// exact two-line layout shims had before the marker was added.
+pub fn is_xe_shim(path: &Path) -> bool {
+    let Ok(content) = fs::read_to_string(path) else {
+        return false;
+    };
+    let lines = content.lines().map(str::trim_end).collect::<Vec<_>>();
+    match lines[..] {
+        [_, marker, _] => {
+            marker == format!("# {SHIM_MARKER}") || marker == format!("rem {SHIM_MARKER}")
+        }
+        ["#!/bin/sh", exec] => exec.starts_with("exec \"") && exec.ends_with("\" \"$@\""),
+        ["@echo off", run] => run.starts_with('"') && run.ends_with("\" %*"),
+        _ => false,
+    }
+}
+
+/// Shims written by `create_shim` quote their target first on the exec line.
+pub fn read_shim_target(path: &Path) -> Option<PathBuf> {
+    let content = fs::read_to_string(path).ok()?;
+    let start = content.find('"')? + 1;
+    let end = start + content[start..].find('"')?;
+    Some(PathBuf::from(&content[start..end]))
+}
+
+pub fn add_to_path(dir: &Path) -> Result<()> {
+    info(&format!("Ensuring {} is in system PATH...", dir.display()));
+    if cfg!(windows) {
+        let escaped = dir.to_string_lossy().replace('\'', "''");
+        let script = format!(
+            "$targetDir = '{}'; \
+             $currentPath = [Environment]::GetEnvironmentVariable('Path', 'User'); \
+             if ($null -eq $currentPath) {{ $currentPath = '' }}; \
+             $pathArray = $currentPath.Split(';', [System.StringSplitOptions]::RemoveEmptyEntries); \
+             if ($pathArray -notcontains $targetDir) {{ \
+               $newPath = ($pathArray + $targetDir) -join ';'; \
+               [Environment]::SetEnvironmentVariable('Path', $newPath, 'User'); \
+               Write-Output 'Added to PATH'; \
+             }} else {{ Write-Output 'Already in PATH'; }}"
+            ,
+            escaped
+        );
+        let status = Command::new("powershell")
+            .arg("-NoProfile")
+            .arg("-Command")
+            .arg(script)
+            .status()
+            .context("failed to run PowerShell PATH update")?;
+        if !status.success() {
+            bail!("failed to update PATH");
+        }
+        return Ok(());
+    }
+    // Shell profiles are only edited on request, by `xe setup`.
+    match current_shell_profile() {
+        Some((profile, _)) => info(&format!("Add this to {}:", profile.display())),
+        None => info("Add this to your shell profile:"),
+    }
+    info(&format!("export PATH=\"{}:$PATH\"", dir.display()));
+    Ok(())
+}
+
+pub(crate) const PROFILE_BLOCK_START: &str = "# >>> xe PATH >>>";
+pub(crate) const PROFILE_BLOCK_END: &str = "# <<< xe PATH <<<";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShellKind {
+    Posix,
+    Fish,
+}
+
+/// The profile the user's login shell reads on startup.
+pub(crate) fn current_shell_profile() -> Option<(PathBuf, ShellKind)> {
+    let home = dirs::home_dir()?;
+    let shell = env::var("SHELL").unwrap_or_default();
+    let shell_name = Path::new(&shell)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let profile = match shell_name.as_str() {
+        "bash" => (home.join(".bashrc"), ShellKind::Posix),
+        "zsh" => {
+            let base = env::var_os("ZDOTDIR").map(PathBuf::from).unwrap_or(home);
+            (base.join(".zshrc"), ShellKind::Posix)
+        }
+        "fish" => (
+            home.join(".config")
+                .join("fish")
+                .join("conf.d")
+                .join("xe.fish"),
+            ShellKind::Fish,
+        ),
+        _ => (home.join(".profile"), ShellKind::Posix),
+    };
+    Some(profile)
+}
+
+/// Every profile xe may have written a PATH block into.
+pub(crate) fn known_shell_profiles() -> Vec<(PathBuf, ShellKind)> {
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    let zdot = env::var_os("ZDOTDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.clone());
+    vec![
+        (home.join(".bashrc"), ShellKind::Posix),
+        (zdot.join(".zshrc"), ShellKind::Posix),
+        (home.join(".profile"), ShellKind::Posix),
+        (
+            home.join(".config")
+                .join("fish")
+                .join("conf.d")
+                .join("xe.fish"),
+            ShellKind::Fish,
+        ),
+    ]
+}
+
+/// The profile of the user's shell and whether its xe block already puts
+/// `dir` on PATH; `None` when the shell is not known.
+pub fn shell_profile_status(dir: &Path) -> Option<(PathBuf, bool)> {
+    let (profile, kind) = current_shell_profile()?;
+    let present = profile_block_dirs(&profile, kind).contains(&dir.to_string_lossy().to_string());
+    Some((profile, present))
+}
+
+/// Adds `dir` to the marked PATH block of the current shell profile and
+/// returns the profile, or `None` when the shell is not known. Whether to
+/// touch the profile at all is the caller's decision.
+pub fn add_to_shell_profile(dir: &Path) -> Result<Option<PathBuf>> {
+    let Some((profile, kind)) = current_shell_profile() else {
+        return Ok(None);
+    };
+    let dir_str = dir.to_string_lossy().to_string();
+    update_profile_block(&profile, kind, |dirs| {
+        if dirs.contains(&dir_str) {
+            return false;
+        }
+        dirs.push(dir_str.clone());
+        true
+    })?;
+    Ok(Some(profile))
+}
+
+pub(crate) fn remove_from_shell_profiles(dir: &Path) -> Result<()> {
+    let dir_str = dir.to_string_lossy().to_string();
+    for (profile, kind) in known_shell_profiles() {
+        let changed = update_profile_block(&profile, kind, |dirs| {
+            let before = dirs.len();
+            dirs.retain(|d| d != &dir_str);
+            dirs.len() != before
+        })?;
+        if changed {
+            success(&format!(
+                "Removed {} from {}",
+                dir.display(),
+                profile.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn profile_block_dirs(profile: &Path, kind: ShellKind) -> Vec<String> {
+    let content = fs::read_to_string(profile).unwrap_or_default();
+    let (_, block, _) = split_profile_block(&content);
+    block
+        .lines()
+        .filter_map(|line| {
+            let rest = match kind {
+                ShellKind::Posix => line.split_once("export PATH=\"")?.1,
+                ShellKind::Fish => line.split_once("set -gx PATH \"")?.1,
+            };
+            let end = match kind {
+                ShellKind::Posix => rest.find(":$PATH\"")?,
+                ShellKind::Fish => rest.find('"')?,
+            };
+            Some(unescape_shell_double_quoted(&rest[..end]))
+        })
+        .collect()
+}
+
+/// Splits profile text into (before, block body, after) around the xe
+/// markers. The block body is empty when no markers are present.
+pub(crate) fn split_profile_block(content: &str) -> (String, String, String) {
+    let Some(start) = content.find(PROFILE_BLOCK_START) else {
+        return (content.to_string(), String::new(), String::new());
+    };
+    let Some(end_rel) = content[start..].find(PROFILE_BLOCK_END) else {
+        return (content.to_string(), String::new(), String::new());
+    };
+    let end = start + end_rel + PROFILE_BLOCK_END.len();
+    let body_start = start + PROFILE_BLOCK_START.len();
+    let after = content[end..].strip_prefix('\n').unwrap_or(&content[end..]);
+    (
+        content[..start].to_string(),
+        content[body_start..start + end_rel].to_string(),
+        after.to_string(),
+    )
+}
+
+pub(crate) fn update_profile_block(
+    profile: &Path,
+    kind: ShellKind,
+    edit: impl FnOnce(&mut Vec<String>) -> bool,
+) -> Result<bool> {
+    let content = if profile.exists() {
+        fs::read_to_string(profile)
+            .with_context(|| format!("failed to read {}", profile.display()))?
+    } else {
+        String::new()
+    };
+    let mut dirs = profile_block_dirs(profile, kind);
+    if !edit(&mut dirs) {
+        return Ok(false);
+    }
+    let (before, _, after) = split_profile_block(&content);
+    let mut out = before;
+    if !dirs.is_empty() {
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(PROFILE_BLOCK_START);
+        out.push('\n');
+        out.push_str("# Managed by xe; remove with `xe setup --remove`.\n");
+        for dir in &dirs {
+            let quoted = escape_shell_double_quoted(dir);
+            match kind {
+                ShellKind::Posix => out.push_str(&format!(
+                    "case \":$PATH:\" in *\":{quoted}:\"*) ;; *) export PATH=\"{quoted}:$PATH\" ;; esac\n"
+                )),
+                ShellKind::Fish => out.push_str(&format!(
+                    "contains \"{quoted}\" $PATH; or set -gx PATH \"{quoted}\" $PATH\n"
+                )),
+            }
+        }
+        out.push_str(PROFILE_BLOCK_END);
+        out.push('\n');
+    }
+    out.push_str(&after);
+    if let Some(parent) = profile.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    fs::write(profile, out).with_context(|| format!("failed to write {}", profile.display()))?;
+    Ok(true)
+}
+
+pub(crate) fn escape_shell_double_quoted(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, '"' | '\\' | '$' | '`') {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+    out
+}
+
+pub(crate) fn unescape_shell_double_quoted(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            if let Some(next) = chars.next() {
+                out.push(next);
+            }
+            continue;
+        }
+        out.push(ch);
+    }
+    out
+}
+
+pub fn remove_from_path(dir: &Path) -> Result<()> {
+    if cfg!(windows) {
+        let escaped = dir.to_string_lossy().replace('\'', "''");
+        let script = format!(
+            "$targetDir = '{}'; \
+             $currentPath = [Environment]::GetEnvironmentVariable('Path', 'User'); \
+             if ($null -eq $currentPath) {{ $currentPath = '' }}; \
+             $pathArray = $currentPath.Split(';', [System.StringSplitOptions]::RemoveEmptyEntries); \
+             if ($pathArray -contains $targetDir) {{ \
+               $newPath = ($pathArray | Where-Object {{ $_ -ne $targetDir }}) -join ';'; \
+               [Environment]::SetEnvironmentVariable('Path', $newPath, 'User'); \
+               Write-Output 'Removed from PATH'; \
+             }}",
+            escaped
+        );
+        let status = Command::new("powershell")
+            .arg("-NoProfile")
+            .arg("-Command")
+            .arg(script)
+            .status()
+            .context("failed to run PowerShell PATH update")?;
+        if !status.success() {
+            bail!("failed to update PATH");
+        }
+        return Ok(());
+    }
+    remove_from_shell_profiles(dir)
+}
+
+pub fn create_shim(name: &str, target: &Path) -> Result<()> {
+    let shim_dir = xe_shim_dir();
+    fs::create_dir_all(&shim_dir)
+        .with_context(|| format!("failed to create {}", shim_dir.display()))?;
+    if cfg!(windows) {
+        let path = shim_dir.join(format!("{name}.bat"));
+        let content = format!(
+            "@echo off\r\nrem {SHIM_MARKER}\r\n\"{}\" %*\r\n",
+            target.display()
+        );
+        fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
+        return Ok(());
+    }
+    let path = shim_dir.join(name);
+    let content = format!(
+        "#!/bin/sh\n# {SHIM_MARKER}\nexec \"{}\" \"$@\"\n",
+        target.display()
+    );
+    fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(&path)?.permissions();
+        perms.set_mode(0o755);
+        fs::set_permissions(&path, perms)?;
+    }
+    Ok(())
+}
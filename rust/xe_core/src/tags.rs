@@ -0,0 +1,468 @@
+//! Wheel tag compatibility (PEP 425, PEP 600 manylinux, PEP 656 musllinux).
+//!
+//! Builds the ordered list of tags an interpreter accepts and ranks wheel
+//! filenames against it, so a lock made on one platform installs the right
+//! artifact on another.
+
+use crate::error::{bail, Result};
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Interpreter and platform facts that decide which wheels are installable.
+#[derive(Debug, Clone)]
+pub struct TargetEnv {
+    /// `sys.implementation.name`: `cpython` or `pypy`.
+    pub implementation: String,
+    pub major: u32,
+    pub minor: u32,
+    /// `platform.python_version()`, e.g. `3.12.4`.
+    pub full_version: String,
+    /// Short hash of `sys.version`, which names the build and compiler.
+    pub build: String,
+    pub freethreaded: bool,
+    pub platform: Platform,
+}
+
+#[derive(Debug, Clone)]
+pub enum Platform {
+    Linux {
+        arch: String,
+        glibc: Option<(u32, u32)>,
+        musl: Option<(u32, u32)>,
+    },
+    MacOs {
+        arch: String,
+        major: u32,
+        minor: u32,
+    },
+    /// Carries the full platform tag: `win_amd64`, `win32` or `win_arm64`.
+    Windows { tag: String },
+}
+
+const PROBE_SCRIPT: &str = r#"
+import glob, json, platform, subprocess, sys, sysconfig
+libc = platform.libc_ver()
+musl = ""
+if sys.platform.startswith("linux") and libc[0] != "glibc":
+    for ld in glob.glob("/lib/ld-musl-*.so.1"):
+        err = subprocess.run([ld], capture_output=True, text=True).stderr
+        for line in err.splitlines():
+            if line.startswith("Version "):
+                musl = line.split()[1]
+print(json.dumps({
+    "implementation": sys.implementation.name,
+    "major": sys.version_info[0],
+    "minor": sys.version_info[1],
+    "full_version": platform.python_version(),
+    "build": sys.version,
+    "freethreaded": bool(sysconfig.get_config_var("Py_GIL_DISABLED")),
+    "sys_platform": sys.platform,
+    "platform": sysconfig.get_platform(),
+    "machine": platform.machine(),
+    "glibc": libc[1] if libc[0] == "glibc" else "",
+    "musl": musl,
+    "mac": platform.mac_ver()[0],
+}))
+"#;
+
+#[derive(Debug, Deserialize)]
+struct Probe {
+    implementation: String,
+    major: u32,
+    minor: u32,
+    #[serde(default)]
+    full_version: String,
+    #[serde(default)]
+    build: String,
+    #[serde(default)]
+    freethreaded: bool,
+    sys_platform: String,
+    platform: String,
+    machine: String,
+    #[serde(default)]
+    glibc: String,
+    #[serde(default)]
+    musl: String,
+    #[serde(default)]
+    mac: String,
+}
+
+impl TargetEnv {
+    /// Asks the interpreter that will receive the install about itself.
+    pub fn probe(python_exe: &Path) -> Result<Self> {
+        let output = Command::new(python_exe)
+            .args(["-c", PROBE_SCRIPT])
+            .output()
+            .with_context(|| format!("failed to run {}", python_exe.display()))?;
+        if !output.status.success() {
+            bail!(
+                "failed to inspect {}: {}",
+                python_exe.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let probe: Probe = serde_json::from_slice(&output.stdout).with_context(|| {
+            format!(
+                "failed to parse interpreter facts from {}",
+                python_exe.display()
+            )
+        })?;
+
+        let platform = if probe.sys_platform == "win32" {
+            Platform::Windows {
+                tag: probe.platform.replace(['-', '.'], "_"),
+            }
+        } else if probe.sys_platform == "darwin" {
+            let (major, minor) = parse_version_pair(&probe.mac).unwrap_or((11, 0));
+            Platform::MacOs {
+                arch: probe.machine,
+                major,
+                minor,
+            }
+        } else {
+            Platform::Linux {
+                arch: probe.machine,
+                glibc: parse_version_pair(&probe.glibc),
+                musl: parse_version_pair(&probe.musl),
+            }
+        };
+        Ok(Self {
+            implementation: probe.implementation,
+            major: probe.major,
+            minor: probe.minor,
+            full_version: probe.full_version,
+            build: hex::encode(&Sha256::digest(probe.build.as_bytes())[..6]),
+            freethreaded: probe.freethreaded,
+            platform,
+        })
+    }
+
+    /// Retargets to another Python `major.minor`, for resolving a lock meant
+    /// for a different interpreter than the one running the resolver.
+    pub fn with_python_version(mut self, version: &str) -> Result<Self> {
+        let Some((major, minor)) = version
+            .split_once('.')
+            .and_then(|(major, minor)| Some((major.parse().ok()?, minor.parse().ok()?)))
+        else {
+            bail!("invalid Python version '{version}'; expected major.minor, e.g. 3.11");
+        };
+        self.major = major;
+        self.minor = minor;
+        self.full_version = format!("{major}.{minor}");
+        self.build.clear();
+        Ok(self)
+    }
+
+    /// Retargets to another platform: `linux`, `musllinux`, `macos` or
+    /// `windows`, optionally followed by an architecture as in
+    /// `linux-aarch64`. Linux targets assume the manylinux2014 glibc floor.
+    pub fn with_platform(mut self, name: &str) -> Result<Self> {
+        let (os, arch) = name.split_once('-').unwrap_or((name, ""));
+        let arch = match arch {
+            "amd64" => "x86_64",
+            "arm64" if os != "macos" => "aarch64",
+            "aarch64" if os == "macos" => "arm64",
+            other => other,
+        };
+        self.platform = match (os, arch) {
+            ("linux", "") => Platform::Linux {
+                arch: "x86_64".to_string(),
+                glibc: Some((2, 17)),
+                musl: None,
+            },
+            ("musllinux", "") => Platform::Linux {
+                arch: "x86_64".to_string(),
+                glibc: None,
+                musl: Some((1, 2)),
+            },
+            ("linux", arch) => Platform::Linux {
+                arch: arch.to_string(),
+                glibc: Some((2, 17)),
+                musl: None,
+            },
+            ("musllinux", arch) => Platform::Linux {
+                arch: arch.to_string(),
+                glibc: None,
+                musl: Some((1, 2)),
+            },
+            ("macos", "") | ("macos", "arm64") => Platform::MacOs {
+                arch: "arm64".to_string(),
+                major: 11,
+                minor: 0,
+            },
+            ("macos", "x86_64") => Platform::MacOs {
+                arch: "x86_64".to_string(),
+                major: 10,
+                minor: 12,
+            },
+            ("windows", "") | ("windows", "x86_64") => Platform::Windows {
+                tag: "win_amd64".to_string(),
+            },
+            ("windows", "aarch64") => Platform::Windows {
+                tag: "win_arm64".to_string(),
+            },
+            ("windows", "x86") => Platform::Windows {
+                tag: "win32".to_string(),
+            },
+            _ => bail!(
+                "unknown platform '{name}'; expected linux, musllinux, macos or windows, optionally with an architecture such as linux-aarch64"
+            ),
+        };
+        self.build.clear();
+        Ok(self)
+    }
+
+    /// The PEP 508 marker environment of the target, as
+    /// `packaging.markers.default_environment()` would report it there.
+    /// Release and version strings of the OS are unknown and left empty.
+    pub fn marker_environment(&self) -> Value {
+        let (os_name, sys_platform, system, machine) = match &self.platform {
+            Platform::Linux { arch, .. } => ("posix", "linux", "Linux", arch.clone()),
+            Platform::MacOs { arch, .. } => ("posix", "darwin", "Darwin", arch.clone()),
+            Platform::Windows { tag } => {
+                let machine = match tag.as_str() {
+                    "win32" => "x86",
+                    "win_arm64" => "ARM64",
+                    _ => "AMD64",
+                };
+                ("nt", "win32", "Windows", machine.to_string())
+            }
+        };
+        let full_version = if self.full_version.matches('.').count() >= 2 {
+            self.full_version.clone()
+        } else {
+            format!("{}.{}.0", self.major, self.minor)
+        };
+        let python_implementation = if self.implementation == "pypy" {
+            "PyPy"
+        } else {
+            "CPython"
+        };
+        json!({
+            "implementation_name": self.implementation,
+            "implementation_version": full_version,
+            "os_name": os_name,
+            "platform_machine": machine,
+            "platform_python_implementation": python_implementation,
+            "platform_release": "",
+            "platform_system": system,
+            "platform_version": "",
+            "python_full_version": full_version,
+            "python_version": format!("{}.{}", self.major, self.minor),
+            "sys_platform": sys_platform,
+        })
+    }
+
+    /// pip options that make it pick wheels for this target rather than for
+    /// the interpreter it runs in. Sdists would be built for the host, so
+    /// only wheels are accepted.
+    pub fn pip_args(&self) -> Vec<String> {
+        let implementation = if self.implementation == "pypy" {
+            "pp"
+        } else {
+            "cp"
+        };
+        let mut args = vec![
+            "--only-binary=:all:".to_string(),
+            "--python-version".to_string(),
+            format!("{}.{}", self.major, self.minor),
+            "--implementation".to_string(),
+            implementation.to_string(),
+        ];
+        for tag in self.platform_tags() {
+            args.push("--platform".to_string());
+            args.push(tag);
+        }
+        args
+    }
+
+    /// The interpreter's own ABI tag: `cp312`, `cp313t` or `pypy310_pp73`.
+    pub fn abi_tag(&self) -> String {
+        let (major, minor) = (self.major, self.minor);
+        if self.implementation == "pypy" {
+            format!("pypy{major}{minor}_pp73")
+        } else if self.freethreaded {
+            format!("cp{major}{minor}t")
+        } else {
+            format!("cp{major}{minor}")
+        }
+    }
+
+    /// Operating system and architecture without version floors, e.g.
+    /// `linux_x86_64`, `macosx_arm64` or `win_amd64`.
+    pub fn platform_name(&self) -> String {
+        match &self.platform {
+            Platform::Linux { arch, .. } => format!("linux_{arch}"),
+            Platform::MacOs { arch, .. } => format!("macosx_{arch}"),
+            Platform::Windows { tag } => tag.clone(),
+        }
+    }
+
+    /// Platform tags, most specific first.
+    pub fn platform_tags(&self) -> Vec<String> {
+        match &self.platform {
+            Platform::Windows { tag } => vec![tag.clone()],
+            Platform::Linux { arch, glibc, musl } => {
+                let mut tags = Vec::new();
+                if let Some((2, glibc_minor)) = glibc {
+                    for minor in (5..=*glibc_minor).rev() {
+                        tags.push(format!("manylinux_2_{minor}_{arch}"));
+                        let legacy = match minor {
+                            17 => Some("manylinux2014"),
+                            12 => Some("manylinux2010"),
+                            5 => Some("manylinux1"),
+                            _ => None,
+                        };
+                        if let Some(legacy) = legacy {
+                            tags.push(format!("{legacy}_{arch}"));
+                        }
+                    }
+                }
+                if let Some((1, musl_minor)) = musl {
+                    for minor in (0..=*musl_minor).rev() {
+                        tags.push(format!("musllinux_1_{minor}_{arch}"));
+                    }
+                }
+                tags.push(format!("linux_{arch}"));
+                tags
+            }
+            Platform::MacOs { arch, major, minor } => {
+                let formats: &[&str] = if arch == "arm64" {
+                    &["arm64", "universal2"]
+                } else {
+                    &[
+                        "x86_64",
+                        "intel",
+                        "fat64",
+                        "fat32",
+                        "universal2",
+                        "universal",
+                    ]
+                };
+                let mut versions = Vec::new();
+                if *major >= 11 {
+                    for v in (11..=*major).rev() {
+                        versions.push((v, 0));
+                    }
+                    // x86_64 wheels built for 10.x keep working on 11+.
+                    if arch != "arm64" {
+                        for v in (4..=16).rev() {
+                            versions.push((10, v));
+                        }
+                    }
+                } else {
+                    for v in (4..=*minor).rev() {
+                        versions.push((10, v));
+                    }
+                }
+                let mut tags = Vec::new();
+                for (major, minor) in versions {
+                    for format in formats {
+                        tags.push(format!("macosx_{major}_{minor}_{format}"));
+                    }
+                }
+                tags
+            }
+        }
+    }
+
+    /// Every tag the interpreter accepts, best first, following the order
+    /// `packaging.tags.sys_tags()` produces.
+    pub fn supported_tags(&self) -> Vec<String> {
+        let platforms = self.platform_tags();
+        let (major, minor) = (self.major, self.minor);
+        let mut tags = Vec::new();
+        let mut push_all = |interp: &str, abi: &str, plats: &[String]| {
+            for plat in plats {
+                tags.push(format!("{interp}-{abi}-{plat}"));
+            }
+        };
+
+        let interp = if self.implementation == "pypy" {
+            let interp = format!("pp{major}{minor}");
+            push_all(&interp, &self.abi_tag(), &platforms);
+            push_all(&interp, "none", &platforms);
+            interp
+        } else {
+            let interp = format!("cp{major}{minor}");
+            push_all(&interp, &self.abi_tag(), &platforms);
+            if !self.freethreaded {
+                push_all(&interp, "abi3", &platforms);
+            }
+            push_all(&interp, "none", &platforms);
+            if !self.freethreaded {
+                for older in (2..minor).rev() {
+                    push_all(&format!("cp{major}{older}"), "abi3", &platforms);
+                }
+            }
+            interp
+        };
+
+        let mut py_versions = vec![format!("py{major}{minor}"), format!("py{major}")];
+        for older in (0..minor).rev() {
+            py_versions.push(format!("py{major}{older}"));
+        }
+        for py in &py_versions {
+            push_all(py, "none", &platforms);
+        }
+        let any = ["any".to_string()];
+        push_all(&interp, "none", &any);
+        for py in &py_versions {
+            push_all(py, "none", &any);
+        }
+        tags
+    }
+}
+
+/// Ranks wheel filenames for one target; lower ranks are better.
+pub struct TagSet {
+    priorities: HashMap<String, usize>,
+}
+
+impl TagSet {
+    pub fn new(target: &TargetEnv) -> Self {
+        let mut priorities = HashMap::new();
+        for (rank, tag) in target.supported_tags().into_iter().enumerate() {
+            priorities.entry(tag).or_insert(rank);
+        }
+        Self { priorities }
+    }
+
+    /// Best rank among the (possibly compressed) tags of a wheel filename,
+    /// or `None` when the wheel cannot be installed on the target.
+    pub fn rank(&self, filename: &str) -> Option<usize> {
+        let stem = filename.strip_suffix(".whl")?;
+        let parts = stem.split('-').collect::<Vec<_>>();
+        if parts.len() < 5 {
+            return None;
+        }
+        let (interps, abis, plats) = (
+            parts[parts.len() - 3],
+            parts[parts.len() - 2],
+            parts[parts.len() - 1],
+        );
+        let mut best: Option<usize> = None;
+        for interp in interps.split('.') {
+            for abi in abis.split('.') {
+                for plat in plats.split('.') {
+                    if let Some(rank) = self.priorities.get(&format!("{interp}-{abi}-{plat}")) {
+                        best = Some(best.map_or(*rank, |b| b.min(*rank)));
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+fn parse_version_pair(text: &str) -> Option<(u32, u32)> {
+    let mut parts = text.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().unwrap_or("0").parse().ok()?;
+    Some((major, minor))
+}
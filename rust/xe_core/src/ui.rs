@@ -0,0 +1,251 @@
+//! Terminal status lines, colors and transfer progress.
+
+use crate::error::{bail, Result};
+use crate::report::report_transfer;
+use crate::report::TransferUpdate;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// When to emit ANSI colors, from the global `--color` flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color terminals unless `NO_COLOR` is set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(raw: &str) -> Result<Self> {
+        Ok(match raw {
+            "auto" => Self::Auto,
+            "always" => Self::Always,
+            "never" => Self::Never,
+            _ => bail!("invalid --color value '{raw}'; expected auto, always or never"),
+        })
+    }
+}
+
+/// Palette from `[ui] theme`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    #[default]
+    Default,
+    /// Bright, bold colors for dark or low-contrast terminals.
+    HighContrast,
+    /// Bold and dim only, no hues.
+    Mono,
+}
+
+impl Theme {
+    pub fn parse(raw: &str) -> Result<Self> {
+        Ok(match raw {
+            "" | "default" => Self::Default,
+            "high-contrast" => Self::HighContrast,
+            "mono" => Self::Mono,
+            _ => bail!("unknown theme '{raw}'; expected default, high-contrast or mono"),
+        })
+    }
+
+    fn sgr(self, style: Style) -> &'static str {
+        use Style::*;
+        match (self, style) {
+            (Self::Default, Info | Accent) => "36",
+            (Self::Default, Success) => "32",
+            (Self::Default, Warning) => "33",
+            (Self::Default, Error) => "31",
+            (Self::HighContrast, Info | Accent) => "1;96",
+            (Self::HighContrast, Success) => "1;92",
+            (Self::HighContrast, Warning) => "1;93",
+            (Self::HighContrast, Error) => "1;91",
+            (Self::Mono, Info | Success | Accent) => "1",
+            (Self::Mono, Warning | Error) => "1;7",
+            (_, Header) => "1;4",
+            (Self::HighContrast, Muted) => "37",
+            (_, Muted) => "2",
+        }
+    }
+}
+
+/// Role of a piece of output; the theme maps it to a color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Info,
+    Success,
+    Warning,
+    Error,
+    /// Table headers.
+    Header,
+    /// Tree branches, hints and other secondary text.
+    Muted,
+    /// Package names and other highlighted values.
+    Accent,
+}
+
+struct Output {
+    stdout: bool,
+    stderr: bool,
+    theme: Theme,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+/// How many times `-v` was given.
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+/// Fixes the color mode and theme for the rest of the process. Output
+/// printed before this uses `auto` and the default theme.
+pub fn init_output(choice: ColorChoice, theme: Theme) {
+    let _ = OUTPUT.set(Output::new(choice, theme));
+}
+
+/// Sets the level the global `-v` flag asks for: 1 for `-v`, 2 for `-vv`.
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+fn output() -> &'static Output {
+    OUTPUT.get_or_init(|| Output::new(ColorChoice::Auto, Theme::Default))
+}
+
+impl Output {
+    fn new(choice: ColorChoice, theme: Theme) -> Self {
+        let auto = env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+        let (stdout, stderr) = match choice {
+            ColorChoice::Always => (true, true),
+            ColorChoice::Never => (false, false),
+            ColorChoice::Auto => (
+                auto && io::stdout().is_terminal(),
+                auto && io::stderr().is_terminal(),
+            ),
+        };
+        Self {
+            stdout,
+            stderr,
+            theme,
+        }
+    }
+}
+
+fn paint_if(enabled: bool, style: Style, text: &str) -> String {
+    if enabled {
+        format!("\x1b[{}m{}\x1b[0m", output().theme.sgr(style), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// `text` in the color of `style`, for printing to stdout.
+pub fn paint(style: Style, text: &str) -> String {
+    paint_if(output().stdout, style, text)
+}
+
+pub fn info(msg: &str) {
+    println!("{} {msg}", paint(Style::Info, " INFO "));
+}
+
+pub fn success(msg: &str) {
+    println!("{} {msg}", paint(Style::Success, " SUCCESS "));
+}
+
+pub fn warning(msg: &str) {
+    println!("{} {msg}", paint(Style::Warning, " WARNING "));
+}
+
+/// A diagnostic line on stderr, printed only at `level` or more `-v`.
+pub fn debug(level: u8, msg: &str) {
+    if verbosity() >= level {
+        eprintln!(
+            "{} {msg}",
+            paint_if(output().stderr, Style::Muted, "  DEBUG  ")
+        );
+    }
+}
+
+pub fn error(msg: &str) {
+    eprintln!(
+        "{} {msg}",
+        paint_if(output().stderr, Style::Error, "  ERROR  ")
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressUnit {
+    Bytes,
+    Items,
+}
+
+/// Progress of one download or extraction, handed to the reporters: a
+/// single-line bar on terminals, nothing in redirected output and CI logs.
+pub struct TransferProgress {
+    pub(crate) label: String,
+    pub(crate) total: Option<u64>,
+    pub(crate) done: u64,
+    pub(crate) unit: ProgressUnit,
+    pub(crate) started: Instant,
+    pub(crate) last_draw: Option<Instant>,
+}
+
+impl TransferProgress {
+    pub fn new(label: &str, total: Option<u64>, unit: ProgressUnit) -> Self {
+        Self {
+            label: label.to_string(),
+            total: total.filter(|t| *t > 0),
+            done: 0,
+            unit,
+            started: Instant::now(),
+            last_draw: None,
+        }
+    }
+
+    pub fn advance(&mut self, amount: u64) {
+        self.done += amount;
+        let due = self
+            .last_draw
+            .map(|t| t.elapsed() >= Duration::from_millis(100))
+            .unwrap_or(true);
+        if due {
+            self.draw(false);
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if self.last_draw.is_some() {
+            self.draw(true);
+        }
+    }
+
+    pub(crate) fn draw(&mut self, finished: bool) {
+        self.last_draw = Some(Instant::now());
+        report_transfer(&TransferUpdate {
+            label: &self.label,
+            unit: self.unit,
+            done: self.done,
+            total: self.total,
+            elapsed: self.started.elapsed(),
+            finished,
+        });
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0usize;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
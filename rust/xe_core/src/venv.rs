@@ -0,0 +1,435 @@
+//! Named virtual environments under the xe home.
+
+use crate::error::{bail, Result};
+use crate::paths::remove_dir_all;
+use crate::paths::xe_venv_dir;
+use crate::profile::timestamp_iso8601;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha1::Digest as Sha1Digest;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::{self};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Short hash of the canonical project path, keeping auto-venvs of projects
+/// that share a directory name apart.
+pub fn project_path_hash(project_dir: &Path) -> String {
+    let path = fs::canonicalize(project_dir).unwrap_or_else(|_| project_dir.to_path_buf());
+    let mut hasher = Sha256::new();
+    hasher.update(path.to_string_lossy().as_bytes());
+    hex::encode(hasher.finalize())[..8].to_string()
+}
+
+pub fn normalize_venv_name(name: &str) -> String {
+    let mut n = name.trim().to_lowercase();
+    n = n.replace([' ', '_'], "-");
+    let mut out = String::with_capacity(n.len());
+    for ch in n.chars() {
+        if ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-' {
+            out.push(ch);
+        }
+    }
+    out.trim_matches('-').to_string()
+}
+
+pub fn detect_venv_site_packages(venv_exe: &Path) -> Result<PathBuf> {
+    let output = Command::new(venv_exe)
+        .args(["-c", "import site; print(site.getsitepackages()[0])"])
+        .output()
+        .context("failed to detect venv site-packages")?;
+    if !output.status.success() {
+        bail!("failed to detect venv site-packages");
+    }
+    let site = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if site.is_empty() {
+        bail!("empty site-packages response");
+    }
+    Ok(PathBuf::from(site))
+}
+
+#[derive(Debug, Clone)]
+pub struct VenvManager {
+    pub base_dir: PathBuf,
+}
+
+pub const VENV_METADATA: &str = "xe-venv.json";
+
+/// Written into each venv xe creates, so venvs can be traced back to the
+/// project that made them.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct VenvMetadata {
+    /// Absolute project directory; empty for venvs not tied to a project.
+    #[serde(default)]
+    pub project: String,
+    #[serde(default)]
+    pub python: String,
+    #[serde(default)]
+    pub created_at: String,
+}
+
+impl VenvManager {
+    pub fn new() -> Result<Self> {
+        let base_dir = xe_venv_dir();
+        fs::create_dir_all(&base_dir)
+            .with_context(|| format!("failed to create {}", base_dir.display()))?;
+        Ok(Self { base_dir })
+    }
+
+    pub fn create(&self, name: &str, python_path: &Path, project: Option<&Path>) -> Result<()> {
+        let venv_path = self.base_dir.join(name);
+        if venv_path.exists() {
+            bail!("venv {} already exists", name);
+        }
+        self.create_env(&venv_path, python_path)?;
+        let meta = VenvMetadata {
+            project: project
+                .map(|p| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf()))
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            python: self.python_version(name),
+            created_at: timestamp_iso8601(),
+        };
+        let meta_path = venv_path.join(VENV_METADATA);
+        let data = serde_json::to_vec_pretty(&meta).context("failed to encode venv metadata")?;
+        fs::write(&meta_path, data)
+            .with_context(|| format!("failed to write {}", meta_path.display()))?;
+        Ok(())
+    }
+
+    pub(crate) fn create_env(&self, venv_path: &Path, python_path: &Path) -> Result<()> {
+        let status = Command::new(python_path)
+            .arg("-m")
+            .arg("venv")
+            .arg(venv_path)
+            .status()
+            .context("failed to create venv with stdlib venv")?;
+        if status.success() {
+            return Ok(());
+        }
+
+        let bootstrap = Command::new(python_path)
+            .args([
+                "-m",
+                "pip",
+                "install",
+                "--disable-pip-version-check",
+                "--no-warn-script-location",
+                "--upgrade",
+                "--force-reinstall",
+                "virtualenv",
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context("failed to bootstrap virtualenv")?;
+        if !bootstrap.success() {
+            bail!("failed to bootstrap virtualenv");
+        }
+        let fallback = Command::new(python_path)
+            .arg("-m")
+            .arg("virtualenv")
+            .arg(venv_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context("failed to create venv with virtualenv")?;
+        if !fallback.success() {
+            bail!("failed to create venv with virtualenv");
+        }
+        Ok(())
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.base_dir.join(name).exists()
+    }
+
+    pub fn metadata(&self, name: &str) -> Option<VenvMetadata> {
+        let data = fs::read(self.base_dir.join(name).join(VENV_METADATA)).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    /// Python version recorded in the venv's `pyvenv.cfg`.
+    pub fn python_version(&self, name: &str) -> String {
+        let cfg = read_pyvenv_cfg(&self.base_dir.join(name));
+        cfg.get("version")
+            .or_else(|| cfg.get("version_info"))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("venv name required");
+        }
+        let path = self.base_dir.join(name);
+        if path.exists() {
+            remove_dir_all(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut out = Vec::new();
+        for entry in fs::read_dir(&self.base_dir)
+            .with_context(|| format!("failed to read {}", self.base_dir.display()))?
+        {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                out.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        Ok(out)
+    }
+
+    /// Venvs whose `pyvenv.cfg` points at `python_home` and records the
+    /// Python `version`, an `X.Y.Z` release. Releases are compared whole,
+    /// so 3.12.1 does not match a venv of 3.12.10.
+    pub fn built_from(&self, python_home: &Path, version: &str) -> Result<Vec<String>> {
+        let Some(wanted) = python_release(version) else {
+            bail!("invalid Python version: {version}");
+        };
+        let mut out = Vec::new();
+        for name in self.list()? {
+            let cfg = read_pyvenv_cfg(&self.base_dir.join(&name));
+            let home = cfg.get("home").map(PathBuf::from).unwrap_or_default();
+            let recorded = cfg
+                .get("version")
+                .or_else(|| cfg.get("version_info"))
+                .and_then(|recorded| python_release(recorded));
+            if home.starts_with(python_home) && recorded == Some(wanted) {
+                out.push(name);
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn upgrade_base(&self, name: &str, python_path: &Path) -> Result<()> {
+        let venv_path = self.base_dir.join(name);
+        let status = Command::new(python_path)
+            .arg("-m")
+            .arg("venv")
+            .arg("--upgrade")
+            .arg(&venv_path)
+            .status()
+            .context("failed to upgrade venv")?;
+        if !status.success() {
+            bail!("failed to upgrade venv {}: {}", name, status);
+        }
+        Ok(())
+    }
+
+    /// Rewrites the venv `name` to work from any directory: console
+    /// scripts start the interpreter next to them, the activation scripts
+    /// find the venv from their own path, and `pyvenv.cfg` is marked so
+    /// scripts installed later are written the same way. `home` keeps
+    /// naming the base interpreter, which has to stay where it is, and
+    /// `activate.csh` keeps the absolute path since csh cannot tell where a
+    /// sourced file lives.
+    pub fn make_relocatable(&self, name: &str) -> Result<()> {
+        let venv_dir = self.base_dir.join(name);
+        let prefix = venv_dir.to_string_lossy().into_owned();
+        let cfg_path = venv_dir.join("pyvenv.cfg");
+        let text = fs::read_to_string(&cfg_path)
+            .with_context(|| format!("failed to read {}", cfg_path.display()))?;
+        let mut lines = text
+            .lines()
+            .filter(|line| {
+                let key = line.split_once('=').map(|(key, _)| key.trim());
+                !matches!(key, Some("command" | "relocatable"))
+            })
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        lines.push("relocatable = true".to_string());
+        fs::write(&cfg_path, lines.join("\n") + "\n")
+            .with_context(|| format!("failed to write {}", cfg_path.display()))?;
+
+        let scripts_dir = if cfg!(windows) {
+            venv_dir.join("Scripts")
+        } else {
+            venv_dir.join("bin")
+        };
+        for entry in fs::read_dir(&scripts_dir)
+            .with_context(|| format!("failed to read {}", scripts_dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_symlink() || !path.is_file() {
+                continue;
+            }
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let file_name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let rewritten = match file_name.as_str() {
+                "activate" => relocate_activate(&text, &prefix),
+                "activate.bat" => replace_any(
+                    &text,
+                    &[
+                        format!("set \"VIRTUAL_ENV={prefix}\""),
+                        format!("set VIRTUAL_ENV={prefix}"),
+                    ],
+                    "set \"VIRTUAL_ENV=%~dp0..\"",
+                ),
+                "activate.fish" => replace_any(
+                    &text,
+                    &[
+                        format!("set -gx VIRTUAL_ENV \"{prefix}\""),
+                        format!("set -gx VIRTUAL_ENV '{prefix}'"),
+                    ],
+                    "set -gx VIRTUAL_ENV (builtin realpath (dirname (status filename))/..)",
+                ),
+                _ => match text.split_once('\n') {
+                    Some((shebang, body)) if shebang.starts_with(&format!("#!{prefix}")) => {
+                        let interpreter = Path::new(&shebang[2..])
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| "python".to_string());
+                        Some(format!("{}{body}", relocatable_shebang(&interpreter)))
+                    }
+                    _ => continue,
+                },
+            };
+            // An activate script that still held the absolute path would
+            // silently activate the original location after a move.
+            let Some(rewritten) = rewritten else {
+                bail!(
+                    "{} does not set VIRTUAL_ENV in a form xe knows how to make relocatable",
+                    path.display()
+                );
+            };
+            if rewritten != text {
+                fs::write(&path, rewritten)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_python_exe(&self, name: &str) -> PathBuf {
+        if cfg!(windows) {
+            self.base_dir.join(name).join("Scripts").join("python.exe")
+        } else {
+            self.base_dir.join(name).join("bin").join("python")
+        }
+    }
+
+    pub(crate) fn get_site_packages_dir(&self, name: &str) -> PathBuf {
+        if cfg!(windows) {
+            self.base_dir.join(name).join("Lib").join("site-packages")
+        } else {
+            self.base_dir.join(name).join("lib")
+        }
+    }
+}
+
+/// Sets `VIRTUAL_ENV` in `bin/activate` from where the script was sourced,
+/// under bash, zsh and ksh.
+const ACTIVATE_FIND_VENV: &str = r#"if [ -n "${BASH_VERSION:-}" ] ; then
+    _XE_ACTIVATE="${BASH_SOURCE[0]}"
+elif [ -n "${ZSH_VERSION:-}" ] ; then
+    _XE_ACTIVATE="${(%):-%x}"
+elif [ -n "${KSH_VERSION:-}" ] ; then
+    _XE_ACTIVATE="${.sh.file}"
+else
+    _XE_ACTIVATE="$0"
+fi
+VIRTUAL_ENV="$(CDPATH= cd -- "$(dirname -- "$_XE_ACTIVATE")/.." && pwd)"
+unset _XE_ACTIVATE
+"#;
+
+/// `bin/activate` with every line that sets `VIRTUAL_ENV` to `prefix`
+/// (plain, exported or through `cygpath`, in either quote style) replaced
+/// by [`ACTIVATE_FIND_VENV`]; `None` when no line does.
+fn relocate_activate(text: &str, prefix: &str) -> Option<String> {
+    let values = [format!("\"{prefix}\""), format!("'{prefix}'")];
+    let mut found = false;
+    let mut out = String::with_capacity(text.len() + ACTIVATE_FIND_VENV.len());
+    for line in text.split_inclusive('\n') {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let (export, assignment) = match line.trim().strip_prefix("export ") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, line.trim()),
+        };
+        let sets_prefix = assignment
+            .strip_prefix("VIRTUAL_ENV=")
+            .is_some_and(|value| {
+                values
+                    .iter()
+                    .any(|v| value == v || value == format!("$(cygpath {v})"))
+            });
+        if !sets_prefix {
+            out.push_str(line);
+            continue;
+        }
+        found = true;
+        for block_line in ACTIVATE_FIND_VENV.lines() {
+            out.push_str(indent);
+            out.push_str(block_line);
+            out.push('\n');
+        }
+        if export {
+            out.push_str(indent);
+            out.push_str("export VIRTUAL_ENV\n");
+        }
+    }
+    found.then_some(out)
+}
+
+/// `text` with each of `patterns` replaced by `with`; `None` when none of
+/// them occurs.
+fn replace_any(text: &str, patterns: &[String], with: &str) -> Option<String> {
+    patterns.iter().any(|p| text.contains(p.as_str())).then(|| {
+        patterns
+            .iter()
+            .fold(text.to_string(), |text, p| text.replace(p.as_str(), with))
+    })
+}
+
+/// First lines of a console script that runs `interpreter` from the
+/// script's own directory. `sh` runs the `exec`; Python reads the lines
+/// after the `#!` as a string and moves on.
+pub(crate) fn relocatable_shebang(interpreter: &str) -> String {
+    format!(
+        "#!/bin/sh\n'''exec' \"$(dirname -- \"$(realpath -- \"$0\")\")\"/'{interpreter}' \"$0\" \"$@\"\n' '''\n"
+    )
+}
+
+/// Whether the venv around `python_exe` was made with
+/// [`VenvManager::make_relocatable`].
+pub(crate) fn is_relocatable(python_exe: &Path) -> bool {
+    python_exe
+        .parent()
+        .and_then(Path::parent)
+        .map(read_pyvenv_cfg)
+        .and_then(|cfg| cfg.get("relocatable").cloned())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+}
+
+/// `[major, minor, micro]` of a `3.12.1` version, or of the
+/// `3.12.1.final.0` form virtualenv records.
+fn python_release(version: &str) -> Option<[u32; 3]> {
+    let mut parts = version.trim().split('.');
+    let mut release = [0; 3];
+    for part in &mut release {
+        *part = parts.next()?.parse().ok()?;
+    }
+    Some(release)
+}
+
+pub(crate) fn read_pyvenv_cfg(venv_dir: &Path) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    let Ok(text) = fs::read_to_string(venv_dir.join("pyvenv.cfg")) else {
+        return out;
+    };
+    for line in text.lines() {
+        if let Some((key, value)) = line.split_once('=') {
+            out.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    out
+}
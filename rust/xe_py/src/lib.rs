@@ -0,0 +1,203 @@
+//! The `xe` Python extension module: resolve, install, lock and sync a
+//! project in-process through xe_core, for build tooling written in Python.
+//!
+//! ```python
+//! import xe
+//! xe.lock("services/api")
+//! for pkg in xe.sync("services/api"):
+//!     print(pkg.name, pkg.version)
+//! ```
+
+use anyhow::anyhow;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use std::env;
+use std::path::PathBuf;
+use xe_core::config::{load_or_create_project, save_project};
+use xe_core::install::Package;
+use xe_core::lock::{load_lock, lock_fingerprint, save_lock, LockFile, XE_LOCK};
+use xe_core::paths::xe_config_file;
+use xe_core::profile::AppContext;
+use xe_core::runtime::{ensure_runtime_for_project, RuntimeSelection};
+use xe_core::tags::TargetEnv;
+use xe_core::{Config, Installer};
+
+create_exception!(xe, XeError, PyException, "An xe operation failed.");
+
+/// A resolved or installed distribution.
+#[pyclass(name = "Package", module = "xe", frozen, get_all)]
+struct PyPackage {
+    name: String,
+    version: String,
+    editable: bool,
+}
+
+#[pymethods]
+impl PyPackage {
+    fn __repr__(&self) -> String {
+        format!("Package({:?}, {:?})", self.name, self.version)
+    }
+}
+
+impl From<&Package> for PyPackage {
+    fn from(pkg: &Package) -> Self {
+        Self {
+            name: pkg.name.clone(),
+            version: pkg.version.clone(),
+            editable: pkg.editable,
+        }
+    }
+}
+
+fn to_py_err(err: xe_core::Error) -> PyErr {
+    XeError::new_err(format!("{err:#}"))
+}
+
+fn packages(packages: &[Package]) -> Vec<PyPackage> {
+    packages.iter().map(PyPackage::from).collect()
+}
+
+/// A project opened the way the CLI opens it from `project_dir`.
+struct Project {
+    dir: PathBuf,
+    cfg: Config,
+    runtime: RuntimeSelection,
+    installer: Installer,
+}
+
+impl Project {
+    /// Loads (or creates) `xe.toml` and selects the runtime, installing
+    /// the Python it asks for when missing.
+    fn open(project_dir: Option<PathBuf>) -> xe_core::Result<Self> {
+        let dir = match project_dir {
+            Some(dir) => dir,
+            None => env::current_dir()?,
+        };
+        let (mut cfg, toml_path) = load_or_create_project(&dir)?;
+        let runtime = ensure_runtime_for_project(&context(), &dir, &mut cfg)?;
+        if runtime.config_changed {
+            save_project(&toml_path, &cfg)?;
+        }
+        let installer = Installer::new(&cfg, &dir)?;
+        Ok(Self {
+            dir,
+            cfg,
+            runtime: runtime.selection,
+            installer,
+        })
+    }
+
+    fn requirements(&self, requirements: Option<Vec<String>>) -> Vec<String> {
+        requirements.unwrap_or_else(|| self.cfg.requirements(&self.dir))
+    }
+}
+
+fn context() -> AppContext {
+    AppContext {
+        config_file: xe_config_file(),
+        profiler: None,
+    }
+}
+
+/// Resolves `requirements` (by default the project's) for the project's
+/// interpreter without installing anything.
+#[pyfunction]
+#[pyo3(signature = (requirements=None, project_dir=None))]
+fn resolve(
+    py: Python<'_>,
+    requirements: Option<Vec<String>>,
+    project_dir: Option<PathBuf>,
+) -> PyResult<Vec<PyPackage>> {
+    py.detach(|| {
+        let project = Project::open(project_dir)?;
+        let reqs = project.requirements(requirements);
+        let graph = project
+            .installer
+            .resolve(&project.cfg, &reqs, &project.runtime.python_exe)?;
+        Ok(packages(&graph.packages))
+    })
+    .map_err(to_py_err)
+}
+
+/// Resolves and installs `requirements` (by default the project's) into
+/// the project's environment.
+#[pyfunction]
+#[pyo3(signature = (requirements=None, project_dir=None))]
+fn install(
+    py: Python<'_>,
+    requirements: Option<Vec<String>>,
+    project_dir: Option<PathBuf>,
+) -> PyResult<Vec<PyPackage>> {
+    py.detach(|| {
+        let project = Project::open(project_dir)?;
+        let reqs = project.requirements(requirements);
+        let installed = project.installer.install(
+            &context(),
+            &project.cfg,
+            &reqs,
+            &project.dir,
+            &project.runtime.site_packages,
+            &project.runtime.python_exe,
+        )?;
+        Ok(packages(&installed))
+    })
+    .map_err(to_py_err)
+}
+
+/// Resolves the project and writes `xe.lock` with every platform's wheels,
+/// without installing anything.
+#[pyfunction]
+#[pyo3(signature = (project_dir=None))]
+fn lock(py: Python<'_>, project_dir: Option<PathBuf>) -> PyResult<Vec<PyPackage>> {
+    py.detach(|| {
+        let project = Project::open(project_dir)?;
+        let reqs = project.requirements(None);
+        let graph = project
+            .installer
+            .resolve(&project.cfg, &reqs, &project.runtime.python_exe)?;
+        let target = TargetEnv::probe(&project.runtime.python_exe)?;
+        let lock = LockFile::new(&project.cfg, &graph.packages, &target)?;
+        save_lock(&project.dir, &lock)?;
+        Ok(packages(&lock.packages))
+    })
+    .map_err(to_py_err)
+}
+
+/// Installs the project's up-to-date `xe.lock` into its environment.
+#[pyfunction]
+#[pyo3(signature = (project_dir=None))]
+fn sync(py: Python<'_>, project_dir: Option<PathBuf>) -> PyResult<Vec<PyPackage>> {
+    py.detach(|| {
+        let project = Project::open(project_dir)?;
+        let lock = match load_lock(&project.dir)? {
+            Some(lock) if lock.fingerprint == lock_fingerprint(&project.cfg) => lock,
+            Some(_) => {
+                return Err(
+                    anyhow!("{XE_LOCK} is out of date with xe.toml; call xe.lock() first").into(),
+                )
+            }
+            None => return Err(anyhow!("no {XE_LOCK}; call xe.lock() first").into()),
+        };
+        let installed = project.installer.install_locked(
+            &context(),
+            &lock,
+            &project.runtime.site_packages,
+            &project.runtime.python_exe,
+        )?;
+        Ok(packages(&installed))
+    })
+    .map_err(to_py_err)
+}
+
+#[pymodule]
+fn xe(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add("XeError", m.py().get_type::<XeError>())?;
+    m.add_class::<PyPackage>()?;
+    m.add_function(wrap_pyfunction!(resolve, m)?)?;
+    m.add_function(wrap_pyfunction!(install, m)?)?;
+    m.add_function(wrap_pyfunction!(lock, m)?)?;
+    m.add_function(wrap_pyfunction!(sync, m)?)?;
+    Ok(())
+}